@@ -0,0 +1,325 @@
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range, Url};
+
+use crate::{BaconLs, DiagnosticData, PKG_NAME, PROC_MACRO_PANIC_CODE};
+
+/// Diagnostic of the compiler, as found in the analysis exported by bacon.
+#[derive(Debug, Deserialize)]
+struct CompilerDiagnostic {
+    message: String,
+    level: String,
+    code: Option<CompilerCode>,
+    rendered: Option<String>,
+    spans: Vec<CompilerSpan>,
+    #[serde(default)]
+    children: Vec<CompilerDiagnostic>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompilerCode {
+    code: String,
+}
+
+/// Span of a compiler diagnostic, shared with the cargo JSON parser of the custom commands.
+#[derive(Debug, Deserialize)]
+pub(crate) struct CompilerSpan {
+    pub(crate) file_name: String,
+    pub(crate) line_start: u32,
+    pub(crate) line_end: u32,
+    pub(crate) column_start: u32,
+    pub(crate) column_end: u32,
+    #[serde(default)]
+    pub(crate) is_primary: bool,
+    suggested_replacement: Option<String>,
+    /// Macro invocation which expanded into the code of the span.
+    expansion: Option<Box<SpanExpansion>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpanExpansion {
+    span: CompilerSpan,
+}
+
+impl CompilerSpan {
+    /// Return true if the span is in a file of the project which the user can open, rather than
+    /// in the code generated by a macro, in a dependency or under `target`.
+    fn is_in_project(&self, folder_path: &Path) -> bool {
+        let path = folder_path.join(&self.file_name);
+        !self.file_name.starts_with('<')
+            && path.starts_with(folder_path)
+            && !path.starts_with(folder_path.join("target"))
+    }
+
+    /// The span, or the first macro invocation in the project it was expanded from when it is
+    /// outside, like rust-analyzer does.
+    pub(crate) fn invocation_site(&self, folder_path: &Path) -> &Self {
+        let mut span = self;
+        while !span.is_in_project(folder_path) {
+            match span.expansion.as_deref() {
+                Some(expansion) => span = &expansion.span,
+                None => return self,
+            }
+        }
+        span
+    }
+
+    fn location(&self, folder_path: &Path) -> Option<(Url, Range)> {
+        let url = Url::from_file_path(folder_path.join(&self.file_name)).ok()?;
+        let range = Range::new(
+            Position::new(
+                self.line_start.saturating_sub(1),
+                self.column_start.saturating_sub(1),
+            ),
+            Position::new(
+                self.line_end.saturating_sub(1),
+                self.column_end.saturating_sub(1),
+            ),
+        );
+        Some((url, range))
+    }
+}
+
+/// Return true if the locations file is a JSON analysis rather than lines in the `line_format`
+/// of the bacon preferences.
+pub(crate) fn is_analysis(content: &[u8]) -> bool {
+    content
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| matches!(byte, b'{' | b'['))
+}
+
+/// Collect the compiler diagnostics found anywhere in a JSON value: bacon wraps them in its
+/// analysis, possibly as the lines of cargo output, and cargo in its `compiler-message` lines.
+fn collect(value: Value, diagnostics: &mut Vec<CompilerDiagnostic>) {
+    match value {
+        Value::Array(values) => values
+            .into_iter()
+            .for_each(|value| collect(value, diagnostics)),
+        Value::Object(ref object)
+            if object.contains_key("spans") && object.contains_key("level") =>
+        {
+            match serde_json::from_value(value) {
+                Ok(diagnostic) => diagnostics.push(diagnostic),
+                Err(e) => tracing::debug!("skipping malformed diagnostic: {e}"),
+            }
+        }
+        Value::Object(object) => object
+            .into_iter()
+            .for_each(|(_, value)| collect(value, diagnostics)),
+        Value::String(line) if line.starts_with('{') => {
+            if let Ok(value) = serde_json::from_str(&line) {
+                collect(value, diagnostics);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Convert a compiler diagnostic into the same diagnostics as its lines exported with the
+/// `line_format`: the diagnostic on its primary span, followed by the suggestions of its help.
+fn convert(
+    diagnostic: CompilerDiagnostic,
+    folder_path: &Path,
+    entries: &mut Vec<(Url, Diagnostic)>,
+) {
+    let Some((url, range)) = diagnostic
+        .spans
+        .iter()
+        .find(|span| span.is_primary)
+        .and_then(|span| span.invocation_site(folder_path).location(folder_path))
+    else {
+        return;
+    };
+    // The payload of a proc-macro panic is only part of its help.
+    let (message, code) = if BaconLs::is_proc_macro_panic(&diagnostic.message) {
+        let payload = diagnostic
+            .children
+            .iter()
+            .find_map(|child| child.message.strip_prefix("message: "));
+        let message = match payload {
+            Some(payload) => format!("{}: {payload}", diagnostic.message),
+            None => diagnostic.message,
+        };
+        (message, Some(PROC_MACRO_PANIC_CODE.to_string()))
+    } else {
+        (diagnostic.message, diagnostic.code.map(|code| code.code))
+    };
+    let data = diagnostic.rendered.map(|rendered| {
+        serde_json::json!(DiagnosticData {
+            corrections: vec![],
+            edits: vec![],
+            title: None,
+            rendered: Some(rendered.into()),
+        })
+    });
+    entries.push((
+        url,
+        Diagnostic {
+            range,
+            severity: Some(BaconLs::parse_severity(&diagnostic.level)),
+            code: code.map(NumberOrString::String),
+            source: Some(PKG_NAME.to_string()),
+            message,
+            data,
+            ..Diagnostic::default()
+        },
+    ));
+    for child in diagnostic.children {
+        for span in &child.spans {
+            let Some(replacement) = span.suggested_replacement.as_deref() else {
+                continue;
+            };
+            let Some((url, range)) = span.location(folder_path) else {
+                continue;
+            };
+            entries.push((
+                url,
+                Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::HINT),
+                    source: Some(PKG_NAME.to_string()),
+                    message: format!("{}: {replacement}", child.message),
+                    data: Some(serde_json::json!(DiagnosticData {
+                        corrections: vec![replacement.into()],
+                        edits: vec![],
+                        title: None,
+                        rendered: None,
+                    })),
+                    ..Diagnostic::default()
+                },
+            ));
+        }
+    }
+}
+
+/// Parse the diagnostics of a JSON analysis exported by bacon, returning false with them if
+/// it is incomplete. The file is parsed as a stream of values, so both a single document and one
+/// value per line are supported, and a document still being written keeps the values completed
+/// so far.
+pub(crate) fn parse_analysis(content: &[u8], folder_path: &Path) -> (Vec<(Url, Diagnostic)>, bool) {
+    let mut diagnostics = Vec::new();
+    let mut complete = true;
+    for value in serde_json::Deserializer::from_slice(content).into_iter::<Value>() {
+        match value {
+            Ok(value) => collect(value, &mut diagnostics),
+            Err(e) => {
+                tracing::debug!("incomplete bacon analysis: {e}");
+                complete = false;
+                break;
+            }
+        }
+    }
+    let mut entries = Vec::new();
+    for diagnostic in diagnostics {
+        convert(diagnostic, folder_path, &mut entries);
+    }
+    (entries, complete)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const DIAGNOSTIC: &str = r#"{"message":"unused variable: `x`","code":{"code":"unused_variables","explanation":null},"level":"warning","spans":[{"file_name":"src/lib.rs","byte_start":30,"byte_end":31,"line_start":3,"line_end":3,"column_start":9,"column_end":10,"is_primary":true,"text":[],"label":null,"suggested_replacement":null}],"children":[{"message":"`#[warn(unused_variables)]` on by default","code":null,"level":"note","spans":[],"children":[],"rendered":null},{"message":"if this is intentional, prefix it with an underscore","code":null,"level":"help","spans":[{"file_name":"src/lib.rs","byte_start":30,"byte_end":31,"line_start":3,"line_end":3,"column_start":9,"column_end":10,"is_primary":true,"text":[],"label":null,"suggested_replacement":"_x"}],"children":[],"rendered":null}],"rendered":"warning: unused variable: `x`\n --> src/lib.rs:3:9\n"}"#;
+
+    #[test]
+    fn test_parse_analysis() {
+        let folder_path = Path::new("/projects/app");
+        let summary = |content: &str| -> Vec<(String, Range, Option<DiagnosticSeverity>, String)> {
+            assert!(is_analysis(content.as_bytes()));
+            parse_analysis(content.as_bytes(), folder_path)
+                .0
+                .into_iter()
+                .map(|(url, diagnostic)| {
+                    (
+                        url.path().to_string(),
+                        diagnostic.range,
+                        diagnostic.severity,
+                        diagnostic.message,
+                    )
+                })
+                .collect()
+        };
+        let range = Range::new(Position::new(2, 8), Position::new(2, 9));
+        let expected = vec![
+            (
+                "/projects/app/src/lib.rs".to_string(),
+                range,
+                Some(DiagnosticSeverity::WARNING),
+                "unused variable: `x`".to_string(),
+            ),
+            (
+                "/projects/app/src/lib.rs".to_string(),
+                range,
+                Some(DiagnosticSeverity::HINT),
+                "if this is intentional, prefix it with an underscore: _x".to_string(),
+            ),
+        ];
+
+        let analysis = format!("{{\n  \"diagnostics\": [\n    {DIAGNOSTIC}\n  ]\n}}");
+        assert_eq!(summary(&analysis), expected);
+        let lines = format!(
+            "{{\"reason\":\"compiler-artifact\"}}\n{{\"reason\":\"compiler-message\",\"message\":{DIAGNOSTIC}}}\n"
+        );
+        assert_eq!(summary(&lines), expected);
+        // The diagnostics completed before the end of a file being written are kept.
+        assert_eq!(summary(&format!("{DIAGNOSTIC}\n{{\"message\":")), expected);
+
+        let (entries, complete) = parse_analysis(DIAGNOSTIC.as_bytes(), folder_path);
+        assert!(complete);
+        assert!(
+            !parse_analysis(
+                format!("{DIAGNOSTIC}\n{{\"message\":").as_bytes(),
+                folder_path
+            )
+            .1
+        );
+        assert_eq!(
+            entries[0].1.code,
+            Some(NumberOrString::String("unused_variables".to_string()))
+        );
+        assert_eq!(
+            entries[1].1.data,
+            Some(serde_json::json!({"corrections": ["_x"]}))
+        );
+        assert!(!is_analysis(b"warning|:|src/lib.rs|:|3"));
+    }
+
+    #[test]
+    fn test_invocation_site() {
+        let folder_path = Path::new("/projects/app");
+        let span = |file_name: &str, line: u32, expansion: Option<CompilerSpan>| CompilerSpan {
+            file_name: file_name.to_string(),
+            line_start: line,
+            line_end: line,
+            column_start: 1,
+            column_end: 2,
+            is_primary: true,
+            suggested_replacement: None,
+            expansion: expansion.map(|span| Box::new(SpanExpansion { span })),
+        };
+        let in_project = span("src/lib.rs", 3, None);
+        assert_eq!(in_project.invocation_site(folder_path).line_start, 3);
+        // Generated code and the macros of the dependencies are walked back to the invocation.
+        let expanded = span(
+            "<::serde::macros>",
+            1,
+            Some(span(
+                "/home/user/.cargo/registry/src/serde/macros.rs",
+                20,
+                Some(span("src/main.rs", 7, None)),
+            )),
+        );
+        let site = expanded.invocation_site(folder_path);
+        assert_eq!(
+            (site.file_name.as_str(), site.line_start),
+            ("src/main.rs", 7)
+        );
+        let generated = span("target/debug/build/app/out/bindings.rs", 10, None);
+        assert_eq!(generated.invocation_site(folder_path).line_start, 10);
+    }
+}
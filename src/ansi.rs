@@ -0,0 +1,145 @@
+/// Part of a line of rendered output sharing the same weight.
+#[derive(Debug, PartialEq)]
+struct Segment {
+    text: String,
+    bold: bool,
+}
+
+/// Split a line into segments, following the SGR sequences setting and resetting the bold
+/// weight. Colors and the other escape sequences are dropped. The weight at the end of the line
+/// is carried to the next one in `bold`.
+fn segments(line: &str, bold: &mut bool) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next_if_eq(&'[').is_none() {
+                continue;
+            }
+            let mut params = String::new();
+            let mut command = None;
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    command = Some(c);
+                    break;
+                }
+                params.push(c);
+            }
+            if command == Some('m') {
+                for param in params.split(';') {
+                    match param {
+                        "" | "0" | "22" => *bold = false,
+                        "1" => *bold = true,
+                        _ => {}
+                    }
+                }
+            }
+            continue;
+        }
+        match segments.last_mut() {
+            Some(segment) if segment.bold == *bold => segment.text.push(c),
+            _ => segments.push(Segment {
+                text: c.to_string(),
+                bold: *bold,
+            }),
+        }
+    }
+    segments
+}
+
+/// Remove the escape sequences of the text.
+pub(crate) fn strip(text: &str) -> String {
+    let mut bold = false;
+    text.split('\n')
+        .map(|line| {
+            segments(line, &mut bold)
+                .into_iter()
+                .map(|segment| segment.text)
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// True for the lines of the code frame of rustc, drawn next to its gutter, like
+/// `  --> src/lib.rs:3:5`, `3 |     let x = 1;` or `  = note: ...`.
+fn is_code_frame(line: &str) -> bool {
+    let line = line.trim_start();
+    let after_line_number = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    line.starts_with("-->")
+        || line.starts_with(":::")
+        || line.starts_with('=')
+        || line == "..."
+        || after_line_number.trim_start().starts_with('|')
+}
+
+/// Convert the rendered output of rustc, with or without its ANSI colors, to Markdown. The
+/// code frames are fenced to keep their alignment and the bold text of the other lines, like
+/// the primary message, is emphasized.
+pub(crate) fn to_markdown(rendered: &str) -> String {
+    let mut blocks: Vec<String> = Vec::new();
+    let mut code_frame: Vec<String> = Vec::new();
+    let mut bold = false;
+    let flush = |code_frame: &mut Vec<String>, blocks: &mut Vec<String>| {
+        if !code_frame.is_empty() {
+            blocks.push(format!("```text\n{}\n```", code_frame.join("\n")));
+            code_frame.clear();
+        }
+    };
+    for line in rendered.trim_end().split('\n') {
+        let segments = segments(line, &mut bold);
+        let text: String = segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect();
+        if is_code_frame(&text) {
+            code_frame.push(text.trim_end().to_string());
+            continue;
+        }
+        flush(&mut code_frame, &mut blocks);
+        if text.trim().is_empty() {
+            continue;
+        }
+        let prose: String = segments
+            .iter()
+            .map(|segment| match segment.text.trim() {
+                trimmed if segment.bold && !trimmed.is_empty() => {
+                    segment.text.replacen(trimmed, &format!("**{trimmed}**"), 1)
+                }
+                _ => segment.text.clone(),
+            })
+            .collect();
+        blocks.push(prose.trim().to_string());
+    }
+    flush(&mut code_frame, &mut blocks);
+    blocks.join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const RENDERED: &str = "\x1b[0m\x1b[1m\x1b[38;5;9merror[E0425]\x1b[0m\x1b[0m\x1b[1m: cannot find value `one` in this scope\x1b[0m\n\x1b[0m   \x1b[0m\x1b[0m\x1b[1m\x1b[38;5;12m--> \x1b[0m\x1b[0msrc/lib.rs:3:5\x1b[0m\n\x1b[0m    \x1b[0m\x1b[0m\x1b[1m\x1b[38;5;12m|\x1b[0m\n\x1b[0m\x1b[1m\x1b[38;5;12m3\x1b[0m\x1b[0m \x1b[0m\x1b[0m\x1b[1m\x1b[38;5;12m|\x1b[0m\x1b[0m \x1b[0m\x1b[0m    one\x1b[0m\n\x1b[0m    \x1b[0m\x1b[0m\x1b[1m\x1b[38;5;12m|\x1b[0m\x1b[0m     \x1b[0m\x1b[0m\x1b[1m\x1b[38;5;9m^^^\x1b[0m\x1b[0m \x1b[0m\x1b[0m\x1b[1m\x1b[38;5;9mnot found in this scope\x1b[0m\n\n\x1b[0m\x1b[1mFor more information about this error, try `rustc --explain E0425`.\x1b[0m\n";
+
+    #[test]
+    fn test_strip() {
+        assert_eq!(
+            strip(RENDERED),
+            "error[E0425]: cannot find value `one` in this scope\n   --> src/lib.rs:3:5\n    |\n3 |     one\n    |     ^^^ not found in this scope\n\nFor more information about this error, try `rustc --explain E0425`.\n"
+        );
+        assert_eq!(strip("no colors"), "no colors");
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        assert_eq!(
+            to_markdown(RENDERED),
+            "**error[E0425]: cannot find value `one` in this scope**\n\n```text\n   --> src/lib.rs:3:5\n    |\n3 |     one\n    |     ^^^ not found in this scope\n```\n\n**For more information about this error, try `rustc --explain E0425`.**"
+        );
+        assert_eq!(
+            to_markdown("unused import: `std::fs`\n  |\n1 | use std::fs;\n  |     ^^^^^^^\n  |\n  = note: `#[warn(unused_imports)]` on by default\n"),
+            "unused import: `std::fs`\n\n```text\n  |\n1 | use std::fs;\n  |     ^^^^^^^\n  |\n  = note: `#[warn(unused_imports)]` on by default\n```"
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{
+    CodeDescription, Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range, Url,
+};
+
+use crate::error::{BaconLsError, Result};
+use crate::parsed_output_command;
+use crate::provider::DiagnosticsProvider;
+use crate::usage::{self, record_json_bytes};
+
+pub(crate) const AUDIT_PROVIDER: &str = "cargo-audit";
+pub(crate) const CARGO_MANIFEST: &str = "Cargo.toml";
+
+#[derive(Debug, Default, Deserialize)]
+struct AuditReport {
+    #[serde(default)]
+    vulnerabilities: Vulnerabilities,
+    #[serde(default)]
+    warnings: HashMap<String, Vec<Finding>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Vulnerabilities {
+    #[serde(default)]
+    list: Vec<Finding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Finding {
+    advisory: Option<Advisory>,
+    package: Package,
+}
+
+#[derive(Debug, Deserialize)]
+struct Advisory {
+    id: String,
+    title: String,
+    url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+}
+
+/// Declaration of a dependency in a Cargo manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DependencyDeclaration {
+    /// Key of the dependency, which differs from the package name for renamed dependencies.
+    pub(crate) key: String,
+    /// Range of the dependency key.
+    pub(crate) range: Range,
+    /// Range of all the lines declaring the dependency, including a dedicated table.
+    pub(crate) entry: Range,
+}
+
+/// Declarations of the dependencies of a Cargo manifest, keyed by table and package name, as a
+/// package can be declared in several tables, like both a dependency and a dev-dependency.
+pub(crate) type DependencyDeclarations = HashMap<(String, String), DependencyDeclaration>;
+
+/// Declaration of a package in the tables of a kind of dependencies, like `dev-dependencies`,
+/// including the target specific ones, or in any table if the kind is unknown. The name is
+/// either the package name or the key of a renamed dependency, and the normal dependencies are
+/// preferred when several tables declare it.
+pub(crate) fn find_declaration<'d>(
+    declarations: &'d DependencyDeclarations,
+    kind: Option<&str>,
+    name: &str,
+) -> Option<&'d DependencyDeclaration> {
+    declarations
+        .iter()
+        .filter(|((table, _), _)| {
+            kind.is_none_or(|kind| table == kind || table.ends_with(&format!(".{kind}")))
+        })
+        .filter(|((_, package), declaration)| package == name || declaration.key == name)
+        .min_by_key(|((table, package), _)| (package != name, table != "dependencies", table))
+        .map(|(_, declaration)| declaration)
+}
+
+/// Nesting of inline tables and arrays opened and not closed in a line of TOML.
+fn bracket_depth(text: &str) -> i32 {
+    let mut depth = 0;
+    let mut in_string = false;
+    for c in text.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => break,
+            '{' | '[' if !in_string => depth += 1,
+            '}' | ']' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn close_entry(
+    declarations: &mut DependencyDeclarations,
+    key: &(String, String),
+    start_line: u32,
+    end_line: u32,
+) {
+    if let Some(declaration) = declarations
+        .get_mut(key)
+        .filter(|declaration| declaration.entry.start.line == start_line)
+    {
+        declaration.entry.end = Position::new(end_line + 1, 0);
+    }
+}
+
+/// Declaration of each dependency in a Cargo manifest.
+pub(crate) fn dependency_declarations(manifest: &str) -> DependencyDeclarations {
+    let mut declarations = DependencyDeclarations::new();
+    // Dependency table being read, like `dev-dependencies`.
+    let mut dependencies_table: Option<String> = None;
+    // Dependency declared in a `[dependencies.name]` table, with its first and last lines.
+    let mut open_table: Option<((String, String), u32, u32)> = None;
+    // Dependency declared in an inline table spanning multiple lines, with its first line.
+    let mut open_inline: Option<((String, String), u32)> = None;
+    let mut depth = 0;
+    for (index, line) in manifest.lines().enumerate() {
+        let index = index as u32;
+        let trimmed = line.trim();
+        if let Some((table_key, start_line)) = open_inline.take() {
+            depth += bracket_depth(trimmed);
+            if depth > 0 {
+                open_inline = Some((table_key, start_line));
+            } else {
+                close_entry(&mut declarations, &table_key, start_line, index);
+            }
+            continue;
+        }
+        if let Some(section) = trimmed
+            .strip_prefix('[')
+            .and_then(|section| section.strip_suffix(']'))
+        {
+            if let Some((table_key, start_line, end_line)) = open_table.take() {
+                close_entry(&mut declarations, &table_key, start_line, end_line);
+            }
+            let section = section.trim_matches(['[', ']']);
+            let (table, name) = match section.rsplit_once("dependencies.") {
+                Some((table, name)) => (format!("{table}dependencies"), Some(name)),
+                None => (section.to_string(), None),
+            };
+            let is_dependency_table = table.ends_with("dependencies");
+            dependencies_table =
+                Some(table.clone()).filter(|_| is_dependency_table && name.is_none());
+            if let Some(name) = name.filter(|_| is_dependency_table) {
+                let start = line.find(name).unwrap_or_default() as u32;
+                let key = name.trim_matches('"').to_string();
+                let table_key = (table, key.clone());
+                declarations
+                    .entry(table_key.clone())
+                    .or_insert(DependencyDeclaration {
+                        key: key.clone(),
+                        range: Range::new(
+                            Position::new(index, start),
+                            Position::new(index, start + name.len() as u32),
+                        ),
+                        entry: Range::new(Position::new(index, 0), Position::new(index + 1, 0)),
+                    });
+                open_table = Some((table_key, index, index));
+            }
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some((_, _, end_line)) = open_table.as_mut() {
+            *end_line = index;
+        }
+        let Some(table) = &dependencies_table else {
+            continue;
+        };
+        let Some((key, value)) = trimmed.split_once('=') else {
+            continue;
+        };
+        let key = key
+            .trim()
+            .split('.')
+            .next()
+            .unwrap_or_default()
+            .trim_matches('"');
+        if key.is_empty() {
+            continue;
+        }
+        // A renamed dependency is declared with `alias = { package = "name" }`.
+        let name = value
+            .split_once("package =")
+            .or_else(|| value.split_once("package="))
+            .and_then(|(_, package)| package.split('"').nth(1))
+            .unwrap_or(key);
+        let start = line.find(key).unwrap_or_default() as u32;
+        let table_key = (table.clone(), name.to_string());
+        declarations
+            .entry(table_key.clone())
+            .or_insert(DependencyDeclaration {
+                key: key.to_string(),
+                range: Range::new(
+                    Position::new(index, start),
+                    Position::new(index, start + key.len() as u32),
+                ),
+                entry: Range::new(Position::new(index, 0), Position::new(index + 1, 0)),
+            });
+        depth = bracket_depth(value);
+        if depth > 0 {
+            open_inline = Some((table_key, index));
+        }
+    }
+    if let Some((table_key, start_line, end_line)) = open_table {
+        close_entry(&mut declarations, &table_key, start_line, end_line);
+    }
+    declarations
+}
+
+fn finding_diagnostic(finding: &Finding, kind: &str, range: Option<Range>) -> Diagnostic {
+    let package = format!("{} {}", finding.package.name, finding.package.version);
+    let (code, code_description, title) = match &finding.advisory {
+        Some(advisory) => (
+            Some(NumberOrString::String(advisory.id.clone())),
+            advisory
+                .url
+                .as_deref()
+                .and_then(|url| Url::parse(url).ok())
+                .map(|href| CodeDescription { href }),
+            advisory.title.clone(),
+        ),
+        None => (None, None, kind.to_string()),
+    };
+    let message = match range {
+        Some(_) => format!("{package}: {title}"),
+        None => format!("{package} (transitive dependency): {title}"),
+    };
+    Diagnostic {
+        range: range.unwrap_or_default(),
+        severity: Some(DiagnosticSeverity::WARNING),
+        code,
+        code_description,
+        source: Some(AUDIT_PROVIDER.to_string()),
+        message,
+        ..Diagnostic::default()
+    }
+}
+
+fn report_diagnostics(report: &AuditReport, manifest: &str) -> Vec<Diagnostic> {
+    let declarations = dependency_declarations(manifest);
+    report
+        .vulnerabilities
+        .list
+        .iter()
+        .map(|finding| ("vulnerability", finding))
+        .chain(report.warnings.iter().flat_map(|(kind, findings)| {
+            findings.iter().map(move |finding| (kind.as_str(), finding))
+        }))
+        .map(|(kind, finding)| {
+            let range = find_declaration(&declarations, None, &finding.package.name)
+                .map(|declaration| declaration.range);
+            finding_diagnostic(finding, kind, range)
+        })
+        .collect()
+}
+
+/// Report the vulnerable dependencies found by `cargo audit` on the Cargo manifest.
+#[derive(Debug)]
+pub(crate) struct CargoAudit;
+
+/// Return true if the file is a Cargo manifest or lock file.
+pub(crate) fn is_cargo_manifest(uri: &Url) -> bool {
+    uri.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .is_some_and(|name| name == CARGO_MANIFEST || name == "Cargo.lock")
+}
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for CargoAudit {
+    fn name(&self) -> &'static str {
+        AUDIT_PROVIDER
+    }
+
+    fn runs_on_save(&self, uri: &Url) -> bool {
+        is_cargo_manifest(uri)
+    }
+
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let manifest_path = folder_path.join(CARGO_MANIFEST);
+        let manifest = tokio::fs::read_to_string(&manifest_path)
+            .await
+            .map_err(|e| {
+                BaconLsError::io(format!("unable to read {}", manifest_path.display()), e)
+            })?;
+        let output = usage::output(
+            parsed_output_command("cargo")
+                .envs(env)
+                .args(["audit", "--json"])
+                .current_dir(folder_path),
+        )
+        .await
+        .map_err(|e| BaconLsError::Spawn(format!("failed to run cargo audit: {e}")))?;
+        record_json_bytes(output.stdout.len());
+        // cargo audit exits with an error when vulnerabilities are found, so only a missing or
+        // unparsable output is considered a failure.
+        if output.stdout.is_empty() {
+            return Err(BaconLsError::Spawn(format!(
+                "cargo audit failed, is cargo-audit installed? {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let report: AuditReport = serde_json::from_slice(&output.stdout)
+            .map_err(|e| BaconLsError::Parse(format!("failed to parse cargo audit output: {e}")))?;
+        let url = Url::from_file_path(&manifest_path).map_err(|_| {
+            BaconLsError::Parse(format!("invalid manifest path {}", manifest_path.display()))
+        })?;
+        Ok(HashMap::from([(
+            url,
+            report_diagnostics(&report, &manifest),
+        )]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const MANIFEST: &str = r#"[package]
+name = "bacon-ls"
+version = "0.12.0"
+
+[dependencies]
+argh = "0.1.13"
+tokio = { version = "1.43.0", features = [
+    "fs",
+], default-features = false }
+json = { version = "1.0", package = "serde_json" }
+
+[dev-dependencies.tempdir]
+version = "0.3.7"
+
+[target.'cfg(unix)'.dependencies]
+libc.workspace = true
+
+[dev-dependencies]
+argh = "0.1.13"
+"#;
+
+    #[test]
+    fn test_dependency_declarations() {
+        let declarations = dependency_declarations(MANIFEST);
+        let declaration = |table: &str, name: &str| &declarations[&(table.into(), name.into())];
+        let ranges = |table: &str, name: &str| {
+            let declaration = declaration(table, name);
+            (declaration.range, declaration.entry)
+        };
+        assert_eq!(
+            ranges("dependencies", "argh"),
+            (
+                Range::new(Position::new(5, 0), Position::new(5, 4)),
+                Range::new(Position::new(5, 0), Position::new(6, 0))
+            )
+        );
+        assert_eq!(
+            ranges("dependencies", "tokio"),
+            (
+                Range::new(Position::new(6, 0), Position::new(6, 5)),
+                Range::new(Position::new(6, 0), Position::new(9, 0))
+            )
+        );
+        assert_eq!(declaration("dependencies", "serde_json").key, "json");
+        assert_eq!(
+            ranges("dependencies", "serde_json"),
+            (
+                Range::new(Position::new(9, 0), Position::new(9, 4)),
+                Range::new(Position::new(9, 0), Position::new(10, 0))
+            )
+        );
+        assert_eq!(
+            ranges("dev-dependencies", "tempdir"),
+            (
+                Range::new(Position::new(11, 18), Position::new(11, 25)),
+                Range::new(Position::new(11, 0), Position::new(13, 0))
+            )
+        );
+        assert_eq!(
+            ranges("target.'cfg(unix)'.dependencies", "libc"),
+            (
+                Range::new(Position::new(15, 0), Position::new(15, 4)),
+                Range::new(Position::new(15, 0), Position::new(16, 0))
+            )
+        );
+        assert!(declarations.keys().all(|(_, name)| name != "version"));
+        assert_eq!(declarations.len(), 6);
+        // The dev-dependency is only found in its own table, while the normal one is preferred
+        // when the kind is unknown.
+        let found = |kind, name| find_declaration(&declarations, kind, name).map(|d| d.range.start);
+        assert_eq!(found(None, "tempdir"), Some(Position::new(11, 18)));
+        assert_eq!(found(None, "argh"), Some(Position::new(5, 0)));
+        assert_eq!(
+            found(Some("dev-dependencies"), "argh"),
+            Some(Position::new(18, 0))
+        );
+        assert_eq!(
+            found(Some("dependencies"), "libc"),
+            Some(Position::new(15, 0))
+        );
+        assert_eq!(
+            found(Some("dependencies"), "json"),
+            Some(Position::new(9, 0))
+        );
+        assert_eq!(found(Some("build-dependencies"), "argh"), None);
+    }
+
+    #[test]
+    fn test_report_diagnostics() {
+        let report: AuditReport = serde_json::from_str(
+            r#"{
+                "vulnerabilities": {
+                    "found": true,
+                    "count": 1,
+                    "list": [{
+                        "advisory": {
+                            "id": "RUSTSEC-2020-0071",
+                            "title": "Potential segfault in the time crate",
+                            "url": "https://github.com/time-rs/time/issues/293"
+                        },
+                        "package": {"name": "tokio", "version": "1.0.0"}
+                    }]
+                },
+                "warnings": {
+                    "unmaintained": [{
+                        "kind": "unmaintained",
+                        "advisory": {
+                            "id": "RUSTSEC-2020-0036",
+                            "title": "failure is officially deprecated/unmaintained",
+                            "url": null
+                        },
+                        "package": {"name": "failure", "version": "0.1.8"}
+                    }]
+                }
+            }"#,
+        )
+        .unwrap();
+        let diagnostics = report_diagnostics(&report, MANIFEST);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].range.start, Position::new(6, 0));
+        assert_eq!(
+            diagnostics[0].code,
+            Some(NumberOrString::String("RUSTSEC-2020-0071".to_string()))
+        );
+        assert_eq!(
+            diagnostics[0].message,
+            "tokio 1.0.0: Potential segfault in the time crate"
+        );
+        assert_eq!(diagnostics[1].range.start, Position::new(0, 0));
+        assert_eq!(
+            diagnostics[1].message,
+            "failure 0.1.8 (transitive dependency): failure is officially deprecated/unmaintained"
+        );
+    }
+}
@@ -1,28 +1,32 @@
-use std::path::Path;
-use std::process::Stdio;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tower_lsp::lsp_types::MessageType;
+use tower_lsp::Client;
 
-use crate::LOCATIONS_FILE;
+use crate::error::{BaconLsError, Result};
+use crate::line_format::{LineFormat, LINE_FORMAT};
+use crate::messages::{Locale, Message};
+use crate::{parsed_output_command, BACON_JOB, LOCATIONS_FILE, PKG_NAME};
 
 #[derive(Debug, Deserialize, Serialize)]
 struct BaconConfig {
-    jobs: Jobs,
+    jobs: BTreeMap<String, Job>,
     exports: Exports,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct Jobs {
-    #[serde(rename = "bacon-ls")]
-    bacon_ls: BaconLs,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct BaconLs {
+struct Job {
     #[serde(skip_deserializing)]
     command: Vec<String>,
     analyzer: String,
@@ -39,6 +43,8 @@ struct Exports {
 struct CargoJsonSpans {
     auto: bool,
     exporter: String,
+    /// Only used by the analyzer exporter, the analysis exporter writing JSON.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     line_format: String,
     path: String,
 }
@@ -46,6 +52,9 @@ struct CargoJsonSpans {
 const ERROR_MESSAGE: &str = "bacon configuration is not compatible with bacon-ls: please take a look to https://github.com/crisidev/bacon-ls?tab=readme-ov-file#configuration and adapt your bacon configuration";
 const BACON_ANALYZER: &str = "cargo_json";
 const BACON_EXPORTER: &str = "analyzer";
+const BACON_ANALYSIS_EXPORTER: &str = "analysis";
+/// Job running the tests, created along the main job in the bacon preferences.
+const BACON_TEST_JOB: &str = "bacon-ls-test";
 const BACON_COMMAND: [&str; 7] = [
     "cargo",
     "clippy",
@@ -55,139 +64,639 @@ const BACON_COMMAND: [&str; 7] = [
     "--message-format",
     "json-diagnostic-rendered-ansi",
 ];
-const LINE_FORMAT: &str = "{diagnostic.level}|:|{span.file_name}|:|{span.line_start}|:|{span.line_end}|:|{span.column_start}|:|{span.column_end}|:|{diagnostic.message}|:|{span.suggested_replacement}";
+const BACON_TEST_COMMAND: [&str; 5] = [
+    "cargo",
+    "test",
+    "--no-fail-fast",
+    "--message-format",
+    "json-diagnostic-rendered-ansi",
+];
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// Preferences of a project, read by bacon from its directory.
+const WORKSPACE_PREFERENCES_FILE: &str = "bacon.toml";
+/// Placeholder of the diagnostic code in the `line_format`, with its separator.
+const CODE_PLACEHOLDER: &str = "{diagnostic.code}|:|";
+
+/// Version of bacon, from `bacon --version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct BaconVersion(u32, u32, u32);
+
+impl fmt::Display for BaconVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+impl BaconVersion {
+    /// First version with the `cargo_json` analyzer and the `cargo-json-spans` export.
+    const CARGO_JSON: Self = Self(3, 7, 0);
+    /// First version able to run headless, in background.
+    const HEADLESS: Self = Self(3, 8, 0);
+    /// First version exporting the `{diagnostic.code}` of the `line_format`.
+    const DIAGNOSTIC_CODE: Self = Self(3, 9, 0);
+
+    /// Parse the output of `bacon --version`, like `bacon 3.8.0`.
+    fn parse(output: &str) -> Option<Self> {
+        let version = output.split_whitespace().nth(1)?;
+        // Pre-releases like `3.9.0-beta` have the version of their release.
+        let mut numbers = version
+            .split(['.', '-', '+'])
+            .take(3)
+            .map(|number| number.parse::<u32>().ok());
+        Some(Self(
+            numbers.next()??,
+            numbers.next().flatten().unwrap_or(0),
+            numbers.next().flatten().unwrap_or(0),
+        ))
+    }
+
+    /// Features used by bacon-ls missing in this version, with the version introducing them.
+    pub(crate) fn missing_features(&self, run_in_background: bool) -> Vec<(&'static str, Self)> {
+        let mut missing = Vec::new();
+        if *self < Self::CARGO_JSON {
+            missing.push((
+                "the cargo_json analyzer and the cargo-json-spans export",
+                Self::CARGO_JSON,
+            ));
+        }
+        if run_in_background && *self < Self::HEADLESS {
+            missing.push(("running in background", Self::HEADLESS));
+        }
+        missing
+    }
+
+    /// The `line_format` of the preferences created for this version.
+    fn line_format(&self) -> String {
+        match *self < Self::DIAGNOSTIC_CODE {
+            true => LINE_FORMAT.replace(CODE_PLACEHOLDER, ""),
+            false => LINE_FORMAT.to_string(),
+        }
+    }
+}
+
+/// When to restart bacon running in background after it exits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RestartPolicy {
+    Never,
+    #[default]
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    pub(crate) fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "never" => Some(Self::Never),
+            "on-failure" => Some(Self::OnFailure),
+            "always" => Some(Self::Always),
+            _ => None,
+        }
+    }
+
+    fn should_restart(&self, status: Option<ExitStatus>) -> bool {
+        match self {
+            Self::Never => false,
+            Self::OnFailure => !status.is_some_and(|status| status.success()),
+            Self::Always => true,
+        }
+    }
+}
+
+/// Where the bacon preferences are created when missing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PreferencesTarget {
+    /// The `bacon.toml` of the project.
+    #[default]
+    Workspace,
+    /// The first preferences file listed by `bacon --prefs`, shared by all the projects.
+    Global,
+}
+
+impl PreferencesTarget {
+    pub(crate) fn parse(target: &str) -> Option<Self> {
+        match target {
+            "workspace" => Some(Self::Workspace),
+            "global" => Some(Self::Global),
+            _ => None,
+        }
+    }
+}
+
+/// Locations file exported by the bacon preferences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Export {
+    /// Relative to the project or absolute.
+    pub(crate) path: String,
+    pub(crate) line_format: LineFormat,
+}
+
+/// Content of the lock file of a folder.
+#[derive(Debug, PartialEq)]
+struct BaconLock {
+    /// PID of the bacon-ls instance holding the lock.
+    owner: u32,
+    /// PID of the bacon it runs in background, once started.
+    bacon: Option<u32>,
+}
+
+/// Outcome of taking the lock on the bacon running in background for a folder.
+#[derive(Debug, PartialEq)]
+pub(crate) enum BaconLockStatus {
+    /// The lock is taken, bacon can be started.
+    Acquired,
+    /// The lock is taken, but a bacon already runs in the folder, started by the user or left by
+    /// an instance which exited, and is reused.
+    Adopted { bacon: u32 },
+    /// Another running instance holds the lock and runs bacon.
+    Held { owner: u32 },
+}
 
 pub(crate) struct Bacon;
 
 impl Bacon {
-    async fn validate_preferences_file(path: &Path) -> Result<(), String> {
+    /// Validate a bacon preferences file, which must define the jobs, returning its locations
+    /// export.
+    async fn validate_preferences_file(path: &Path, jobs: &[impl AsRef<str>]) -> Result<Export> {
         let toml_content = tokio::fs::read_to_string(path)
             .await
-            .map_err(|e| format!("{ERROR_MESSAGE}: {e}"))?;
-        let config: BaconConfig =
-            toml::from_str(&toml_content).map_err(|e| format!("{ERROR_MESSAGE}: {e}"))?;
+            .map_err(|e| BaconLsError::Config(format!("{ERROR_MESSAGE}: {e}")))?;
+        let config: BaconConfig = toml::from_str(&toml_content)
+            .map_err(|e| BaconLsError::Config(format!("{ERROR_MESSAGE}: {e}")))?;
         tracing::debug!("bacon config is {config:#?}");
-        if config.jobs.bacon_ls.analyzer == BACON_ANALYZER
-            && config.jobs.bacon_ls.need_stdout
-            && config.exports.cargo_json_spans.auto
-            && config.exports.cargo_json_spans.exporter == BACON_EXPORTER
-            && config.exports.cargo_json_spans.line_format == LINE_FORMAT
-            && config.exports.cargo_json_spans.path == LOCATIONS_FILE
-        {
-            tracing::info!("bacon configuration {} is valid", path.display());
-            Ok(())
-        } else {
-            Err(ERROR_MESSAGE.to_string())
+        let export = &config.exports.cargo_json_spans;
+        // The JSON analysis has no line format.
+        let line_format = match export.exporter.as_str() {
+            BACON_ANALYSIS_EXPORTER => Some(LineFormat::default()),
+            BACON_EXPORTER => LineFormat::parse(&export.line_format),
+            _ => None,
+        };
+        match line_format {
+            Some(line_format)
+                if jobs.iter().all(|job| {
+                    config
+                        .jobs
+                        .get(job.as_ref())
+                        .is_some_and(|job| job.analyzer == BACON_ANALYZER && job.need_stdout)
+                }) && export.auto
+                    && !export.path.is_empty() =>
+            {
+                tracing::info!(
+                    "bacon configuration {} is valid, exporting locations to {}",
+                    path.display(),
+                    export.path
+                );
+                Ok(Export {
+                    path: export.path.clone(),
+                    line_format,
+                })
+            }
+            _ => Err(BaconLsError::Config(ERROR_MESSAGE.to_string())),
         }
     }
 
-    async fn create_preferences_file(filename: &str) -> Result<(), String> {
+    /// Create the preferences of bacon-ls, adapted to the version of bacon if known, and return
+    /// the locations file they export.
+    async fn create_preferences_file(path: &Path, version: Option<BaconVersion>) -> Result<Export> {
+        let line_format = version.map_or(LINE_FORMAT.to_string(), |version| version.line_format());
+        let job = |command: &[&str]| Job {
+            command: command.iter().map(|c| c.to_string()).collect(),
+            analyzer: BACON_ANALYZER.to_string(),
+            need_stdout: true,
+        };
         let bacon_config = BaconConfig {
-            jobs: Jobs {
-                bacon_ls: BaconLs {
-                    command: BACON_COMMAND.map(|c| c.to_string()).into_iter().collect(),
-                    analyzer: BACON_ANALYZER.to_string(),
-                    need_stdout: true,
-                },
-            },
+            jobs: BTreeMap::from([
+                (BACON_JOB.to_string(), job(&BACON_COMMAND)),
+                (BACON_TEST_JOB.to_string(), job(&BACON_TEST_COMMAND)),
+            ]),
             exports: Exports {
                 cargo_json_spans: CargoJsonSpans {
                     auto: true,
                     exporter: BACON_EXPORTER.to_string(),
-                    line_format: LINE_FORMAT.to_string(),
+                    line_format: line_format.clone(),
                     path: LOCATIONS_FILE.to_string(),
                 },
             },
         };
+        let filename = path.display();
         tracing::info!("creating new bacon preference file {filename}",);
-        let toml_string = toml::to_string_pretty(&bacon_config)
-            .map_err(|e| format!("error serializing bacon preferences {filename} content: {e}"))?;
-        let mut file = File::create(filename)
-            .await
-            .map_err(|e| format!("error creating bacon preferences {filename}: {e}"))?;
-        file.write_all(toml_string.as_bytes())
-            .await
-            .map_err(|e| format!("error writing bacon preferences {filename}: {e}"))?;
-        Ok(())
+        let toml_string = toml::to_string_pretty(&bacon_config).map_err(|e| {
+            BaconLsError::Parse(format!(
+                "error serializing bacon preferences {filename} content: {e}"
+            ))
+        })?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|e| {
+                BaconLsError::io(
+                    format!(
+                        "error creating bacon preferences directory {}",
+                        parent.display()
+                    ),
+                    e,
+                )
+            })?;
+        }
+        let mut file = File::create(path).await.map_err(|e| {
+            BaconLsError::io(format!("error creating bacon preferences {filename}"), e)
+        })?;
+        // The preferences are read right after, by bacon or by the validation.
+        async {
+            file.write_all(toml_string.as_bytes()).await?;
+            file.flush().await
+        }
+        .await
+        .map_err(|e| BaconLsError::io(format!("error writing bacon preferences {filename}"), e))?;
+        Ok(Export {
+            path: LOCATIONS_FILE.to_string(),
+            line_format: LineFormat::parse(&line_format).unwrap_or_default(),
+        })
     }
 
     async fn validate_preferences_impl(
         bacon_prefs: &[u8],
-        create_prefs_file: bool,
-    ) -> Result<(), String> {
+        folder_path: Option<&Path>,
+        create_prefs_file: Option<PreferencesTarget>,
+        version: Option<BaconVersion>,
+        jobs: &[impl AsRef<str>],
+    ) -> Result<Option<Export>> {
         let bacon_prefs_files = String::from_utf8_lossy(bacon_prefs);
-        let bacon_prefs_files_split: Vec<&str> = bacon_prefs_files.split("\n").collect();
-        let mut preference_file_exists = false;
-        for prefs_file in bacon_prefs_files_split.iter() {
-            let prefs_file_path = Path::new(prefs_file);
+        let global_prefs_files: Vec<PathBuf> = bacon_prefs_files
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(PathBuf::from)
+            .collect();
+        let workspace_prefs_file =
+            folder_path.map(|folder_path| folder_path.join(WORKSPACE_PREFERENCES_FILE));
+        // The files listed last override the previous ones, and the preferences of the project
+        // override the global ones.
+        let mut locations_file = None;
+        for prefs_file_path in global_prefs_files.iter().chain(&workspace_prefs_file) {
             if prefs_file_path.exists() {
-                preference_file_exists = true;
-                Self::validate_preferences_file(prefs_file_path).await?;
+                locations_file =
+                    Some(Self::validate_preferences_file(prefs_file_path, jobs).await?);
             } else {
-                tracing::debug!("skipping non existing bacon preference file {prefs_file}");
+                tracing::debug!(
+                    "skipping non existing bacon preference file {}",
+                    prefs_file_path.display()
+                );
             }
         }
 
-        if !preference_file_exists && create_prefs_file {
-            Self::create_preferences_file(bacon_prefs_files_split[0]).await?;
+        if let (None, Some(target)) = (&locations_file, create_prefs_file) {
+            let prefs_file_path = match (target, &workspace_prefs_file) {
+                (PreferencesTarget::Workspace, Some(workspace_prefs_file)) => workspace_prefs_file,
+                _ => global_prefs_files.first().ok_or_else(|| {
+                    BaconLsError::Config(
+                        "`bacon --prefs` did not return any preferences file".to_string(),
+                    )
+                })?,
+            };
+            locations_file = Some(Self::create_preferences_file(prefs_file_path, version).await?);
         }
 
-        Ok(())
+        Ok(locations_file)
     }
 
-    pub(crate) async fn validate_preferences(create_prefs_file: bool) -> Result<(), String> {
-        let bacon_prefs = Command::new("bacon")
+    /// Return the version of bacon, if it can be parsed, or an error if bacon is not installed.
+    pub(crate) async fn version() -> Result<Option<BaconVersion>> {
+        let output = parsed_output_command("bacon")
+            .arg("--version")
+            .output()
+            .await
+            .map_err(|e| {
+                BaconLsError::Spawn(format!(
+                    "failed to run `bacon --version`, is bacon installed? {e}"
+                ))
+            })?;
+        let version = BaconVersion::parse(&String::from_utf8_lossy(&output.stdout));
+        tracing::info!("bacon version is {version:?}");
+        Ok(version)
+    }
+
+    /// Validate the bacon preferences defining the jobs, global or of the project in the folder,
+    /// creating them where asked to if missing, for the version of bacon if known, and return the
+    /// locations file they export, if any.
+    pub(crate) async fn validate_preferences(
+        folder_path: Option<&Path>,
+        create_prefs_file: Option<PreferencesTarget>,
+        version: Option<BaconVersion>,
+        jobs: &[impl AsRef<str>],
+    ) -> Result<Option<Export>> {
+        let bacon_prefs = parsed_output_command("bacon")
             .arg("--prefs")
             .output()
             .await
-            .map_err(|e| e.to_string())?;
-        Self::validate_preferences_impl(&bacon_prefs.stdout, create_prefs_file).await
+            .map_err(|e| {
+                BaconLsError::Config(format!(
+                    "failed to run `bacon --prefs`, is bacon installed? {e}"
+                ))
+            })?;
+        Self::validate_preferences_impl(
+            &bacon_prefs.stdout,
+            folder_path,
+            create_prefs_file,
+            version,
+            jobs,
+        )
+        .await
     }
 
-    pub(crate) async fn run_in_background(
+    fn lock_file_path(folder_path: &Path) -> PathBuf {
+        folder_path.join(format!("{LOCATIONS_FILE}.lock"))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn is_process_running(pid: u32) -> bool {
+        // Larger values would be read as negative PIDs by kill, which address process groups.
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        if pid == 0 {
+            return false;
+        }
+        // SAFETY: the signal 0 only checks that the process exists.
+        let exists = unsafe { libc::kill(pid, 0) } == 0;
+        // The process also exists when it belongs to another user.
+        exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn is_process_running(pid: u32) -> bool {
+        pid != 0
+            && std::process::Command::new("tasklist")
+                .args(["/NH", "/FI", &format!("PID eq {pid}")])
+                .output()
+                .is_ok_and(|output| {
+                    String::from_utf8_lossy(&output.stdout)
+                        .split_whitespace()
+                        .any(|word| word == pid.to_string())
+                })
+    }
+
+    /// PID of a bacon already running in the folder, like one started by the user in a
+    /// terminal, which writes the locations file of the folder. Only found on Linux, where the
+    /// working directory of the processes is known.
+    fn running_bacon(folder_path: &Path) -> Option<u32> {
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+        let folder_path = folder_path.canonicalize().ok()?;
+        std::fs::read_dir("/proc")
+            .ok()?
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+            .filter(|pid| *pid != std::process::id())
+            .find(|pid| {
+                let process = Path::new("/proc").join(pid.to_string());
+                std::fs::read_to_string(process.join("comm"))
+                    .is_ok_and(|comm| comm.trim() == "bacon")
+                    && std::fs::read_link(process.join("cwd")).is_ok_and(|cwd| cwd == folder_path)
+            })
+    }
+
+    fn read_lock(content: &str) -> Option<BaconLock> {
+        let mut pids = content.split_whitespace().map(str::parse::<u32>);
+        Some(BaconLock {
+            owner: pids.next()?.ok()?,
+            bacon: pids.next().and_then(|pid| pid.ok()),
+        })
+    }
+
+    async fn write_lock(lock_file: &Path, bacon: Option<u32>) -> Result<()> {
+        let mut content = std::process::id().to_string();
+        if let Some(bacon) = bacon {
+            content.push_str(&format!("\n{bacon}"));
+        }
+        tokio::fs::write(lock_file, content).await.map_err(|e| {
+            BaconLsError::io(
+                format!("error writing bacon lock file {}", lock_file.display()),
+                e,
+            )
+        })
+    }
+
+    /// Take the lock on the bacon running in background for the folder, so that only one
+    /// bacon-ls instance starts it. The lock file holds the PID of the instance and the one of
+    /// its bacon, so another instance can take over a bacon left running by an instance which
+    /// exited without releasing the lock.
+    pub(crate) async fn acquire_lock(folder_path: &Path) -> Result<BaconLockStatus> {
+        let lock_file = Self::lock_file_path(folder_path);
+        let pid = std::process::id();
+        // A second attempt is made after removing a stale lock, unless another instance took it
+        // in the meantime.
+        for _ in 0..2 {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_file)
+            {
+                Ok(_) => {
+                    let bacon = Self::running_bacon(folder_path);
+                    Self::write_lock(&lock_file, bacon).await?;
+                    return Ok(match bacon {
+                        Some(bacon) => BaconLockStatus::Adopted { bacon },
+                        None => BaconLockStatus::Acquired,
+                    });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(BaconLsError::io(
+                        format!("error creating bacon lock file {}", lock_file.display()),
+                        e,
+                    ))
+                }
+            }
+            let content = tokio::fs::read_to_string(&lock_file)
+                .await
+                .unwrap_or_default();
+            match Self::read_lock(&content) {
+                Some(lock) if lock.owner == pid => return Ok(BaconLockStatus::Acquired),
+                Some(lock) if Self::is_process_running(lock.owner) => {
+                    return Ok(BaconLockStatus::Held { owner: lock.owner })
+                }
+                Some(BaconLock {
+                    owner,
+                    bacon: Some(bacon),
+                }) if Self::is_process_running(bacon) => {
+                    tracing::info!("taking over bacon {bacon} left by {PKG_NAME} instance {owner}");
+                    Self::write_lock(&lock_file, Some(bacon)).await?;
+                    return Ok(BaconLockStatus::Adopted { bacon });
+                }
+                lock => {
+                    if let Some(lock) = lock {
+                        tracing::info!(
+                            "removing stale bacon lock of {PKG_NAME} instance {}",
+                            lock.owner
+                        );
+                    }
+                    if let Err(e) = tokio::fs::remove_file(&lock_file).await {
+                        tracing::warn!(
+                            "error removing bacon lock file {}: {e}",
+                            lock_file.display()
+                        );
+                    }
+                }
+            }
+        }
+        Err(BaconLsError::Spawn(format!(
+            "unable to take the bacon lock {}, another instance keeps taking it",
+            lock_file.display()
+        )))
+    }
+
+    /// Record the PID of the bacon started by this instance in the lock it holds.
+    async fn record_bacon_pid(folder_path: &Path, bacon: Option<u32>) {
+        let lock_file = Self::lock_file_path(folder_path);
+        let holds_lock = tokio::fs::read_to_string(&lock_file)
+            .await
+            .ok()
+            .and_then(|content| Self::read_lock(&content))
+            .is_some_and(|lock| lock.owner == std::process::id());
+        if holds_lock {
+            if let Err(e) = Self::write_lock(&lock_file, bacon).await {
+                tracing::warn!("{e}");
+            }
+        }
+    }
+
+    /// Release the lock taken by this instance.
+    pub(crate) async fn release_lock(folder_path: &Path) {
+        let lock_file = Self::lock_file_path(folder_path);
+        if tokio::fs::read_to_string(&lock_file)
+            .await
+            .ok()
+            .and_then(|content| Self::read_lock(&content))
+            .is_some_and(|lock| lock.owner == std::process::id())
+        {
+            if let Err(e) = tokio::fs::remove_file(&lock_file).await {
+                tracing::error!(
+                    "error removing bacon lock file {}: {e}",
+                    lock_file.display()
+                );
+            }
+        }
+    }
+
+    fn spawn(
         bacon_command: &str,
         bacon_command_args: &str,
-    ) -> Result<JoinHandle<()>, String> {
-        tracing::info!("starting bacon in background with arguments `{bacon_command_args}`");
-        match Command::new(bacon_command)
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<Child> {
+        let mut child = Command::new(bacon_command)
             .args(bacon_command_args.split_whitespace().collect::<Vec<&str>>())
+            .current_dir(folder_path)
+            .envs(env)
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .kill_on_drop(true)
             .spawn()
-        {
-            Ok(mut child) => {
-                // Handle stdout
-                if let Some(stdout) = child.stdout.take() {
-                    let reader = BufReader::new(stdout).lines();
-                    tokio::spawn(async move {
-                        let mut reader = reader;
-                        while let Ok(Some(line)) = reader.next_line().await {
-                            tracing::info!("[bacon stdout]: {}", line);
-                        }
-                    });
+            .map_err(|e| BaconLsError::Spawn(format!("failed to start bacon: {e}")))?;
+
+        // Handle stdout
+        if let Some(stdout) = child.stdout.take() {
+            let reader = BufReader::new(stdout).lines();
+            tokio::spawn(async move {
+                let mut reader = reader;
+                while let Ok(Some(line)) = reader.next_line().await {
+                    tracing::info!("[bacon stdout]: {}", line);
                 }
+            });
+        }
 
-                // Handle stderr
-                if let Some(stderr) = child.stderr.take() {
-                    let reader = BufReader::new(stderr).lines();
-                    tokio::spawn(async move {
-                        let mut reader = reader;
-                        while let Ok(Some(line)) = reader.next_line().await {
-                            tracing::error!("[bacon stderr]: {}", line);
-                        }
-                    });
+        // Handle stderr
+        if let Some(stderr) = child.stderr.take() {
+            let reader = BufReader::new(stderr).lines();
+            tokio::spawn(async move {
+                let mut reader = reader;
+                while let Ok(Some(line)) = reader.next_line().await {
+                    tracing::error!("[bacon stderr]: {}", line);
                 }
+            });
+        }
+        Ok(child)
+    }
 
-                // Wait for the child process to finish
-                Ok(tokio::spawn(async move {
-                    tracing::debug!("waiting for bacon to terminate");
-                    let _ = child.wait().await;
-                }))
-            }
-            Err(e) => Err(format!("failed to start bacon: {e}")),
+    /// Delay before the given restart attempt, doubling from one second up to a minute.
+    fn restart_delay(attempt: u32) -> Duration {
+        Duration::from_secs(
+            2u64.saturating_pow(attempt)
+                .min(MAX_RESTART_DELAY.as_secs()),
+        )
+    }
+
+    /// Start bacon in the folder and supervise it, restarting it according to the restart
+    /// policy when it exits. Aborting the returned handle terminates bacon. The PID of each bacon
+    /// started is recorded in the lock of the folder if `locked`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn run_in_background(
+        bacon_command: &str,
+        bacon_command_args: &str,
+        folder_path: &Path,
+        env: HashMap<String, String>,
+        restart_policy: RestartPolicy,
+        client: Option<Arc<Client>>,
+        locale: Locale,
+        locked: bool,
+    ) -> Result<JoinHandle<()>> {
+        tracing::info!(
+            "starting bacon in background in {} with arguments `{bacon_command_args}`",
+            folder_path.display()
+        );
+        let mut child = Self::spawn(bacon_command, bacon_command_args, folder_path, &env)?;
+        if locked {
+            Self::record_bacon_pid(folder_path, child.id()).await;
         }
+        let bacon_command = bacon_command.to_string();
+        let bacon_command_args = bacon_command_args.to_string();
+        let folder_path = folder_path.to_path_buf();
+
+        // Wait for the child process to finish
+        Ok(tokio::spawn(async move {
+            let mut attempt = 0;
+            loop {
+                tracing::debug!("waiting for bacon to terminate");
+                let started = Instant::now();
+                let status = child.wait().await.ok();
+                if !restart_policy.should_restart(status) {
+                    tracing::info!("bacon exited with {status:?}");
+                    return;
+                }
+                // A bacon which ran for a while before exiting starts a new series of restarts.
+                if started.elapsed() > MAX_RESTART_DELAY {
+                    attempt = 0;
+                }
+                let delay = Self::restart_delay(attempt);
+                attempt += 1;
+                let status = status.map(|status| status.to_string()).unwrap_or_default();
+                let message = Message::BaconRestarting {
+                    status: &status,
+                    delay_secs: delay.as_secs(),
+                };
+                tracing::warn!("{}", message.text(Locale::English));
+                if let Some(client) = client.as_ref() {
+                    client
+                        .show_message(MessageType::WARNING, message.text(locale))
+                        .await;
+                }
+                tokio::time::sleep(delay).await;
+                match Self::spawn(&bacon_command, &bacon_command_args, &folder_path, &env) {
+                    Ok(restarted) => {
+                        child = restarted;
+                        if locked {
+                            Self::record_bacon_pid(&folder_path, child.id()).await;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("{e}");
+                        if let Some(client) = client.as_ref() {
+                            client.show_message(MessageType::ERROR, e.to_string()).await;
+                        }
+                        return;
+                    }
+                }
+            }
+        }))
     }
 }
 
@@ -217,7 +726,46 @@ mod tests {
         let file_path = tmp_dir.path().join("prefs.toml");
         let mut file = std::fs::File::create(&file_path).unwrap();
         write!(file, "{}", valid_toml).unwrap();
-        assert!(Bacon::validate_preferences_file(&file_path).await.is_ok());
+        assert_eq!(
+            Bacon::validate_preferences_file(&file_path, &[BACON_JOB])
+                .await
+                .unwrap()
+                .path,
+            LOCATIONS_FILE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nested_locations_file() {
+        let valid_toml = format!(
+            r#"
+            [jobs.bacon-ls]
+            analyzer = "{BACON_ANALYZER}"
+            need_stdout = true
+
+            [exports.cargo-json-spans]
+            auto = true
+            exporter = "{BACON_EXPORTER}"
+            line_format = "{LINE_FORMAT}"
+            path = "target/bacon/locations"
+        "#
+        );
+        let tmp_dir = TempDir::new("bacon").unwrap();
+        let file_path = tmp_dir.path().join("prefs.toml");
+        std::fs::write(&file_path, valid_toml).unwrap();
+        assert_eq!(
+            Bacon::validate_preferences_impl(
+                file_path.to_string_lossy().as_bytes(),
+                None,
+                None,
+                None,
+                &[BACON_JOB]
+            )
+            .await
+            .unwrap()
+            .map(|export| export.path),
+            Some("target/bacon/locations".to_string())
+        );
     }
 
     #[tokio::test]
@@ -240,7 +788,9 @@ mod tests {
         let file_path = tmp_dir.path().join("prefs.toml");
         let mut file = std::fs::File::create(&file_path).unwrap();
         write!(file, "{}", invalid_toml).unwrap();
-        assert!(Bacon::validate_preferences_file(&file_path).await.is_err());
+        assert!(Bacon::validate_preferences_file(&file_path, &[BACON_JOB])
+            .await
+            .is_err());
     }
 
     #[tokio::test]
@@ -263,7 +813,69 @@ mod tests {
         let file_path = tmp_dir.path().join("prefs.toml");
         let mut file = std::fs::File::create(&file_path).unwrap();
         write!(file, "{}", invalid_toml).unwrap();
-        assert!(Bacon::validate_preferences_file(&file_path).await.is_err());
+        assert!(Bacon::validate_preferences_file(&file_path, &[BACON_JOB])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_analysis_exporter() {
+        let toml = format!(
+            r#"
+            [jobs.bacon-ls]
+            analyzer = "{BACON_ANALYZER}"
+            need_stdout = true
+
+            [exports.cargo-json-spans]
+            auto = true
+            exporter = "{BACON_ANALYSIS_EXPORTER}"
+            path = "bacon-analysis.json"
+        "#
+        );
+
+        let tmp_dir = TempDir::new("bacon").unwrap();
+        let file_path = tmp_dir.path().join("prefs.toml");
+        let mut file = std::fs::File::create(&file_path).unwrap();
+        write!(file, "{}", toml).unwrap();
+        assert_eq!(
+            Bacon::validate_preferences_file(&file_path, &[BACON_JOB])
+                .await
+                .unwrap()
+                .path,
+            "bacon-analysis.json"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_line_format() {
+        let line_format = "{diagnostic.code}|:|{diagnostic.level}|:|{span.file_name}|:|{span.line_start}|:|{span.line_end}|:|{span.column_start}|:|{span.column_end}|:|{diagnostic.message}";
+        let toml = format!(
+            r#"
+            [jobs.bacon-ls]
+            analyzer = "{BACON_ANALYZER}"
+            need_stdout = true
+
+            [exports.cargo-json-spans]
+            auto = true
+            exporter = "{BACON_EXPORTER}"
+            line_format = "{line_format}"
+            path = "{LOCATIONS_FILE}"
+        "#
+        );
+
+        let tmp_dir = TempDir::new("bacon").unwrap();
+        let file_path = tmp_dir.path().join("prefs.toml");
+        let mut file = std::fs::File::create(&file_path).unwrap();
+        write!(file, "{}", toml).unwrap();
+        assert_eq!(
+            Bacon::validate_preferences_file(&file_path, &[BACON_JOB])
+                .await
+                .unwrap(),
+            Export {
+                path: LOCATIONS_FILE.to_string(),
+                line_format: LineFormat::parse(line_format).unwrap(),
+            }
+        );
     }
 
     #[tokio::test]
@@ -281,20 +893,86 @@ mod tests {
             path = "{LOCATIONS_FILE}"
         "#
         );
-        assert!(
-            Bacon::validate_preferences_impl(valid_toml.as_bytes(), false)
-                .await
-                .is_ok()
+        assert!(Bacon::validate_preferences_impl(
+            valid_toml.as_bytes(),
+            None,
+            None,
+            None,
+            &[BACON_JOB]
+        )
+        .await
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_preferences_creation_target() {
+        let tmp_dir = TempDir::new("bacon").unwrap();
+        let folder_path = tmp_dir.path().join("project");
+        let global_prefs = tmp_dir.path().join("config/bacon/prefs.toml");
+        let bacon_prefs = format!("{}\n", global_prefs.display());
+
+        let export = Bacon::validate_preferences_impl(
+            bacon_prefs.as_bytes(),
+            Some(&folder_path),
+            Some(PreferencesTarget::Workspace),
+            None,
+            &[BACON_JOB],
+        )
+        .await
+        .unwrap();
+        assert_eq!(export.unwrap().path, LOCATIONS_FILE);
+        assert!(folder_path.join(WORKSPACE_PREFERENCES_FILE).exists());
+        assert!(!global_prefs.exists());
+        // The preferences of the project are found without creating others.
+        assert!(Bacon::validate_preferences_impl(
+            bacon_prefs.as_bytes(),
+            Some(&folder_path),
+            None,
+            None,
+            &[BACON_JOB],
+        )
+        .await
+        .unwrap()
+        .is_some());
+
+        Bacon::validate_preferences_impl(
+            bacon_prefs.as_bytes(),
+            Some(&folder_path),
+            Some(PreferencesTarget::Global),
+            None,
+            &[BACON_JOB],
+        )
+        .await
+        .unwrap();
+        assert!(!global_prefs.exists());
+        Bacon::validate_preferences_impl(
+            bacon_prefs.as_bytes(),
+            None,
+            Some(PreferencesTarget::Global),
+            None,
+            &[BACON_JOB],
+        )
+        .await
+        .unwrap();
+        assert!(global_prefs.exists());
+        assert_eq!(
+            PreferencesTarget::parse("global"),
+            Some(PreferencesTarget::Global)
         );
+        assert_eq!(PreferencesTarget::parse("home"), None);
     }
 
     #[tokio::test]
     async fn test_file_creation_failure() {
-        let invalid_path = "/invalid/path/to/file.toml";
-        let result = Bacon::create_preferences_file(invalid_path).await;
+        let tmp_dir = TempDir::new("bacon").unwrap();
+        let file_path = tmp_dir.path().join("file");
+        std::fs::File::create(&file_path).unwrap();
+        // A file can't be the directory of the preferences.
+        let result = Bacon::create_preferences_file(&file_path.join("prefs.toml"), None).await;
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
+            .to_string()
             .contains("error creating bacon preferences"));
     }
 
@@ -305,22 +983,153 @@ mod tests {
         // Simulate write failure by closing the file prematurely
         let file = File::create(&file_path).await.unwrap();
         drop(file); // Close the file to simulate failure
-        let result = Bacon::create_preferences_file(file_path.to_str().unwrap()).await;
+        let result = Bacon::create_preferences_file(&file_path, None).await;
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_created_preferences_define_the_jobs() {
+        let tmp_dir = TempDir::new("bacon").unwrap();
+        let file_path = tmp_dir.path().join("prefs.toml");
+        Bacon::create_preferences_file(&file_path, None)
+            .await
+            .unwrap();
+        let export = Bacon::validate_preferences_file(&file_path, &[BACON_JOB, BACON_TEST_JOB])
+            .await
+            .unwrap();
+        assert_eq!(export.path, LOCATIONS_FILE);
+        assert!(
+            Bacon::validate_preferences_file(&file_path, &[BACON_JOB, "bacon-ls-bench"])
+                .await
+                .is_err()
+        );
+    }
+
     #[tokio::test]
     async fn test_empty_bacon_preferences_file() {
         let tmp_dir = TempDir::new("bacon").unwrap();
         let file_path = tmp_dir.path().join("empty_prefs.toml");
         std::fs::File::create(&file_path).unwrap();
-        assert!(Bacon::validate_preferences_file(&file_path).await.is_err());
+        assert!(Bacon::validate_preferences_file(&file_path, &[BACON_JOB])
+            .await
+            .is_err());
     }
 
     #[tokio::test]
     async fn test_run_in_background() {
-        let handle = Bacon::run_in_background("echo", "I am running").await;
+        let handle = Bacon::run_in_background(
+            "echo",
+            "I am running",
+            &std::env::current_dir().unwrap(),
+            HashMap::new(),
+            RestartPolicy::Never,
+            None,
+            Locale::default(),
+            false,
+        )
+        .await;
         assert!(handle.is_ok());
         handle.unwrap().abort();
     }
+
+    #[tokio::test]
+    async fn test_bacon_lock() {
+        let tmp_dir = TempDir::new("bacon").unwrap();
+        let lock_file = Bacon::lock_file_path(tmp_dir.path());
+        let pid = std::process::id();
+        assert_eq!(
+            Bacon::acquire_lock(tmp_dir.path()).await.unwrap(),
+            BaconLockStatus::Acquired
+        );
+        assert_eq!(
+            std::fs::read_to_string(&lock_file).unwrap(),
+            pid.to_string()
+        );
+        Bacon::record_bacon_pid(tmp_dir.path(), Some(42)).await;
+        assert_eq!(
+            std::fs::read_to_string(&lock_file).unwrap(),
+            format!("{pid}\n42")
+        );
+        // A lock held by a process which is not running anymore is taken over.
+        std::fs::write(&lock_file, "999999999").unwrap();
+        assert_eq!(
+            Bacon::acquire_lock(tmp_dir.path()).await.unwrap(),
+            BaconLockStatus::Acquired
+        );
+        // As well as the bacon it left running.
+        std::fs::write(&lock_file, format!("999999999\n{pid}")).unwrap();
+        assert_eq!(
+            Bacon::acquire_lock(tmp_dir.path()).await.unwrap(),
+            BaconLockStatus::Adopted { bacon: pid }
+        );
+        #[cfg(unix)]
+        {
+            std::fs::write(&lock_file, "1\n999999999").unwrap();
+            assert_eq!(
+                Bacon::acquire_lock(tmp_dir.path()).await.unwrap(),
+                BaconLockStatus::Held { owner: 1 }
+            );
+            // Only the instance holding the lock releases it.
+            Bacon::release_lock(tmp_dir.path()).await;
+            assert!(lock_file.exists());
+            std::fs::write(&lock_file, pid.to_string()).unwrap();
+        }
+        Bacon::release_lock(tmp_dir.path()).await;
+        assert!(!lock_file.exists());
+    }
+
+    #[tokio::test]
+    async fn test_bacon_version() {
+        assert_eq!(
+            BaconVersion::parse("bacon 3.8.0\n"),
+            Some(BaconVersion(3, 8, 0))
+        );
+        assert_eq!(
+            BaconVersion::parse("bacon 3.9.0-beta"),
+            Some(BaconVersion(3, 9, 0))
+        );
+        assert_eq!(BaconVersion::parse("bacon"), None);
+        assert_eq!(BaconVersion(3, 8, 0).missing_features(true), vec![]);
+        assert_eq!(
+            BaconVersion(3, 7, 1).missing_features(true),
+            vec![("running in background", BaconVersion(3, 8, 0))]
+        );
+        assert_eq!(BaconVersion(3, 7, 1).missing_features(false), vec![]);
+        assert_eq!(BaconVersion(2, 21, 0).missing_features(false).len(), 1);
+
+        // The preferences created for a bacon not exporting the codes don't use them.
+        let tmp_dir = TempDir::new("bacon").unwrap();
+        let file_path = tmp_dir.path().join("prefs.toml");
+        let export = Bacon::create_preferences_file(&file_path, Some(BaconVersion(3, 8, 0)))
+            .await
+            .unwrap();
+        let prefs = std::fs::read_to_string(&file_path).unwrap();
+        assert!(!prefs.contains("{diagnostic.code}"));
+        assert_eq!(
+            Bacon::validate_preferences_file(&file_path, &[BACON_JOB])
+                .await
+                .unwrap(),
+            export
+        );
+        Bacon::create_preferences_file(&file_path, Some(BaconVersion(3, 9, 0)))
+            .await
+            .unwrap();
+        let prefs = std::fs::read_to_string(&file_path).unwrap();
+        assert!(prefs.contains("{diagnostic.code}"));
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        assert_eq!(
+            RestartPolicy::parse("on-failure"),
+            Some(RestartPolicy::OnFailure)
+        );
+        assert_eq!(RestartPolicy::parse("sometimes"), None);
+        assert!(!RestartPolicy::Never.should_restart(None));
+        assert!(RestartPolicy::OnFailure.should_restart(None));
+        assert!(RestartPolicy::Always.should_restart(None));
+        assert_eq!(Bacon::restart_delay(0), Duration::from_secs(1));
+        assert_eq!(Bacon::restart_delay(3), Duration::from_secs(8));
+        assert_eq!(Bacon::restart_delay(10), MAX_RESTART_DELAY);
+    }
 }
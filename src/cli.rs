@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+
+use crate::error::{BaconLsError, Result};
+use crate::usage::CommandLimits;
+use crate::{BaconLs, DiagnosticsFilter};
+
+/// Settings of the commands run from the terminal, read from a JSON file with the same keys as
+/// the client settings.
+#[derive(Debug, Default)]
+pub(crate) struct CliSettings {
+    pub(crate) filter: DiagnosticsFilter,
+    pub(crate) cargo_env: HashMap<String, String>,
+    /// Locations file set in the settings, or else the one exported by the bacon preferences.
+    pub(crate) locations_file: Option<String>,
+    pub(crate) command_limits: CommandLimits,
+}
+
+impl CliSettings {
+    pub(crate) async fn read(path: &Path) -> Result<Self> {
+        let content = tokio::fs::read_to_string(path).await.map_err(|e| {
+            BaconLsError::io(format!("unable to read settings {}", path.display()), e)
+        })?;
+        let value: serde_json::Value = serde_json::from_str(&content).map_err(|e| {
+            BaconLsError::Config(format!("invalid settings {}: {e}", path.display()))
+        })?;
+        let values = value.as_object().ok_or_else(|| {
+            BaconLsError::Config(format!("settings {} are not a JSON object", path.display()))
+        })?;
+        let invalid = || BaconLsError::Config(format!("invalid settings {}", path.display()));
+        let mut command_limits = CommandLimits::default();
+        if let Some(value) = values.get("maxCommandOutputMb") {
+            command_limits.set_max_output_mb(
+                value
+                    .as_u64()
+                    .filter(|max_output_mb| *max_output_mb > 0)
+                    .ok_or_else(invalid)?,
+            );
+        }
+        Ok(Self {
+            filter: DiagnosticsFilter::parse(values).map_err(|_| invalid())?,
+            cargo_env: values
+                .get("cargoEnv")
+                .map(BaconLs::parse_cargo_env)
+                .transpose()
+                .map_err(|_| invalid())?
+                .unwrap_or_default(),
+            locations_file: values
+                .get("locationsFile")
+                .map(|value| value.as_str().map(str::to_string).ok_or_else(invalid))
+                .transpose()?,
+            command_limits,
+        })
+    }
+
+    /// Read the settings file if given, or use the defaults.
+    pub(crate) async fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::read(path).await,
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// Line printed for a finding, in the format of the compilers and of the gcc-style parser.
+pub(crate) fn finding_line(path: &Path, diagnostic: &Diagnostic) -> String {
+    let severity = match diagnostic.severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        _ => "hint",
+    };
+    let code = match &diagnostic.code {
+        Some(NumberOrString::String(code)) => format!("[{code}]"),
+        Some(NumberOrString::Number(code)) => format!("[{code}]"),
+        None => String::new(),
+    };
+    format!(
+        "{}:{}:{}: {severity}{code}: {}",
+        path.display(),
+        diagnostic.range.start.line + 1,
+        diagnostic.range.start.character + 1,
+        diagnostic.message.lines().next().unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tower_lsp::lsp_types::{Position, Range};
+
+    #[test]
+    fn test_finding_line() {
+        let diagnostic = Diagnostic {
+            range: Range::new(Position::new(9, 4), Position::new(9, 10)),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(
+                "clippy::needless_return".to_string(),
+            )),
+            message: "unneeded `return` statement\nhelp: remove `return`".to_string(),
+            ..Diagnostic::default()
+        };
+        assert_eq!(
+            finding_line(Path::new("src/lib.rs"), &diagnostic),
+            "src/lib.rs:10:5: warning[clippy::needless_return]: unneeded `return` statement"
+        );
+    }
+}
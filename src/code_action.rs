@@ -0,0 +1,290 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionClientCapabilities, CodeActionDisabled, TextEdit, Url,
+};
+
+use crate::publisher::Publisher;
+
+/// Reason shown by the client for the suggestions which can't be applied as they are.
+const PLACEHOLDERS_REASON: &str = "suggestion has placeholders";
+/// Prefix of the titles of the suggestions with placeholders, for the clients which can't show
+/// them disabled.
+const PLACEHOLDERS_TITLE_PREFIX: &str = "(needs edits) ";
+/// Title of the quick fixes of the diagnostics without a message.
+const DEFAULT_TITLE: &str = "Replace with clippy suggestion";
+/// Length after which the replacement is shortened in the titles.
+const MAX_TITLE_REPLACEMENT: usize = 40;
+
+/// Title of the quick fix of a suggestion, like ``clippy::needless_return: remove `return`: `x` ``,
+/// built from the lint, the message of the suggestion and its replacement. The `quickFixTitle`
+/// template replaces the `{lint}`, `{message}` and `{replacement}` placeholders instead.
+pub(crate) fn quick_fix_title(
+    template: Option<&str>,
+    lint: Option<&str>,
+    message: &str,
+    replacement: &str,
+) -> String {
+    let mut lines = replacement.trim().lines();
+    let mut replacement = lines.next().unwrap_or_default().to_string();
+    if lines.next().is_some() || replacement.chars().count() > MAX_TITLE_REPLACEMENT {
+        replacement = replacement.chars().take(MAX_TITLE_REPLACEMENT).collect();
+        replacement.push('…');
+    }
+    if let Some(template) = template {
+        return template
+            .replace("{lint}", lint.unwrap_or_default())
+            .replace("{message}", message)
+            .replace("{replacement}", &replacement);
+    }
+    let message = if message.is_empty() {
+        DEFAULT_TITLE
+    } else {
+        message
+    };
+    let description = if replacement.is_empty() {
+        message.to_string()
+    } else {
+        format!("{message}: `{replacement}`")
+    };
+    match lint {
+        Some(lint) => format!("{lint}: {description}"),
+        None => description,
+    }
+}
+
+/// Code action features supported by the client.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct CodeActionSupport {
+    /// Actions which can't be applied are shown disabled with a reason, instead of hidden.
+    disabled: bool,
+    /// Edits are built when the client resolves the chosen action, instead of for every
+    /// action listed.
+    resolve: bool,
+}
+
+/// Data of an action whose edit is built on resolve, pointing at the quick fix prepared when
+/// publishing.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ResolveData {
+    uri: Url,
+    index: usize,
+}
+
+impl CodeActionSupport {
+    pub(crate) fn new(capabilities: Option<&CodeActionClientCapabilities>) -> Self {
+        let Some(capabilities) = capabilities else {
+            return Self::default();
+        };
+        Self {
+            disabled: capabilities.disabled_support == Some(true),
+            resolve: capabilities.data_support == Some(true)
+                && capabilities
+                    .resolve_support
+                    .as_ref()
+                    .is_some_and(|support| support.properties.iter().any(|p| p == "edit")),
+        }
+    }
+
+    pub(crate) fn resolve(&self) -> bool {
+        self.resolve
+    }
+
+    /// Adapt a quick fix to the client: suggestions with placeholders are disabled, or marked
+    /// as needing edits and not preferred if the client can't show them disabled, and the edit
+    /// is left to resolve if supported.
+    pub(crate) fn present(
+        &self,
+        uri: &Url,
+        index: usize,
+        action: &CodeAction,
+    ) -> Option<CodeAction> {
+        let action = if has_placeholders(action) {
+            if self.disabled {
+                return Some(CodeAction {
+                    edit: None,
+                    disabled: Some(CodeActionDisabled {
+                        reason: PLACEHOLDERS_REASON.to_string(),
+                    }),
+                    ..action.clone()
+                });
+            }
+            CodeAction {
+                title: format!("{PLACEHOLDERS_TITLE_PREFIX}{}", action.title),
+                is_preferred: Some(false),
+                ..action.clone()
+            }
+        } else {
+            action.clone()
+        };
+        if self.resolve {
+            let data = ResolveData {
+                uri: uri.clone(),
+                index,
+            };
+            return Some(CodeAction {
+                edit: None,
+                data: serde_json::to_value(data).ok(),
+                ..action
+            });
+        }
+        Some(action)
+    }
+}
+
+/// Fill the edit of an action returned by [`CodeActionSupport::present`] with the one of its
+/// quick fix. The action is returned unchanged if the file was published again in the meantime
+/// and the quick fix is gone.
+pub(crate) fn resolve(action: CodeAction, publisher: &Publisher) -> CodeAction {
+    let Some(data) = action
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<ResolveData>(data).ok())
+    else {
+        return action;
+    };
+    let title = action
+        .title
+        .strip_prefix(PLACEHOLDERS_TITLE_PREFIX)
+        .unwrap_or(&action.title);
+    match publisher.code_actions(&data.uri).get(data.index) {
+        Some((_, quick_fix)) if quick_fix.title == title => CodeAction {
+            edit: quick_fix.edit.clone(),
+            ..action
+        },
+        _ => {
+            tracing::debug!("quick fix {} of {} is gone", data.index, data.uri);
+            action
+        }
+    }
+}
+
+/// True if the text contains a placeholder of rustc, a single word or an ellipsis in a block
+/// comment like `/* Type */` or `/* ... */`, unlike the comments of the code.
+fn contains_placeholder(text: &str) -> bool {
+    text.match_indices("/* ").any(|(start, _)| {
+        text[start + 3..]
+            .split_once(" */")
+            .is_some_and(|(placeholder, _)| {
+                placeholder == "..."
+                    || (!placeholder.is_empty()
+                        && placeholder.chars().all(|c| c.is_alphanumeric() || c == '_'))
+            })
+    })
+}
+
+/// True if the suggestion contains placeholders like `/* Type */`, to be filled by the user.
+pub(crate) fn has_placeholders(action: &CodeAction) -> bool {
+    action
+        .edit
+        .iter()
+        .flat_map(|edit| edit.changes.iter().flatten())
+        .flat_map(|(_, edits)| edits)
+        .any(|TextEdit { new_text, .. }| contains_placeholder(new_text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+    use tower_lsp::lsp_types::{
+        CodeActionCapabilityResolveSupport, Diagnostic, Range, WorkspaceEdit,
+    };
+
+    fn quick_fix(uri: &Url, new_text: &str) -> CodeAction {
+        CodeAction {
+            title: format!("{DEFAULT_TITLE}: `{new_text}`"),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(
+                    uri.clone(),
+                    vec![TextEdit {
+                        range: Range::default(),
+                        new_text: new_text.to_string(),
+                    }],
+                )])),
+                ..WorkspaceEdit::default()
+            }),
+            ..CodeAction::default()
+        }
+    }
+
+    #[test]
+    fn test_quick_fix_title() {
+        assert_eq!(
+            quick_fix_title(None, Some("clippy::redundant_clone"), "remove this", ""),
+            "clippy::redundant_clone: remove this"
+        );
+        assert_eq!(
+            quick_fix_title(
+                None,
+                None,
+                "consider importing this function",
+                "use foo::bar;\n"
+            ),
+            "consider importing this function: `use foo::bar;`"
+        );
+        assert_eq!(
+            quick_fix_title(None, None, "", "if a {\n    b\n}"),
+            "Replace with clippy suggestion: `if a {…`"
+        );
+        assert_eq!(
+            quick_fix_title(
+                Some("Fix {lint} with {replacement}"),
+                Some("clippy::needless_return"),
+                "remove `return`",
+                "x"
+            ),
+            "Fix clippy::needless_return with x"
+        );
+    }
+
+    #[test]
+    fn test_present_code_actions() {
+        let uri = Url::parse("file:///projects/bacon-ls/src/lib.rs").unwrap();
+        let placeholder = quick_fix(&uri, "let x: /* Type */ = 1;");
+        let action = quick_fix(&uri, "let x = 1;");
+
+        let support = CodeActionSupport::new(None);
+        let needs_edits = support.present(&uri, 0, &placeholder).unwrap();
+        assert_eq!(
+            needs_edits.title,
+            format!("{PLACEHOLDERS_TITLE_PREFIX}{}", placeholder.title)
+        );
+        assert_eq!(needs_edits.is_preferred, Some(false));
+        assert_eq!(needs_edits.edit, placeholder.edit);
+        assert_eq!(support.present(&uri, 1, &action), Some(action.clone()));
+        // The comments of the code are not placeholders.
+        let comment = quick_fix(&uri, "let x = 1; /* the answer is 42 */");
+        assert_eq!(support.present(&uri, 2, &comment), Some(comment.clone()));
+        assert!(has_placeholders(&quick_fix(&uri, "S { /* fields */ }")));
+        assert!(has_placeholders(&quick_fix(&uri, "f(/* ... */)")));
+
+        let support = CodeActionSupport::new(Some(&CodeActionClientCapabilities {
+            disabled_support: Some(true),
+            data_support: Some(true),
+            resolve_support: Some(CodeActionCapabilityResolveSupport {
+                properties: vec!["edit".to_string()],
+            }),
+            ..CodeActionClientCapabilities::default()
+        }));
+        let disabled = support.present(&uri, 0, &placeholder).unwrap();
+        assert_eq!(disabled.edit, None);
+        assert_eq!(disabled.disabled.unwrap().reason, PLACEHOLDERS_REASON);
+
+        let unresolved = support.present(&uri, 0, &action).unwrap();
+        assert_eq!(unresolved.edit, None);
+        let mut publisher = Publisher::default();
+        publisher.provide_code_actions = true;
+        let diagnostic = Diagnostic {
+            data: Some(serde_json::json!({"corrections": ["let x = 1;"]})),
+            ..Diagnostic::default()
+        };
+        publisher.prepare(&uri, vec![Diagnostic::default(), diagnostic]);
+        let resolved = resolve(unresolved.clone(), &publisher);
+        assert!(resolved.edit.is_some());
+
+        // The quick fixes changed since the action was listed.
+        publisher.prepare(&uri, vec![]);
+        assert_eq!(resolve(unresolved.clone(), &publisher), unresolved);
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, MessageType, Url};
+use tower_lsp::Client;
+
+use crate::messages::{Locale, Message};
+use crate::{BaconLs, State};
+
+pub(crate) const DIAGNOSTIC_COUNTS_METHOD: &str = "bacon-ls/diagnosticCounts";
+
+/// Number of diagnostics by severity, the information ones being counted as hints.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct Counts {
+    errors: usize,
+    warnings: usize,
+    hints: usize,
+}
+
+impl Counts {
+    fn add(&mut self, diagnostic: &Diagnostic) {
+        match diagnostic.severity {
+            Some(DiagnosticSeverity::ERROR) => self.errors += 1,
+            Some(DiagnosticSeverity::WARNING) => self.warnings += 1,
+            _ => self.hints += 1,
+        }
+    }
+}
+
+/// Counts of the diagnostics of the workspace, returned by the `bacon-ls/diagnosticCounts`
+/// request for the statuslines.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DiagnosticCounts {
+    #[serde(flatten)]
+    total: Counts,
+    per_file: BTreeMap<Url, Counts>,
+}
+
+impl DiagnosticCounts {
+    /// Count the diagnostics of every file of the store, open or not, filtered and merged like
+    /// when they are published. The documents of the server, like `bacon-ls://status`, are left
+    /// out.
+    fn new(state: &State) -> Self {
+        let mut counts = Self::default();
+        for uri in state.diagnostics_store.uris() {
+            if uri.scheme() != "file" {
+                continue;
+            }
+            let Some(file) = state.diagnostics_store.get(uri) else {
+                continue;
+            };
+            let diagnostics = BaconLs::merge_provider_diagnostics(
+                state
+                    .diagnostics_filter
+                    .apply(file.source(state.bacon_source(uri)).to_vec()),
+                state
+                    .provider_diagnostics(file)
+                    .into_iter()
+                    .map(|(_, diagnostic)| diagnostic)
+                    .collect(),
+            );
+            if diagnostics.is_empty() {
+                continue;
+            }
+            let file_counts = counts.per_file.entry(uri.clone()).or_default();
+            for diagnostic in &diagnostics {
+                file_counts.add(diagnostic);
+                counts.total.add(diagnostic);
+            }
+        }
+        counts
+    }
+}
+
+/// Record whether the project has errors, returning the message of its transition between
+/// failing and clean, if any. The first check is not a transition.
+fn build_status_transition(state: &mut State) -> Option<Message<'static>> {
+    let errors = DiagnosticCounts::new(state).total.errors;
+    let failing = errors > 0;
+    match state.build_failing.replace(failing) {
+        Some(was_failing) if was_failing != failing => Some(match failing {
+            true => Message::BuildFailing { errors },
+            false => Message::BuildClean,
+        }),
+        _ => None,
+    }
+}
+
+impl BaconLs {
+    /// Handle the `bacon-ls/diagnosticCounts` request.
+    pub(crate) async fn diagnostic_counts(&self) -> jsonrpc::Result<DiagnosticCounts> {
+        Ok(DiagnosticCounts::new(&*self.state.read().await))
+    }
+
+    /// Show a message when the project goes from having errors to building cleanly or the other
+    /// way around, if `notifyBuildStatus` is enabled. Called once the runs are completed.
+    pub(crate) async fn notify_build_status(
+        client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+    ) {
+        let mut write_state = state.write().await;
+        if !write_state.notify_build_status {
+            return;
+        }
+        let locale = write_state.locale;
+        let Some(message) = build_status_transition(&mut write_state) else {
+            return;
+        };
+        drop(write_state);
+        tracing::info!("{}", message.text(Locale::English));
+        let message_type = match message {
+            Message::BuildClean => MessageType::INFO,
+            _ => MessageType::WARNING,
+        };
+        if let Some(client) = client {
+            client
+                .show_message(message_type, message.text(locale))
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::BACON_SOURCE;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_diagnostic_counts() {
+        let diagnostic = |severity, message: &str| Diagnostic {
+            severity: Some(severity),
+            message: message.to_string(),
+            ..Diagnostic::default()
+        };
+        let lib = Url::parse("file:///project/src/lib.rs").unwrap();
+        let main = Url::parse("file:///project/src/main.rs").unwrap();
+        let mut state = State::default();
+        let store = &mut state.diagnostics_store;
+        store.set(
+            BACON_SOURCE,
+            &lib,
+            vec![
+                diagnostic(DiagnosticSeverity::ERROR, "mismatched types"),
+                diagnostic(DiagnosticSeverity::WARNING, "unused variable: `x`"),
+            ],
+        );
+        // Reported by both bacon and a provider, it is only counted once.
+        store.set(
+            "typos",
+            &lib,
+            vec![
+                diagnostic(DiagnosticSeverity::WARNING, "unused variable: `x`"),
+                diagnostic(DiagnosticSeverity::HINT, "`teh` should be `the`"),
+            ],
+        );
+        store.set(
+            BACON_SOURCE,
+            &main,
+            vec![diagnostic(DiagnosticSeverity::INFORMATION, "note")],
+        );
+        store.set(
+            "status",
+            &Url::parse("bacon-ls://status").unwrap(),
+            vec![diagnostic(DiagnosticSeverity::ERROR, "invalid preferences")],
+        );
+
+        let counts = DiagnosticCounts::new(&state);
+        assert_eq!(
+            serde_json::to_value(&counts).unwrap(),
+            serde_json::json!({
+                "errors": 1,
+                "warnings": 1,
+                "hints": 2,
+                "perFile": {
+                    "file:///project/src/lib.rs": {"errors": 1, "warnings": 1, "hints": 1},
+                    "file:///project/src/main.rs": {"errors": 0, "warnings": 0, "hints": 1},
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_status_transition() {
+        let lib = Url::parse("file:///project/src/lib.rs").unwrap();
+        let error = Diagnostic {
+            severity: Some(DiagnosticSeverity::ERROR),
+            message: "mismatched types".to_string(),
+            ..Diagnostic::default()
+        };
+        let mut state = State::default();
+        assert_eq!(build_status_transition(&mut state), None);
+        assert_eq!(build_status_transition(&mut state), None);
+        state
+            .diagnostics_store
+            .set(BACON_SOURCE, &lib, vec![error.clone(), error]);
+        assert_eq!(
+            build_status_transition(&mut state),
+            Some(Message::BuildFailing { errors: 2 })
+        );
+        assert_eq!(build_status_transition(&mut state), None);
+        state.diagnostics_store.set(BACON_SOURCE, &lib, vec![]);
+        assert_eq!(
+            build_status_transition(&mut state),
+            Some(Message::BuildClean)
+        );
+        // The project failing from the start is not a transition.
+        let mut state = State::default();
+        state.diagnostics_store.set(
+            BACON_SOURCE,
+            &lib,
+            vec![Diagnostic {
+                severity: Some(DiagnosticSeverity::ERROR),
+                ..Diagnostic::default()
+            }],
+        );
+        assert_eq!(build_status_transition(&mut state), None);
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+
+use regex::Regex;
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tower_lsp::lsp_types::{
+    notification::Notification, Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range,
+    Url,
+};
+use tower_lsp::Client;
+
+use crate::analysis::CompilerSpan;
+use crate::error::{BaconLsError, Result};
+use crate::progress;
+use crate::provider::DiagnosticsProvider;
+use crate::usage::{discard_rest, record_json_bytes, CommandLimits, MeasuredChild};
+use crate::{parsed_output_command, DiagnosticData};
+
+pub(crate) const CUSTOM_PROVIDER: &str = "custom";
+pub(crate) const CARGO_EVENT_METHOD: &str = "bacon-ls/cargoEvent";
+const GCC_STYLE_REGEX: &str = r"^(?P<file>(?:[A-Za-z]:)?[^:]+):(?P<line>\d+):(?:(?P<column>\d+):)?\s*(?P<severity>fatal error|error|warning|note|info|help|hint)(?:\[(?P<code>[^\]]+)\])?:\s*(?P<message>.+)$";
+/// Message printed by cargo while another cargo process holds the lock of the build directory
+/// or of the package cache.
+const LOCK_WAIT_MESSAGE: &str = "Blocking waiting for file lock";
+
+/// Return true for the line of cargo waiting for a lock held by another cargo process.
+fn is_lock_wait(line: &str) -> bool {
+    line.trim_start().starts_with(LOCK_WAIT_MESSAGE)
+}
+
+/// Parser of the output of a custom command.
+#[derive(Debug, Clone)]
+pub(crate) enum CustomParser {
+    /// Cargo `--message-format json` output.
+    CargoJson,
+    /// Regex with the `file`, `line` and `message` named capture groups, and optionally
+    /// `column`, `end_line`, `end_column`, `severity` and `code`.
+    Regex(Regex),
+}
+
+impl CustomParser {
+    pub(crate) fn parse(parser: &str, regex: Option<&str>) -> Result<Self> {
+        match parser {
+            "cargo-json" => Ok(Self::CargoJson),
+            "gcc-style" => Ok(Self::Regex(
+                Regex::new(GCC_STYLE_REGEX).expect("gcc-style regex is valid"),
+            )),
+            "regex" => {
+                let regex = regex.ok_or_else(|| {
+                    BaconLsError::Config(
+                        "customCommandRegex is required by the regex parser".to_string(),
+                    )
+                })?;
+                let regex = Regex::new(regex).map_err(|e| {
+                    BaconLsError::Config(format!("invalid customCommandRegex {regex}: {e}"))
+                })?;
+                for group in ["file", "line", "message"] {
+                    if !regex.capture_names().any(|name| name == Some(group)) {
+                        return Err(BaconLsError::Config(format!(
+                            "customCommandRegex has no `{group}` named capture group"
+                        )));
+                    }
+                }
+                Ok(Self::Regex(regex))
+            }
+            _ => Err(BaconLsError::Config(format!(
+                "unknown customCommandParser {parser}"
+            ))),
+        }
+    }
+}
+
+/// Notification of a raw cargo JSON message, e.g. a built artifact or the end of the build.
+pub(crate) enum CargoEvent {}
+
+impl Notification for CargoEvent {
+    type Params = serde_json::Value;
+    const METHOD: &'static str = CARGO_EVENT_METHOD;
+}
+
+/// Forwarding of the cargo JSON messages with the selected reasons to the client, as they are
+/// printed.
+#[derive(Debug, Clone)]
+pub(crate) struct CargoEvents {
+    pub(crate) client: Arc<Client>,
+    pub(crate) reasons: Vec<String>,
+}
+
+impl CargoEvents {
+    /// Return the forwarding of the selected reasons, if any and if the client is connected.
+    pub(crate) fn new(client: Option<Arc<Client>>, reasons: &[String]) -> Option<Self> {
+        if reasons.is_empty() {
+            return None;
+        }
+        Some(Self {
+            client: client?,
+            reasons: reasons.to_vec(),
+        })
+    }
+
+    async fn forward(&self, line: &str) {
+        let Ok(message) = serde_json::from_str::<serde_json::Value>(line) else {
+            return;
+        };
+        let selected = message
+            .get("reason")
+            .and_then(|reason| reason.as_str())
+            .is_some_and(|reason| self.reasons.iter().any(|selected| selected == reason));
+        if selected {
+            self.client.send_notification::<CargoEvent>(message).await;
+        }
+    }
+}
+
+/// User configured command whose output is parsed into diagnostics.
+#[derive(Debug, Clone)]
+pub(crate) struct CustomCommand {
+    pub(crate) command: String,
+    pub(crate) args: Vec<String>,
+    pub(crate) parser: CustomParser,
+    /// Forwarding of the cargo events, only used by the cargo JSON parser.
+    pub(crate) events: Option<CargoEvents>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompilerMessage {
+    message: String,
+    /// Output of the compiler as printed in a terminal, with ANSI colors when the command uses
+    /// `--message-format json-diagnostic-rendered-ansi`.
+    rendered: Option<String>,
+    level: String,
+    code: Option<CompilerCode>,
+    #[serde(default)]
+    spans: Vec<CompilerSpan>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompilerCode {
+    code: String,
+}
+
+fn severity(level: &str) -> DiagnosticSeverity {
+    match level {
+        "error" | "fatal error" | "error: internal compiler error" => DiagnosticSeverity::ERROR,
+        "note" | "info" => DiagnosticSeverity::INFORMATION,
+        "help" | "hint" => DiagnosticSeverity::HINT,
+        _ => DiagnosticSeverity::WARNING,
+    }
+}
+
+/// Convert one based lines and columns into a range.
+fn range(line: u32, column: u32, end_line: u32, end_column: u32) -> Range {
+    Range::new(
+        Position::new(line.saturating_sub(1), column.saturating_sub(1)),
+        Position::new(end_line.saturating_sub(1), end_column.saturating_sub(1)),
+    )
+}
+
+fn file_url(folder_path: &Path, file: &str) -> Option<Url> {
+    Url::from_file_path(folder_path.join(file.trim())).ok()
+}
+
+fn diagnostic(
+    source: &str,
+    range: Range,
+    severity: DiagnosticSeverity,
+    code: Option<String>,
+    message: String,
+) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        code: code.map(NumberOrString::String),
+        source: Some(source.to_string()),
+        message,
+        ..Diagnostic::default()
+    }
+}
+
+pub(crate) fn parse_cargo_json(
+    output: &str,
+    folder_path: &Path,
+    source: &str,
+) -> Vec<(Url, Diagnostic)> {
+    record_json_bytes(output.len());
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<CargoMessage>(line).ok())
+        .filter(|message| message.reason == "compiler-message")
+        .filter_map(|message| {
+            let message = message.message?;
+            let span = message
+                .spans
+                .iter()
+                .find(|span| span.is_primary)?
+                .invocation_site(folder_path);
+            let url = file_url(folder_path, &span.file_name)?;
+            let mut diagnostic = diagnostic(
+                source,
+                range(
+                    span.line_start,
+                    span.column_start,
+                    span.line_end,
+                    span.column_end,
+                ),
+                severity(&message.level),
+                message.code.map(|code| code.code),
+                message.message,
+            );
+            diagnostic.data = message.rendered.map(|rendered| {
+                serde_json::json!(DiagnosticData {
+                    corrections: vec![],
+                    edits: vec![],
+                    title: None,
+                    rendered: Some(rendered.into()),
+                })
+            });
+            Some((url, diagnostic))
+        })
+        .collect()
+}
+
+fn parse_regex(
+    output: &str,
+    folder_path: &Path,
+    regex: &Regex,
+    source: &str,
+) -> Vec<(Url, Diagnostic)> {
+    output
+        .lines()
+        .filter_map(|line| regex.captures(line))
+        .filter_map(|captures| {
+            let number = |group: &str| {
+                captures
+                    .name(group)
+                    .and_then(|value| value.as_str().parse::<u32>().ok())
+            };
+            let line = number("line")?;
+            let column = number("column").unwrap_or(1);
+            let url = file_url(folder_path, captures.name("file")?.as_str())?;
+            Some((
+                url,
+                diagnostic(
+                    source,
+                    range(
+                        line,
+                        column,
+                        number("end_line").unwrap_or(line),
+                        number("end_column").unwrap_or(column),
+                    ),
+                    captures
+                        .name("severity")
+                        .map(|severity_match| severity(severity_match.as_str()))
+                        .unwrap_or(DiagnosticSeverity::ERROR),
+                    captures.name("code").map(|code| code.as_str().to_string()),
+                    captures.name("message")?.as_str().trim().to_string(),
+                ),
+            ))
+        })
+        .collect()
+}
+
+impl CustomCommand {
+    #[tracing::instrument(level = "debug", name = "parse", skip_all)]
+    fn parse_output(&self, output: &str, folder_path: &Path) -> HashMap<Url, Vec<Diagnostic>> {
+        let diagnostics = match &self.parser {
+            CustomParser::CargoJson => parse_cargo_json(output, folder_path, &self.command),
+            CustomParser::Regex(regex) => parse_regex(output, folder_path, regex, &self.command),
+        };
+        let mut by_file: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+        for (url, diagnostic) in diagnostics {
+            by_file.entry(url).or_default().push(diagnostic);
+        }
+        by_file
+    }
+}
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for CustomCommand {
+    fn name(&self) -> &'static str {
+        CUSTOM_PROVIDER
+    }
+
+    fn runs_on_save(&self, _uri: &Url) -> bool {
+        true
+    }
+
+    /// Run the command in the workspace folder and return its parsed output as diagnostics.
+    #[tracing::instrument(level = "debug", name = "spawn", skip_all, fields(program = %self.command))]
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let failed = |e| BaconLsError::Spawn(format!("failed to run {}: {e}", self.command));
+        let mut child = MeasuredChild::spawn(
+            parsed_output_command(&self.command)
+                .envs(env)
+                .args(&self.args)
+                .current_dir(folder_path)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .map_err(failed)?;
+        let events = match self.parser {
+            CustomParser::CargoJson => self.events.as_ref(),
+            CustomParser::Regex(_) => None,
+        };
+        let (child_stdout, child_stderr) = child
+            .take_stdout()
+            .and_then(|stdout| Ok((stdout, child.take_stderr()?)))
+            .map_err(failed)?;
+        let limits = CommandLimits::current();
+        let limit = limits.max_output_bytes;
+        let read_stdout = async {
+            let mut stdout = String::new();
+            if let Some(mut child_stdout) = child_stdout {
+                let mut lines = BufReader::new((&mut child_stdout).take(limit)).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(events) = events {
+                        events.forward(&line).await;
+                    }
+                    stdout.push_str(&line);
+                    stdout.push('\n');
+                }
+                let _ = discard_rest(&mut child_stdout, &self.command, "stdout").await;
+            }
+            stdout
+        };
+        let read_stderr = async {
+            let mut stderr = Vec::new();
+            if let Some(mut child_stderr) = child_stderr {
+                let mut reader = BufReader::new((&mut child_stderr).take(limit));
+                let mut line_start = 0;
+                while let Ok(1..) = reader.read_until(b'\n', &mut stderr).await {
+                    let line = String::from_utf8_lossy(&stderr[line_start..]);
+                    line_start = stderr.len();
+                    if !is_lock_wait(&line) {
+                        continue;
+                    }
+                    let message = line.trim();
+                    tracing::warn!("{}: {message}", self.command);
+                    // Dropping the run kills the process waiting for the lock.
+                    if limits.skip_when_locked {
+                        return Err(BaconLsError::Locked(self.command.clone()));
+                    }
+                    progress::report_message(message);
+                }
+                drop(reader);
+                let _ = discard_rest(&mut child_stderr, &self.command, "stderr").await;
+            }
+            Ok(stderr)
+        };
+        let (stdout, stderr) = tokio::try_join!(async { Ok(read_stdout.await) }, read_stderr)?;
+        child.wait().await.map_err(failed)?;
+        // Failing is the expected outcome of a command reporting errors, and tools print their
+        // diagnostics either on stdout or stderr.
+        let output = format!("{stdout}\n{}", String::from_utf8_lossy(&stderr));
+        Ok(self.parse_output(&output, folder_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn folder_path() -> &'static Path {
+        if cfg!(windows) {
+            Path::new("C:\\projects\\bacon-ls")
+        } else {
+            Path::new("/projects/bacon-ls")
+        }
+    }
+
+    fn command(parser: CustomParser) -> CustomCommand {
+        CustomCommand {
+            command: "cargo".to_string(),
+            args: vec![],
+            parser,
+            events: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_cargo_json() {
+        let output = r#"{"reason":"compiler-artifact","package_id":"bacon-ls"}
+{"reason":"compiler-message","message":{"message":"mismatched types","level":"error","code":{"code":"E0308"},"spans":[{"file_name":"src/lib.rs","line_start":10,"line_end":10,"column_start":5,"column_end":9,"is_primary":true}]}}
+{"reason":"build-finished","success":false}
+"#;
+        let diagnostics = command(CustomParser::CargoJson).parse_output(output, folder_path());
+        let url = file_url(folder_path(), "src/lib.rs").unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[&url][0];
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(9, 4), Position::new(9, 8))
+        );
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String("E0308".to_string()))
+        );
+        assert_eq!(diagnostic.message, "mismatched types");
+        assert_eq!(diagnostic.source.as_deref(), Some("cargo"));
+
+        // The diagnostics in the code expanded by a macro are on its invocation.
+        let output = r#"{"reason":"compiler-message","message":{"message":"mismatched types","level":"error","code":null,"spans":[{"file_name":"/rustc/library/core/src/macros/mod.rs","line_start":40,"line_end":40,"column_start":9,"column_end":20,"is_primary":true,"expansion":{"span":{"file_name":"src/lib.rs","line_start":3,"line_end":3,"column_start":5,"column_end":25,"is_primary":false,"expansion":null},"macro_decl_name":"assert_eq!","def_site_span":null}}]}}"#;
+        let diagnostics = command(CustomParser::CargoJson).parse_output(output, folder_path());
+        assert_eq!(
+            diagnostics[&url][0].range,
+            Range::new(Position::new(2, 4), Position::new(2, 24))
+        );
+    }
+
+    #[test]
+    fn test_parse_gcc_style() {
+        let output = "src/main.rs:3:7: warning: unused variable\n\
+            compiling...\n\
+            src/app.rs:12: error[E0425]: cannot find value `x`\n";
+        let parser = CustomParser::parse("gcc-style", None).unwrap();
+        let diagnostics = command(parser).parse_output(output, folder_path());
+        let main = &diagnostics[&file_url(folder_path(), "src/main.rs").unwrap()][0];
+        assert_eq!(main.range.start, Position::new(2, 6));
+        assert_eq!(main.severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(main.message, "unused variable");
+        let app = &diagnostics[&file_url(folder_path(), "src/app.rs").unwrap()][0];
+        assert_eq!(app.range.start, Position::new(11, 0));
+        assert_eq!(app.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(app.code, Some(NumberOrString::String("E0425".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_run_custom_command() {
+        let command = CustomCommand {
+            command: "echo".to_string(),
+            args: vec!["src/main.rs:3:7: warning: unused variable".to_string()],
+            parser: CustomParser::parse("gcc-style", None).unwrap(),
+            events: None,
+        };
+        let folder_path = std::env::current_dir().unwrap();
+        let diagnostics = command.run(&folder_path, &HashMap::new()).await.unwrap();
+        let main = &diagnostics[&file_url(&folder_path, "src/main.rs").unwrap()][0];
+        assert_eq!(main.message, "unused variable");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_skip_when_locked() {
+        assert!(is_lock_wait(
+            "    Blocking waiting for file lock on package cache\n"
+        ));
+        assert!(!is_lock_wait("    Checking bacon-ls v0.1.0"));
+
+        let command = CustomCommand {
+            command: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                "echo '    Blocking waiting for file lock on build directory' >&2; sleep 30"
+                    .to_string(),
+            ],
+            parser: CustomParser::CargoJson,
+            events: None,
+        };
+        let limits = CommandLimits {
+            skip_when_locked: true,
+            ..CommandLimits::default()
+        };
+        let folder_path = std::env::current_dir().unwrap();
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            limits.scope(command.run(&folder_path, &HashMap::new())),
+        )
+        .await;
+        assert!(matches!(result, Ok(Err(BaconLsError::Locked(_)))));
+    }
+
+    #[test]
+    fn test_parse_regex() {
+        let parser = CustomParser::parse(
+            "regex",
+            Some(r"^(?P<severity>\w+) in (?P<file>\S+) at (?P<line>\d+)-(?P<end_line>\d+): (?P<message>.*)$"),
+        )
+        .unwrap();
+        let diagnostics = command(parser).parse_output(
+            "hint in src/lib.rs at 4-6: consider a rename",
+            folder_path(),
+        );
+        let diagnostic = &diagnostics[&file_url(folder_path(), "src/lib.rs").unwrap()][0];
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(3, 0), Position::new(5, 0))
+        );
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::HINT));
+
+        assert!(CustomParser::parse("regex", None).is_err());
+        assert!(CustomParser::parse("regex", Some(r"(?P<file>\S+)")).is_err());
+        assert!(CustomParser::parse("xml", None).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::RwLock;
+use tower_lsp::jsonrpc;
+
+use crate::fnv::FnvHasher;
+use crate::{parsed_output_command, BaconLs, State, PKG_VERSION};
+
+pub(crate) const DEBUG_INFO_METHOD: &str = "bacon-ls/debugInfo";
+
+/// Snapshot of the environment of the server, logged on startup and returned by the
+/// `bacon-ls/debugInfo` request so bug reports carry it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DebugInfo {
+    bacon_ls: &'static str,
+    bacon: Option<String>,
+    cargo: Option<String>,
+    rustc: Option<String>,
+    toolchain: Option<String>,
+    os: &'static str,
+    arch: &'static str,
+    settings_digest: String,
+}
+
+/// Return the first line printed by a command, if it succeeded.
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = parsed_output_command(program)
+        .args(args)
+        .output()
+        .await
+        .inspect_err(|e| tracing::debug!("failed to run {program}: {e}"))
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    first_line(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn first_line(output: &str) -> Option<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+/// Digest of the initialization options, to tell whether two reports share the same settings
+/// without including them.
+pub(crate) fn settings_digest(settings: Option<&Value>) -> String {
+    // Reports are compared across versions, so the hash must not change with the Rust version.
+    let settings = settings.map(Value::to_string).unwrap_or_default();
+    format!("{:016x}", FnvHasher::hash(settings.as_bytes()))
+}
+
+impl DebugInfo {
+    pub(crate) async fn collect(settings_digest: String) -> Self {
+        let (bacon, cargo, rustc, toolchain) = tokio::join!(
+            command_output("bacon", &["--version"]),
+            command_output("cargo", &["--version"]),
+            command_output("rustc", &["--version"]),
+            command_output("rustup", &["show", "active-toolchain"]),
+        );
+        Self {
+            bacon_ls: PKG_VERSION,
+            bacon,
+            cargo,
+            rustc,
+            toolchain,
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            settings_digest,
+        }
+    }
+}
+
+impl BaconLs {
+    /// Collect the environment snapshot and write it to the log.
+    pub(crate) async fn log_debug_info(state: Arc<RwLock<State>>) {
+        let settings_digest = state.read().await.settings_digest.clone();
+        let debug_info = DebugInfo::collect(settings_digest).await;
+        tracing::info!(
+            "environment: {}",
+            serde_json::to_string(&debug_info).unwrap_or_default()
+        );
+        state.write().await.debug_info = Some(debug_info);
+    }
+
+    /// Handle the `bacon-ls/debugInfo` request.
+    pub(crate) async fn debug_info(&self) -> jsonrpc::Result<DebugInfo> {
+        let state = self.state.read().await;
+        if let Some(debug_info) = &state.debug_info {
+            return Ok(debug_info.clone());
+        }
+        let settings_digest = state.settings_digest.clone();
+        drop(state);
+        Ok(DebugInfo::collect(settings_digest).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_debug_info_helpers() {
+        assert_eq!(
+            first_line("\nstable-x86_64-unknown-linux-gnu (default)\nfoo\n").as_deref(),
+            Some("stable-x86_64-unknown-linux-gnu (default)")
+        );
+        assert_eq!(first_line("  \n"), None);
+
+        let settings = serde_json::json!({"runCargoAudit": true});
+        assert_eq!(
+            settings_digest(Some(&settings)),
+            settings_digest(Some(&settings.clone()))
+        );
+        assert_ne!(settings_digest(Some(&settings)), settings_digest(None));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Url};
+
+use crate::audit::{dependency_declarations, find_declaration, is_cargo_manifest, CARGO_MANIFEST};
+use crate::error::{BaconLsError, Result};
+use crate::parsed_output_command;
+use crate::provider::DiagnosticsProvider;
+use crate::usage::{self, record_json_bytes};
+
+pub(crate) const DENY_PROVIDER: &str = "cargo-deny";
+const DENY_CONFIG: &str = "deny.toml";
+
+/// Line of the `cargo deny --format json` output.
+#[derive(Debug, Deserialize)]
+struct DenyMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    fields: Option<DenyDiagnostic>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DenyDiagnostic {
+    severity: String,
+    message: String,
+    code: Option<String>,
+    #[serde(default)]
+    graphs: Vec<Graph>,
+}
+
+/// Crate of a finding with the crates depending on it, up to the workspace members.
+#[derive(Debug, Deserialize)]
+struct Graph {
+    #[serde(rename = "Krate")]
+    krate: Option<Krate>,
+    #[serde(default)]
+    parents: Vec<Graph>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Krate {
+    name: String,
+}
+
+fn severity(severity: &str) -> DiagnosticSeverity {
+    match severity {
+        "error" => DiagnosticSeverity::ERROR,
+        "note" => DiagnosticSeverity::INFORMATION,
+        "help" => DiagnosticSeverity::HINT,
+        _ => DiagnosticSeverity::WARNING,
+    }
+}
+
+/// Names of the crates of the graphs, from the crates of the findings to the workspace members.
+fn crate_names(graphs: &[Graph]) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut queue: VecDeque<&Graph> = graphs.iter().collect();
+    while let Some(graph) = queue.pop_front() {
+        if let Some(krate) = graph.krate.as_ref() {
+            names.push(krate.name.as_str());
+        }
+        queue.extend(&graph.parents);
+    }
+    names
+}
+
+/// Diagnostics of the findings on the declarations of the dependencies in the manifest. The
+/// findings on a transitive dependency go on the direct dependency pulling it in.
+fn deny_diagnostics(output: &str, manifest: &str) -> Vec<Diagnostic> {
+    let declarations = dependency_declarations(manifest);
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<DenyMessage>(line).ok())
+        .filter(|message| message.kind == "diagnostic")
+        .filter_map(|message| message.fields)
+        .map(|finding| {
+            let names = crate_names(&finding.graphs);
+            let declared = names
+                .iter()
+                .find_map(|name| Some((*name, find_declaration(&declarations, None, name)?)));
+            let message = match (declared, names.first()) {
+                (Some((declared, declaration)), Some(name)) if declared != *name => {
+                    format!("{} (via {})", finding.message, declaration.key)
+                }
+                (None, Some(_)) => format!("{} (transitive dependency)", finding.message),
+                _ => finding.message,
+            };
+            let declaration = declared.map(|(_, declaration)| declaration);
+            Diagnostic {
+                range: declaration
+                    .map(|declaration| declaration.range)
+                    .unwrap_or_default(),
+                severity: Some(severity(&finding.severity)),
+                code: finding.code.map(NumberOrString::String),
+                source: Some(DENY_PROVIDER.to_string()),
+                message,
+                ..Diagnostic::default()
+            }
+        })
+        .collect()
+}
+
+/// Report the banned crates, the duplicate versions and the license violations found by
+/// `cargo deny` on the Cargo manifest.
+#[derive(Debug)]
+pub(crate) struct CargoDeny;
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for CargoDeny {
+    fn name(&self) -> &'static str {
+        DENY_PROVIDER
+    }
+
+    fn runs_on_save(&self, uri: &Url) -> bool {
+        is_cargo_manifest(uri) || uri.path().ends_with(DENY_CONFIG)
+    }
+
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let manifest_path = folder_path.join(CARGO_MANIFEST);
+        let manifest = tokio::fs::read_to_string(&manifest_path)
+            .await
+            .map_err(|e| {
+                BaconLsError::io(format!("unable to read {}", manifest_path.display()), e)
+            })?;
+        let output = usage::output(
+            parsed_output_command("cargo")
+                .envs(env)
+                .args(["deny", "--format", "json", "check", "bans", "licenses"])
+                .current_dir(folder_path),
+        )
+        .await
+        .map_err(|e| BaconLsError::Spawn(format!("failed to run cargo deny: {e}")))?;
+        // cargo deny prints its findings on stderr, and exits with an error when some are
+        // denied.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        record_json_bytes(stderr.len());
+        if !output.status.success() && !stderr.lines().any(|line| line.starts_with('{')) {
+            return Err(BaconLsError::Spawn(format!(
+                "cargo deny failed, is cargo-deny installed? {}",
+                stderr.trim()
+            )));
+        }
+        let url = Url::from_file_path(&manifest_path).map_err(|_| {
+            BaconLsError::Parse(format!("invalid manifest path {}", manifest_path.display()))
+        })?;
+        Ok(HashMap::from([(url, deny_diagnostics(&stderr, &manifest))]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tower_lsp::lsp_types::{Position, Range};
+
+    const MANIFEST: &str = r#"[package]
+name = "app"
+version = "0.1.0"
+
+[dependencies]
+openssl = "0.10"
+reqwest = "0.12"
+"#;
+
+    #[test]
+    fn test_deny_diagnostics() {
+        let output = [
+            r#"{"type":"log","fields":{"timestamp":"2026-10-16T10:00:00Z","level":"WARN","message":"license exception was not encountered"}}"#,
+            r#"{"type":"diagnostic","fields":{"severity":"error","message":"crate 'openssl = 0.10.59' is explicitly banned","code":"banned","labels":[],"notes":[],"graphs":[{"Krate":{"name":"openssl","version":"0.10.59"},"parents":[{"Krate":{"name":"app","version":"0.1.0"}}]}]}}"#,
+            r#"{"type":"diagnostic","fields":{"severity":"warning","message":"found 2 duplicate entries for crate 'base64'","code":"duplicate","labels":[],"notes":[],"graphs":[{"Krate":{"name":"base64","version":"0.21.7"},"parents":[{"Krate":{"name":"reqwest","version":"0.12.0"},"parents":[{"Krate":{"name":"app","version":"0.1.0"}}]}]}]}}"#,
+            r#"{"type":"diagnostic","fields":{"severity":"error","message":"failed to satisfy license requirements","code":"rejected","labels":[],"notes":[],"graphs":[{"Krate":{"name":"ring","version":"0.17.8"},"parents":[]}]}}"#,
+            r#"{"type":"summary","fields":{"bans":{"errors":1,"warnings":1},"licenses":{"errors":1}}}"#,
+        ]
+        .join("\n");
+        let diagnostics = deny_diagnostics(&output, MANIFEST);
+        let summary: Vec<(Range, Option<DiagnosticSeverity>, String, String)> = diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                let Some(NumberOrString::String(code)) = diagnostic.code else {
+                    panic!("missing code");
+                };
+                (
+                    diagnostic.range,
+                    diagnostic.severity,
+                    code,
+                    diagnostic.message,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    Range::new(Position::new(5, 0), Position::new(5, 7)),
+                    Some(DiagnosticSeverity::ERROR),
+                    "banned".to_string(),
+                    "crate 'openssl = 0.10.59' is explicitly banned".to_string()
+                ),
+                (
+                    Range::new(Position::new(6, 0), Position::new(6, 7)),
+                    Some(DiagnosticSeverity::WARNING),
+                    "duplicate".to_string(),
+                    "found 2 duplicate entries for crate 'base64' (via reqwest)".to_string()
+                ),
+                (
+                    Range::default(),
+                    Some(DiagnosticSeverity::ERROR),
+                    "rejected".to_string(),
+                    "failed to satisfy license requirements (transitive dependency)".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use std::collections::{HashMap, VecDeque};
+
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, TextDocumentContentChangeEvent, Url};
+
+/// Shift of the positions following a change of a document, in the negotiated encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Shift {
+    start: Position,
+    old_end: Position,
+    new_end: Position,
+}
+
+impl Shift {
+    fn new(
+        start: Position,
+        old_end: Position,
+        text: &str,
+        encoding: &PositionEncodingKind,
+    ) -> Self {
+        let new_end = match text.rsplit_once('\n') {
+            Some((before, last_line)) => Position::new(
+                start.line + before.matches('\n').count() as u32 + 1,
+                units(last_line, encoding),
+            ),
+            None => Position::new(start.line, start.character + units(text, encoding)),
+        };
+        Self {
+            start,
+            old_end: old_end.max(start),
+            new_end,
+        }
+    }
+
+    /// Position of the same text after the change. The positions in the replaced text move to
+    /// its start.
+    pub(crate) fn apply(&self, position: Position) -> Position {
+        if position < self.start {
+            position
+        } else if position < self.old_end {
+            self.start
+        } else if position.line == self.old_end.line {
+            Position::new(
+                self.new_end.line,
+                self.new_end.character + position.character - self.old_end.character,
+            )
+        } else {
+            Position::new(
+                position.line - self.old_end.line + self.new_end.line,
+                position.character,
+            )
+        }
+    }
+}
+
+/// Maximum number of contents of a document kept while their diagnostics are awaited.
+const MAX_CHECKPOINTS: usize = 16;
+
+/// Content of a document when it was saved or written in an overlay to be checked.
+#[derive(Debug)]
+struct Checkpoint {
+    id: u64,
+    /// Number of shifts of the document then.
+    shifts: usize,
+    text: String,
+    /// Version of the stored diagnostics when the document was saved. Once they change, they
+    /// were computed on the saved content. None for the checks of the unsaved content, whose
+    /// diagnostics are recorded with [`Documents::checked`].
+    saved_version: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Document {
+    text: String,
+    /// Shifts of the changes since the oldest content the stored diagnostics may have been
+    /// computed on.
+    shifts: Vec<Shift>,
+    /// Contents being checked, oldest first.
+    checkpoints: VecDeque<Checkpoint>,
+    next_checkpoint: u64,
+    /// Checkpoint of the unsaved content the stored diagnostics of the unsaved files were
+    /// computed on, none if it was the saved content.
+    unsaved_checked: Option<u64>,
+}
+
+impl Document {
+    /// Position of the checkpoint the stored diagnostics were computed on, none if it is the
+    /// content the document was opened with.
+    fn base(&self, diagnostics_version: u64, unsaved: bool) -> Option<usize> {
+        let unsaved_checked = self.unsaved_checked.filter(|_| unsaved).and_then(|id| {
+            self.checkpoints
+                .iter()
+                .position(|checkpoint| checkpoint.id == id)
+        });
+        unsaved_checked.or_else(|| {
+            self.checkpoints.iter().rposition(|checkpoint| {
+                checkpoint
+                    .saved_version
+                    .is_some_and(|version| version != diagnostics_version)
+            })
+        })
+    }
+
+    /// Drop the checkpoints older than the ones the stored diagnostics were computed on, with
+    /// their shifts, and the oldest ones past [`MAX_CHECKPOINTS`].
+    fn prune(&mut self, diagnostics_version: u64) {
+        // The opened content is needed until the diagnostics of a saved content are stored.
+        if let Some(saved) = self.base(diagnostics_version, false) {
+            let first = self
+                .base(diagnostics_version, true)
+                .map_or(saved, |unsaved| unsaved.min(saved));
+            let drained = self.checkpoints[first].shifts;
+            self.checkpoints.drain(..first);
+            self.shifts.drain(..drained);
+            for checkpoint in self.checkpoints.iter_mut() {
+                checkpoint.shifts -= drained;
+            }
+        }
+        while self.checkpoints.len() > MAX_CHECKPOINTS {
+            self.checkpoints.pop_front();
+        }
+    }
+}
+
+/// Content of the open files, kept in sync with the incremental changes sent by the client,
+/// with the shifts of the positions of their diagnostics until they are computed again.
+#[derive(Debug, Default)]
+pub(crate) struct Documents(HashMap<Url, Document>);
+
+fn unit_len(c: char, position_encoding: &PositionEncodingKind) -> u32 {
+    if *position_encoding == PositionEncodingKind::UTF8 {
+        c.len_utf8() as u32
+    } else if *position_encoding == PositionEncodingKind::UTF32 {
+        1
+    } else {
+        c.len_utf16() as u32
+    }
+}
+
+fn units(text: &str, position_encoding: &PositionEncodingKind) -> u32 {
+    text.chars().map(|c| unit_len(c, position_encoding)).sum()
+}
+
+/// Byte offset of a position in the negotiated encoding, clamped to the end of its line, or of
+/// the text.
+fn offset(text: &str, position: Position, position_encoding: &PositionEncodingKind) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(end) => line_start += end + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= position.character {
+            return line_start + index;
+        }
+        units += unit_len(c, position_encoding);
+    }
+    line_start + line.len()
+}
+
+impl Documents {
+    pub(crate) fn open(&mut self, uri: Url, text: String) {
+        self.0.insert(
+            uri,
+            Document {
+                text,
+                ..Document::default()
+            },
+        );
+    }
+
+    pub(crate) fn close(&mut self, uri: &Url) {
+        self.0.remove(uri);
+    }
+
+    /// Move the content of a document renamed while open.
+    pub(crate) fn rename(&mut self, old_uri: &Url, new_uri: Url) {
+        if let Some(document) = self.0.remove(old_uri) {
+            self.0.insert(new_uri, document);
+        }
+    }
+
+    pub(crate) fn get(&self, uri: &Url) -> Option<&str> {
+        self.0.get(uri).map(|document| document.text.as_str())
+    }
+
+    /// Record that the content of a document is being checked, given the version of its
+    /// stored diagnostics: when it is saved, or written in an overlay if `saved` is false.
+    /// Returns the checkpoint to give to [`Documents::checked`] once the diagnostics of an
+    /// overlay are stored.
+    pub(crate) fn checkpoint(
+        &mut self,
+        uri: &Url,
+        diagnostics_version: u64,
+        saved: bool,
+    ) -> Option<u64> {
+        let document = self.0.get_mut(uri)?;
+        document.prune(diagnostics_version);
+        let id = document.next_checkpoint;
+        document.next_checkpoint += 1;
+        document.checkpoints.push_back(Checkpoint {
+            id,
+            shifts: document.shifts.len(),
+            text: document.text.clone(),
+            saved_version: saved.then_some(diagnostics_version),
+        });
+        Some(id)
+    }
+
+    /// Record the checkpoint the diagnostics of an overlay about to be stored were computed on,
+    /// none for a document which was not unsaved.
+    pub(crate) fn checked(&mut self, uri: &Url, checkpoint: Option<u64>) {
+        if let Some(document) = self.0.get_mut(uri) {
+            document.unsaved_checked = checkpoint;
+        }
+    }
+
+    /// Shifts to apply to the stored diagnostics of a document, given their version and whether
+    /// they are the ones of an overlay.
+    pub(crate) fn shifts(&self, uri: &Url, diagnostics_version: u64, unsaved: bool) -> &[Shift] {
+        let Some(document) = self.0.get(uri) else {
+            return &[];
+        };
+        match document.base(diagnostics_version, unsaved) {
+            Some(base) => &document.shifts[document.checkpoints[base].shifts..],
+            None => &document.shifts,
+        }
+    }
+
+    /// Content the stored diagnostics of a document were computed on, if it was checkpointed.
+    pub(crate) fn checked_text(
+        &self,
+        uri: &Url,
+        diagnostics_version: u64,
+        unsaved: bool,
+    ) -> Option<&str> {
+        let document = self.0.get(uri)?;
+        let base = document.base(diagnostics_version, unsaved)?;
+        Some(&document.checkpoints[base].text)
+    }
+
+    /// Apply the changes of a document in order, each replacing a range, or the whole content
+    /// when it has none. Returns the new content, or none if the document is not open.
+    pub(crate) fn change(
+        &mut self,
+        uri: &Url,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        position_encoding: &PositionEncodingKind,
+    ) -> Option<&str> {
+        let document = self.0.get_mut(uri)?;
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let text = &mut document.text;
+                    let start = offset(text, range.start, position_encoding);
+                    let end = offset(text, range.end, position_encoding).max(start);
+                    text.replace_range(start..end, &change.text);
+                    document.shifts.push(Shift::new(
+                        range.start,
+                        range.end,
+                        &change.text,
+                        position_encoding,
+                    ));
+                }
+                // The positions can't be followed across a replaced content.
+                None => {
+                    document.text = change.text;
+                    document.shifts.clear();
+                    document.checkpoints.clear();
+                    document.unsaved_checked = None;
+                }
+            }
+        }
+        Some(&document.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tower_lsp::lsp_types::Range;
+
+    fn change(range: Option<(u32, u32, u32, u32)>, text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: range.map(|(start_line, start_character, end_line, end_character)| {
+                Range::new(
+                    Position::new(start_line, start_character),
+                    Position::new(end_line, end_character),
+                )
+            }),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_documents() {
+        let uri = Url::parse("file:///project/src/lib.rs").unwrap();
+        let mut documents = Documents::default();
+        assert_eq!(
+            documents.change(&uri, vec![change(None, "")], &PositionEncodingKind::UTF16),
+            None
+        );
+        documents.open(
+            uri.clone(),
+            "fn main() {\r\n    let s = \"é🦀\";\r\n}\n".to_string(),
+        );
+        let utf16 = PositionEncodingKind::UTF16;
+        assert_eq!(
+            documents.change(
+                &uri,
+                vec![
+                    // After the crab, two UTF-16 units.
+                    change(Some((1, 16, 1, 16)), "!"),
+                    change(Some((0, 3, 0, 7)), "run"),
+                    // Past the end of the line, before the line break.
+                    change(Some((2, 1, 2, 10)), " // end"),
+                ],
+                &utf16,
+            ),
+            Some("fn run() {\r\n    let s = \"é🦀!\";\r\n} // end\n")
+        );
+        assert_eq!(
+            documents.change(
+                &uri,
+                vec![change(Some((1, 15, 1, 19)), "")],
+                &PositionEncodingKind::UTF8
+            ),
+            Some("fn run() {\r\n    let s = \"é!\";\r\n} // end\n")
+        );
+        // Inserting at the end of the text, and past its last line.
+        assert_eq!(
+            documents.change(&uri, vec![change(Some((9, 0, 9, 0)), "// eof")], &utf16),
+            Some("fn run() {\r\n    let s = \"é!\";\r\n} // end\n// eof")
+        );
+        assert_eq!(
+            documents.change(&uri, vec![change(None, "fn main() {}")], &utf16),
+            Some("fn main() {}")
+        );
+        let renamed = Url::parse("file:///project/src/main.rs").unwrap();
+        documents.rename(&uri, renamed.clone());
+        assert_eq!(documents.get(&uri), None);
+        assert_eq!(documents.get(&renamed), Some("fn main() {}"));
+        documents.close(&renamed);
+        assert_eq!(documents.get(&renamed), None);
+    }
+
+    #[test]
+    fn test_shifts() {
+        let uri = Url::parse("file:///project/src/lib.rs").unwrap();
+        let mut documents = Documents::default();
+        documents.open(uri.clone(), "fn main() {\n    let x = 1;\n}\n".to_string());
+        let utf16 = PositionEncodingKind::UTF16;
+        let shift = |documents: &Documents, version, line, character| {
+            documents
+                .shifts(&uri, version, false)
+                .iter()
+                .fold(Position::new(line, character), |position, shift| {
+                    shift.apply(position)
+                })
+        };
+        // A line inserted above, and `x` renamed to `🦀`, two UTF-16 units.
+        documents.change(
+            &uri,
+            vec![
+                change(Some((0, 0, 0, 0)), "use std::io;\n"),
+                change(Some((2, 8, 2, 9)), "🦀"),
+            ],
+            &utf16,
+        );
+        assert_eq!(shift(&documents, 1, 1, 8), Position::new(2, 8));
+        assert_eq!(shift(&documents, 1, 1, 13), Position::new(2, 14));
+        assert_eq!(shift(&documents, 1, 0, 3), Position::new(1, 3));
+
+        // Saved, the shifts are kept until the diagnostics are computed again.
+        documents.checkpoint(&uri, 1, true);
+        documents.change(&uri, vec![change(Some((0, 0, 1, 0)), "")], &utf16);
+        assert_eq!(shift(&documents, 1, 1, 8), Position::new(1, 8));
+        assert_eq!(shift(&documents, 2, 2, 8), Position::new(1, 8));
+        // The removed text moves to the start of the change.
+        assert_eq!(shift(&documents, 2, 0, 4), Position::new(0, 0));
+        documents.checkpoint(&uri, 2, true);
+        assert_eq!(documents.shifts(&uri, 2, false).len(), 1);
+
+        documents.change(&uri, vec![change(None, "fn main() {}")], &utf16);
+        assert_eq!(documents.shifts(&uri, 3, false), []);
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let uri = Url::parse("file:///project/src/lib.rs").unwrap();
+        let mut documents = Documents::default();
+        documents.open(uri.clone(), "fn a() {}\n".to_string());
+        let utf16 = PositionEncodingKind::UTF16;
+        let insert_line = |documents: &mut Documents, text| {
+            documents.change(&uri, vec![change(Some((0, 0, 0, 0)), text)], &utf16);
+        };
+        insert_line(&mut documents, "fn b() {}\n");
+        // Checked in an overlay, then saved with another line while the check runs.
+        let overlay = documents.checkpoint(&uri, 1, false);
+        insert_line(&mut documents, "fn c() {}\n");
+        documents.checkpoint(&uri, 1, true);
+        insert_line(&mut documents, "fn d() {}\n");
+        // The diagnostics of the overlay are stored: they were computed on its content.
+        documents.checked(&uri, overlay);
+        assert_eq!(documents.shifts(&uri, 2, true).len(), 2);
+        assert_eq!(
+            documents.checked_text(&uri, 2, true),
+            Some("fn b() {}\nfn a() {}\n")
+        );
+        // The ones of the saved content.
+        assert_eq!(documents.shifts(&uri, 2, false).len(), 1);
+        assert_eq!(
+            documents.checked_text(&uri, 2, false),
+            Some("fn c() {}\nfn b() {}\nfn a() {}\n")
+        );
+        // Until they are computed, the ones of the opened content.
+        assert_eq!(documents.shifts(&uri, 1, false).len(), 3);
+        assert_eq!(documents.checked_text(&uri, 1, false), None);
+
+        // The checkpoints older than the ones the diagnostics were computed on are dropped.
+        documents.checkpoint(&uri, 2, true);
+        assert_eq!(documents.shifts(&uri, 2, true).len(), 2);
+        assert_eq!(documents.shifts(&uri, 2, false).len(), 1);
+        assert_eq!(documents.shifts(&uri, 3, false), []);
+        documents.checked(&uri, None);
+        assert_eq!(documents.shifts(&uri, 3, true), []);
+    }
+}
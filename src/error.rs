@@ -0,0 +1,98 @@
+use std::io;
+
+/// Error of bacon-ls. Its category decides how it is reported to the user, see
+/// [`BaconLsError::report`].
+#[derive(Debug, thiserror::Error)]
+pub enum BaconLsError {
+    /// Invalid settings or `bacon` preferences, which the user has to fix.
+    #[error("{0}")]
+    Config(String),
+    /// A command which couldn't be started or which failed.
+    #[error("{0}")]
+    Spawn(String),
+    /// Output of a command or content of a file which couldn't be parsed.
+    #[error("{0}")]
+    Parse(String),
+    /// A file which couldn't be read or written.
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// A run cancelled by the user, whose previous diagnostics are kept.
+    #[error("cancelled")]
+    Cancelled,
+    /// A run skipped because another cargo process holds the build lock, whose previous
+    /// diagnostics are kept.
+    #[error("{0} skipped, another cargo process holds the build lock")]
+    Locked(String),
+}
+
+pub type Result<T, E = BaconLsError> = std::result::Result<T, E>;
+
+/// How an error is reported to the user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Report {
+    /// Shown with `window/showMessage`, logged and kept as a status problem.
+    ShowMessage,
+    /// Logged and kept as a status problem, for the failures of the tools run on every save
+    /// which would otherwise pop up a message each time.
+    Status,
+    /// Only logged.
+    Log,
+    /// Not reported at all.
+    Silent,
+}
+
+impl BaconLsError {
+    pub(crate) fn io(context: impl Into<String>, source: io::Error) -> Self {
+        Self::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn report(&self) -> Report {
+        match self {
+            Self::Config(_) => Report::ShowMessage,
+            Self::Spawn(_) => Report::Status,
+            Self::Parse(_) | Self::Io { .. } | Self::Locked(_) => Report::Log,
+            Self::Cancelled => Report::Silent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_report() {
+        let error = BaconLsError::io(
+            "error writing bacon lock file .bacon-locations.lock",
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        );
+        assert_eq!(
+            error.to_string(),
+            "error writing bacon lock file .bacon-locations.lock: permission denied"
+        );
+        assert_eq!(error.report(), Report::Log);
+        assert_eq!(
+            BaconLsError::Config("invalid bacon preferences".to_string()).report(),
+            Report::ShowMessage
+        );
+        assert_eq!(
+            BaconLsError::Spawn("cargo fmt failed".to_string()).report(),
+            Report::Status
+        );
+        assert_eq!(BaconLsError::Cancelled.report(), Report::Silent);
+        let error = BaconLsError::Locked("cargo clippy".to_string());
+        assert_eq!(
+            error.to_string(),
+            "cargo clippy skipped, another cargo process holds the build lock"
+        );
+        assert_eq!(error.report(), Report::Log);
+    }
+}
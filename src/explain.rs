@@ -0,0 +1,171 @@
+use serde_json::Value;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{
+    Diagnostic, MessageType, NumberOrString, Position, ShowDocumentParams, Url,
+};
+
+use crate::ansi;
+use crate::error::{BaconLsError, Result};
+use crate::usage;
+use crate::{parsed_output_command, BaconLs, DiagnosticData, PKG_NAME};
+
+pub(crate) const EXPLAIN_COMMAND: &str = "bacon-ls.explain";
+/// Rendered note of rustc pointing at the explanation of an error.
+const EXPLAIN_NOTE: &str = "rustc --explain ";
+
+/// Return true for the codes of the rustc errors, like `E0425`, which have an explanation.
+fn is_error_code(code: &str) -> bool {
+    code.len() == 5 && code.starts_with('E') && code[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Error code of a diagnostic, from its code or from the `rustc --explain` note of its rendered
+/// output, as bacon doesn't export the codes.
+pub(crate) fn error_code(diagnostic: &Diagnostic) -> Option<String> {
+    if let Some(NumberOrString::String(code)) = diagnostic.code.as_ref() {
+        if is_error_code(code) {
+            return Some(code.clone());
+        }
+    }
+    let rendered = DiagnosticData::rendered(diagnostic)
+        .map(|rendered| ansi::strip(&rendered))
+        .unwrap_or_else(|| diagnostic.message.clone());
+    rendered
+        .match_indices(EXPLAIN_NOTE)
+        .filter_map(|(index, _)| rendered.get(index + EXPLAIN_NOTE.len()..)?.get(..5))
+        .find(|code| is_error_code(code))
+        .map(|code| code.to_string())
+}
+
+pub(crate) fn contains(diagnostic: &Diagnostic, position: Position) -> bool {
+    diagnostic.range.start <= position && position <= diagnostic.range.end
+}
+
+/// Explanation of an error code by `rustc --explain`, as Markdown.
+pub(crate) async fn explain(code: &str) -> Result<String> {
+    let output = usage::output(parsed_output_command("rustc").args(["--explain", code]))
+        .await
+        .map_err(|e| BaconLsError::Spawn(format!("failed to run rustc --explain {code}: {e}")))?;
+    if !output.status.success() {
+        return Err(BaconLsError::Spawn(format!(
+            "rustc --explain {code} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(format!(
+        "# {code}\n\n{}",
+        String::from_utf8_lossy(&output.stdout).trim_end()
+    ))
+}
+
+impl BaconLs {
+    /// Error code of the published diagnostic at the position of the file.
+    async fn error_code_at(&self, uri: &Url, position: Position) -> Option<String> {
+        let state = self.state.read().await;
+        state
+            .publisher
+            .published(uri)
+            .iter()
+            .filter(|diagnostic| contains(diagnostic, position))
+            .find_map(error_code)
+    }
+
+    /// Handle the `bacon-ls.explain` command, taking either an error code or the uri and the
+    /// position of a diagnostic. The explanation is returned as Markdown and shown as a document
+    /// by the clients supporting it.
+    pub(crate) async fn explain_command(&self, arguments: &[Value]) -> jsonrpc::Result<Value> {
+        let code = match arguments {
+            [Value::String(code)] if is_error_code(code) => Some(code.clone()),
+            [uri, position] => {
+                let (Some(uri), Ok(position)) = (
+                    uri.as_str().and_then(|uri| Url::parse(uri).ok()),
+                    serde_json::from_value::<Position>(position.clone()),
+                ) else {
+                    return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams));
+                };
+                self.error_code_at(&uri, position).await
+            }
+            _ => return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams)),
+        };
+        let Some(code) = code else {
+            tracing::info!("no error code to explain");
+            return Ok(Value::Null);
+        };
+        let explanation = match explain(&code).await {
+            Ok(explanation) => explanation,
+            Err(e) => {
+                tracing::error!("{e}");
+                if let Some(client) = self.client.as_ref() {
+                    client.show_message(MessageType::ERROR, e.to_string()).await;
+                }
+                return Ok(Value::Null);
+            }
+        };
+        if self.state.read().await.show_document {
+            self.show_explanation(&code, &explanation).await;
+        }
+        Ok(Value::String(explanation))
+    }
+
+    /// Write the explanation to a Markdown file and ask the client to show it.
+    async fn show_explanation(&self, code: &str, explanation: &str) {
+        let Some(client) = self.client.as_ref() else {
+            return;
+        };
+        let folder_path = std::env::temp_dir().join(format!("{PKG_NAME}-explain"));
+        let path = folder_path.join(format!("{code}.md"));
+        let written = match tokio::fs::create_dir_all(&folder_path).await {
+            Ok(()) => tokio::fs::write(&path, explanation).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            tracing::warn!("unable to write explanation {}: {e}", path.display());
+            return;
+        }
+        let Ok(uri) = Url::from_file_path(&path) else {
+            return;
+        };
+        let params = ShowDocumentParams {
+            uri,
+            external: Some(false),
+            take_focus: Some(true),
+            selection: None,
+        };
+        if let Err(e) = client.show_document(params).await {
+            tracing::warn!("unable to show explanation of {code}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_error_code() {
+        let diagnostic = |code: Option<&str>, message: &str| Diagnostic {
+            code: code.map(|code| NumberOrString::String(code.to_string())),
+            message: message.to_string(),
+            ..Diagnostic::default()
+        };
+        assert_eq!(
+            error_code(&diagnostic(Some("E0308"), "mismatched types")),
+            Some("E0308".to_string())
+        );
+        let rendered = Diagnostic {
+            data: Some(serde_json::json!({
+                "rendered": "cannot find value `one` in this scope\n\nFor more information about this error, try `rustc --explain E0425`."
+            })),
+            ..diagnostic(None, "cannot find value `one` in this scope")
+        };
+        assert_eq!(error_code(&rendered), Some("E0425".to_string()));
+        assert_eq!(
+            error_code(&diagnostic(
+                Some("clippy::needless_return"),
+                "unneeded `return` statement"
+            )),
+            None
+        );
+        assert_eq!(error_code(&diagnostic(None, "rustc --explain E04")), None);
+    }
+}
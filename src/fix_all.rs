@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use serde_json::Value;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{
+    CodeAction, Diagnostic, MessageType, Range, TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::code_action::has_placeholders;
+use crate::messages::{Locale, Message};
+use crate::{BaconLs, UNSAVED_PROVIDER};
+
+pub(crate) const FIX_ALL_WORKSPACE_COMMAND: &str = "bacon-ls.fixAllWorkspace";
+
+/// Return true if a file was modified after its diagnostics were last computed.
+fn is_stale(modified: Option<SystemTime>, checked: Option<SystemTime>) -> bool {
+    match (modified, checked) {
+        (Some(modified), Some(checked)) => modified > checked,
+        _ => false,
+    }
+}
+
+fn overlaps(a: &Range, b: &Range) -> bool {
+    a.start == b.start || (a.start < b.end && b.start < a.end)
+}
+
+/// Edits of the fixes which can be applied without looking at them: the preferred quick fix
+/// of each diagnostic, with a single suggestion and no placeholders. A fix overlapping one
+/// already taken is left for the next run. Returns the edits by file and the number of fixes.
+fn collect_fixes(files: Vec<(Url, Vec<Diagnostic>)>) -> (HashMap<Url, Vec<TextEdit>>, usize) {
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    let mut fixes = 0;
+    for (uri, diagnostics) in files {
+        let mut file_fixes: Vec<Vec<TextEdit>> = diagnostics
+            .iter()
+            .flat_map(|diagnostic| BaconLs::code_actions(&uri, diagnostic, None))
+            .filter(|action| action.is_preferred == Some(true) && !has_placeholders(action))
+            .filter_map(|CodeAction { edit, .. }| edit?.changes?.remove(&uri))
+            .filter(|edits| !edits.is_empty())
+            .collect();
+        file_fixes.sort_by_key(|edits| edits.iter().map(|edit| edit.range.start).min());
+        let mut file_edits: Vec<TextEdit> = Vec::new();
+        for edits in file_fixes {
+            let overlapping = edits.iter().any(|edit| {
+                file_edits
+                    .iter()
+                    .any(|taken| overlaps(&edit.range, &taken.range))
+            });
+            if !overlapping {
+                file_edits.extend(edits);
+                fixes += 1;
+            }
+        }
+        if !file_edits.is_empty() {
+            changes.insert(uri, file_edits);
+        }
+    }
+    (changes, fixes)
+}
+
+impl BaconLs {
+    /// Handle the `bacon-ls.fixAllWorkspace` command, applying the fixes of the diagnostics of
+    /// every file of the workspace in a single `workspace/applyEdit` request. Returns the
+    /// number of fixes applied.
+    pub(crate) async fn fix_all_workspace_command(&self) -> jsonrpc::Result<Value> {
+        let Some(client) = self.client.as_ref() else {
+            return Ok(Value::from(0));
+        };
+        let state = self.state.read().await;
+        let locale = state.locale;
+        let uris: Vec<Url> = state
+            .diagnostics_store
+            .uris()
+            .filter(|uri| uri.scheme() == "file")
+            .cloned()
+            .collect();
+        drop(state);
+        let mut files = Vec::new();
+        for uri in uris {
+            // The diagnostics of a file changed on disk since they were computed may not match
+            // its content anymore.
+            let checked = self
+                .state
+                .read()
+                .await
+                .diagnostics_store
+                .get(&uri)
+                .and_then(|file| file.checked);
+            let modified = match uri.to_file_path() {
+                Ok(path) => tokio::fs::metadata(path)
+                    .await
+                    .and_then(|metadata| metadata.modified())
+                    .ok(),
+                Err(()) => None,
+            };
+            if is_stale(modified, checked) {
+                tracing::debug!("skipping the fixes of {uri}, changed since it was checked");
+                continue;
+            }
+            let mut diagnostics = Self::stored_diagnostics(&self.state, &uri).await;
+            let state = self.state.read().await;
+            let diagnostics_version = state.diagnostics_store.version(&uri);
+            let unsaved = state.bacon_source(&uri) == UNSAVED_PROVIDER;
+            Self::shift_positions(
+                &mut diagnostics,
+                state.documents.shifts(&uri, diagnostics_version, unsaved),
+            );
+            drop(state);
+            files.push((uri, diagnostics));
+        }
+        let (changes, fixes) = collect_fixes(files);
+        if fixes == 0 {
+            client
+                .show_message(MessageType::INFO, Message::NoFixes.text(locale))
+                .await;
+            return Ok(Value::from(0));
+        }
+        let message = Message::FixesApplied {
+            fixes,
+            files: changes.len(),
+        };
+        let edit = WorkspaceEdit {
+            changes: Some(changes),
+            ..WorkspaceEdit::default()
+        };
+        match client.apply_edit(edit).await {
+            Ok(response) if response.applied => {
+                tracing::info!("{}", message.text(Locale::English));
+                client
+                    .show_message(MessageType::INFO, message.text(locale))
+                    .await;
+                Ok(Value::from(fixes))
+            }
+            Ok(response) => {
+                let reason = response.failure_reason.unwrap_or_default();
+                let message = Message::FixesRejected { reason: &reason };
+                tracing::warn!("{}", message.text(Locale::English));
+                client
+                    .show_message(MessageType::WARNING, message.text(locale))
+                    .await;
+                Ok(Value::from(0))
+            }
+            Err(e) => {
+                tracing::error!("workspace/applyEdit request failed: {e}");
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DiagnosticData;
+    use pretty_assertions::assert_eq;
+    use tower_lsp::lsp_types::Position;
+
+    fn diagnostic(line: u32, start: u32, end: u32, corrections: &[&str]) -> Diagnostic {
+        Diagnostic {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            message: "help".to_string(),
+            data: Some(serde_json::json!(DiagnosticData {
+                corrections: corrections.iter().map(|c| (*c).into()).collect(),
+                edits: vec![],
+                title: None,
+                rendered: None,
+            })),
+            ..Diagnostic::default()
+        }
+    }
+
+    #[test]
+    fn test_is_stale() {
+        let checked = SystemTime::now();
+        let second = std::time::Duration::from_secs(1);
+        assert!(is_stale(Some(checked + second), Some(checked)));
+        assert!(!is_stale(Some(checked - second), Some(checked)));
+        assert!(!is_stale(None, Some(checked)));
+        assert!(!is_stale(Some(checked), None));
+    }
+
+    #[test]
+    fn test_collect_fixes() {
+        let lib = Url::parse("file:///project/src/lib.rs").unwrap();
+        let main = Url::parse("file:///project/src/main.rs").unwrap();
+        let (changes, fixes) = collect_fixes(vec![
+            (
+                lib.clone(),
+                vec![
+                    diagnostic(3, 4, 10, &["x"]),
+                    // Overlapping the first one.
+                    diagnostic(3, 8, 12, &["y"]),
+                    // Several suggestions to choose from.
+                    diagnostic(5, 0, 2, &["a", "b"]),
+                    diagnostic(7, 0, 6, &["Vec</* Type */>"]),
+                    diagnostic(1, 0, 1, &[""]),
+                    diagnostic(9, 0, 1, &[]),
+                ],
+            ),
+            (main.clone(), vec![diagnostic(0, 0, 0, &["x"])]),
+            (
+                Url::parse("file:///project/src/bin.rs").unwrap(),
+                vec![diagnostic(0, 0, 3, &["a", "b"])],
+            ),
+        ]);
+        let edit = |line, start, end, new_text: &str| TextEdit {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            new_text: new_text.to_string(),
+        };
+        assert_eq!(fixes, 3);
+        assert_eq!(
+            changes,
+            HashMap::from([
+                (lib, vec![edit(1, 0, 1, ""), edit(3, 4, 10, "x")]),
+                (main, vec![edit(0, 0, 0, "x")]),
+            ])
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::hash::Hasher;
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0100_0000_01b3;
+
+/// 64 bits FNV-1a hasher. Unlike `DefaultHasher`, its hashes are the same across Rust versions
+/// and runs, so they can be written to files and compared with the ones of a previous session.
+/// Only the bytes given to `write` are hashed, the `Hash` implementations of the standard types
+/// may add platform dependent lengths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FnvHasher(u64);
+
+impl FnvHasher {
+    /// Hash of the bytes.
+    pub(crate) fn hash(bytes: &[u8]) -> u64 {
+        let mut hasher = Self::default();
+        hasher.write(bytes);
+        hasher.finish()
+    }
+}
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(OFFSET_BASIS)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_fnv_hasher() {
+        assert_eq!(FnvHasher::hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(FnvHasher::hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        let mut hasher = FnvHasher::default();
+        hasher.write(b"foo");
+        hasher.write(b"bar");
+        assert_eq!(hasher.finish(), FnvHasher::hash(b"foobar"));
+    }
+}
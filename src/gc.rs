@@ -0,0 +1,272 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::error::{BaconLsError, Result};
+use crate::fnv::FnvHasher;
+use crate::overlay::OVERLAY_BUILD_FOLDER;
+use crate::revision::REVISION_BUILD_FOLDER;
+use crate::{git, PKG_NAME};
+
+/// Number of days after which an unused build folder is removed.
+pub(crate) const DEFAULT_BUILD_FOLDER_MAX_AGE_DAYS: u64 = 30;
+
+/// Kinds of the build folders, the prefixes of their names.
+const BUILD_FOLDER_KINDS: [&str; 2] = [REVISION_BUILD_FOLDER, OVERLAY_BUILD_FOLDER];
+
+pub(crate) fn cache_dir() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    dir.filter(|dir| dir.is_absolute())
+}
+
+/// Default build folder of a kind for a repository or a workspace folder, in the cache
+/// directory of the user and named after the hash of its path, so it is the same across
+/// sessions.
+pub(crate) fn default_build_folder(kind: &str, path: &Path) -> PathBuf {
+    let name = build_folder_name(kind, path);
+    cache_dir()
+        .map(|dir| dir.join(PKG_NAME).join(&name))
+        .unwrap_or_else(|| env::temp_dir().join(format!("{PKG_NAME}-{name}")))
+}
+
+fn build_folder_name(kind: &str, path: &Path) -> String {
+    let hash = FnvHasher::hash(path.as_os_str().as_encoded_bytes());
+    format!("{kind}-{hash:016x}")
+}
+
+/// Default build folder of a kind, with its parent directory created. When it can't be created,
+/// like in a read-only or full cache directory, the build folder is made in the target
+/// directory of cargo instead, where it is removed by `cargo clean` rather than by the garbage
+/// collection.
+pub(crate) async fn prepare_build_folder(
+    kind: &str,
+    path: &Path,
+    target_dir: &Path,
+) -> Result<PathBuf> {
+    let fallback = target_dir.join(format!("{PKG_NAME}-{}", build_folder_name(kind, path)));
+    prepare(default_build_folder(kind, path), fallback).await
+}
+
+async fn prepare(build_folder: PathBuf, fallback: PathBuf) -> Result<PathBuf> {
+    let Some(parent) = build_folder.parent() else {
+        return Ok(build_folder);
+    };
+    let Err(e) = tokio::fs::create_dir_all(parent).await else {
+        return Ok(build_folder);
+    };
+    tracing::warn!(
+        "unable to create {}: {e}, using {}",
+        parent.display(),
+        fallback.display()
+    );
+    if let Some(fallback_parent) = fallback.parent() {
+        tokio::fs::create_dir_all(fallback_parent)
+            .await
+            .map_err(|e| {
+                BaconLsError::io(
+                    format!(
+                        "unable to create a build folder in {} or in {}",
+                        parent.display(),
+                        fallback_parent.display()
+                    ),
+                    e,
+                )
+            })?;
+    }
+    Ok(fallback)
+}
+
+/// Directories of the default build folders, with the prefix of their names, including the
+/// temporary folders of the checks of the previous versions and their target directory.
+fn build_folder_roots() -> Vec<(PathBuf, String)> {
+    let mut roots = Vec::new();
+    for kind in BUILD_FOLDER_KINDS {
+        if let Some(dir) = cache_dir() {
+            roots.push((dir.join(PKG_NAME), format!("{kind}-")));
+        }
+        roots.push((env::temp_dir(), format!("{PKG_NAME}-{kind}-")));
+    }
+    roots
+}
+
+fn days(days: u64) -> Duration {
+    Duration::from_secs(days * 24 * 60 * 60)
+}
+
+/// Last time a build folder was used: the modification time of the `.git` file of the
+/// checkout, touched by each check, or else of the folder.
+async fn last_used(path: &Path) -> Option<SystemTime> {
+    let modified = |path: PathBuf| async move {
+        tokio::fs::metadata(path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    modified(path.join(".git"))
+        .await
+        .max(modified(path.to_path_buf()).await)
+}
+
+/// Build folders of bacon-ls, the current and the ones of the previous versions.
+async fn build_folders() -> Vec<PathBuf> {
+    let mut folders = Vec::new();
+    for (dir, prefix) in build_folder_roots() {
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_name().to_string_lossy().starts_with(&prefix)
+                && entry
+                    .file_type()
+                    .await
+                    .is_ok_and(|file_type| file_type.is_dir())
+            {
+                folders.push(entry.path());
+            }
+        }
+    }
+    folders
+}
+
+/// Remove a build folder, and the worktree of the checkout from its repository.
+pub(crate) async fn remove_build_folder(path: &Path) -> Result<()> {
+    // The `.git` file of a worktree points to `.git/worktrees/<name>` in the repository.
+    let common_dir = tokio::fs::read_to_string(path.join(".git"))
+        .await
+        .ok()
+        .and_then(|content| {
+            let git_dir = PathBuf::from(content.trim().strip_prefix("gitdir:")?.trim());
+            Some(git_dir.parent()?.parent()?.to_path_buf())
+        });
+    tokio::fs::remove_dir_all(path)
+        .await
+        .map_err(|e| BaconLsError::io(format!("unable to remove {}", path.display()), e))?;
+    if let Some(common_dir) = common_dir {
+        if let Err(e) = git(&common_dir, &["worktree", "prune"]).await {
+            tracing::debug!("{e}");
+        }
+    }
+    Ok(())
+}
+
+/// Remove the build folders not used for longer than `max_age`, returning the removed ones.
+pub(crate) async fn collect_build_folders(max_age: Duration) -> Vec<PathBuf> {
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+    for path in build_folders().await {
+        let Some(last_used) = last_used(&path).await else {
+            continue;
+        };
+        if now.duration_since(last_used).is_ok_and(|age| age > max_age) {
+            match remove_build_folder(&path).await {
+                Ok(()) => removed.push(path),
+                Err(e) => tracing::warn!("{e}"),
+            }
+        }
+    }
+    removed
+}
+
+/// Remove the build folders of bacon-ls not used for the number of days, printing them.
+pub async fn gc(max_age_days: Option<u64>) -> Result<()> {
+    let max_age_days = max_age_days.unwrap_or(DEFAULT_BUILD_FOLDER_MAX_AGE_DAYS);
+    for path in collect_build_folders(days(max_age_days)).await {
+        println!("removed {}", path.display());
+    }
+    Ok(())
+}
+
+/// Remove the stale build folders in background, at startup.
+pub(crate) fn spawn_collect_build_folders(max_age_days: u64) {
+    if max_age_days == 0 {
+        return;
+    }
+    tokio::task::spawn(async move {
+        for path in collect_build_folders(days(max_age_days)).await {
+            tracing::info!("removed stale build folder {}", path.display());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_default_build_folder() {
+        let build_folder = default_build_folder("revision", Path::new("/projects/bacon-ls"));
+        assert_eq!(
+            build_folder,
+            default_build_folder("revision", Path::new("/projects/bacon-ls"))
+        );
+        assert_ne!(
+            build_folder,
+            default_build_folder("revision", Path::new("/projects/bacon"))
+        );
+        assert!(build_folder
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("revision-"));
+    }
+
+    #[tokio::test]
+    async fn test_prepare_build_folder() {
+        let tmp_dir = TempDir::new("gc").unwrap();
+        let cache = tmp_dir.path().join("cache");
+        let fallback = tmp_dir.path().join("target/bacon-ls-overlay");
+        assert_eq!(
+            prepare(cache.join("bacon-ls/overlay"), fallback.clone())
+                .await
+                .unwrap(),
+            cache.join("bacon-ls/overlay")
+        );
+        assert!(cache.join("bacon-ls").is_dir());
+
+        // A cache directory which can't be written to.
+        let file = tmp_dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert_eq!(
+            prepare(file.join("bacon-ls/overlay"), fallback.clone())
+                .await
+                .unwrap(),
+            fallback
+        );
+        assert!(tmp_dir.path().join("target").is_dir());
+        assert!(prepare(file.join("bacon-ls/overlay"), file.join("overlay"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remove_build_folder() {
+        let tmp_dir = TempDir::new("gc").unwrap();
+        let repo_git_dir = tmp_dir.path().join("repo/.git");
+        std::fs::create_dir_all(repo_git_dir.join("worktrees/checkout")).unwrap();
+        let checkout = tmp_dir.path().join("checkout");
+        std::fs::create_dir_all(checkout.join("src")).unwrap();
+        std::fs::write(
+            checkout.join(".git"),
+            format!(
+                "gitdir: {}\n",
+                repo_git_dir.join("worktrees/checkout").display()
+            ),
+        )
+        .unwrap();
+        let before = last_used(&checkout).await.unwrap();
+        assert!(before <= SystemTime::now());
+
+        remove_build_folder(&checkout).await.unwrap();
+        assert!(!checkout.exists());
+        assert_eq!(last_used(&checkout).await, None);
+        assert!(remove_build_folder(&checkout).await.is_err());
+    }
+}
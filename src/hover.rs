@@ -0,0 +1,84 @@
+use tower_lsp::lsp_types::{
+    Diagnostic, Hover, HoverContents, HoverParams, MarkupContent, MarkupKind,
+};
+
+use crate::ansi;
+use crate::explain::{contains, error_code, explain};
+use crate::{BaconLs, DiagnosticData};
+
+/// Separator of the diagnostics and of the explanation in a hover.
+const SEPARATOR: &str = "\n\n---\n\n";
+
+/// Markdown of the rendered output of the diagnostics.
+fn rendered_markdown(diagnostics: &[Diagnostic]) -> Option<String> {
+    let sections: Vec<String> = diagnostics
+        .iter()
+        .filter_map(DiagnosticData::rendered)
+        .map(|rendered| ansi::to_markdown(&rendered))
+        .collect();
+    (!sections.is_empty()).then(|| sections.join(SEPARATOR))
+}
+
+impl BaconLs {
+    /// Full rendered output of the diagnostics under the cursor, followed by the explanation of
+    /// their error if `explainOnHover` is enabled.
+    pub(crate) async fn hover_diagnostics(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let state = self.state.read().await;
+        let diagnostics: Vec<Diagnostic> = state
+            .publisher
+            .published(&position.text_document.uri)
+            .iter()
+            .filter(|diagnostic| contains(diagnostic, position.position))
+            .cloned()
+            .collect();
+        let explain_on_hover = state.explain_on_hover;
+        drop(state);
+
+        let mut sections: Vec<String> = rendered_markdown(&diagnostics).into_iter().collect();
+        if explain_on_hover {
+            if let Some(code) = diagnostics.iter().find_map(error_code) {
+                match explain(&code).await {
+                    Ok(explanation) => sections.push(explanation),
+                    Err(e) => tracing::warn!("{e}"),
+                }
+            }
+        }
+        if sections.is_empty() {
+            return None;
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: sections.join(SEPARATOR),
+            }),
+            range: diagnostics.first().map(|diagnostic| diagnostic.range),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_rendered_markdown() {
+        let diagnostic = |rendered: Option<&str>| Diagnostic {
+            data: rendered.map(|rendered| serde_json::json!({ "rendered": rendered })),
+            ..Diagnostic::default()
+        };
+        assert_eq!(
+            rendered_markdown(&[
+                diagnostic(Some("unused variable: `x`\n  |\n3 |     let x = 1;\n  |         ^\n")),
+                diagnostic(None),
+                diagnostic(Some("unused import: `std::fs`")),
+            ]),
+            Some(
+                "unused variable: `x`\n\n```text\n  |\n3 |     let x = 1;\n  |         ^\n```\n\n---\n\nunused import: `std::fs`"
+                    .to_string()
+            )
+        );
+        assert_eq!(rendered_markdown(&[diagnostic(None)]), None);
+    }
+}
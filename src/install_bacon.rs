@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{Diagnostic, MessageType, Url};
+
+use crate::custom::{CargoEvents, CustomCommand, CustomParser};
+use crate::error::{BaconLsError, Result};
+use crate::messages::{Locale, Message};
+use crate::progress::{self, WorkProgress};
+use crate::provider::DiagnosticsProvider;
+use crate::usage::MeasuredChild;
+use crate::{parsed_output_command, BaconLs, FALLBACK_COMMAND_ARGS, FALLBACK_PROVIDER};
+
+pub(crate) const INSTALL_BACON_COMMAND: &str = "bacon-ls.installBacon";
+const INSTALL_ARGS: [&str; 3] = ["install", "--locked", "bacon"];
+
+/// Run cargo on save in place of bacon, when bacon is not installed.
+#[derive(Debug)]
+pub(crate) struct CargoFallback(CustomCommand);
+
+impl CargoFallback {
+    pub(crate) fn new(command: CustomCommand) -> Self {
+        Self(command)
+    }
+}
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for CargoFallback {
+    fn name(&self) -> &'static str {
+        FALLBACK_PROVIDER
+    }
+
+    fn runs_on_save(&self, uri: &Url) -> bool {
+        self.0.runs_on_save(uri)
+    }
+
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        self.0.run(folder_path, env).await
+    }
+}
+
+/// Run `cargo install --locked bacon`, showing the crates being compiled in the progress.
+async fn install_bacon(env: &HashMap<String, String>) -> Result<()> {
+    let command = format!("cargo {}", INSTALL_ARGS.join(" "));
+    let failed = |e| BaconLsError::Spawn(format!("failed to run {command}: {e}"));
+    let mut child = MeasuredChild::spawn(
+        parsed_output_command("cargo")
+            .envs(env)
+            .args(INSTALL_ARGS)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped()),
+    )
+    .map_err(failed)?;
+    let mut last_line = String::new();
+    if let Some(stderr) = child.take_stderr().map_err(failed)? {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            progress::report_message(line);
+            last_line = line.to_string();
+        }
+    }
+    let status = child.wait().await.map_err(failed)?;
+    if !status.success() {
+        return Err(BaconLsError::Spawn(format!(
+            "{command} failed: {last_line}"
+        )));
+    }
+    Ok(())
+}
+
+impl BaconLs {
+    /// Run cargo on save instead of bacon, which is not installed, telling the user how to
+    /// install it.
+    pub(crate) async fn fall_back_to_cargo(&self) {
+        let mut state = self.state.write().await;
+        state.bacon_missing = true;
+        let locale = state.locale;
+        let events = CargoEvents::new(self.client.clone(), &state.cargo_events);
+        state
+            .providers
+            .push(Arc::new(CargoFallback::new(CustomCommand {
+                command: "cargo".to_string(),
+                args: FALLBACK_COMMAND_ARGS.map(|arg| arg.to_string()).to_vec(),
+                parser: CustomParser::CargoJson,
+                events,
+            })));
+        drop(state);
+        let command = format!("cargo {}", FALLBACK_COMMAND_ARGS.join(" "));
+        let message = Message::BaconMissing { command: &command };
+        tracing::warn!("{}", message.text(Locale::English));
+        if let Some(client) = self.client.as_ref() {
+            client
+                .show_message(MessageType::WARNING, message.text(locale))
+                .await;
+        }
+    }
+
+    /// Handle the `bacon-ls.installBacon` command: install bacon with cargo, then drop the cargo
+    /// fallback and start bacon according to the settings.
+    pub(crate) async fn install_bacon_command(&self) -> jsonrpc::Result<()> {
+        let state = self.state.read().await;
+        let cargo_env = state.cargo_env.clone();
+        let locale = state.locale;
+        drop(state);
+
+        let client = self.client.as_deref();
+        let progress = WorkProgress::begin(client, "installing bacon").await;
+        // Dropping the installation kills cargo.
+        let result = tokio::select! {
+            result = progress.show_messages(install_bacon(&cargo_env)) => result,
+            _ = progress.cancelled() => Err(BaconLsError::Cancelled),
+        };
+        let (message_type, message) = match result {
+            Ok(()) => {
+                progress.end(None).await;
+                let mut state = self.state.write().await;
+                let was_missing = std::mem::take(&mut state.bacon_missing);
+                state
+                    .providers
+                    .retain(|provider| provider.name() != FALLBACK_PROVIDER);
+                drop(state);
+                if was_missing {
+                    Self::set_provider_diagnostics(
+                        self.client.as_ref(),
+                        &self.state,
+                        FALLBACK_PROVIDER,
+                        HashMap::new(),
+                    )
+                    .await;
+                    if let Some(client) = client {
+                        self.start_bacon(client).await;
+                    }
+                }
+                let message = Message::BaconInstalled;
+                tracing::info!("{}", message.text(Locale::English));
+                (MessageType::INFO, message.text(locale))
+            }
+            Err(BaconLsError::Cancelled) => {
+                progress
+                    .end(Some(BaconLsError::Cancelled.to_string()))
+                    .await;
+                tracing::info!("bacon installation cancelled");
+                return Ok(());
+            }
+            Err(e) => {
+                progress.end(Some(e.to_string())).await;
+                tracing::error!("{e}");
+                (MessageType::ERROR, e.to_string())
+            }
+        };
+        if let Some(client) = client {
+            client.show_message(message_type, message).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_fall_back_to_cargo() {
+        let bacon_ls = BaconLs {
+            client: None,
+            state: Arc::new(RwLock::new(State::default())),
+        };
+        bacon_ls.fall_back_to_cargo().await;
+        let state = bacon_ls.state.read().await;
+        assert!(state.bacon_missing);
+        let names: Vec<_> = state
+            .providers
+            .iter()
+            .map(|provider| provider.name())
+            .collect();
+        assert_eq!(names, vec![FALLBACK_PROVIDER]);
+        assert!(state.providers[0].runs_on_save(&Url::parse("file:///project/src/lib.rs").unwrap()));
+    }
+}
@@ -1,29 +1,185 @@
 //! Bacon Language Server
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
-use std::path::Path;
+use std::ffi::OsStr;
+use std::hash::Hasher;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use argh::FromArgs;
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::sync::RwLock;
-use tokio::task::JoinHandle;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, RwLock, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
 use tower_lsp::{
-    lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, Url, WorkspaceFolder},
+    jsonrpc,
+    lsp_types::{
+        notification::{DidChangeWatchedFiles, Notification},
+        CodeAction, CodeActionKind, Diagnostic, DiagnosticSeverity,
+        DidChangeWatchedFilesRegistrationOptions, FileSystemWatcher, GlobPattern, InitializeParams,
+        MessageType, NumberOrString, Position, PositionEncodingKind, Range, Registration, TextEdit,
+        Url, WorkspaceEdit, WorkspaceFolder,
+    },
     Client, LspService, Server,
 };
-use tracing_subscriber::fmt::format::FmtSpan;
+use tracing::Instrument;
 
+#[cfg(feature = "bacon")]
+use bacon::{Bacon, BaconLockStatus, Export, PreferencesTarget, RestartPolicy};
+use code_action::CodeActionSupport;
+use counts::DIAGNOSTIC_COUNTS_METHOD;
+use custom::{CargoEvents, CustomCommand, CustomParser};
+use debug_info::{DebugInfo, DEBUG_INFO_METHOD};
+use documents::{Documents, Shift};
+use error::{Report, Result};
+use fnv::FnvHasher;
+use gc::DEFAULT_BUILD_FOLDER_MAX_AGE_DAYS;
+use line_format::LineFormat;
+use log::{configure_tracing, LogSettings, SET_TRACE_METHOD};
+use messages::{Locale, Message};
+use overlay::{UnsavedCheck, UNSAVED_PROVIDER};
+use progress::{WorkProgress, WORK_DONE_PROGRESS_CANCEL_METHOD};
+use provider::{DiagnosticsProvider, RunQueue};
+use publisher::Publisher;
+use run_id::RunId;
+#[cfg(feature = "runnables")]
+use runnables::{Runnables, TestRunner, TEST_PROVIDER};
+use rust_project::RUST_PROJECT_FILE;
+use store::{DiagnosticsStore, FileDiagnostics, BACON_SOURCE};
+use usage::{CommandLimits, RunUsage, TIMINGS_METHOD};
+
+mod analysis;
+mod ansi;
+mod audit;
+#[cfg(feature = "bacon")]
 mod bacon;
+mod cli;
+mod code_action;
+mod counts;
+mod custom;
+mod debug_info;
+mod deny;
+mod documents;
+mod error;
+mod explain;
+mod fix_all;
+mod fnv;
+mod gc;
+mod hover;
+#[cfg(feature = "bacon")]
+mod install_bacon;
+mod line_format;
+mod log;
 mod lsp;
+mod matrix;
+mod messages;
+mod miri;
+mod overlay;
+mod path_mapping;
+mod pipe;
+mod pipeline;
+mod precommit;
+mod progress;
+mod provider;
+mod publisher;
+mod revision;
+mod run_id;
+#[cfg(feature = "runnables")]
+mod runnables;
+mod rust_project;
+mod rustfmt;
+#[cfg(feature = "bacon")]
+mod select_job;
+#[cfg(feature = "self-update")]
+mod self_update;
+mod socket;
+mod store;
+mod target_dir;
+mod typos;
+mod unused_deps;
+mod usage;
+mod watch;
+
+pub use error::BaconLsError;
+pub use gc::gc;
+pub use precommit::precommit;
+#[cfg(feature = "self-update")]
+pub use self_update::self_update;
+pub use watch::watch;
 
 const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 const LOCATIONS_FILE: &str = ".bacon-locations";
+/// Target directory of the cargo commands run by bacon-ls, inside the one of the project.
+const DEFAULT_CARGO_TARGET_DIR: &str = "target/bacon-ls";
+const PROC_MACRO_PANIC_CODE: &str = "proc-macro-panic";
 const BACON_BACKGROUND_COMMAND_ARGS: &str = "--headless -j bacon-ls";
+/// Job run by bacon for bacon-ls, exporting the locations file.
+const BACON_JOB: &str = "bacon-ls";
+const STATUS_URI: &str = "bacon-ls://status";
+/// Time without any write after which a locations file ending with an incomplete line, or an
+/// incomplete analysis, is considered entirely written.
+const LOCATIONS_QUIESCENCE: Duration = Duration::from_millis(500);
+/// Interval between the checks of the instance running bacon for a folder, to take over once
+/// it exits.
+#[cfg(feature = "bacon")]
+const BACON_TAKEOVER_INTERVAL: Duration = Duration::from_secs(5);
+/// Environment of the child processes whose output is parsed, which must not depend on the
+/// user locale or terminal.
+const PARSED_OUTPUT_ENV: [(&str, &str); 3] = [
+    ("LC_ALL", "C"),
+    ("CARGO_TERM_COLOR", "never"),
+    ("NO_COLOR", "1"),
+];
+const STATUS_PROVIDER: &str = "status";
+const SERVER_STATUS_METHOD: &str = "bacon-ls/serverStatus";
+const FALLBACK_PROVIDER: &str = "fallback";
+const DORMANT_ORIGIN: &str = "dormant";
+const FALLBACK_COMMAND_ARGS: [&str; 4] = [
+    "clippy",
+    "--all-targets",
+    "--message-format",
+    "json-diagnostic-rendered-ansi",
+];
+/// Files which change the build without being Rust sources open in the editor.
+const BUILD_FILES: [&str; 3] = ["Cargo.toml", "Cargo.lock", "build.rs"];
+
+/// Build a command whose output is parsed by bacon-ls rather than shown to the user.
+pub(crate) fn parsed_output_command(program: impl AsRef<OsStr>) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(program);
+    command.envs(PARSED_OUTPUT_ENV);
+    command
+}
+
+/// Name of a workspace folder in the messages, its last component.
+fn folder_name(folder_path: &Path) -> String {
+    folder_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| folder_path.display().to_string())
+}
+
+/// Run git in the folder, returning its trimmed output.
+pub(crate) async fn git(folder_path: &Path, args: &[&str]) -> Result<String> {
+    let output = usage::output(
+        parsed_output_command("git")
+            .args(args)
+            .current_dir(folder_path),
+    )
+    .await
+    .map_err(|e| BaconLsError::Spawn(format!("failed to run git: {e}")))?;
+    if !output.status.success() {
+        return Err(BaconLsError::Spawn(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
 /// bacon-ls - https://github.com/crisidev/bacon-ls
 #[derive(Debug, FromArgs)]
@@ -31,23 +187,213 @@ pub struct Args {
     /// display version information
     #[argh(switch, short = 'v')]
     pub version: bool,
+    /// number of worker threads of the async runtime (default: number of CPUs)
+    #[argh(option)]
+    pub worker_threads: Option<usize>,
+    /// maximum number of threads used for blocking operations (default: 512)
+    #[argh(option)]
+    pub blocking_threads: Option<usize>,
+    /// talk to the client over the named pipe (Windows) or the socket file (other platforms)
+    /// it created, rather than over stdio
+    #[argh(option)]
+    pub pipe: Option<String>,
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Args {
+    /// Parse the command line arguments, accepting `--pipe=name` like `--pipe name`.
+    pub fn from_env() -> Self {
+        let mut args = env::args().flat_map(pipe::split_pipe_arg);
+        let command = args.next().unwrap_or_else(|| PKG_NAME.to_string());
+        let args: Vec<String> = args.collect();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        Self::from_args(&[&command], &args).unwrap_or_else(|early_exit| match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                std::process::exit(0);
+            }
+            Err(()) => {
+                eprintln!(
+                    "{}\nRun {command} --help for more information.",
+                    early_exit.output
+                );
+                std::process::exit(1);
+            }
+        })
+    }
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Gc(GcArgs),
+    Precommit(PrecommitArgs),
+    #[cfg(feature = "self-update")]
+    SelfUpdate(SelfUpdateArgs),
+    Watch(WatchArgs),
+}
+
+/// remove the build folders of bacon-ls not used for some days
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "gc")]
+pub struct GcArgs {
+    /// number of days after which an unused build folder is removed (default: 30)
+    #[argh(option)]
+    pub max_age_days: Option<u64>,
+}
+
+/// check the Rust files staged in git, printing the findings and failing on errors
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "precommit")]
+pub struct PrecommitArgs {
+    /// JSON file with the settings of the client, like `minimumSeverity`, `ignoredLints`,
+    /// `allowedLints` and `cargoEnv`
+    #[argh(option)]
+    pub settings: Option<PathBuf>,
+}
+
+/// run bacon and print its diagnostics each time they change, filtered like in the editor
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "watch")]
+pub struct WatchArgs {
+    /// JSON file with the settings of the client, like `minimumSeverity`, `ignoredLints`,
+    /// `allowedLints`, `cargoEnv`, `maxCommandOutputMb` and `locationsFile`
+    #[argh(option)]
+    pub settings: Option<PathBuf>,
+    /// file where the number of errors, warnings and files with diagnostics is written as
+    /// JSON after each update
+    #[argh(option)]
+    pub status_file: Option<PathBuf>,
+}
+
+/// replace bacon-ls with the latest binary published on GitHub releases
+#[cfg(feature = "self-update")]
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "self-update")]
+pub struct SelfUpdateArgs {
+    /// install the latest release even if it is older than the running version
+    #[argh(switch)]
+    pub force: bool,
 }
 
 #[derive(Debug)]
 struct State {
     workspace_folders: Option<Vec<WorkspaceFolder>>,
     locations_file: String,
+    /// True if `locationsFile` is set, rather than read from the bacon preferences.
+    locations_file_configured: bool,
+    /// Fields of the lines of the locations file, from the bacon preferences.
+    line_format: LineFormat,
+    /// Socket where bacon streams its analysis, replacing the locations file.
+    bacon_socket: Option<String>,
+    /// Jobs run by bacon, the first one exporting the locations file and the others a locations
+    /// file suffixed with their name.
+    bacon_jobs: Vec<String>,
+    /// Whether bacon is not installed, with cargo running on save instead.
+    bacon_missing: bool,
     update_on_save: bool,
     update_on_save_wait_millis: Duration,
     update_on_change: bool,
+    update_on_change_cooldown_millis: Duration,
+    /// Maximum number of workspace folders checked at the same time.
+    folder_parallelism: usize,
     validate_bacon_preferences: bool,
     run_bacon_in_background: bool,
     run_bacon_in_background_command_args: String,
+    #[cfg(feature = "bacon")]
+    bacon_restart_policy: RestartPolicy,
     create_bacon_preferences_file: bool,
-    bacon_command_handle: Option<JoinHandle<()>>,
+    #[cfg(feature = "bacon")]
+    preferences_creation_target: PreferencesTarget,
     syncronize_all_open_files_wait_millis: Duration,
-    diagnostics_data_supported: bool,
     open_files: HashSet<Url>,
+    position_encoding: PositionEncodingKind,
+    #[cfg(feature = "runnables")]
+    test_runner: TestRunner,
+    /// Diagnostics of bacon and of the providers.
+    diagnostics_store: DiagnosticsStore,
+    providers: Vec<Arc<dyn DiagnosticsProvider>>,
+    publish_trace_file: Option<PathBuf>,
+    publish_status_diagnostics: bool,
+    /// True if the client handles `bacon-ls/serverStatus` notifications.
+    server_status_notification: bool,
+    /// True if the client can show documents with `window/showDocument`.
+    show_document: bool,
+    explain_on_hover: bool,
+    /// True if the folders with unsaved files are checked as they are changed.
+    check_unsaved_files: bool,
+    /// Content of the open files.
+    documents: Documents,
+    /// Open files changed since they were saved, when `checkUnsavedFiles` is enabled.
+    unsaved_files: HashSet<Url>,
+    /// Workspace folders whose bacon diagnostics are replaced by the ones of their unsaved
+    /// files.
+    unsaved_folders: HashSet<PathBuf>,
+    /// True if the transitions of the project between failing and clean are shown.
+    notify_build_status: bool,
+    /// Whether the project had errors at the last check of its build status, unknown before
+    /// the first one.
+    build_failing: Option<bool>,
+    /// True if the `bacon-ls.runMiri` command is enabled.
+    miri: bool,
+    /// Problems of the whole run which don't belong to a source file, by origin.
+    status_problems: BTreeMap<String, Vec<String>>,
+    diagnostics_filter: DiagnosticsFilter,
+    /// Number of consecutive saves without a locations file update before falling back to a
+    /// one-shot cargo run, disabled if zero.
+    bacon_fallback_after_saves: u32,
+    /// State of each workspace folder, by folder path.
+    projects: BTreeMap<PathBuf, Project>,
+    dormant_without_cargo_project: bool,
+    publish_all_files: bool,
+    publish_all_files_max: usize,
+    /// True while waiting for a Cargo project to be created in the workspace.
+    dormant: bool,
+    publisher: Publisher,
+    /// Parsed locations file of each workspace folder, locked apart from the state while the
+    /// appended lines are read.
+    locations_indexes: Arc<Mutex<HashMap<PathBuf, LocationsIndex>>>,
+    settings_digest: String,
+    debug_info: Option<DebugInfo>,
+    log_settings: LogSettings,
+    code_action_support: CodeActionSupport,
+    /// Language of the messages shown to the user.
+    locale: Locale,
+    /// Source of the published diagnostics.
+    diagnostic_source: String,
+    /// True if the client can watch the build files and the locations files for the server.
+    watch_files: bool,
+    /// True once the client watches the locations files, which are not polled anymore.
+    locations_watched: bool,
+    /// Files saved since the scheduled update was queued.
+    pending_saves: HashSet<Url>,
+    /// Time of the scheduled update, postponed by each save.
+    save_deadline: tokio::time::Instant,
+    /// Time of the scheduled publish of each changed document, postponed by each change.
+    change_deadlines: HashMap<Url, tokio::time::Instant>,
+    /// Resources used by the last run of each provider.
+    run_usages: BTreeMap<&'static str, RunUsage>,
+    /// True if the diagnostics of a file stay published after it is closed.
+    keep_diagnostics_on_close: bool,
+    /// Environment of the cargo commands, including bacon.
+    cargo_env: HashMap<String, String>,
+    /// Target directory of the cargo commands, relative to the Cargo workspace, so they don't
+    /// share the build lock and cache with the other builds. Empty to use the one of cargo.
+    cargo_target_dir: String,
+    /// True if bacon also uses the target directory of the cargo commands.
+    bacon_cargo_target_dir: bool,
+    /// Folder of the checkouts of the revision checks, relative to the workspace folder,
+    /// defaulting to a folder of the project in the cache directory.
+    build_folder: Option<String>,
+    /// Number of days after which the unused build folders are removed, zero to keep them.
+    build_folder_max_age_days: u64,
+    /// Build folder of this session in the temporary directory, removed on shutdown.
+    temporary_build_folder: Option<PathBuf>,
+    /// Reasons of the cargo JSON messages forwarded as `bacon-ls/cargoEvent` notifications.
+    cargo_events: Vec<String>,
+    /// Limits of the commands run by the server.
+    command_limits: CommandLimits,
 }
 
 impl Default for State {
@@ -55,24 +401,519 @@ impl Default for State {
         Self {
             workspace_folders: None,
             locations_file: LOCATIONS_FILE.to_string(),
+            locations_file_configured: false,
+            line_format: LineFormat::default(),
+            bacon_socket: None,
+            bacon_jobs: vec![BACON_JOB.to_string()],
+            bacon_missing: false,
             update_on_save: true,
             update_on_save_wait_millis: Duration::from_millis(1000),
             update_on_change: true,
+            update_on_change_cooldown_millis: Duration::from_millis(500),
+            folder_parallelism: 2,
             validate_bacon_preferences: true,
             run_bacon_in_background: true,
             run_bacon_in_background_command_args: BACON_BACKGROUND_COMMAND_ARGS.to_string(),
+            #[cfg(feature = "bacon")]
+            bacon_restart_policy: RestartPolicy::default(),
             create_bacon_preferences_file: true,
-            bacon_command_handle: None,
+            #[cfg(feature = "bacon")]
+            preferences_creation_target: PreferencesTarget::default(),
             syncronize_all_open_files_wait_millis: Duration::from_millis(2000),
-            diagnostics_data_supported: false,
             open_files: HashSet::new(),
+            position_encoding: PositionEncodingKind::UTF16,
+            #[cfg(feature = "runnables")]
+            test_runner: TestRunner::default(),
+            diagnostics_store: DiagnosticsStore::default(),
+            providers: Vec::new(),
+            publish_trace_file: None,
+            publish_status_diagnostics: false,
+            server_status_notification: false,
+            show_document: false,
+            explain_on_hover: false,
+            check_unsaved_files: false,
+            documents: Documents::default(),
+            unsaved_files: HashSet::new(),
+            unsaved_folders: HashSet::new(),
+            notify_build_status: false,
+            build_failing: None,
+            miri: false,
+            status_problems: BTreeMap::new(),
+            diagnostics_filter: DiagnosticsFilter::default(),
+            bacon_fallback_after_saves: 0,
+            projects: BTreeMap::new(),
+            dormant_without_cargo_project: true,
+            publish_all_files: false,
+            publish_all_files_max: 500,
+            dormant: false,
+            publisher: Publisher::default(),
+            locations_indexes: Arc::default(),
+            settings_digest: String::new(),
+            debug_info: None,
+            log_settings: LogSettings::default(),
+            code_action_support: CodeActionSupport::default(),
+            locale: Locale::default(),
+            diagnostic_source: PKG_NAME.to_string(),
+            watch_files: false,
+            locations_watched: false,
+            pending_saves: HashSet::new(),
+            save_deadline: tokio::time::Instant::now(),
+            change_deadlines: HashMap::new(),
+            run_usages: BTreeMap::new(),
+            keep_diagnostics_on_close: true,
+            cargo_env: HashMap::new(),
+            cargo_target_dir: DEFAULT_CARGO_TARGET_DIR.to_string(),
+            bacon_cargo_target_dir: false,
+            build_folder: None,
+            build_folder_max_age_days: DEFAULT_BUILD_FOLDER_MAX_AGE_DAYS,
+            temporary_build_folder: None,
+            cargo_events: Vec::new(),
+            command_limits: CommandLimits::default(),
+        }
+    }
+}
+
+impl State {
+    /// Source of the diagnostics of a file published as the bacon ones, which are replaced by
+    /// the ones of the unsaved files of its folder, if any.
+    fn bacon_source(&self, uri: &Url) -> &'static str {
+        match BaconLs::workspace_folder_path(uri, self.workspace_folders.as_deref()) {
+            Some(folder_path) if self.unsaved_folders.contains(&folder_path) => UNSAVED_PROVIDER,
+            _ => BACON_SOURCE,
+        }
+    }
+
+    /// Diagnostics of the other sources than the bacon ones of a file.
+    fn other_sources<'a>(
+        file: &'a FileDiagnostics,
+    ) -> impl Iterator<Item = (&'static str, &'a Diagnostic)> + 'a {
+        file.other_sources(BACON_SOURCE)
+            .filter(|(source, _)| *source != UNSAVED_PROVIDER)
+    }
+
+    /// Diagnostics of the other sources than the bacon ones of a file, with their source. The
+    /// ones of the fallback run stand in for the bacon ones, so they are filtered like them.
+    fn provider_diagnostics(&self, file: &FileDiagnostics) -> Vec<(&'static str, Diagnostic)> {
+        let fallback = self
+            .diagnostics_filter
+            .apply(file.source(FALLBACK_PROVIDER).to_vec());
+        fallback
+            .into_iter()
+            .map(|diagnostic| (FALLBACK_PROVIDER, diagnostic))
+            .chain(
+                Self::other_sources(file)
+                    .filter(|(source, _)| *source != FALLBACK_PROVIDER)
+                    .map(|(source, diagnostic)| (source, diagnostic.clone())),
+            )
+            .collect()
+    }
+
+    /// Locations file of each bacon job.
+    fn jobs_locations_files(&self) -> Vec<(String, String)> {
+        self.bacon_jobs
+            .iter()
+            .enumerate()
+            .map(|(index, job)| {
+                let locations_file = match index {
+                    0 => self.locations_file.clone(),
+                    _ => format!("{}-{job}", self.locations_file),
+                };
+                (job.clone(), locations_file)
+            })
+            .collect()
+    }
+
+    /// State of a workspace folder, created the first time it's needed.
+    fn project(&mut self, folder_path: &Path) -> &mut Project {
+        let fallback_after_saves = self.bacon_fallback_after_saves;
+        self.projects
+            .entry(folder_path.to_path_buf())
+            .or_insert_with(|| Project {
+                bacon_health: BaconHealth {
+                    fallback_after_saves,
+                    ..BaconHealth::default()
+                },
+                ..Project::default()
+            })
+    }
+}
+
+/// State of a workspace folder, so each Cargo project of a multi-root workspace has its own
+/// bacon and is checked independently of the others.
+#[derive(Debug, Default)]
+struct Project {
+    bacon_command_handle: Option<JoinHandle<()>>,
+    /// Bacon running the jobs other than the first one.
+    bacon_job_handles: Vec<JoinHandle<()>>,
+    /// Task following the analysis streamed by bacon on `baconSocket`.
+    bacon_socket_handle: Option<JoinHandle<()>>,
+    /// Task starting bacon once the instance running it for the folder exits.
+    bacon_takeover_handle: Option<JoinHandle<()>>,
+    /// Target directory set for the cargo commands, resolved on first use, if cargo doesn't
+    /// already get one.
+    cargo_target_dir: Option<Option<PathBuf>>,
+    bacon_health: BaconHealth,
+    /// Modification time of the locations file at the previous synchronization.
+    locations_last_modified: Option<SystemTime>,
+    run_queue: Arc<RunQueue>,
+    /// Overlay of the folder, held while checking its unsaved files.
+    unsaved_check: Arc<Mutex<UnsavedCheck>>,
+}
+
+/// Notification of the server health, sent only to clients supporting it with the
+/// `experimental.serverStatusNotification` capability, like the companion VS Code extension.
+enum ServerStatus {}
+
+impl Notification for ServerStatus {
+    type Params = ServerStatusParams;
+    const METHOD: &'static str = SERVER_STATUS_METHOD;
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct ServerStatusParams {
+    health: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+/// User configured filtering of the diagnostics by severity and lint name.
+#[derive(Debug, Default, Clone)]
+struct DiagnosticsFilter {
+    minimum_severity: Option<DiagnosticSeverity>,
+    ignored_lints: Vec<String>,
+    allowed_lints: Vec<String>,
+}
+
+impl DiagnosticsFilter {
+    /// Parse the `minimumSeverity`, `ignoredLints` and `allowedLints` settings.
+    fn parse(values: &serde_json::Map<String, serde_json::Value>) -> jsonrpc::Result<Self> {
+        let mut filter = Self::default();
+        if let Some(value) = values.get("minimumSeverity") {
+            filter.minimum_severity = Some(
+                value
+                    .as_str()
+                    .and_then(BaconLs::parse_severity_setting)
+                    .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?,
+            );
+        }
+        if let Some(value) = values.get("ignoredLints") {
+            filter.ignored_lints = BaconLs::parse_string_list(value)?;
+        }
+        if let Some(value) = values.get("allowedLints") {
+            filter.allowed_lints = BaconLs::parse_string_list(value)?;
+        }
+        Ok(filter)
+    }
+
+    fn is_lint_allowed(&self, lint: &str) -> bool {
+        !self.ignored_lints.iter().any(|ignored| ignored == lint)
+            && (self.allowed_lints.is_empty()
+                || self.allowed_lints.iter().any(|allowed| allowed == lint))
+    }
+
+    /// Filter the diagnostics of a file. Help and note diagnostics following a lint inherit its
+    /// name, so they are hidden together with it.
+    fn apply(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        let mut parent_lint: Option<String> = None;
+        diagnostics
+            .into_iter()
+            .filter(|diagnostic| {
+                let is_child = matches!(
+                    diagnostic.severity,
+                    Some(DiagnosticSeverity::HINT | DiagnosticSeverity::INFORMATION)
+                );
+                let lint = match &diagnostic.code {
+                    Some(NumberOrString::String(code)) if code != PROC_MACRO_PANIC_CODE => {
+                        Some(code.clone())
+                    }
+                    _ if is_child => parent_lint.clone(),
+                    _ => None,
+                };
+                if !is_child {
+                    parent_lint = lint.clone();
+                }
+                let severity_allowed = match (self.minimum_severity, diagnostic.severity) {
+                    (Some(minimum), Some(severity)) => severity <= minimum,
+                    _ => true,
+                };
+                severity_allowed && lint.is_none_or(|lint| self.is_lint_allowed(&lint))
+            })
+            .collect()
+    }
+}
+
+/// Tracking of the locations file updates across saves, to detect a bacon which stopped
+/// producing diagnostics.
+#[derive(Debug, Default)]
+struct BaconHealth {
+    /// Number of consecutive saves without a locations file update before falling back to a
+    /// one-shot cargo run, disabled if zero.
+    fallback_after_saves: u32,
+    saves_without_update: u32,
+    last_modified: Option<SystemTime>,
+}
+
+impl BaconHealth {
+    /// Record a save and the current modification time of the locations file, returning true if
+    /// the fallback must run.
+    fn record_save(&mut self, modified: Option<SystemTime>) -> bool {
+        if modified.is_some() && modified != self.last_modified {
+            self.saves_without_update = 0;
+        } else {
+            self.saves_without_update += 1;
+        }
+        self.last_modified = modified;
+        self.is_failing()
+    }
+
+    fn is_failing(&self) -> bool {
+        self.fallback_after_saves > 0 && self.saves_without_update >= self.fallback_after_saves
+    }
+}
+
+/// Diagnostics parsed from a locations file. Bacon appends the diagnostics while a job runs and
+/// rewrites the file for the next one, so only the appended lines are parsed unless the file
+/// was rewritten. A JSON analysis is a single document, always parsed entirely.
+#[derive(Debug, Default)]
+struct LocationsIndex {
+    modified: Option<SystemTime>,
+    len: u64,
+    /// Length of the complete lines already parsed.
+    offset: u64,
+    /// Hash of the bytes before the offset, which no longer match if the file was rewritten.
+    parsed_hash: FnvHasher,
+    /// Lines of the last entry, which can still be continued by the next lines.
+    buffer: String,
+    /// Line being written by bacon, without the line terminator yet.
+    partial: String,
+    /// False while the partial line may still be written, in which case it is not parsed.
+    settled: bool,
+    entries: Vec<(Url, Diagnostic)>,
+    line_format: LineFormat,
+}
+
+impl LocationsIndex {
+    fn new(line_format: LineFormat) -> Self {
+        Self {
+            line_format,
+            ..Self::default()
+        }
+    }
+
+    /// Replace the diagnostics with the ones received from bacon rather than parsed from the
+    /// file.
+    fn set_entries(&mut self, entries: Vec<(Url, Diagnostic)>) {
+        *self = Self {
+            entries,
+            ..Self::new(self.line_format.clone())
+        };
+    }
+
+    fn push_line(
+        buffer: &mut String,
+        entries: &mut Vec<(Url, Diagnostic)>,
+        line: &str,
+        folder_path: &Path,
+        line_format: &LineFormat,
+    ) {
+        let trimmed = line.trim_end();
+        if line_format.starts_entry(trimmed) && !buffer.is_empty() {
+            // Process the collected buffer before starting a new entry
+            entries.extend(BaconLs::parse_bacon_diagnostic_line(
+                buffer,
+                folder_path,
+                line_format,
+            ));
+            buffer.clear();
+        }
+        if !buffer.is_empty() {
+            buffer.push('\n'); // Preserve multiline structure
+        }
+        buffer.push_str(trimmed);
+    }
+
+    /// Bring the index up to date with the locations file, returning true if it changed.
+    #[tracing::instrument(level = "debug", name = "parse", skip_all, fields(file = %bacon_locations.display()))]
+    async fn refresh(&mut self, bacon_locations: &Path, folder_path: &Path) -> bool {
+        let file = match File::open(bacon_locations).await {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::error!("unable to read file {}: {e}", bacon_locations.display());
+                let changed = self.len > 0;
+                *self = Self::new(self.line_format.clone());
+                return changed;
+            }
+        };
+        let metadata = file.metadata().await.ok();
+        let modified = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok());
+        let len = metadata.as_ref().map(|metadata| metadata.len());
+        if modified.is_some() && modified == self.modified && len == Some(self.len) {
+            // The partial line is complete once bacon stopped writing.
+            if !self.settled && Self::is_quiet(modified) {
+                self.settled = true;
+                return true;
+            }
+            return false;
+        }
+        let mut file = file;
+        let rewritten = !self.is_appended(&mut file, len).await;
+        if rewritten {
+            if let Err(e) = file.seek(SeekFrom::Start(0)).await {
+                tracing::error!("unable to read file {}: {e}", bacon_locations.display());
+                *self = Self::new(self.line_format.clone());
+                return true;
+            }
+        }
+        let mut content = Vec::new();
+        if let Err(e) = file.read_to_end(&mut content).await {
+            tracing::error!(
+                "error reading line from file {}: {e}",
+                bacon_locations.display()
+            );
+        }
+        if rewritten && analysis::is_analysis(&content) {
+            let (entries, complete) = analysis::parse_analysis(&content, folder_path);
+            // The previous diagnostics are kept until the analysis is entirely written.
+            if !complete && !Self::is_quiet(modified) {
+                tracing::debug!("{} is being written", bacon_locations.display());
+                return false;
+            }
+            *self = Self {
+                entries,
+                modified,
+                len: content.len() as u64,
+                settled: true,
+                ..Self::new(self.line_format.clone())
+            };
+            return true;
+        }
+        if rewritten {
+            *self = Self::new(self.line_format.clone());
+        }
+        let consumed = content
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |position| position + 1);
+        for line in String::from_utf8_lossy(&content[..consumed]).lines() {
+            Self::push_line(
+                &mut self.buffer,
+                &mut self.entries,
+                line,
+                folder_path,
+                &self.line_format,
+            );
+        }
+        self.partial = String::from_utf8_lossy(&content[consumed..]).into_owned();
+        self.offset += consumed as u64;
+        self.parsed_hash.write(&content[..consumed]);
+        self.settled = self.partial.is_empty() || Self::is_quiet(modified);
+        self.modified = modified;
+        self.len = self.offset + self.partial.len() as u64;
+        true
+    }
+
+    /// Return true if the file was not written for the quiescence window.
+    fn is_quiet(modified: Option<SystemTime>) -> bool {
+        modified
+            .and_then(|modified| modified.elapsed().ok())
+            .is_none_or(|elapsed| elapsed >= LOCATIONS_QUIESCENCE)
+    }
+
+    /// Return true if the file still starts with the parsed content, leaving the file at the
+    /// offset to resume parsing from.
+    async fn is_appended(&self, file: &mut File, len: Option<u64>) -> bool {
+        if self.offset == 0 || len.is_none_or(|len| len < self.offset) {
+            return false;
+        }
+        // A rewrite can keep the length and the last lines, like a diagnostic moved by a line,
+        // so the whole parsed content is compared.
+        let mut parsed = vec![0; self.offset as usize];
+        file.seek(SeekFrom::Start(0)).await.is_ok()
+            && file.read_exact(&mut parsed).await.is_ok()
+            && FnvHasher::hash(&parsed) == self.parsed_hash.finish()
+    }
+
+    /// Append the diagnostics of each file to the diagnostics of the previous workspace folders.
+    #[tracing::instrument(level = "debug", name = "dedupe", skip_all)]
+    fn diagnostics(&self, folder_path: &Path, by_file: &mut HashMap<Url, Vec<(Url, Diagnostic)>>) {
+        // The last entry is complete once the file is entirely written, while a partial line
+        // still being written would be truncated.
+        let mut buffer = self.buffer.clone();
+        let mut last_entries = Vec::new();
+        if !self.partial.is_empty() && self.settled {
+            Self::push_line(
+                &mut buffer,
+                &mut last_entries,
+                &self.partial,
+                folder_path,
+                &self.line_format,
+            );
+        }
+        if !buffer.is_empty() {
+            last_entries.extend(BaconLs::parse_bacon_diagnostic_line(
+                &buffer,
+                folder_path,
+                &self.line_format,
+            ));
+        }
+        // The help of a lint follows it without the notes naming the lint, so it inherits it.
+        let mut lint = None;
+        // Index of the diagnostic the helps belong to, and the one of the last diagnostic kept
+        // for each file, as only the spans of the same help are merged.
+        let mut parent = 0;
+        let mut last_parents: HashMap<&Url, usize> = HashMap::new();
+        for (index, (path, diagnostic)) in self.entries.iter().chain(&last_entries).enumerate() {
+            let mut diagnostic = diagnostic.clone();
+            if diagnostic.severity != Some(DiagnosticSeverity::HINT) {
+                lint = diagnostic.code.clone();
+                parent = index;
+            } else if diagnostic.code.is_none() {
+                diagnostic.code = lint.clone();
+            }
+            let mergeable = last_parents.get(path) == Some(&parent);
+            if BaconLs::deduplicate_diagnostics(
+                path.clone(),
+                Some(path),
+                diagnostic,
+                by_file.entry(path.clone()).or_default(),
+                mergeable,
+            ) {
+                last_parents.insert(path, parent);
+            }
         }
     }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct DiagnosticData<'c> {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     corrections: Vec<Cow<'c, str>>,
+    /// Replacements spanning multiple locations which must be applied together.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    edits: Vec<DiagnosticEdit<'c>>,
+    /// Title of the code action, when it doesn't replace the code with a clippy suggestion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<Cow<'c, str>>,
+    /// Full rendered output of the compiler, with its code frame and notes, shown on hover
+    /// while the message only keeps its first line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rendered: Option<Cow<'c, str>>,
+}
+
+impl DiagnosticData<'_> {
+    /// Rendered output stored in the data of the diagnostic, if any.
+    fn rendered(diagnostic: &Diagnostic) -> Option<String> {
+        let data: DiagnosticData = serde_json::from_value(diagnostic.data.clone()?).ok()?;
+        data.rendered.map(Cow::into_owned)
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct DiagnosticEdit<'c> {
+    range: Range,
+    new_text: Cow<'c, str>,
 }
 
 #[derive(Debug, Default)]
@@ -89,129 +930,166 @@ impl BaconLs {
         }
     }
 
-    fn configure_tracing(log_level: Option<String>) {
-        // Configure logging to file.
-        let level = log_level.unwrap_or_else(|| env::var("RUST_LOG").unwrap_or("off".to_string()));
-        if level != "off" {
-            tracing_subscriber::fmt()
-                .with_env_filter(level)
-                .with_writer(
-                    std::fs::OpenOptions::new()
-                        .create(true)
-                        .write(true)
-                        .truncate(true)
-                        .open(format!("{PKG_NAME}.log"))
-                        .unwrap(),
-                )
-                .with_thread_names(true)
-                .with_span_events(FmtSpan::CLOSE)
-                .with_line_number(true)
-                .with_target(false)
-                .compact()
-                .init();
+    /// Run the LSP server, serving the client over stdio or over the pipe it created, if any.
+    pub async fn serve(pipe: Option<String>) -> Result<()> {
+        configure_tracing(env::var("RUST_LOG").unwrap_or("off".to_string()));
+        // Start the service.
+        let (service, socket) = LspService::build(Self::new)
+            .custom_method(DEBUG_INFO_METHOD, Self::debug_info)
+            .custom_method(DIAGNOSTIC_COUNTS_METHOD, Self::diagnostic_counts)
+            .custom_method(SET_TRACE_METHOD, Self::set_trace)
+            .custom_method(TIMINGS_METHOD, Self::timings)
+            .custom_method(
+                WORK_DONE_PROGRESS_CANCEL_METHOD,
+                Self::work_done_progress_cancel,
+            )
+            .finish();
+        match pipe {
+            Some(name) => {
+                let (read, write) = pipe::connect(&name).await.map_err(|e| {
+                    BaconLsError::io(format!("error connecting to the pipe {name}"), e)
+                })?;
+                Server::new(read, write, socket).serve(service).await;
+            }
+            None => {
+                // Lock stdin / stdout.
+                let stdin = tokio::io::stdin();
+                let stdout = tokio::io::stdout();
+                Server::new(stdin, stdout, socket).serve(service).await;
+            }
         }
+        Ok(())
     }
 
-    /// Run the LSP server.
-    pub async fn serve() {
-        Self::configure_tracing(None);
-        // Lock stdin / stdout.
-        let stdin = tokio::io::stdin();
-        let stdout = tokio::io::stdout();
-        // Start the service.
-        let (service, socket) = LspService::new(Self::new);
-        Server::new(stdin, stdout, socket).serve(service).await;
+    /// Bring the indexes of the locations files up to date, returning true if any changed. Only
+    /// the lines appended by bacon since the previous refresh are parsed.
+    async fn refresh_locations_indexes(
+        indexes: &mut HashMap<PathBuf, LocationsIndex>,
+        locations_file: &str,
+        line_format: &LineFormat,
+        workspace_folders: Option<&[WorkspaceFolder]>,
+    ) -> bool {
+        let mut changed = false;
+        for folder in workspace_folders.unwrap_or_default() {
+            let folder_path = Path::new(folder.uri.path());
+            let bacon_locations = folder_path.join(locations_file);
+            let index = indexes
+                .entry(bacon_locations.clone())
+                .or_insert_with(|| LocationsIndex::new(line_format.clone()));
+            // The lines are parsed again when the bacon preferences change the format.
+            if index.line_format != *line_format {
+                *index = LocationsIndex::new(line_format.clone());
+                changed = true;
+            }
+            changed |= index.refresh(&bacon_locations, folder_path).await;
+        }
+        changed
     }
 
-    async fn diagnostics(
-        uri: Option<&Url>,
+    /// Diagnostics of each file in the indexed locations files.
+    fn diagnostics(
+        indexes: &HashMap<PathBuf, LocationsIndex>,
         locations_file: &str,
         workspace_folders: Option<&[WorkspaceFolder]>,
-    ) -> Vec<(Url, Diagnostic)> {
-        let mut diagnostics: Vec<(Url, Diagnostic)> = vec![];
-
-        if let Some(workspace_folders) = workspace_folders {
-            for folder in workspace_folders.iter() {
-                let folder_path = Path::new(folder.uri.path());
-                let bacon_locations = folder_path.join(locations_file);
-
-                match File::open(&bacon_locations).await {
-                    Ok(fd) => {
-                        let reader = BufReader::new(fd);
-                        let mut lines = reader.lines();
-                        let mut buffer = String::new();
-
-                        while let Some(line) = lines.next_line().await.unwrap_or_else(|e| {
-                            tracing::error!(
-                                "error reading line from file {}: {e}",
-                                bacon_locations.display()
-                            );
-                            None
-                        }) {
-                            let trimmed = line.trim_end();
-
-                            // Use the first word to determine the start of a new diagnostic
-                            let is_new_diagnostic = trimmed.starts_with("warning")
-                                || trimmed.starts_with("error")
-                                || trimmed.starts_with("info")
-                                || trimmed.starts_with("note")
-                                || trimmed.starts_with("failure-note")
-                                || trimmed.starts_with("help");
-
-                            if is_new_diagnostic {
-                                // Process the collected buffer before starting a new entry
-                                if !buffer.is_empty() {
-                                    if let Some((path, diagnostic)) =
-                                        Self::parse_bacon_diagnostic_line(&buffer, folder_path)
-                                    {
-                                        Self::deduplicate_diagnostics(
-                                            path,
-                                            uri,
-                                            diagnostic,
-                                            &mut diagnostics,
-                                        );
-                                    }
-                                }
-                                // Reset buffer for new diagnostic entry
-                                buffer.clear();
-                            }
-
-                            // Append current line to buffer
-                            if !buffer.is_empty() {
-                                buffer.push('\n'); // Preserve multiline structure
-                            }
-                            buffer.push_str(trimmed);
-                        }
+    ) -> HashMap<Url, Vec<Diagnostic>> {
+        let mut by_file: HashMap<Url, Vec<(Url, Diagnostic)>> = HashMap::new();
+        for folder in workspace_folders.unwrap_or_default() {
+            let folder_path = Path::new(folder.uri.path());
+            if let Some(index) = indexes.get(&folder_path.join(locations_file)) {
+                index.diagnostics(folder_path, &mut by_file);
+            }
+        }
+        by_file
+            .into_iter()
+            .map(|(uri, diagnostics)| {
+                let diagnostics = diagnostics.into_iter().map(|(_, diagnostic)| diagnostic);
+                (uri, diagnostics.collect())
+            })
+            .collect()
+    }
 
-                        // Flush the remaining buffer after loop ends
-                        if !buffer.is_empty() {
-                            if let Some((path, diagnostic)) =
-                                Self::parse_bacon_diagnostic_line(&buffer, folder_path)
-                            {
-                                Self::deduplicate_diagnostics(
-                                    path,
-                                    uri,
-                                    diagnostic,
-                                    &mut diagnostics,
-                                );
-                            }
+    /// Diagnostics of each file in the locations files of the bacon jobs. With several jobs,
+    /// the diagnostics are tagged with the job reporting them first, the others being dropped.
+    fn jobs_diagnostics(
+        indexes: &HashMap<PathBuf, LocationsIndex>,
+        jobs_locations_files: &[(String, String)],
+        workspace_folders: Option<&[WorkspaceFolder]>,
+    ) -> HashMap<Url, Vec<Diagnostic>> {
+        let [(_, locations_file)] = jobs_locations_files else {
+            let mut by_file: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+            for (job, locations_file) in jobs_locations_files {
+                for (uri, diagnostics) in
+                    Self::diagnostics(indexes, locations_file, workspace_folders)
+                {
+                    let file_diagnostics = by_file.entry(uri).or_default();
+                    for diagnostic in diagnostics {
+                        if !file_diagnostics.iter().any(|existing| {
+                            existing.range == diagnostic.range
+                                && existing.severity == diagnostic.severity
+                                && existing.message == diagnostic.message
+                        }) {
+                            file_diagnostics.push(Diagnostic {
+                                source: Some(job.clone()),
+                                ..diagnostic
+                            });
                         }
                     }
-                    Err(e) => {
-                        tracing::error!("unable to read file {}: {e}", bacon_locations.display())
-                    }
                 }
             }
+            return by_file;
+        };
+        Self::diagnostics(indexes, locations_file, workspace_folders)
+    }
+
+    /// Write the bacon diagnostics to the store if the locations files changed, returning the
+    /// files whose diagnostics changed.
+    async fn update_bacon_diagnostics(state: &Arc<RwLock<State>>) -> Vec<Url> {
+        let read_state = state.read().await;
+        // The diagnostics streamed by bacon are stored as they are received.
+        if read_state.bacon_socket.is_some() {
+            return vec![];
         }
-        diagnostics
+        let jobs_locations_files = read_state.jobs_locations_files();
+        let line_format = read_state.line_format.clone();
+        let workspace_folders = read_state.workspace_folders.clone();
+        let locations_indexes = read_state.locations_indexes.clone();
+        drop(read_state);
+        let mut indexes = locations_indexes.lock().await;
+        let mut changed = false;
+        for (_, locations_file) in jobs_locations_files.iter() {
+            changed |= Self::refresh_locations_indexes(
+                &mut indexes,
+                locations_file,
+                &line_format,
+                workspace_folders.as_deref(),
+            )
+            .await;
+        }
+        if !changed {
+            return vec![];
+        }
+        let diagnostics = Self::jobs_diagnostics(
+            &indexes,
+            &jobs_locations_files,
+            workspace_folders.as_deref(),
+        );
+        drop(indexes);
+        state
+            .write()
+            .await
+            .diagnostics_store
+            .replace(BACON_SOURCE, diagnostics)
     }
 
+    /// Add a diagnostic of a file unless it is a duplicate, merging it into the last one if
+    /// `mergeable` and they are the spans of the same suggestion. Returns true if it was kept.
     fn deduplicate_diagnostics(
         path: Url,
         uri: Option<&Url>,
         diagnostic: Diagnostic,
         diagnostics: &mut Vec<(Url, Diagnostic)>,
-    ) {
+        mergeable: bool,
+    ) -> bool {
         if Some(&path) == uri
             && !diagnostics
                 .iter()
@@ -222,107 +1100,1791 @@ impl BaconLs {
                         && diagnostic.message == existing_diagnostic.message
                 })
         {
+            if let Some((last_path, last_diagnostic)) = diagnostics.last_mut() {
+                if mergeable
+                    && *last_path == path
+                    && Self::merge_multi_span_suggestion(last_diagnostic, &diagnostic)
+                {
+                    return true;
+                }
+            }
             diagnostics.push((path, diagnostic));
+            return true;
         }
+        false
     }
 
-    async fn diagnostics_vec(
-        uri: Option<&Url>,
-        locations_file: &str,
-        workspace_folders: Option<&[WorkspaceFolder]>,
-    ) -> Vec<Diagnostic> {
-        Self::diagnostics(uri, locations_file, workspace_folders)
-            .await
-            .into_iter()
-            .map(|(_, y)| y)
-            .collect::<Vec<Diagnostic>>()
+    /// Return the single replacement of a diagnostic together with its message stripped of
+    /// the replacement suffix.
+    fn suggestion(diagnostic: &Diagnostic) -> Option<(DiagnosticData<'static>, String)> {
+        let data: DiagnosticData = serde_json::from_value(diagnostic.data.clone()?).ok()?;
+        let correction = match data.corrections.as_slice() {
+            [correction] => correction,
+            _ => return None,
+        };
+        let message = diagnostic
+            .message
+            .strip_suffix(correction.as_ref())?
+            .strip_suffix(": ")?
+            .to_string();
+        Some((data, message))
     }
 
+    /// Bacon exports one line per span, so a suggestion touching several spans (e.g. adding an
+    /// import and changing a call) is received as consecutive diagnostics sharing the same
+    /// message and lint. Fold them into the first one so they are applied as a single edit.
+    ///
+    /// Returns true if the diagnostic was merged.
+    fn merge_multi_span_suggestion(existing: &mut Diagnostic, diagnostic: &Diagnostic) -> bool {
+        if existing.severity != diagnostic.severity
+            || existing.code != diagnostic.code
+            || existing.range == diagnostic.range
+        {
+            return false;
+        }
+        let (Some((mut existing_data, existing_message)), Some((data, message))) =
+            (Self::suggestion(existing), Self::suggestion(diagnostic))
+        else {
+            return false;
+        };
+        if existing_message != message {
+            return false;
+        }
+        if existing_data.edits.is_empty() {
+            existing_data.edits.push(DiagnosticEdit {
+                range: existing.range,
+                new_text: existing_data.corrections[0].clone(),
+            });
+        }
+        existing_data.edits.push(DiagnosticEdit {
+            range: diagnostic.range,
+            new_text: data.corrections[0].clone(),
+        });
+        existing.data = Some(serde_json::json!(existing_data));
+        true
+    }
+
+    #[tracing::instrument(level = "debug", name = "publish", skip_all, fields(%uri))]
     async fn publish_diagnostics(
         client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
         uri: &Url,
-        locations_file: &str,
-        workspace_folders: Option<&[WorkspaceFolder]>,
+        run_completed: bool,
     ) {
-        if let Some(client) = client {
-            client
-                .publish_diagnostics(
-                    uri.clone(),
-                    Self::diagnostics_vec(Some(uri), locations_file, workspace_folders).await,
-                    None,
-                )
-                .await;
+        let changed = Self::update_bacon_diagnostics(state).await;
+        // Files which are not open are not published periodically, so the ones whose
+        // diagnostics changed since they were published, e.g. because they were fixed, are
+        // published now rather than leaving stale diagnostics in the client.
+        let read_state = state.read().await;
+        let stale: Vec<Url> = changed
+            .into_iter()
+            .filter(|changed| {
+                changed != uri
+                    && !read_state.open_files.contains(changed)
+                    && read_state.publisher.is_published(changed)
+            })
+            .collect();
+        drop(read_state);
+        for stale in stale.iter() {
+            Self::publish_stored_diagnostics(client, state, stale, run_completed).await;
         }
+        Self::publish_stored_diagnostics(client, state, uri, run_completed).await;
     }
 
-    async fn syncronize_diagnostics_for_all_open_files(
-        state: Arc<RwLock<State>>,
-        client: Option<Arc<Client>>,
+    /// Remove the diagnostics of a file from the client, e.g. when it is deleted. They are
+    /// published again only once the file is open.
+    async fn clear_diagnostics(
+        client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+        uri: &Url,
     ) {
-        tracing::info!(
-            "starting background task in charge of syncronizing diagnostics for all open files"
-        );
-        loop {
-            let loop_state = state.read().await;
-            let open_files = loop_state.open_files.clone();
-            let locations_file = loop_state.locations_file.clone();
-            let workspace_folders = loop_state.workspace_folders.clone();
-            let wait_time = loop_state.syncronize_all_open_files_wait_millis;
-            drop(loop_state);
-            tracing::info!("running period diagnostic publish for open files `{open_files:?}`");
-            for uri in open_files.iter() {
-                Self::publish_diagnostics(
-                    client.as_ref(),
+        let cleared = state.write().await.publisher.clear(uri);
+        if let (Some(client), true) = (client, cleared) {
+            client.publish_diagnostics(uri.clone(), vec![], None).await;
+        }
+    }
+
+    /// Stored diagnostics of a file of all the backends, filtered and merged, with their
+    /// positions in the negotiated encoding, before they are batched and shifted to follow the
+    /// edits of the document.
+    async fn stored_diagnostics(state: &Arc<RwLock<State>>, uri: &Url) -> Vec<Diagnostic> {
+        let read_state = state.read().await;
+        let position_encoding = read_state.position_encoding.clone();
+        // Backends are told apart by the source only when there are several of them.
+        let label = read_state.diagnostic_source.as_str();
+        let with_backend = !read_state.providers.is_empty() || read_state.bacon_jobs.len() > 1;
+        let bacon_source = read_state.bacon_source(uri);
+        let (bacon_diagnostics, provider_diagnostics) = read_state
+            .diagnostics_store
+            .get(uri)
+            .map(|file| {
+                (
+                    file.source(bacon_source)
+                        .iter()
+                        .map(|diagnostic| {
+                            Self::label_source(label, with_backend, bacon_source, diagnostic)
+                        })
+                        .collect(),
+                    read_state
+                        .provider_diagnostics(file)
+                        .iter()
+                        .map(|(backend, diagnostic)| {
+                            Self::label_source(label, with_backend, backend, diagnostic)
+                        })
+                        .collect(),
+                )
+            })
+            .unwrap_or_default();
+        let diagnostics_filter = read_state.diagnostics_filter.clone();
+        // The diagnostics of the unsaved files are computed on their content in the editor when
+        // it was checked, the edits since are followed by the shifts.
+        let content = read_state
+            .documents
+            .checked_text(uri, read_state.diagnostics_store.version(uri), true)
+            .filter(|_| bacon_source == UNSAVED_PROVIDER)
+            .map(str::to_string);
+        drop(read_state);
+        let diagnostics = diagnostics_filter.apply(bacon_diagnostics);
+        let mut diagnostics = Self::merge_provider_diagnostics(diagnostics, provider_diagnostics);
+        Self::convert_positions(uri, content, &mut diagnostics, &position_encoding).await;
+        diagnostics
+    }
+
+    async fn publish_stored_diagnostics(
+        client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+        uri: &Url,
+        run_completed: bool,
+    ) {
+        if let Some(client) = client {
+            let diagnostics = Self::stored_diagnostics(state, uri).await;
+            let mut write_state = state.write().await;
+            let mut diagnostics = write_state.publisher.batch(uri, diagnostics, run_completed);
+            let diagnostics_version = write_state.diagnostics_store.version(uri);
+            let unsaved = write_state.bacon_source(uri) == UNSAVED_PROVIDER;
+            Self::shift_positions(
+                &mut diagnostics,
+                write_state
+                    .documents
+                    .shifts(uri, diagnostics_version, unsaved),
+            );
+            let Some(params) = write_state.publisher.prepare(uri, diagnostics) else {
+                return;
+            };
+            let publish_trace_file = write_state.publish_trace_file.clone();
+            drop(write_state);
+            if let Some(publish_trace_file) = publish_trace_file {
+                Self::trace_published_diagnostics(
+                    &publish_trace_file,
                     uri,
-                    &locations_file,
-                    workspace_folders.as_deref(),
+                    params.version,
+                    &params.diagnostics,
                 )
                 .await;
             }
-            tokio::time::sleep(wait_time).await;
+            client
+                .publish_diagnostics(params.uri, params.diagnostics, params.version)
+                .await;
         }
     }
 
-    fn parse_severity(severity_str: &str) -> DiagnosticSeverity {
-        match severity_str {
-            "warning" => DiagnosticSeverity::WARNING,
-            "info" | "information" | "note" | "failure-note" => DiagnosticSeverity::INFORMATION,
-            "hint" | "help" => DiagnosticSeverity::HINT,
-            _ => DiagnosticSeverity::ERROR,
+    /// Copy of a diagnostic of a backend with the configured source, suffixed with the tool
+    /// which reported it, or else the backend, e.g. `bacon-ls:rustfmt`.
+    fn label_source(
+        label: &str,
+        with_backend: bool,
+        backend: &str,
+        diagnostic: &Diagnostic,
+    ) -> Diagnostic {
+        let source = if with_backend {
+            let tool = diagnostic
+                .source
+                .as_deref()
+                .filter(|source| *source != PKG_NAME)
+                .unwrap_or(backend);
+            format!("{label}:{tool}")
+        } else {
+            label.to_string()
+        };
+        Diagnostic {
+            source: Some(source),
+            ..diagnostic.clone()
         }
     }
 
-    fn parse_positions(fields: &[&str]) -> Option<(u32, u32, u32, u32)> {
-        let line_start = fields.first()?.parse().ok()?;
-        let line_end = fields.get(1)?.parse().ok()?;
-        let column_start = fields.get(2)?.parse().ok()?;
-        let column_end = fields.get(3)?.parse().ok()?;
-        Some((line_start, line_end, column_start, column_end))
+    /// Merge the diagnostics of the providers with the bacon ones, dropping those already
+    /// reported with the same range, severity and message by bacon or another provider.
+    fn merge_provider_diagnostics(
+        mut diagnostics: Vec<Diagnostic>,
+        provider_diagnostics: Vec<Diagnostic>,
+    ) -> Vec<Diagnostic> {
+        for diagnostic in provider_diagnostics {
+            if !diagnostics.iter().any(|existing| {
+                existing.range == diagnostic.range
+                    && existing.severity == diagnostic.severity
+                    && existing.message == diagnostic.message
+            }) {
+                diagnostics.push(diagnostic);
+            }
+        }
+        diagnostics
     }
 
-    fn parse_bacon_diagnostic_line(line: &str, folder_path: &Path) -> Option<(Url, Diagnostic)> {
-        // Split line into parts; expect exactly 7 parts in the format specified.
-        let line_split: Vec<_> = line.splitn(8, "|:|").collect();
-
-        if line_split.len() != 8 {
-            tracing::error!(
-                "malformed line: expected 8 parts in the format of `severity|:|path|:|line_start|:|line_end|:|column_start|:|column_end|:|message|:|replacement` but found {}: {}",
-                line_split.len(),
-                line
-            );
-            return None;
+    /// Quick fixes applying the replacements stored in the diagnostic data, titled after the
+    /// `quickFixTitle` template if any.
+    fn code_actions(
+        uri: &Url,
+        diagnostic: &Diagnostic,
+        title_template: Option<&str>,
+    ) -> Vec<CodeAction> {
+        let Some(data) = &diagnostic.data else {
+            return vec![];
+        };
+        let Ok(DiagnosticData {
+            corrections,
+            edits,
+            title,
+            ..
+        }) = serde_json::from_value::<DiagnosticData>(data.clone())
+        else {
+            tracing::error!("deserialization failed: received {data:?} as diagnostic data");
+            return vec![];
+        };
+        let lint = match diagnostic.code.as_ref() {
+            Some(NumberOrString::String(lint)) => Some(lint.as_str()),
+            _ => None,
+        };
+        let title_of = |replacement: &str| match title.as_deref() {
+            Some(title) => title.to_string(),
+            None => {
+                // The replacement of a single suggestion is appended to the message.
+                let message = diagnostic
+                    .message
+                    .strip_suffix(replacement)
+                    .and_then(|message| message.strip_suffix(": "))
+                    .unwrap_or(&diagnostic.message);
+                code_action::quick_fix_title(title_template, lint, message, replacement)
+            }
+        };
+        let quick_fix =
+            |title: String, edits: Vec<TextEdit>, is_preferred: Option<bool>| CodeAction {
+                title,
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), edits)])),
+                    ..WorkspaceEdit::default()
+                }),
+                is_preferred,
+                ..CodeAction::default()
+            };
+        if !edits.is_empty() {
+            // Multi-span suggestions are applied in a single edit.
+            let edits = edits
+                .iter()
+                .map(|edit| TextEdit {
+                    range: edit.range,
+                    new_text: edit.new_text.to_string(),
+                })
+                .collect();
+            let title = title_of(corrections.first().map(AsRef::as_ref).unwrap_or_default());
+            return vec![quick_fix(title, edits, Some(true))];
         }
+        let is_preferred = (corrections.len() == 1).then_some(true);
+        corrections
+            .iter()
+            .map(|correction| {
+                let edit = TextEdit {
+                    range: diagnostic.range,
+                    new_text: correction.to_string(),
+                };
+                quick_fix(title_of(correction), vec![edit], is_preferred)
+            })
+            .collect()
+    }
 
-        // Parse elements from the split line
-        let severity = Self::parse_severity(line_split[0]);
-        let file_path = folder_path.join(line_split[1]);
+    /// Build the JSON line recorded in the publish trace file for a publishDiagnostics payload.
+    fn publish_trace_line(
+        timestamp: Duration,
+        uri: &Url,
+        version: Option<i32>,
+        diagnostics: &[Diagnostic],
+    ) -> String {
+        // The hash is compared across sessions, so it must not change with the Rust version.
+        let hash = FnvHasher::hash(
+            serde_json::to_string(diagnostics)
+                .unwrap_or_default()
+                .as_bytes(),
+        );
+        serde_json::json!({
+            "timestamp": timestamp.as_millis() as u64,
+            "uri": uri,
+            "version": version,
+            "count": diagnostics.len(),
+            "hash": format!("{hash:016x}"),
+        })
+        .to_string()
+    }
 
-        // Handle potential parse errors
+    /// Append the publishDiagnostics payload summary to the trace file, so what the server sent
+    /// and when can be correlated with reports of stale diagnostics.
+    async fn trace_published_diagnostics(
+        publish_trace_file: &Path,
+        uri: &Url,
+        version: Option<i32>,
+        diagnostics: &[Diagnostic],
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = Self::publish_trace_line(timestamp, uri, version, diagnostics);
+        line.push('\n');
+        let result = match tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(publish_trace_file)
+            .await
+        {
+            Ok(mut file) => file.write_all(line.as_bytes()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!(
+                "unable to write publish trace file {}: {e}",
+                publish_trace_file.display()
+            );
+        }
+    }
+
+    /// Replace the diagnostics produced by an auxiliary provider, like tests or cargo audit, and
+    /// republish the files affected by the previous and the new diagnostics.
+    async fn set_provider_diagnostics(
+        client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+        provider: &'static str,
+        diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    ) {
+        let uris = state
+            .write()
+            .await
+            .diagnostics_store
+            .replace(provider, diagnostics);
+        for uri in uris.iter() {
+            Self::publish_diagnostics(client, state, uri, false).await;
+        }
+    }
+
+    /// Replace the diagnostics produced by a provider in a workspace folder, keeping the ones of
+    /// the other folders, and republish the affected files.
+    async fn set_folder_diagnostics(
+        client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+        provider: &'static str,
+        folder_path: &Path,
+        diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    ) {
+        let uris = state.write().await.diagnostics_store.replace_in_folder(
+            provider,
+            folder_path,
+            diagnostics,
+        );
+        for uri in uris.iter() {
+            Self::publish_diagnostics(client, state, uri, false).await;
+        }
+    }
+
+    /// Diagnostics of the status document, one per line for each problem of the run.
+    fn status_diagnostics(status_problems: &BTreeMap<String, Vec<String>>) -> Vec<Diagnostic> {
+        status_problems
+            .iter()
+            .flat_map(|(origin, problems)| problems.iter().map(move |problem| (origin, problem)))
+            .enumerate()
+            .map(|(line, (origin, problem))| Diagnostic {
+                range: Range::new(Position::new(line as u32, 0), Position::new(line as u32, 0)),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String(origin.to_string())),
+                source: Some(PKG_NAME.to_string()),
+                message: problem.clone(),
+                ..Diagnostic::default()
+            })
+            .collect()
+    }
+
+    /// Status of the server sent to the companion VS Code extension, healthy without problems.
+    fn server_status(status_problems: &BTreeMap<String, Vec<String>>) -> ServerStatusParams {
+        let problems: Vec<&str> = status_problems
+            .values()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        ServerStatusParams {
+            health: if problems.is_empty() { "ok" } else { "warning" }.to_string(),
+            message: (!problems.is_empty()).then(|| problems.join("\n")),
+        }
+    }
+
+    /// Report the errors of an origin according to their category, replacing its previous status
+    /// problems.
+    async fn report_errors(
+        client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+        origin: &str,
+        errors: Vec<BaconLsError>,
+    ) {
+        let mut problems = Vec::new();
+        for error in errors {
+            match error.report() {
+                Report::ShowMessage => {
+                    tracing::error!("{origin}: {error}");
+                    if let Some(client) = client {
+                        client
+                            .show_message(MessageType::ERROR, error.to_string())
+                            .await;
+                    }
+                    problems.push(error.to_string());
+                }
+                Report::Status => {
+                    tracing::error!("{origin}: {error}");
+                    problems.push(error.to_string());
+                }
+                Report::Log => tracing::warn!("{origin}: {error}"),
+                Report::Silent => tracing::debug!("{origin}: {error}"),
+            }
+        }
+        Self::set_status_problems(client, state, origin, problems).await;
+    }
+
+    /// Replace the problems of an origin and publish them on the status document, if enabled,
+    /// so they don't need to be anchored to a source file.
+    async fn set_status_problems(
+        client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+        origin: &str,
+        problems: Vec<String>,
+    ) {
+        let mut write_state = state.write().await;
+        if !write_state.publish_status_diagnostics && !write_state.server_status_notification {
+            return;
+        }
+        if problems.is_empty() {
+            write_state.status_problems.remove(origin);
+        } else {
+            write_state
+                .status_problems
+                .insert(origin.to_string(), problems);
+        }
+        let diagnostics = Self::status_diagnostics(&write_state.status_problems);
+        let server_status = write_state
+            .server_status_notification
+            .then(|| Self::server_status(&write_state.status_problems));
+        let publish_status_diagnostics = write_state.publish_status_diagnostics;
+        drop(write_state);
+        if let (Some(client), Some(server_status)) = (client, server_status) {
+            client
+                .send_notification::<ServerStatus>(server_status)
+                .await;
+        }
+        if !publish_status_diagnostics {
+            return;
+        }
+        let Ok(uri) = Url::parse(STATUS_URI) else {
+            return;
+        };
+        Self::set_provider_diagnostics(
+            client,
+            state,
+            STATUS_PROVIDER,
+            HashMap::from([(uri, diagnostics)]),
+        )
+        .await;
+    }
+
+    /// Log the resources used by the last run of a provider and keep them for the
+    /// `bacon-ls/timings` request.
+    async fn record_run_usage(state: &Arc<RwLock<State>>, provider: &'static str, usage: RunUsage) {
+        tracing::debug!(
+            "{provider} run took {}ms, {} processes used {}ms of CPU and {}KiB of peak RSS, parsed {} bytes of JSON",
+            usage.duration_ms,
+            usage.processes,
+            usage.cpu_time_ms,
+            usage.peak_rss_kb,
+            usage.json_bytes
+        );
+        state.write().await.run_usages.insert(provider, usage);
+    }
+
+    /// Run the diagnostics providers concurrently on all the workspace folders, or only the
+    /// providers and folders affected by the saved files, and publish their diagnostics as they
+    /// complete. The diagnostics of the folders which didn't run are kept.
+    async fn run_providers(
+        client: Option<Arc<Client>>,
+        state: Arc<RwLock<State>>,
+        saved: Option<Vec<Url>>,
+    ) {
+        let read_state = state.read().await;
+        let folder_paths = match saved.as_deref() {
+            Some(saved) => Self::folders_of(saved, read_state.workspace_folders.as_deref()),
+            None => read_state
+                .workspace_folders
+                .iter()
+                .flatten()
+                .map(|folder| PathBuf::from(folder.uri.path()))
+                .collect(),
+        };
+        let folder_parallelism = read_state.folder_parallelism;
+        let providers: Vec<Arc<dyn DiagnosticsProvider>> = read_state
+            .providers
+            .iter()
+            .filter(|provider| {
+                saved
+                    .as_ref()
+                    .is_none_or(|saved| saved.iter().any(|saved| provider.runs_on_save(saved)))
+            })
+            .cloned()
+            .collect();
+        drop(read_state);
+
+        // The spawned tasks don't inherit the run of this one.
+        let run_id = RunId::current().unwrap_or_else(RunId::next);
+        let semaphore = Arc::new(Semaphore::new(folder_parallelism));
+        // A single folder doesn't need its own progress, its tools report theirs.
+        let show_progress = folder_paths.len() > 1;
+        let mut tasks = JoinSet::new();
+        for folder_path in folder_paths {
+            let client = client.clone();
+            let state = state.clone();
+            let providers = providers.clone();
+            let cargo_env = Arc::new(Self::folder_cargo_env(&state, &folder_path).await);
+            let semaphore = semaphore.clone();
+            let run_queue = state.write().await.project(&folder_path).run_queue.clone();
+            let span = tracing::info_span!("folder", path = %folder_path.display());
+            tasks.spawn(
+                run_id.scope(
+                    async move {
+                        let Some((_running, providers)) = run_queue.next(providers).await else {
+                            tracing::debug!("providers already run by a queued run, skipping");
+                            return;
+                        };
+                        // The semaphore is never closed.
+                        let _permit = semaphore.acquire_owned().await.ok();
+                        let progress = match show_progress {
+                            true => Some(
+                                WorkProgress::begin(
+                                    client.as_deref(),
+                                    &format!("checking {}", folder_name(&folder_path)),
+                                )
+                                .await,
+                            ),
+                            false => None,
+                        };
+                        let cancelled = async {
+                            match progress.as_ref() {
+                                Some(progress) => progress.cancelled().await,
+                                None => std::future::pending().await,
+                            }
+                        };
+                        let run = Self::run_folder_providers(
+                            client.clone(),
+                            state,
+                            run_id,
+                            &folder_path,
+                            providers,
+                            cargo_env,
+                        );
+                        let run = async {
+                            match progress.as_ref() {
+                                Some(progress) => progress.show_messages(run).await,
+                                None => run.await,
+                            }
+                        };
+                        // Dropping the runs of the providers kills their processes.
+                        let cancelled = tokio::select! {
+                            () = run => false,
+                            () = cancelled => true,
+                        };
+                        if cancelled {
+                            tracing::info!(
+                                "run of {} cancelled, keeping its previous diagnostics",
+                                folder_path.display()
+                            );
+                        }
+                        if let Some(progress) = progress {
+                            progress
+                                .end(cancelled.then(|| "cancelled".to_string()))
+                                .await;
+                        }
+                    }
+                    .instrument(span),
+                ),
+            );
+        }
+        while let Some(result) = tasks.join_next().await {
+            if let Err(e) = result {
+                tracing::error!("workspace folder task failed: {e}");
+            }
+        }
+        Self::notify_build_status(client.as_ref(), &state).await;
+    }
+
+    /// Run the providers concurrently on a workspace folder and publish their diagnostics as
+    /// they complete. Their errors are reported in the status entry of the folder.
+    async fn run_folder_providers(
+        client: Option<Arc<Client>>,
+        state: Arc<RwLock<State>>,
+        run_id: RunId,
+        folder_path: &Path,
+        providers: Vec<Arc<dyn DiagnosticsProvider>>,
+        cargo_env: Arc<HashMap<String, String>>,
+    ) {
+        let mut tasks = JoinSet::new();
+        let command_limits = state.read().await.command_limits;
+        for provider in providers {
+            let folder_path = folder_path.to_path_buf();
+            let cargo_env = cargo_env.clone();
+            let messages = progress::messages();
+            let span = tracing::info_span!("provider", name = provider.name());
+            tasks.spawn(
+                run_id.scope(progress::with_messages(
+                    messages,
+                    async move {
+                        let (result, usage) = command_limits
+                            .scope(RunUsage::measure(provider.run(&folder_path, &cargo_env)))
+                            .await;
+                        (provider.name(), result, usage)
+                    }
+                    .instrument(span),
+                )),
+            );
+        }
+        while let Some(result) = tasks.join_next().await {
+            let (provider, result, usage) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    tracing::error!("diagnostics provider task failed: {e}");
+                    continue;
+                }
+            };
+            Self::record_run_usage(&state, provider, usage).await;
+            let origin = format!("{provider} ({})", folder_name(folder_path));
+            match result {
+                Ok(diagnostics) => {
+                    Self::report_errors(client.as_ref(), &state, &origin, vec![]).await;
+                    Self::set_folder_diagnostics(
+                        client.as_ref(),
+                        &state,
+                        provider,
+                        folder_path,
+                        diagnostics,
+                    )
+                    .await;
+                }
+                Err(BaconLsError::Cancelled) => {
+                    tracing::info!("{provider} run cancelled, keeping its previous diagnostics");
+                }
+                Err(e @ BaconLsError::Locked(_)) => {
+                    tracing::info!("{provider}: {e}, keeping its previous diagnostics");
+                }
+                Err(e) => Self::report_errors(client.as_ref(), &state, &origin, vec![e]).await,
+            }
+        }
+    }
+
+    /// Queue a saved file, scheduling an update `updateOnSaveWaitMillis` after the save. A save
+    /// while an update is scheduled postpones it, so the saves of a "save all" or in quick
+    /// succession are handled by a single update.
+    async fn schedule_save(client: Option<Arc<Client>>, state: Arc<RwLock<State>>, uri: Url) {
+        let mut write_state = state.write().await;
+        let scheduled = !write_state.pending_saves.is_empty();
+        write_state.pending_saves.insert(uri);
+        write_state.save_deadline =
+            tokio::time::Instant::now() + write_state.update_on_save_wait_millis;
+        drop(write_state);
+        if !scheduled {
+            tokio::task::spawn(Self::flush_saves(client, state));
+        }
+    }
+
+    /// Schedule the publish of a changed document `updateOnChangeCooldownMillis` after the
+    /// change. A change while a publish is scheduled postpones it, so the last change of a
+    /// burst is always published.
+    async fn schedule_change(client: Option<Arc<Client>>, state: Arc<RwLock<State>>, uri: Url) {
+        let mut write_state = state.write().await;
+        let deadline = tokio::time::Instant::now() + write_state.update_on_change_cooldown_millis;
+        let scheduled = write_state
+            .change_deadlines
+            .insert(uri.clone(), deadline)
+            .is_some();
+        drop(write_state);
+        if !scheduled {
+            tokio::task::spawn(Self::flush_change(client, state, uri));
+        }
+    }
+
+    /// Wait for the burst of changes of a document to end, then publish its diagnostics.
+    async fn flush_change(client: Option<Arc<Client>>, state: Arc<RwLock<State>>, uri: Url) {
+        loop {
+            let Some(deadline) = state.read().await.change_deadlines.get(&uri).copied() else {
+                return;
+            };
+            tokio::time::sleep_until(deadline).await;
+            let mut write_state = state.write().await;
+            // Wait again if a change postponed the publish in the meantime.
+            if write_state
+                .change_deadlines
+                .get(&uri)
+                .is_none_or(|deadline| *deadline <= tokio::time::Instant::now())
+            {
+                write_state.change_deadlines.remove(&uri);
+                break;
+            }
+        }
+        Self::publish_diagnostics(client.as_ref(), &state, &uri, false).await;
+        if state.read().await.unsaved_files.contains(&uri) {
+            Self::check_unsaved_files(client, state, uri).await;
+        }
+    }
+
+    /// Wait for the burst of saves to end, then run the providers affected by the saved files,
+    /// check bacon and publish the diagnostics of the saved files.
+    async fn flush_saves(client: Option<Arc<Client>>, state: Arc<RwLock<State>>) {
+        let (saved, update_on_save) = loop {
+            let deadline = state.read().await.save_deadline;
+            tokio::time::sleep_until(deadline).await;
+            let mut write_state = state.write().await;
+            // Wait again if a save postponed the update in the meantime.
+            if write_state.save_deadline <= tokio::time::Instant::now() {
+                let saved: Vec<Url> = write_state.pending_saves.drain().collect();
+                break (saved, write_state.update_on_save);
+            }
+        };
+        let run_id = RunId::next();
+        tracing::debug!(
+            "handling saves of {saved:?} in {run_id}, updateOnSave is {update_on_save}"
+        );
+        tokio::task::spawn(run_id.scope(Self::run_providers(
+            client.clone(),
+            state.clone(),
+            Some(saved.clone()),
+        )));
+        tokio::task::spawn(run_id.scope(Self::check_bacon_health(
+            client.clone(),
+            state.clone(),
+            saved.clone(),
+        )));
+        if update_on_save {
+            run_id
+                .scope(async {
+                    for uri in saved.iter() {
+                        Self::publish_diagnostics(client.as_ref(), &state, uri, false).await;
+                    }
+                })
+                .await;
+        }
+    }
+
+    /// Check that bacon updated the locations file of the folders of the saved files since the
+    /// previous save. After too many saves without updates, run cargo once instead in the folder
+    /// and publish its diagnostics until bacon recovers.
+    async fn check_bacon_health(
+        client: Option<Arc<Client>>,
+        state: Arc<RwLock<State>>,
+        saved: Vec<Url>,
+    ) {
+        let read_state = state.read().await;
+        // Without bacon, cargo already runs on save.
+        if read_state.bacon_fallback_after_saves == 0 || read_state.bacon_missing {
+            return;
+        }
+        let locations_file = read_state.locations_file.clone();
+        let folder_paths = Self::folders_of(&saved, read_state.workspace_folders.as_deref());
+        let events = CargoEvents::new(client.clone(), &read_state.cargo_events);
+        let locale = read_state.locale;
+        drop(read_state);
+        for folder_path in folder_paths {
+            let modified = Self::locations_last_modified(&locations_file, &folder_path).await;
+            let mut write_state = state.write().await;
+            let health = &mut write_state.project(&folder_path).bacon_health;
+            let was_failing = health.is_failing();
+            let is_failing = health.record_save(modified);
+            let saves = health.saves_without_update;
+            let failing_folders = write_state
+                .projects
+                .values()
+                .filter(|project| project.bacon_health.is_failing())
+                .count();
+            drop(write_state);
+            let bacon_locations = folder_path.join(&locations_file);
+            let bacon_locations = bacon_locations.display().to_string();
+
+            if !is_failing {
+                if was_failing {
+                    tracing::info!("bacon updated {bacon_locations} again, dropping the fallback");
+                    if failing_folders == 0 {
+                        Self::set_status_problems(
+                            client.as_ref(),
+                            &state,
+                            FALLBACK_PROVIDER,
+                            vec![],
+                        )
+                        .await;
+                    }
+                    Self::set_folder_diagnostics(
+                        client.as_ref(),
+                        &state,
+                        FALLBACK_PROVIDER,
+                        &folder_path,
+                        HashMap::new(),
+                    )
+                    .await;
+                }
+                continue;
+            }
+            let command = format!("cargo {}", FALLBACK_COMMAND_ARGS.join(" "));
+            let message = Message::BaconNotUpdating {
+                locations_file: &bacon_locations,
+                saves,
+                command: &command,
+            };
+            tracing::warn!("{}", message.text(Locale::English));
+            let message = message.text(locale);
+            if !was_failing {
+                if let Some(client) = client.as_ref() {
+                    client
+                        .show_message(MessageType::WARNING, message.clone())
+                        .await;
+                }
+            }
+            Self::set_status_problems(client.as_ref(), &state, FALLBACK_PROVIDER, vec![message])
+                .await;
+            let fallback = CustomCommand {
+                command: "cargo".to_string(),
+                args: FALLBACK_COMMAND_ARGS.map(|arg| arg.to_string()).to_vec(),
+                parser: CustomParser::CargoJson,
+                events: events.clone(),
+            };
+            let command_limits = state.read().await.command_limits;
+            let (result, usage) = command_limits
+                .scope(RunUsage::measure(async {
+                    let cargo_env = Self::folder_cargo_env(&state, &folder_path).await;
+                    fallback.run(&folder_path, &cargo_env).await
+                }))
+                .await;
+            Self::record_run_usage(&state, FALLBACK_PROVIDER, usage).await;
+            let diagnostics = match result {
+                Ok(diagnostics) => diagnostics,
+                Err(e @ BaconLsError::Locked(_)) => {
+                    tracing::info!("{e}, keeping the previous diagnostics");
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("{e}");
+                    HashMap::new()
+                }
+            };
+            Self::set_folder_diagnostics(
+                client.as_ref(),
+                &state,
+                FALLBACK_PROVIDER,
+                &folder_path,
+                diagnostics,
+            )
+            .await;
+        }
+    }
+
+    /// Return true if the file changes the build, like a Cargo manifest or a build script.
+    fn is_build_file(uri: &Url) -> bool {
+        uri.path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .is_some_and(|name| BUILD_FILES.contains(&name))
+    }
+
+    /// Return true if the file is the locations file of a workspace folder.
+    fn is_locations_file(uri: &Url, locations_file: &str) -> bool {
+        Path::new(uri.path()).ends_with(locations_file)
+    }
+
+    /// Ask the client to notify the changes to the build files, which may not be open in the
+    /// editor, so they trigger an update like a save, and to the locations files, so the bacon
+    /// diagnostics are published as soon as they are written rather than when polled. Only the
+    /// locations file of each workspace folder is watched, not the ones of nested folders.
+    /// Returns true if the client accepted to watch them.
+    async fn watch_files(
+        client: &Client,
+        locations_files: &[String],
+        workspace_folders: &[WorkspaceFolder],
+    ) -> bool {
+        // An absolute locations file is shared by the workspace folders.
+        let locations_files: BTreeSet<String> = workspace_folders
+            .iter()
+            .flat_map(|folder| {
+                locations_files.iter().map(|locations_file| {
+                    Path::new(folder.uri.path())
+                        .join(locations_file)
+                        .display()
+                        .to_string()
+                })
+            })
+            .collect();
+        let watchers = BUILD_FILES
+            .iter()
+            .map(|name| format!("**/{name}"))
+            .chain(locations_files)
+            .map(|pattern| FileSystemWatcher {
+                glob_pattern: GlobPattern::String(pattern),
+                kind: None,
+            })
+            .collect();
+        let registration = Registration {
+            id: "bacon-ls/watchFiles".to_string(),
+            method: DidChangeWatchedFiles::METHOD.to_string(),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                watchers,
+            })
+            .ok(),
+        };
+        match client.register_capability(vec![registration]).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("unable to watch the build files and the locations files: {e}");
+                false
+            }
+        }
+    }
+
+    /// Return true if a workspace folder belongs to a Cargo project, or else the current
+    /// directory, where bacon runs without workspace folders.
+    fn has_cargo_project(workspace_folders: Option<&[WorkspaceFolder]>) -> bool {
+        let is_project = |folder_path: &Path| {
+            folder_path.ancestors().any(|dir| {
+                dir.join("Cargo.toml").is_file() || dir.join(RUST_PROJECT_FILE).is_file()
+            })
+        };
+        match workspace_folders {
+            Some(workspace_folders) => workspace_folders
+                .iter()
+                .any(|folder| is_project(Path::new(folder.uri.path()))),
+            None => env::current_dir().is_ok_and(|current_dir| is_project(&current_dir)),
+        }
+    }
+
+    /// Start bacon and the diagnostics providers, unless there is no Cargo project in the
+    /// workspace and the server stays dormant until one is created.
+    async fn start(&self) {
+        let state = self.state.read().await;
+        let dormant = state.dormant_without_cargo_project
+            && !Self::has_cargo_project(state.workspace_folders.as_deref());
+        let locale = state.locale;
+        drop(state);
+        self.state.write().await.dormant = dormant;
+        let problems = if dormant {
+            tracing::info!("{}", Message::Dormant.text(Locale::English));
+            let message = Message::Dormant.text(locale);
+            if let Some(client) = self.client.as_ref() {
+                client.log_message(MessageType::INFO, message.clone()).await;
+            }
+            vec![message]
+        } else {
+            vec![]
+        };
+        Self::set_status_problems(self.client.as_ref(), &self.state, DORMANT_ORIGIN, problems)
+            .await;
+        if dormant {
+            return;
+        }
+        #[cfg(feature = "bacon")]
+        if let Some(client) = self.client.as_ref() {
+            self.start_bacon(client).await;
+        }
+        self.follow_bacon_sockets().await;
+        tokio::task::spawn(RunId::next().scope(Self::run_providers(
+            self.client.clone(),
+            self.state.clone(),
+            None,
+        )));
+    }
+
+    /// Validate the bacon preferences and start bacon in background, according to the settings.
+    #[cfg(feature = "bacon")]
+    async fn start_bacon(&self, client: &Client) {
+        let state = self.state.read().await;
+        let run_bacon = state.run_bacon_in_background;
+        let bacon_command_args = state.run_bacon_in_background_command_args.clone();
+        let restart_policy = state.bacon_restart_policy;
+        let create_bacon_prefs = state
+            .create_bacon_preferences_file
+            .then_some(state.preferences_creation_target);
+        let validate_prefs = state.validate_bacon_preferences;
+        let locale = state.locale;
+        let workspace_folders = state.workspace_folders.clone().unwrap_or_default();
+        let bacon_jobs = state.bacon_jobs.clone();
+        let bacon_socket = state.bacon_socket.clone();
+        drop(state);
+
+        // Each Cargo project of the workspace gets its own bacon.
+        let folder_paths: Vec<PathBuf> = workspace_folders
+            .iter()
+            .filter(|folder| Self::has_cargo_project(Some(std::slice::from_ref(*folder))))
+            .map(|folder| PathBuf::from(folder.uri.path()))
+            .collect();
+        let version = match Bacon::version().await {
+            Ok(version) => version,
+            // A bacon listening on a TCP socket can run on another machine.
+            Err(e) if bacon_socket.is_none() => {
+                tracing::warn!("{e}");
+                self.fall_back_to_cargo().await;
+                return;
+            }
+            Err(e) => {
+                tracing::warn!("{e}");
+                None
+            }
+        };
+        if let Some(version) = version {
+            for (feature, required) in version.missing_features(run_bacon) {
+                let message = Message::BaconTooOld {
+                    version: &version.to_string(),
+                    feature,
+                    required: &required.to_string(),
+                };
+                tracing::warn!("{}", message.text(Locale::English));
+                client
+                    .show_message(MessageType::WARNING, message.text(locale))
+                    .await;
+            }
+        }
+        let mut errors = Vec::new();
+        if validate_prefs {
+            // The locations file is exported the same way in all the projects, so the
+            // preferences of the first one are the reference.
+            match Bacon::validate_preferences(
+                folder_paths.first().map(PathBuf::as_path),
+                create_bacon_prefs,
+                version,
+                &bacon_jobs,
+            )
+            .await
+            {
+                Ok(Some(exported)) => self.use_exported_locations_file(exported).await,
+                Ok(None) => {}
+                Err(e) => errors.push(e),
+            }
+        } else {
+            tracing::warn!(
+                "skipping validation of bacon preferences, validateBaconPreferences is false"
+            );
+        }
+
+        if run_bacon {
+            for folder_path in folder_paths {
+                let cargo_env = Self::bacon_cargo_env(&self.state, &folder_path).await;
+                if let Err(e) = self
+                    .start_folder_bacon(
+                        client,
+                        &folder_path,
+                        &bacon_command_args,
+                        &cargo_env,
+                        restart_policy,
+                        locale,
+                    )
+                    .await
+                {
+                    errors.push(e);
+                }
+            }
+        } else {
+            tracing::warn!("skipping background bacon startup, runBaconInBackground is false");
+        }
+        Self::report_errors(self.client.as_ref(), &self.state, "bacon", errors).await;
+    }
+
+    /// Follow the analysis streamed by bacon in each Cargo project of the workspace, if
+    /// `baconSocket` is set.
+    async fn follow_bacon_sockets(&self) {
+        let state = self.state.read().await;
+        let Some(bacon_socket) = state.bacon_socket.clone() else {
+            return;
+        };
+        let folder_paths: Vec<PathBuf> = state
+            .workspace_folders
+            .iter()
+            .flatten()
+            .filter(|folder| Self::has_cargo_project(Some(std::slice::from_ref(*folder))))
+            .map(|folder| PathBuf::from(folder.uri.path()))
+            .collect();
+        drop(state);
+        for (address, folder_path) in socket::socket_addresses(&bacon_socket, &folder_paths) {
+            let handle = tokio::task::spawn(Self::follow_bacon_socket(
+                self.client.clone(),
+                self.state.clone(),
+                address,
+                folder_path.clone(),
+            ));
+            let mut state = self.state.write().await;
+            let project = state.project(&folder_path);
+            if let Some(previous) = project.bacon_socket_handle.replace(handle) {
+                previous.abort();
+            }
+        }
+    }
+
+    /// Read the locations file where the bacon preferences export, unless `locationsFile` is
+    /// set, with the format of their lines.
+    #[cfg(feature = "bacon")]
+    async fn use_exported_locations_file(&self, exported: Export) {
+        let mut state = self.state.write().await;
+        state.line_format = exported.line_format;
+        if state.locations_file_configured || state.locations_file == exported.path {
+            return;
+        }
+        tracing::info!(
+            "reading the locations file {} exported by bacon",
+            exported.path
+        );
+        state.locations_file = exported.path;
+    }
+
+    /// Start bacon in background in a workspace folder, unless another instance already runs
+    /// it there, in which case it is started once that instance exits.
+    #[cfg(feature = "bacon")]
+    async fn start_folder_bacon(
+        &self,
+        client: &Client,
+        folder_path: &Path,
+        bacon_command_args: &str,
+        cargo_env: &HashMap<String, String>,
+        restart_policy: RestartPolicy,
+        locale: Locale,
+    ) -> Result<()> {
+        match Bacon::acquire_lock(folder_path).await {
+            Ok(BaconLockStatus::Held { owner }) => {
+                let message = Message::BaconReused { pid: owner };
+                tracing::info!(
+                    "{} in {}",
+                    message.text(Locale::English),
+                    folder_path.display()
+                );
+                client
+                    .log_message(MessageType::INFO, message.text(locale))
+                    .await;
+                let handle = tokio::task::spawn(Self::take_over_folder_bacon(
+                    self.client.clone(),
+                    self.state.clone(),
+                    owner,
+                    folder_path.to_path_buf(),
+                    bacon_command_args.to_string(),
+                    cargo_env.clone(),
+                    restart_policy,
+                    locale,
+                ));
+                let mut state = self.state.write().await;
+                let project = state.project(folder_path);
+                if let Some(previous) = project.bacon_takeover_handle.replace(handle) {
+                    previous.abort();
+                }
+                return Ok(());
+            }
+            Ok(BaconLockStatus::Adopted { bacon }) => {
+                let message = Message::BaconAdopted { pid: bacon };
+                tracing::info!(
+                    "{} in {}",
+                    message.text(Locale::English),
+                    folder_path.display()
+                );
+                client
+                    .log_message(MessageType::INFO, message.text(locale))
+                    .await;
+                return Ok(());
+            }
+            Ok(BaconLockStatus::Acquired) => {}
+            Err(e) => tracing::warn!("{e}"),
+        }
+        Self::run_folder_bacon(
+            self.client.clone(),
+            &self.state,
+            folder_path,
+            bacon_command_args,
+            cargo_env,
+            restart_policy,
+            locale,
+        )
+        .await
+    }
+
+    /// Wait for the instance running bacon for the folder to exit, and start bacon in its place
+    /// unless another instance took the lock first.
+    #[cfg(feature = "bacon")]
+    #[allow(clippy::too_many_arguments)]
+    async fn take_over_folder_bacon(
+        client: Option<Arc<Client>>,
+        state: Arc<RwLock<State>>,
+        owner: u32,
+        folder_path: PathBuf,
+        bacon_command_args: String,
+        cargo_env: HashMap<String, String>,
+        restart_policy: RestartPolicy,
+        locale: Locale,
+    ) {
+        while Bacon::is_process_running(owner) {
+            tokio::time::sleep(BACON_TAKEOVER_INTERVAL).await;
+        }
+        match Bacon::acquire_lock(&folder_path).await {
+            Ok(BaconLockStatus::Acquired) => {
+                tracing::info!(
+                    "{PKG_NAME} instance {owner} exited, starting bacon in {}",
+                    folder_path.display()
+                );
+                if let Err(e) = Self::run_folder_bacon(
+                    client,
+                    &state,
+                    &folder_path,
+                    &bacon_command_args,
+                    &cargo_env,
+                    restart_policy,
+                    locale,
+                )
+                .await
+                {
+                    tracing::error!("{e}");
+                }
+            }
+            Ok(status) => tracing::info!(
+                "{PKG_NAME} instance {owner} exited, not starting bacon in {}: {status:?}",
+                folder_path.display()
+            ),
+            Err(e) => tracing::warn!("{e}"),
+        }
+    }
+
+    /// Run bacon in background in a workspace folder whose lock is held, releasing the lock if
+    /// bacon can't be started.
+    #[cfg(feature = "bacon")]
+    async fn run_folder_bacon(
+        client: Option<Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+        folder_path: &Path,
+        bacon_command_args: &str,
+        cargo_env: &HashMap<String, String>,
+        restart_policy: RestartPolicy,
+        locale: Locale,
+    ) -> Result<()> {
+        let command = match Bacon::run_in_background(
+            "bacon",
+            bacon_command_args,
+            folder_path,
+            cargo_env.clone(),
+            restart_policy,
+            client.clone(),
+            locale,
+            true,
+        )
+        .await
+        {
+            Ok(command) => command,
+            Err(e) => {
+                Bacon::release_lock(folder_path).await;
+                return Err(e);
+            }
+        };
+        tracing::info!(
+            "bacon was started successfully and is running in the background in {}",
+            folder_path.display()
+        );
+        state
+            .write()
+            .await
+            .project(folder_path)
+            .bacon_command_handle = Some(command);
+        // The other jobs run in their own bacon, exporting their own locations file.
+        let jobs_locations_files = state.read().await.jobs_locations_files();
+        for (job, locations_file) in jobs_locations_files.iter().skip(1) {
+            let command = Bacon::run_in_background(
+                "bacon",
+                &format!(
+                    "--headless -j {job} --config-toml exports.cargo-json-spans.path=\"{locations_file}\""
+                ),
+                folder_path,
+                cargo_env.clone(),
+                restart_policy,
+                client.clone(),
+                locale,
+                false,
+            )
+            .await?;
+            state
+                .write()
+                .await
+                .project(folder_path)
+                .bacon_job_handles
+                .push(command);
+        }
+        Ok(())
+    }
+
+    /// Return the workspace folders sent by the client or, for clients not supporting them,
+    /// a single folder synthesized from the root uri, the root path or the current directory.
+    #[allow(deprecated)]
+    fn initialize_workspace_folders(params: &InitializeParams) -> Vec<WorkspaceFolder> {
+        if let Some(folders) = params
+            .workspace_folders
+            .as_ref()
+            .filter(|folders| !folders.is_empty())
+        {
+            return folders.clone();
+        }
+        let uri = params
+            .root_uri
+            .clone()
+            .or_else(|| {
+                params
+                    .root_path
+                    .as_ref()
+                    .and_then(|root_path| Url::from_directory_path(root_path).ok())
+            })
+            .or_else(|| {
+                env::current_dir()
+                    .ok()
+                    .and_then(|current_dir| Url::from_directory_path(current_dir).ok())
+            });
+        uri.map(|uri| {
+            let name = folder_name(Path::new(uri.path()));
+            tracing::info!("no workspace folders sent by the client, using {uri}");
+            vec![WorkspaceFolder { uri, name }]
+        })
+        .unwrap_or_default()
+    }
+
+    /// Return the path of the workspace folder containing the given file, defaulting to the
+    /// first workspace folder.
+    fn workspace_folder_path(
+        uri: &Url,
+        workspace_folders: Option<&[WorkspaceFolder]>,
+    ) -> Option<PathBuf> {
+        let folders = workspace_folders.unwrap_or_default();
+        folders
+            .iter()
+            .map(|folder| PathBuf::from(folder.uri.path()))
+            .filter(|folder_path| Path::new(uri.path()).starts_with(folder_path))
+            .max_by_key(|folder_path| folder_path.as_os_str().len())
+            .or_else(|| {
+                folders
+                    .first()
+                    .map(|folder| PathBuf::from(folder.uri.path()))
+            })
+    }
+
+    /// Paths of the workspace folders containing the given files.
+    fn folders_of(uris: &[Url], workspace_folders: Option<&[WorkspaceFolder]>) -> Vec<PathBuf> {
+        let mut folder_paths: Vec<PathBuf> = uris
+            .iter()
+            .filter_map(|uri| Self::workspace_folder_path(uri, workspace_folders))
+            .collect();
+        folder_paths.sort();
+        folder_paths.dedup();
+        folder_paths
+    }
+
+    /// Run the tests or benches selected from a code lens and publish the failures as
+    /// diagnostics.
+    #[cfg(feature = "runnables")]
+    async fn run_runnable(&self, command: &str, uri: &Url, filter: &str) -> Result<()> {
+        let state = self.state.read().await;
+        let folder_path = Self::workspace_folder_path(uri, state.workspace_folders.as_deref())
+            .ok_or_else(|| {
+                BaconLsError::Config(format!("no workspace folder found to run `{filter}`"))
+            })?;
+        let test_runner = state.test_runner;
+        let locale = state.locale;
+        drop(state);
+        let cargo_env = Self::folder_cargo_env(&self.state, &folder_path).await;
+
+        let outcome =
+            Runnables::run(command, filter, &folder_path, test_runner, &cargo_env).await?;
+        Self::set_folder_diagnostics(
+            self.client.as_ref(),
+            &self.state,
+            TEST_PROVIDER,
+            &folder_path,
+            outcome.diagnostics,
+        )
+        .await;
+        if let Some(client) = self.client.as_ref() {
+            let (message_type, message) = if outcome.success {
+                (MessageType::INFO, Message::RunnableSucceeded { filter })
+            } else {
+                (MessageType::WARNING, Message::RunnableFailed { filter })
+            };
+            client
+                .show_message(message_type, message.text(locale))
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Bacon columns are expressed in characters (UTF-32 code units), convert them to the
+    /// position encoding negotiated with the client, on the given content of the file or else
+    /// on the saved one.
+    async fn convert_positions(
+        uri: &Url,
+        content: Option<String>,
+        diagnostics: &mut [Diagnostic],
+        position_encoding: &PositionEncodingKind,
+    ) {
+        if diagnostics.is_empty() || *position_encoding == PositionEncodingKind::UTF32 {
+            return;
+        }
+        let Ok(path) = uri.to_file_path() else {
+            return;
+        };
+        let content = match content {
+            Some(content) => content,
+            None => match tokio::fs::read_to_string(&path).await {
+                Ok(content) => content,
+                Err(e) => {
+                    tracing::warn!(
+                        "unable to read {} to convert diagnostic positions: {e}",
+                        path.display()
+                    );
+                    return;
+                }
+            },
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        let convert = |position: &mut Position| {
+            if let Some(line) = lines.get(position.line as usize) {
+                position.character =
+                    Self::convert_column(line, position.character, position_encoding);
+            }
+        };
+        for diagnostic in diagnostics.iter_mut() {
+            Self::for_each_position(diagnostic, convert);
+        }
+    }
+
+    /// Move the positions of diagnostics computed on a previous content of a document, so they
+    /// follow the edits made since.
+    fn shift_positions(diagnostics: &mut [Diagnostic], shifts: &[Shift]) {
+        if shifts.is_empty() {
+            return;
+        }
+        for diagnostic in diagnostics.iter_mut() {
+            Self::for_each_position(diagnostic, |position| {
+                *position = shifts
+                    .iter()
+                    .fold(*position, |position, shift| shift.apply(position));
+            });
+        }
+    }
+
+    /// Update the positions of the range of a diagnostic and of the edits of its quick fixes.
+    fn for_each_position(diagnostic: &mut Diagnostic, f: impl Fn(&mut Position)) {
+        f(&mut diagnostic.range.start);
+        f(&mut diagnostic.range.end);
+        if let Some(Ok(mut data)) = diagnostic
+            .data
+            .take()
+            .map(serde_json::from_value::<DiagnosticData>)
+        {
+            for edit in data.edits.iter_mut() {
+                f(&mut edit.range.start);
+                f(&mut edit.range.end);
+            }
+            diagnostic.data = Some(serde_json::json!(data));
+        }
+    }
+
+    fn convert_column(line: &str, column: u32, position_encoding: &PositionEncodingKind) -> u32 {
+        if *position_encoding == PositionEncodingKind::UTF32 {
+            return column;
+        }
+        let mut chars = 0;
+        let mut units = 0;
+        for c in line.chars().take(column as usize) {
+            chars += 1;
+            units += if *position_encoding == PositionEncodingKind::UTF8 {
+                c.len_utf8()
+            } else {
+                c.len_utf16()
+            } as u32;
+        }
+        // Columns past the end of the line are kept as they are.
+        units + column.saturating_sub(chars)
+    }
+
+    /// Return the modification time of the locations file of a workspace folder.
+    async fn locations_last_modified(
+        locations_file: &str,
+        folder_path: &Path,
+    ) -> Option<SystemTime> {
+        tokio::fs::metadata(folder_path.join(locations_file))
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Open files followed by the other files with diagnostics, or whose published diagnostics
+    /// must be cleared, sorted and capped to the maximum number of files.
+    fn files_to_publish(
+        open_files: &HashSet<Url>,
+        store: &DiagnosticsStore,
+        publisher: &Publisher,
+        max_files: usize,
+    ) -> Vec<Url> {
+        let mut others: Vec<&Url> = store
+            .uris()
+            .chain(publisher.published_uris())
+            .filter(|uri| !open_files.contains(*uri))
+            .collect();
+        others.sort();
+        others.dedup();
+        let max_others = max_files.saturating_sub(open_files.len());
+        if others.len() > max_others {
+            tracing::warn!(
+                "{} files have diagnostics, only publishing {max_files} of them",
+                others.len() + open_files.len()
+            );
+        }
+        open_files
+            .iter()
+            .chain(others.into_iter().take(max_others))
+            .cloned()
+            .collect()
+    }
+
+    /// Publish the diagnostics of the open files, or of all the files with `publishAllFiles`,
+    /// the bacon run of the given folders being completed.
+    async fn publish_bacon_diagnostics(
+        state: &Arc<RwLock<State>>,
+        client: Option<&Arc<Client>>,
+        completed_folders: &HashSet<PathBuf>,
+    ) {
+        let read_state = state.read().await;
+        let open_files = read_state.open_files.clone();
+        let publish_all_files = read_state.publish_all_files;
+        let publish_all_files_max = read_state.publish_all_files_max;
+        let workspace_folders = read_state.workspace_folders.clone();
+        drop(read_state);
+        let files = if publish_all_files {
+            Self::update_bacon_diagnostics(state).await;
+            let read_state = state.read().await;
+            let files = Self::files_to_publish(
+                &open_files,
+                &read_state.diagnostics_store,
+                &read_state.publisher,
+                publish_all_files_max,
+            );
+            drop(read_state);
+            files
+        } else {
+            open_files.into_iter().collect()
+        };
+        RunId::next()
+            .scope(async {
+                tracing::info!("running period diagnostic publish for files `{files:?}`");
+                for uri in files.iter() {
+                    let run_completed =
+                        Self::workspace_folder_path(uri, workspace_folders.as_deref())
+                            .is_none_or(|folder_path| completed_folders.contains(&folder_path));
+                    Self::publish_diagnostics(client, state, uri, run_completed).await;
+                }
+                let all_completed = workspace_folders
+                    .iter()
+                    .flatten()
+                    .all(|folder| completed_folders.contains(&PathBuf::from(folder.uri.path())));
+                if all_completed {
+                    Self::notify_build_status(client, state).await;
+                }
+            })
+            .await;
+    }
+
+    async fn syncronize_diagnostics_for_all_open_files(
+        state: Arc<RwLock<State>>,
+        client: Option<Arc<Client>>,
+    ) {
+        tracing::info!(
+            "starting background task in charge of syncronizing diagnostics for all open files"
+        );
+        loop {
+            let loop_state = state.read().await;
+            let dormant = loop_state.dormant;
+            let locations_watched = loop_state.locations_watched;
+            let locations_file = loop_state.locations_file.clone();
+            let workspace_folders = loop_state.workspace_folders.clone();
+            let wait_time = loop_state.syncronize_all_open_files_wait_millis;
+            drop(loop_state);
+            if dormant {
+                if Self::has_cargo_project(workspace_folders.as_deref()) {
+                    tracing::info!("a Cargo project was created, waking up");
+                    let bacon_ls = Self {
+                        client: client.clone(),
+                        state: state.clone(),
+                    };
+                    bacon_ls.start().await;
+                }
+                tokio::time::sleep(wait_time).await;
+                continue;
+            }
+            // The changes of the locations files are notified by the client.
+            if locations_watched {
+                tokio::time::sleep(wait_time).await;
+                continue;
+            }
+            // The bacon run of a folder is considered completed when its locations file has
+            // not changed since the previous iteration.
+            let mut completed_folders = HashSet::new();
+            for folder in workspace_folders.iter().flatten() {
+                let folder_path = PathBuf::from(folder.uri.path());
+                let last_modified =
+                    Self::locations_last_modified(&locations_file, &folder_path).await;
+                let mut write_state = state.write().await;
+                let project = write_state.project(&folder_path);
+                if last_modified == project.locations_last_modified {
+                    completed_folders.insert(folder_path);
+                }
+                project.locations_last_modified = last_modified;
+            }
+            Self::publish_bacon_diagnostics(&state, client.as_ref(), &completed_folders).await;
+            tokio::time::sleep(wait_time).await;
+        }
+    }
+
+    fn parse_severity(severity_str: &str) -> DiagnosticSeverity {
+        match severity_str {
+            "warning" => DiagnosticSeverity::WARNING,
+            "info" | "information" | "note" | "failure-note" => DiagnosticSeverity::INFORMATION,
+            "hint" | "help" => DiagnosticSeverity::HINT,
+            _ => DiagnosticSeverity::ERROR,
+        }
+    }
+
+    /// Parse the cargo environment setting, either a JSON object or the legacy
+    /// `KEY=value,KEY=value` string.
+    fn parse_cargo_env(value: &serde_json::Value) -> jsonrpc::Result<HashMap<String, String>> {
+        if let Some(env) = value.as_str() {
+            return env
+                .split(',')
+                .map(str::trim)
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    pair.split_once('=')
+                        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))
+                })
+                .collect();
+        }
+        value
+            .as_object()
+            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+            .iter()
+            .map(|(key, value)| {
+                value
+                    .as_str()
+                    .map(|value| (key.clone(), value.to_string()))
+                    .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))
+            })
+            .collect()
+    }
+
+    /// Parse a list of strings coming from the LSP settings.
+    fn parse_string_list(value: &serde_json::Value) -> jsonrpc::Result<Vec<String>> {
+        value
+            .as_array()
+            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(|item| item.to_string())
+                    .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))
+            })
+            .collect()
+    }
+
+    /// Parse a severity coming from the LSP settings, rejecting unknown values.
+    fn parse_severity_setting(severity_str: &str) -> Option<DiagnosticSeverity> {
+        match severity_str {
+            "error" => Some(DiagnosticSeverity::ERROR),
+            "warning" => Some(DiagnosticSeverity::WARNING),
+            "info" | "information" => Some(DiagnosticSeverity::INFORMATION),
+            "hint" => Some(DiagnosticSeverity::HINT),
+            _ => None,
+        }
+    }
+
+    fn parse_positions(fields: &[&str]) -> Option<(u32, u32, u32, u32)> {
+        let line_start = fields.first()?.parse().ok()?;
+        let line_end = fields.get(1)?.parse().ok()?;
+        let column_start = fields.get(2)?.parse().ok()?;
+        let column_end = fields.get(3)?.parse().ok()?;
+        Some((line_start, line_end, column_start, column_end))
+    }
+
+    /// Extract the lint name from the rendered notes of a diagnostic, e.g.
+    /// ``= note: `#[warn(clippy::needless_return)]` on by default`` or
+    /// ``= note: `-D unused-variables` implied by `-D warnings` ``.
+    fn parse_lint_name(message: &str) -> Option<String> {
+        message.lines().find_map(|line| {
+            let note = line.trim_start().strip_prefix("= note: `")?;
+            let lint = if let Some(attribute) = ["#[warn(", "#[deny(", "#[forbid(", "#[expect("]
+                .iter()
+                .find_map(|prefix| note.strip_prefix(prefix))
+            {
+                attribute.split(')').next()?.to_string()
+            } else {
+                let flag = ["-D ", "-W ", "-F "]
+                    .iter()
+                    .find_map(|prefix| note.strip_prefix(prefix))?;
+                flag.split('`').next()?.replace('-', "_")
+            };
+            (lint != "warnings").then_some(lint)
+        })
+    }
+
+    fn is_proc_macro_panic(message: &str) -> bool {
+        message.starts_with("proc macro panicked")
+            || message.starts_with("proc-macro derive panicked")
+    }
+
+    /// Proc-macro panics are reported at the macro invocation with a generic message, while the
+    /// actual panic payload is only part of the rendered help. Move the payload into the first
+    /// line of the message so it is visible at a glance.
+    ///
+    /// Returns true if the message was a proc-macro panic.
+    fn enrich_proc_macro_panic(message: &mut String) -> bool {
+        let first_line = message.lines().next().unwrap_or_default();
+        if !Self::is_proc_macro_panic(first_line) {
+            return false;
+        }
+        let payload = message.lines().find_map(|line| {
+            line.trim_start()
+                .trim_start_matches('=')
+                .trim_start()
+                .strip_prefix("help: message: ")
+        });
+        if let Some(payload) = payload {
+            let enriched = format!("{first_line}: {payload}");
+            message.replace_range(..first_line.len(), &enriched);
+        }
+        true
+    }
+
+    fn parse_bacon_diagnostic_line(
+        line: &str,
+        folder_path: &Path,
+        line_format: &LineFormat,
+    ) -> Option<(Url, Diagnostic)> {
+        let fields = line_format.split(line)?;
+
+        // Parse elements from the split line
+        let severity = Self::parse_severity(fields.level);
+        let file_path = folder_path.join(fields.file_name);
+
+        // Handle potential parse errors
         let (line_start, line_end, column_start, column_end) =
-            match Self::parse_positions(&line_split[2..6]) {
+            match Self::parse_positions(&fields.positions) {
                 Some(values) => values,
                 None => {
-                    tracing::error!("error parsing diagnostic position {:?}", &line_split[2..6]);
+                    tracing::error!("error parsing diagnostic position {:?}", fields.positions);
                     return None;
                 }
             };
@@ -335,20 +2897,44 @@ impl BaconLs {
             }
         };
 
-        let mut message = line_split[6].replace("\\n", "\n");
-        let replacement = line_split[7];
-        let data = if replacement != "none" {
+        let mut rendered = fields.message.replace("\\n", "\n");
+        let code = if Self::enrich_proc_macro_panic(&mut rendered) {
+            Some(NumberOrString::String(PROC_MACRO_PANIC_CODE.to_string()))
+        } else {
+            fields
+                .code
+                .map(str::trim)
+                .filter(|code| !code.is_empty() && *code != "none")
+                .map(str::to_string)
+                .or_else(|| Self::parse_lint_name(&rendered))
+                .map(NumberOrString::String)
+        };
+
+        // Some editors truncate long messages, so the message only keeps the primary message
+        // and the full rendered output is shown on hover.
+        let (mut message, rendered) = match rendered.split_once('\n') {
+            Some((message, _)) => (message.to_string(), Some(rendered.into())),
+            None => (rendered, None),
+        };
+        let replacement = fields.replacement.unwrap_or("none");
+        let corrections = if replacement != "none" {
             tracing::debug!(
                 "storing potential quick fix code action to replace word with {replacement}"
             );
             message.push_str(": ");
             message.push_str(replacement);
-            Some(serde_json::json!(DiagnosticData {
-                corrections: vec![replacement.into()]
-            }))
+            vec![replacement.into()]
         } else {
-            None
+            vec![]
         };
+        let data = (!corrections.is_empty() || rendered.is_some()).then(|| {
+            serde_json::json!(DiagnosticData {
+                corrections,
+                edits: vec![],
+                title: None,
+                rendered,
+            })
+        });
 
         tracing::debug!(
             "new diagnostic: severity: {severity:?}, path: {path:?}, line_start: {line_start}, line_end: {line_end}, column_start: {column_start}, column_end: {column_end}, message: {message}",
@@ -361,6 +2947,7 @@ impl BaconLs {
                 Position::new(line_end - 1, column_end - 1),
             ),
             severity: Some(severity),
+            code,
             source: Some(PKG_NAME.to_string()),
             message,
             data,
@@ -380,18 +2967,42 @@ mod tests {
     use pretty_assertions::assert_eq;
     use tempdir::TempDir;
 
+    async fn locations_diagnostics(
+        uri: &Url,
+        workspace_folders: Option<&[WorkspaceFolder]>,
+    ) -> Vec<(Url, Diagnostic)> {
+        let mut indexes = HashMap::new();
+        BaconLs::refresh_locations_indexes(
+            &mut indexes,
+            LOCATIONS_FILE,
+            &LineFormat::default(),
+            workspace_folders,
+        )
+        .await;
+        BaconLs::diagnostics(&indexes, LOCATIONS_FILE, workspace_folders)
+            .remove(uri)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|diagnostic| (uri.clone(), diagnostic))
+            .collect()
+    }
+
     const ERROR_LINE: &str = "error|:|/app/github/bacon-ls/src/lib.rs|:|352|:|352|:|9|:|20|:|cannot find value `one` in this scope\n    |\n352 |         one\n    |         ^^^ help: a unit variant with a similar name exists: `None`\n    |\n   ::: /Users/matteobigoi/.rustup/toolchains/stable-aarch64-apple-darwin/lib/rustlib/src/rust/library/core/src/option.rs:576:5\n    |\n576 |     None,\n    |     ---- similarly named unit variant `None` defined here\n\nFor more information about this error, try `rustc --explain E0425`.\nerror: could not compile `bacon-ls` (lib) due to 1 previous error|:|none";
 
     #[test]
     fn test_parse_bacon_diagnostic_line_with_spans_ok() {
-        let result =
-            BaconLs::parse_bacon_diagnostic_line(ERROR_LINE, Path::new("/app/github/bacon-ls"));
+        let result = BaconLs::parse_bacon_diagnostic_line(
+            ERROR_LINE,
+            Path::new("/app/github/bacon-ls"),
+            &LineFormat::default(),
+        );
         let (url, diagnostic) = result.unwrap();
         assert_eq!(url.to_string(), "file:///app/github/bacon-ls/src/lib.rs");
         assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
         assert_eq!(diagnostic.source, Some(PKG_NAME.to_string()));
+        assert_eq!(diagnostic.message, "cannot find value `one` in this scope");
         assert_eq!(
-            diagnostic.message,
+            DiagnosticData::rendered(&diagnostic).unwrap(),
             r#"cannot find value `one` in this scope
     |
 352 |         one
@@ -405,26 +3016,392 @@ mod tests {
 For more information about this error, try `rustc --explain E0425`.
 error: could not compile `bacon-ls` (lib) due to 1 previous error"#
         );
-        let result =
-            BaconLs::parse_bacon_diagnostic_line(ERROR_LINE, Path::new("/app/github/bacon-ls"));
+        let result = BaconLs::parse_bacon_diagnostic_line(
+            ERROR_LINE,
+            Path::new("/app/github/bacon-ls"),
+            &LineFormat::default(),
+        );
         let (url, diagnostic) = result.unwrap();
         assert_eq!(url.to_string(), "file:///app/github/bacon-ls/src/lib.rs");
         assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
         assert_eq!(diagnostic.source, Some(PKG_NAME.to_string()));
     }
 
+    #[test]
+    fn test_parse_bacon_diagnostic_line_with_line_format() {
+        let line_format = LineFormat::parse("{diagnostic.code}|:|{diagnostic.level}|:|{span.file_name}|:|{span.line_start}|:|{span.line_end}|:|{span.column_start}|:|{span.column_end}|:|{diagnostic.message}").unwrap();
+        let (url, diagnostic) = BaconLs::parse_bacon_diagnostic_line(
+            "E0425|:|error|:|src/lib.rs|:|352|:|352|:|9|:|12|:|cannot find value `one` in this scope",
+            Path::new("/app/github/bacon-ls"),
+            &line_format,
+        )
+        .unwrap();
+        assert_eq!(url.to_string(), "file:///app/github/bacon-ls/src/lib.rs");
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String("E0425".to_string()))
+        );
+        assert_eq!(diagnostic.message, "cannot find value `one` in this scope");
+        assert_eq!(diagnostic.data, None);
+
+        let (_, diagnostic) = BaconLs::parse_bacon_diagnostic_line(
+            "warning|:|src/lib.rs|:|3|:|3|:|5|:|16|:|clippy::needless_return|:|unneeded `return` statement|:|none",
+            Path::new("/app/github/bacon-ls"),
+            &LineFormat::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String(
+                "clippy::needless_return".to_string()
+            ))
+        );
+        assert_eq!(diagnostic.message, "unneeded `return` statement");
+    }
+
     #[test]
     fn test_parse_bacon_diagnostic_line_with_spans_ko() {
         // Unparsable line
         let result = BaconLs::parse_bacon_diagnostic_line(
             "warning:/file:1:1",
             Path::new("/app/github/bacon-ls"),
+            &LineFormat::default(),
+        );
+        assert_eq!(result, None);
+
+        // Empty line
+        let result = BaconLs::parse_bacon_diagnostic_line(
+            "",
+            Path::new("/app/github/bacon-ls"),
+            &LineFormat::default(),
+        );
+        assert_eq!(result, None);
+    }
+
+    // TODO: I need a windows machine to understand why this test fails. I am pretty sure it's
+    // because of how the Url is handled in Windows compared to *NIX, but until I don't have a
+    // proper test bed Windows support is probably broken.
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_multiline_diagnostics_production() {
+        let tmp_dir = TempDir::new("bacon-ls").unwrap();
+        let file_path = tmp_dir.path().join(".bacon-locations");
+        let mut tmp_file = std::fs::File::create(file_path).unwrap();
+        let error_path = format!("{}/src/lib.rs", tmp_dir.path().display());
+        let error_path_url = Url::from_str(&format!("file://{error_path}")).unwrap();
+        writeln!(
+            tmp_file,
+            "warning|:|src/lib.rs|:|130|:|142|:|33|:|34|:|this if statement can be collapsed|:|none"
+        )
+        .unwrap();
+        writeln!(
+            tmp_file,
+            r#"help|:|{error_path}|:|130|:|142|:|33|:|34|:|collapse nested if block|:|if Some(&path) == uri && !diagnostics.iter().any(
+                                        |(existing_path, existing_diagnostic)| {{
+                                            existing_path.path() == path.path()
+                                                && diagnostic.range == existing_diagnostic.range
+                                                && diagnostic.severity
+                                                    == existing_diagnostic.severity
+                                                && diagnostic.message == existing_diagnostic.message
+                                        }},
+                                    ) {{
+                                    diagnostics.push((path, diagnostic));
+                                }}"#
+        ).unwrap();
+        writeln!(
+            tmp_file,
+            "warning|:|{error_path}|:|150|:|162|:|33|:|34|:|this if statement can be collapsed|:|none"
+        )
+        .unwrap();
+        writeln!(
+            tmp_file,
+            r#"help|:|{error_path}|:|150|:|162|:|33|:|34|:|collapse nested if block|:|if Some(&path) == uri && !diagnostics.iter().any(
+                                        |(existing_path, existing_diagnostic)| {{
+                                            existing_path.path() == path.path()
+                                                && diagnostic.range == existing_diagnostic.range
+                                                && diagnostic.severity
+                                                    == existing_diagnostic.severity
+                                                && diagnostic.message == existing_diagnostic.message
+                                        }},
+                                    ) {{
+                                    diagnostics.push((path, diagnostic));
+                                }}"#
+        ).unwrap();
+        // let bacon_ls = BaconLs::default();
+        // let mut state = bacon_ls.state.write().await;
+        let workspace_folders = Some(vec![WorkspaceFolder {
+            name: tmp_dir.path().display().to_string(),
+            uri: Url::from_directory_path(tmp_dir.path()).unwrap(),
+        }]);
+        let diagnostics =
+            locations_diagnostics(&error_path_url, workspace_folders.as_deref()).await;
+        assert_eq!(diagnostics.len(), 4);
+        assert!(diagnostics[0].1.data.is_none());
+        assert_eq!(diagnostics[0].1.message.len(), 34);
+        assert!(diagnostics[1].1.data.is_some());
+        assert_eq!(diagnostics[1].1.message.len(), 780);
+        assert!(diagnostics[2].1.data.is_none());
+        assert_eq!(diagnostics[2].1.message.len(), 34);
+        assert!(diagnostics[3].1.data.is_some());
+        assert_eq!(diagnostics[3].1.message.len(), 780);
+    }
+
+    // TODO: I need a windows machine to understand why this test fails. I am pretty sure it's
+    // because of how the Url is handled in Windows compared to *NIX, but until I don't have a
+    // proper test bed Windows support is probably broken.
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_diagnostics_production_and_deduplication() {
+        let tmp_dir = TempDir::new("bacon-ls").unwrap();
+        let file_path = tmp_dir.path().join(".bacon-locations");
+        let mut tmp_file = std::fs::File::create(file_path).unwrap();
+        let error_path = format!("{}/src/lib.rs", tmp_dir.path().display());
+        let error_path_url = Url::from_str(&format!("file://{error_path}")).unwrap();
+        writeln!(
+            tmp_file,
+            "error|:|{error_path}|:|352|:|352|:|9|:|20|:|cannot find value `one` in this scope|:|none"
+        )
+        .unwrap();
+        // duplicate the line
+        writeln!(
+            tmp_file,
+            "error|:|{error_path}|:|352|:|352|:|9|:|20|:|cannot find value `one` in this scope|:|none"
+        )
+        .unwrap();
+        writeln!(
+            tmp_file,
+            "warning|:|{error_path}|:|354|:|354|:|9|:|20|:|cannot find value `two` in this scope|:|some"
+        )
+        .unwrap();
+        writeln!(
+            tmp_file,
+            "help|:|{error_path}|:|356|:|356|:|9|:|20|:|cannot find value `three` in this scope|:|some other"
+        )
+        .unwrap();
+
+        let workspace_folders = Some(vec![WorkspaceFolder {
+            name: tmp_dir.path().display().to_string(),
+            uri: Url::from_directory_path(tmp_dir.path()).unwrap(),
+        }]);
+        let diagnostics =
+            locations_diagnostics(&error_path_url, workspace_folders.as_deref()).await;
+        assert_eq!(diagnostics.len(), 3);
+        let state = Arc::new(RwLock::new(State {
+            workspace_folders,
+            ..State::default()
+        }));
+        BaconLs::update_bacon_diagnostics(&state).await;
+        let state = state.read().await;
+        let file = state.diagnostics_store.get(&error_path_url).unwrap();
+        assert_eq!(file.source(BACON_SOURCE).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_diagnostics() {
+        let tmp_dir = TempDir::new("bacon-ls").unwrap();
+        let error_path = format!("{}/src/lib.rs", tmp_dir.path().display());
+        let error_path_url = Url::from_str(&format!("file://{error_path}")).unwrap();
+        let line = |line: u32, message: &str| {
+            format!("warning|:|{error_path}|:|{line}|:|{line}|:|1|:|2|:|{message}|:|none\n")
+        };
+        let state = State {
+            bacon_jobs: vec![BACON_JOB.to_string(), "bacon-ls-test".to_string()],
+            ..State::default()
+        };
+        let jobs_locations_files = state.jobs_locations_files();
+        assert_eq!(
+            jobs_locations_files,
+            vec![
+                (BACON_JOB.to_string(), LOCATIONS_FILE.to_string()),
+                (
+                    "bacon-ls-test".to_string(),
+                    format!("{LOCATIONS_FILE}-bacon-ls-test")
+                ),
+            ]
+        );
+        std::fs::write(
+            tmp_dir.path().join(LOCATIONS_FILE),
+            line(1, "unused import"),
+        )
+        .unwrap();
+        std::fs::write(
+            tmp_dir.path().join(&jobs_locations_files[1].1),
+            line(1, "unused import") + &line(5, "unused variable"),
+        )
+        .unwrap();
+        let workspace_folders = [WorkspaceFolder {
+            uri: Url::from_directory_path(tmp_dir.path()).unwrap(),
+            name: "bacon-ls".to_string(),
+        }];
+        let mut indexes = HashMap::new();
+        for (_, locations_file) in jobs_locations_files.iter() {
+            BaconLs::refresh_locations_indexes(
+                &mut indexes,
+                locations_file,
+                &LineFormat::default(),
+                Some(&workspace_folders),
+            )
+            .await;
+        }
+        let diagnostics: Vec<(String, Option<String>)> =
+            BaconLs::jobs_diagnostics(&indexes, &jobs_locations_files, Some(&workspace_folders))
+                .remove(&error_path_url)
+                .unwrap()
+                .into_iter()
+                .map(|diagnostic| (diagnostic.message, diagnostic.source))
+                .collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                ("unused import".to_string(), Some(BACON_JOB.to_string())),
+                (
+                    "unused variable".to_string(),
+                    Some("bacon-ls-test".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_locations_index_parses_appended_lines() {
+        let tmp_dir = TempDir::new("bacon-ls").unwrap();
+        let bacon_locations = tmp_dir.path().join(LOCATIONS_FILE);
+        let error_path = format!("{}/src/lib.rs", tmp_dir.path().display());
+        let error_path_url = Url::from_str(&format!("file://{error_path}")).unwrap();
+        let line = |line: u32, message: &str| {
+            format!("warning|:|{error_path}|:|{line}|:|{line}|:|1|:|2|:|{message}|:|none")
+        };
+        let mut index = LocationsIndex::default();
+        let messages = |index: &LocationsIndex| {
+            let mut by_file = HashMap::new();
+            index.diagnostics(tmp_dir.path(), &mut by_file);
+            by_file
+                .remove(&error_path_url)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(_, diagnostic)| diagnostic.message)
+                .collect::<Vec<_>>()
+        };
+
+        std::fs::write(&bacon_locations, format!("{}\n", line(1, "first"))).unwrap();
+        assert!(index.refresh(&bacon_locations, tmp_dir.path()).await);
+        assert_eq!(messages(&index), vec!["first"]);
+        assert!(!index.refresh(&bacon_locations, tmp_dir.path()).await);
+
+        // A line being written is only served once bacon stopped writing, and parsed again
+        // once complete.
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&bacon_locations)
+            .unwrap();
+        write!(file, "{}", &line(2, "second")[..20]).unwrap();
+        index.refresh(&bacon_locations, tmp_dir.path()).await;
+        assert_eq!(messages(&index), vec!["first"]);
+        write!(file, "{}", &line(2, "second")[20..]).unwrap();
+        file.set_modified(SystemTime::now() - LOCATIONS_QUIESCENCE)
+            .unwrap();
+        index.refresh(&bacon_locations, tmp_dir.path()).await;
+        assert_eq!(messages(&index), vec!["first", "second"]);
+        let offset = index.offset;
+        writeln!(file, "\n{}", line(3, "third")).unwrap();
+        index.refresh(&bacon_locations, tmp_dir.path()).await;
+        assert_eq!(messages(&index), vec!["first", "second", "third"]);
+        assert!(index.offset > offset);
+        assert_eq!(index.entries.len(), 2);
+
+        // A new bacon run rewrites the file from the start.
+        std::fs::write(&bacon_locations, format!("{}\n", line(4, "fourth"))).unwrap();
+        index.refresh(&bacon_locations, tmp_dir.path()).await;
+        assert_eq!(messages(&index), vec!["fourth"]);
+
+        // A rewrite of the same length and with the same last bytes, moving the diagnostic
+        // from line 12 to 13.
+        let message = "long message ".repeat(10);
+        std::fs::write(&bacon_locations, format!("{}\n", line(12, &message))).unwrap();
+        index.refresh(&bacon_locations, tmp_dir.path()).await;
+        std::fs::write(&bacon_locations, format!("{}\n", line(13, &message))).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&bacon_locations)
+            .unwrap()
+            .set_modified(SystemTime::now() - LOCATIONS_QUIESCENCE)
+            .unwrap();
+        index.refresh(&bacon_locations, tmp_dir.path()).await;
+        let mut by_file = HashMap::new();
+        index.diagnostics(tmp_dir.path(), &mut by_file);
+        let lines: Vec<u32> = by_file[&error_path_url]
+            .iter()
+            .map(|(_, diagnostic)| diagnostic.range.start.line)
+            .collect();
+        assert_eq!(lines, vec![12]);
+
+        std::fs::remove_file(&bacon_locations).unwrap();
+        assert!(index.refresh(&bacon_locations, tmp_dir.path()).await);
+        assert!(messages(&index).is_empty());
+    }
+
+    #[test]
+    fn test_parse_bacon_diagnostic_line_proc_macro_panic() {
+        let line = "error|:|src/main.rs|:|3|:|3|:|10|:|15|:|proc macro panicked\n --> src/main.rs:3:10\n  |\n3 | #[derive(Bacon)]\n  |          ^^^^^\n  |\n  = help: message: bacon is not crispy enough|:|none";
+        let (_, diagnostic) = BaconLs::parse_bacon_diagnostic_line(
+            line,
+            Path::new("/app/github/bacon-ls"),
+            &LineFormat::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String(PROC_MACRO_PANIC_CODE.to_string()))
+        );
+        assert_eq!(
+            diagnostic.message,
+            "proc macro panicked: bacon is not crispy enough"
+        );
+        assert!(DiagnosticData::rendered(&diagnostic)
+            .unwrap()
+            .ends_with("= help: message: bacon is not crispy enough"));
+
+        let (_, diagnostic) = BaconLs::parse_bacon_diagnostic_line(
+            ERROR_LINE,
+            Path::new("/app/github/bacon-ls"),
+            &LineFormat::default(),
+        )
+        .unwrap();
+        assert_eq!(diagnostic.code, None);
+    }
+
+    #[test]
+    fn test_convert_column() {
+        let line = "let 🐽 = \"bacon\";";
+        assert_eq!(
+            BaconLs::convert_column(line, 8, &PositionEncodingKind::UTF32),
+            8
+        );
+        assert_eq!(
+            BaconLs::convert_column(line, 8, &PositionEncodingKind::UTF16),
+            9
+        );
+        assert_eq!(
+            BaconLs::convert_column(line, 8, &PositionEncodingKind::UTF8),
+            11
+        );
+        assert_eq!(
+            BaconLs::convert_column("abc", 5, &PositionEncodingKind::UTF8),
+            5
+        );
+    }
+
+    #[test]
+    fn test_parse_severity_setting() {
+        assert_eq!(
+            BaconLs::parse_severity_setting("warning"),
+            Some(DiagnosticSeverity::WARNING)
+        );
+        assert_eq!(
+            BaconLs::parse_severity_setting("info"),
+            Some(DiagnosticSeverity::INFORMATION)
         );
-        assert_eq!(result, None);
-
-        // Empty line
-        let result = BaconLs::parse_bacon_diagnostic_line("", Path::new("/app/github/bacon-ls"));
-        assert_eq!(result, None);
+        assert_eq!(BaconLs::parse_severity_setting("warn"), None);
     }
 
     // TODO: I need a windows machine to understand why this test fails. I am pretty sure it's
@@ -432,7 +3409,7 @@ error: could not compile `bacon-ls` (lib) due to 1 previous error"#
     // proper test bed Windows support is probably broken.
     #[cfg(not(target_os = "windows"))]
     #[tokio::test]
-    async fn test_multiline_diagnostics_production() {
+    async fn test_multi_span_suggestion_is_merged() {
         let tmp_dir = TempDir::new("bacon-ls").unwrap();
         let file_path = tmp_dir.path().join(".bacon-locations");
         let mut tmp_file = std::fs::File::create(file_path).unwrap();
@@ -440,71 +3417,112 @@ error: could not compile `bacon-ls` (lib) due to 1 previous error"#
         let error_path_url = Url::from_str(&format!("file://{error_path}")).unwrap();
         writeln!(
             tmp_file,
-            "warning|:|src/lib.rs|:|130|:|142|:|33|:|34|:|this if statement can be collapsed|:|none"
+            "error|:|{error_path}|:|10|:|10|:|5|:|8|:|cannot find function `bar` in this scope|:|none"
         )
         .unwrap();
         writeln!(
             tmp_file,
-            r#"help|:|{error_path}|:|130|:|142|:|33|:|34|:|collapse nested if block|:|if Some(&path) == uri && !diagnostics.iter().any(
-                                        |(existing_path, existing_diagnostic)| {{
-                                            existing_path.path() == path.path()
-                                                && diagnostic.range == existing_diagnostic.range
-                                                && diagnostic.severity
-                                                    == existing_diagnostic.severity
-                                                && diagnostic.message == existing_diagnostic.message
-                                        }},
-                                    ) {{
-                                    diagnostics.push((path, diagnostic));
-                                }}"#
-        ).unwrap();
-        writeln!(
-            tmp_file,
-            "warning|:|{error_path}|:|150|:|162|:|33|:|34|:|this if statement can be collapsed|:|none"
+            "help|:|{error_path}|:|1|:|1|:|1|:|1|:|consider importing this function|:|use foo::bar;"
         )
         .unwrap();
         writeln!(
             tmp_file,
-            r#"help|:|{error_path}|:|150|:|162|:|33|:|34|:|collapse nested if block|:|if Some(&path) == uri && !diagnostics.iter().any(
-                                        |(existing_path, existing_diagnostic)| {{
-                                            existing_path.path() == path.path()
-                                                && diagnostic.range == existing_diagnostic.range
-                                                && diagnostic.severity
-                                                    == existing_diagnostic.severity
-                                                && diagnostic.message == existing_diagnostic.message
-                                        }},
-                                    ) {{
-                                    diagnostics.push((path, diagnostic));
-                                }}"#
-        ).unwrap();
-        // let bacon_ls = BaconLs::default();
-        // let mut state = bacon_ls.state.write().await;
+            "help|:|{error_path}|:|10|:|10|:|5|:|8|:|consider importing this function|:|foo::bar"
+        )
+        .unwrap();
+
         let workspace_folders = Some(vec![WorkspaceFolder {
             name: tmp_dir.path().display().to_string(),
             uri: Url::from_directory_path(tmp_dir.path()).unwrap(),
         }]);
-        let diagnostics = BaconLs::diagnostics(
-            Some(&error_path_url),
-            LOCATIONS_FILE,
-            workspace_folders.as_deref(),
-        )
-        .await;
-        assert_eq!(diagnostics.len(), 4);
-        assert!(diagnostics[0].1.data.is_none());
-        assert_eq!(diagnostics[0].1.message.len(), 34);
-        assert!(diagnostics[1].1.data.is_some());
-        assert_eq!(diagnostics[1].1.message.len(), 780);
-        assert!(diagnostics[2].1.data.is_none());
-        assert_eq!(diagnostics[2].1.message.len(), 34);
-        assert!(diagnostics[3].1.data.is_some());
-        assert_eq!(diagnostics[3].1.message.len(), 780);
+        let diagnostics =
+            locations_diagnostics(&error_path_url, workspace_folders.as_deref()).await;
+        assert_eq!(diagnostics.len(), 2);
+        let data: DiagnosticData =
+            serde_json::from_value(diagnostics[1].1.data.clone().unwrap()).unwrap();
+        assert_eq!(data.edits.len(), 2);
+        assert_eq!(data.edits[0].new_text, "use foo::bar;");
+        assert_eq!(data.edits[1].range.start, Position::new(9, 4));
+        assert_eq!(data.edits[1].new_text, "foo::bar");
+
+        let actions = BaconLs::code_actions(&error_path_url, &diagnostics[1].1, None);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(
+            actions[0].title,
+            "consider importing this function: `use foo::bar;`"
+        );
+        let changes = actions[0].edit.as_ref().unwrap().changes.as_ref().unwrap();
+        assert_eq!(changes[&error_path_url].len(), 2);
+        assert_eq!(actions[0].is_preferred, Some(true));
+        assert!(BaconLs::code_actions(&error_path_url, &diagnostics[0].1, None).is_empty());
     }
 
-    // TODO: I need a windows machine to understand why this test fails. I am pretty sure it's
-    // because of how the Url is handled in Windows compared to *NIX, but until I don't have a
-    // proper test bed Windows support is probably broken.
     #[cfg(not(target_os = "windows"))]
     #[tokio::test]
-    async fn test_diagnostics_production_and_deduplication() {
+    async fn test_independent_suggestions_are_not_merged() {
+        let tmp_dir = TempDir::new("bacon-ls").unwrap();
+        let file_path = tmp_dir.path().join(".bacon-locations");
+        let mut tmp_file = std::fs::File::create(file_path).unwrap();
+        let error_path = format!("{}/src/lib.rs", tmp_dir.path().display());
+        let error_path_url = Url::from_str(&format!("file://{error_path}")).unwrap();
+        let warning = |line: u32| {
+            format!("warning|:|{error_path}|:|{line}|:|{line}|:|5|:|16|:|unneeded `return` statement\\n  |\\n  = note: `#[warn(clippy::needless_return)]` on by default|:|none")
+        };
+        let help = |line: u32, replacement: &str| {
+            format!(
+                "help|:|{error_path}|:|{line}|:|{line}|:|5|:|16|:|remove `return`|:|{replacement}"
+            )
+        };
+        // The same lint at two sites, the parent of the last help being a duplicate, as when a
+        // file is checked for several targets: each help stays a separate fix.
+        for line in [
+            warning(7),
+            help(7, "y"),
+            warning(3),
+            help(3, "x"),
+            warning(7),
+            help(8, "z"),
+        ] {
+            writeln!(tmp_file, "{line}").unwrap();
+        }
+
+        let workspace_folders = Some(vec![WorkspaceFolder {
+            name: tmp_dir.path().display().to_string(),
+            uri: Url::from_directory_path(tmp_dir.path()).unwrap(),
+        }]);
+        let diagnostics =
+            locations_diagnostics(&error_path_url, workspace_folders.as_deref()).await;
+        assert_eq!(diagnostics.len(), 5);
+        let actions: Vec<(String, usize)> = diagnostics
+            .iter()
+            .flat_map(|(_, diagnostic)| BaconLs::code_actions(&error_path_url, diagnostic, None))
+            .map(|action| {
+                let edits = action.edit.unwrap().changes.unwrap()[&error_path_url].len();
+                (action.title, edits)
+            })
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                (
+                    "clippy::needless_return: remove `return`: `y`".to_string(),
+                    1
+                ),
+                (
+                    "clippy::needless_return: remove `return`: `x`".to_string(),
+                    1
+                ),
+                (
+                    "clippy::needless_return: remove `return`: `z`".to_string(),
+                    1
+                ),
+            ]
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_help_inherits_lint() {
         let tmp_dir = TempDir::new("bacon-ls").unwrap();
         let file_path = tmp_dir.path().join(".bacon-locations");
         let mut tmp_file = std::fs::File::create(file_path).unwrap();
@@ -512,23 +3530,12 @@ error: could not compile `bacon-ls` (lib) due to 1 previous error"#
         let error_path_url = Url::from_str(&format!("file://{error_path}")).unwrap();
         writeln!(
             tmp_file,
-            "error|:|{error_path}|:|352|:|352|:|9|:|20|:|cannot find value `one` in this scope|:|none"
-        )
-        .unwrap();
-        // duplicate the line
-        writeln!(
-            tmp_file,
-            "error|:|{error_path}|:|352|:|352|:|9|:|20|:|cannot find value `one` in this scope|:|none"
-        )
-        .unwrap();
-        writeln!(
-            tmp_file,
-            "warning|:|{error_path}|:|354|:|354|:|9|:|20|:|cannot find value `two` in this scope|:|some"
+            "warning|:|{error_path}|:|3|:|3|:|5|:|16|:|unneeded `return` statement\\n  |\\n  = note: `#[warn(clippy::needless_return)]` on by default|:|none"
         )
         .unwrap();
         writeln!(
             tmp_file,
-            "help|:|{error_path}|:|356|:|356|:|9|:|20|:|cannot find value `three` in this scope|:|some other"
+            "help|:|{error_path}|:|3|:|3|:|5|:|16|:|remove `return`|:|x"
         )
         .unwrap();
 
@@ -536,24 +3543,546 @@ error: could not compile `bacon-ls` (lib) due to 1 previous error"#
             name: tmp_dir.path().display().to_string(),
             uri: Url::from_directory_path(tmp_dir.path()).unwrap(),
         }]);
-        let diagnostics = BaconLs::diagnostics(
-            Some(&error_path_url),
-            LOCATIONS_FILE,
-            workspace_folders.as_deref(),
-        )
-        .await;
+        let diagnostics =
+            locations_diagnostics(&error_path_url, workspace_folders.as_deref()).await;
+        assert_eq!(diagnostics.len(), 2);
+        let lint = Some(NumberOrString::String(
+            "clippy::needless_return".to_string(),
+        ));
+        assert_eq!(diagnostics[0].1.code, lint);
+        assert_eq!(diagnostics[1].1.code, lint);
+        let actions = BaconLs::code_actions(&error_path_url, &diagnostics[1].1, None);
+        assert_eq!(
+            actions[0].title,
+            "clippy::needless_return: remove `return`: `x`"
+        );
+    }
+
+    #[test]
+    fn test_label_source() {
+        let diagnostic = |source: &str| Diagnostic {
+            source: Some(source.to_string()),
+            ..Diagnostic::default()
+        };
+        let label = |with_backend, backend, source| {
+            BaconLs::label_source("bacon-ls", with_backend, backend, &diagnostic(source)).source
+        };
+        assert_eq!(
+            label(false, "rustfmt", "rustfmt"),
+            Some("bacon-ls".to_string())
+        );
+        assert_eq!(
+            label(true, "rustfmt", "rustfmt"),
+            Some("bacon-ls:rustfmt".to_string())
+        );
+        assert_eq!(
+            label(true, BACON_SOURCE, PKG_NAME),
+            Some("bacon-ls:bacon".to_string())
+        );
+    }
+
+    #[test]
+    fn test_merge_provider_diagnostics() {
+        let diagnostic = |source: &str, line: u32| Diagnostic {
+            range: Range::new(Position::new(line, 0), Position::new(line, 4)),
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some(source.to_string()),
+            message: "mismatched types".to_string(),
+            ..Diagnostic::default()
+        };
+        let merged = BaconLs::merge_provider_diagnostics(
+            vec![diagnostic(PKG_NAME, 1)],
+            vec![
+                diagnostic("cargo", 1),
+                diagnostic("cargo", 2),
+                diagnostic("dx", 2),
+            ],
+        );
+        assert_eq!(
+            merged,
+            vec![diagnostic(PKG_NAME, 1), diagnostic("cargo", 2)]
+        );
+    }
+
+    #[test]
+    fn test_bacon_health() {
+        let first = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let second = SystemTime::UNIX_EPOCH + Duration::from_secs(2);
+        let mut health = BaconHealth {
+            fallback_after_saves: 2,
+            ..BaconHealth::default()
+        };
+        assert!(!health.record_save(Some(first)));
+        assert!(!health.record_save(Some(first)));
+        assert!(health.record_save(Some(first)));
+        assert!(health.record_save(None));
+        assert!(!health.record_save(Some(second)));
+
+        let mut disabled = BaconHealth::default();
+        assert!(!disabled.record_save(None));
+        assert!(!disabled.record_save(None));
+    }
+
+    #[test]
+    fn test_status_diagnostics() {
+        let status_problems = BTreeMap::from([
+            (
+                "cargo-audit".to_string(),
+                vec!["failed to run cargo audit".to_string()],
+            ),
+            (
+                "bacon".to_string(),
+                vec![
+                    "invalid preferences".to_string(),
+                    "bacon not found".to_string(),
+                ],
+            ),
+        ]);
+        let diagnostics = BaconLs::status_diagnostics(&status_problems);
         assert_eq!(diagnostics.len(), 3);
-        let diagnostics_vec = BaconLs::diagnostics_vec(
-            Some(&error_path_url),
-            LOCATIONS_FILE,
-            workspace_folders.as_deref(),
-        )
-        .await;
-        assert_eq!(diagnostics_vec.len(), 3);
+        assert_eq!(diagnostics[0].message, "invalid preferences");
+        assert_eq!(
+            diagnostics[0].code,
+            Some(NumberOrString::String("bacon".to_string()))
+        );
+        assert_eq!(diagnostics[2].range.start, Position::new(2, 0));
+        assert_eq!(diagnostics[2].message, "failed to run cargo audit");
+    }
+
+    #[test]
+    fn test_server_status() {
+        let mut status_problems = BTreeMap::new();
+        assert_eq!(
+            BaconLs::server_status(&status_problems),
+            ServerStatusParams {
+                health: "ok".to_string(),
+                message: None
+            }
+        );
+        status_problems.insert("bacon".to_string(), vec!["invalid preferences".to_string()]);
+        status_problems.insert("rustfmt".to_string(), vec!["cargo fmt failed".to_string()]);
+        assert_eq!(
+            BaconLs::server_status(&status_problems),
+            ServerStatusParams {
+                health: "warning".to_string(),
+                message: Some("invalid preferences\ncargo fmt failed".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_publish_trace_line() {
+        let uri = Url::parse("file:///app/src/lib.rs").unwrap();
+        let diagnostics = vec![Diagnostic {
+            message: "bacon".to_string(),
+            ..Diagnostic::default()
+        }];
+        let line =
+            BaconLs::publish_trace_line(Duration::from_millis(42), &uri, Some(3), &diagnostics);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["timestamp"], 42);
+        assert_eq!(value["uri"], "file:///app/src/lib.rs");
+        assert_eq!(value["version"], 3);
+        assert_eq!(value["count"], 1);
+        assert_eq!(
+            value["hash"],
+            serde_json::from_str::<serde_json::Value>(&BaconLs::publish_trace_line(
+                Duration::from_millis(43),
+                &uri,
+                None,
+                &diagnostics
+            ))
+            .unwrap()["hash"]
+        );
+    }
+
+    #[test]
+    fn test_parse_lint_name() {
+        assert_eq!(
+            BaconLs::parse_lint_name(
+                "unneeded `return` statement\n  |\n  = note: `#[warn(clippy::needless_return)]` on by default"
+            ),
+            Some("clippy::needless_return".to_string())
+        );
+        assert_eq!(
+            BaconLs::parse_lint_name(
+                "unused variable: `x`\n  |\n  = note: `-D unused-variables` implied by `-D warnings`"
+            ),
+            Some("unused_variables".to_string())
+        );
+        assert_eq!(BaconLs::parse_lint_name(ERROR_LINE), None);
+    }
+
+    #[test]
+    fn test_diagnostics_filter() {
+        let diagnostic = |severity, lint: Option<&str>| Diagnostic {
+            severity: Some(severity),
+            code: lint.map(|lint| NumberOrString::String(lint.to_string())),
+            ..Diagnostic::default()
+        };
+        let diagnostics = vec![
+            diagnostic(DiagnosticSeverity::ERROR, None),
+            diagnostic(DiagnosticSeverity::WARNING, Some("clippy::needless_return")),
+            diagnostic(DiagnosticSeverity::HINT, None),
+            diagnostic(DiagnosticSeverity::WARNING, Some("unused_variables")),
+            diagnostic(DiagnosticSeverity::INFORMATION, None),
+        ];
+
+        let filter = DiagnosticsFilter::default();
+        assert_eq!(filter.apply(diagnostics.clone()).len(), 5);
+
+        let filter = DiagnosticsFilter {
+            minimum_severity: Some(DiagnosticSeverity::WARNING),
+            ..DiagnosticsFilter::default()
+        };
+        assert_eq!(filter.apply(diagnostics.clone()).len(), 3);
+
+        // The help following the ignored lint is hidden as well.
+        let filter = DiagnosticsFilter {
+            ignored_lints: vec!["clippy::needless_return".to_string()],
+            ..DiagnosticsFilter::default()
+        };
+        assert_eq!(
+            filter.apply(diagnostics.clone()),
+            vec![
+                diagnostics[0].clone(),
+                diagnostics[3].clone(),
+                diagnostics[4].clone()
+            ]
+        );
+
+        // Diagnostics without a lint, like compilation errors, are always kept.
+        let filter = DiagnosticsFilter {
+            allowed_lints: vec!["clippy::needless_return".to_string()],
+            ..DiagnosticsFilter::default()
+        };
+        assert_eq!(
+            filter.apply(diagnostics.clone()),
+            vec![
+                diagnostics[0].clone(),
+                diagnostics[1].clone(),
+                diagnostics[2].clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fallback_diagnostics_are_filtered() {
+        let diagnostic = |lint: &str| Diagnostic {
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(lint.to_string())),
+            ..Diagnostic::default()
+        };
+        let uri = Url::parse("file:///project/src/lib.rs").unwrap();
+        let mut state = State {
+            diagnostics_filter: DiagnosticsFilter {
+                ignored_lints: vec!["clippy::needless_return".to_string()],
+                ..DiagnosticsFilter::default()
+            },
+            ..State::default()
+        };
+        let needless_return = diagnostic("clippy::needless_return");
+        let unused_variables = diagnostic("unused_variables");
+        state.diagnostics_store.set(
+            FALLBACK_PROVIDER,
+            &uri,
+            vec![needless_return.clone(), unused_variables.clone()],
+        );
+        state
+            .diagnostics_store
+            .set("typos", &uri, vec![needless_return.clone()]);
+        let file = state.diagnostics_store.get(&uri).unwrap();
+        // The other providers are not filtered.
+        assert_eq!(
+            state.provider_diagnostics(file),
+            vec![
+                (FALLBACK_PROVIDER, unused_variables),
+                ("typos", needless_return)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_saves_are_debounced() {
+        let url =
+            |name: &str| Url::parse(&format!("file:///projects/bacon-ls/src/{name}")).unwrap();
+        let state = Arc::new(RwLock::new(State {
+            update_on_save: false,
+            update_on_save_wait_millis: Duration::from_millis(50),
+            ..State::default()
+        }));
+        for name in ["lib.rs", "main.rs", "lib.rs"] {
+            BaconLs::schedule_save(None, state.clone(), url(name)).await;
+        }
+        assert_eq!(state.read().await.pending_saves.len(), 2);
+        // Each save postpones the update.
+        for name in ["build.rs", "main.rs"] {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            BaconLs::schedule_save(None, state.clone(), url(name)).await;
+        }
+        assert_eq!(state.read().await.pending_saves.len(), 3);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(state.read().await.pending_saves.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_debounced() {
+        let url =
+            |name: &str| Url::parse(&format!("file:///projects/bacon-ls/src/{name}")).unwrap();
+        let state = Arc::new(RwLock::new(State {
+            update_on_change_cooldown_millis: Duration::from_millis(50),
+            ..State::default()
+        }));
+        for name in ["lib.rs", "main.rs", "lib.rs"] {
+            BaconLs::schedule_change(None, state.clone(), url(name)).await;
+        }
+        assert_eq!(state.read().await.change_deadlines.len(), 2);
+        // Each change postpones the publish of its document only.
+        for _ in 0..2 {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            BaconLs::schedule_change(None, state.clone(), url("lib.rs")).await;
+        }
+        let pending: Vec<Url> = state
+            .read()
+            .await
+            .change_deadlines
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(pending, vec![url("lib.rs")]);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(state.read().await.change_deadlines.is_empty());
+    }
+
+    /// Provider sleeping in each folder, which fails in the folders named `broken`.
+    #[derive(Debug, Default)]
+    struct SlowProvider {
+        running: std::sync::atomic::AtomicUsize,
+        max_running: std::sync::atomic::AtomicUsize,
+    }
+
+    #[tower_lsp::async_trait]
+    impl DiagnosticsProvider for SlowProvider {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        fn runs_on_save(&self, _uri: &Url) -> bool {
+            true
+        }
+
+        async fn run(
+            &self,
+            folder_path: &Path,
+            _env: &HashMap<String, String>,
+        ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+            use std::sync::atomic::Ordering;
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            match folder_path.ends_with("broken") {
+                true => Err(BaconLsError::Spawn("slow failed".to_string())),
+                false => Ok(HashMap::new()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_folders_run_concurrently() {
+        let folder = |path: &str| WorkspaceFolder {
+            uri: Url::parse(&format!("file:///projects/{path}")).unwrap(),
+            name: path.to_string(),
+        };
+        let provider = Arc::new(SlowProvider::default());
+        let state = Arc::new(RwLock::new(State {
+            workspace_folders: Some(vec![folder("app"), folder("broken"), folder("lib")]),
+            providers: vec![provider.clone()],
+            folder_parallelism: 2,
+            server_status_notification: true,
+            ..State::default()
+        }));
+        BaconLs::run_providers(None, state.clone(), None).await;
+        assert_eq!(
+            provider
+                .max_running
+                .load(std::sync::atomic::Ordering::SeqCst),
+            2
+        );
+        // The errors of a folder are reported in its own status entry.
+        let origins: Vec<String> = state.read().await.status_problems.keys().cloned().collect();
+        assert_eq!(origins, vec!["slow (broken)"]);
+    }
+
+    #[test]
+    fn test_projects() {
+        let folder = |path: &str| WorkspaceFolder {
+            uri: Url::from_directory_path(path).unwrap(),
+            name: path.to_string(),
+        };
+        let workspace_folders = vec![folder("/projects/bacon-ls"), folder("/projects/other")];
+        let url = |path: &str| Url::parse(&format!("file://{path}")).unwrap();
+        assert_eq!(
+            BaconLs::folders_of(
+                &[
+                    url("/projects/other/src/lib.rs"),
+                    url("/projects/bacon-ls/src/lib.rs"),
+                    url("/projects/other/Cargo.toml"),
+                ],
+                Some(&workspace_folders)
+            ),
+            vec![
+                PathBuf::from("/projects/bacon-ls/"),
+                PathBuf::from("/projects/other/")
+            ]
+        );
+
+        // Each folder has its own bacon health, with the configured fallback.
+        let mut state = State {
+            bacon_fallback_after_saves: 1,
+            ..State::default()
+        };
+        assert!(state
+            .project(Path::new("/projects/bacon-ls"))
+            .bacon_health
+            .record_save(None));
+        assert!(!state
+            .project(Path::new("/projects/other"))
+            .bacon_health
+            .is_failing());
+    }
+
+    #[test]
+    fn test_files_to_publish() {
+        let url =
+            |name: &str| Url::parse(&format!("file:///projects/bacon-ls/src/{name}")).unwrap();
+        let open_files = HashSet::from([url("main.rs")]);
+        let mut store = DiagnosticsStore::default();
+        for name in ["lib.rs", "main.rs", "bacon.rs"] {
+            store.set(BACON_SOURCE, &url(name), vec![Diagnostic::default()]);
+        }
+        // Fixed since it was published, so it must be cleared.
+        let mut publisher = Publisher::default();
+        publisher.prepare(&url("lsp.rs"), vec![Diagnostic::default()]);
+
+        let files = BaconLs::files_to_publish(&open_files, &store, &publisher, 10);
+        assert_eq!(
+            files,
+            vec![
+                url("main.rs"),
+                url("bacon.rs"),
+                url("lib.rs"),
+                url("lsp.rs")
+            ]
+        );
+        let files = BaconLs::files_to_publish(&open_files, &store, &publisher, 2);
+        assert_eq!(files, vec![url("main.rs"), url("bacon.rs")]);
+    }
+
+    #[test]
+    fn test_parse_cargo_env() {
+        let env = BaconLs::parse_cargo_env(&serde_json::json!({
+            "RUSTFLAGS": "--cfg foo,bar",
+            "CARGO_PROFILE_DEV_DEBUG": "a=b",
+        }))
+        .unwrap();
+        assert_eq!(env["RUSTFLAGS"], "--cfg foo,bar");
+        assert_eq!(env["CARGO_PROFILE_DEV_DEBUG"], "a=b");
+
+        let env = BaconLs::parse_cargo_env(&serde_json::json!(
+            "RUSTFLAGS=--cfg foo, CARGO_TARGET_DIR=target/ls"
+        ))
+        .unwrap();
+        assert_eq!(env["RUSTFLAGS"], "--cfg foo");
+        assert_eq!(env["CARGO_TARGET_DIR"], "target/ls");
+
+        assert!(BaconLs::parse_cargo_env(&serde_json::json!("RUSTFLAGS")).is_err());
+        assert!(BaconLs::parse_cargo_env(&serde_json::json!({"JOBS": 4})).is_err());
+    }
+
+    #[test]
+    fn test_is_build_file() {
+        let url = |path: &str| Url::parse(&format!("file:///projects/bacon-ls/{path}")).unwrap();
+        assert!(BaconLs::is_build_file(&url("Cargo.toml")));
+        assert!(BaconLs::is_build_file(&url("crates/foo/Cargo.lock")));
+        assert!(BaconLs::is_build_file(&url("build.rs")));
+        assert!(!BaconLs::is_build_file(&url("src/build.rs.bak")));
+        assert!(!BaconLs::is_build_file(&url("src/lib.rs")));
+        assert!(BaconLs::is_locations_file(
+            &url(".bacon-locations"),
+            LOCATIONS_FILE
+        ));
+        assert!(!BaconLs::is_locations_file(
+            &url("src/.bacon-locations.lock"),
+            LOCATIONS_FILE
+        ));
+    }
+
+    #[test]
+    fn test_has_cargo_project() {
+        let tmp_dir = TempDir::new("bacon-ls").unwrap();
+        let docs = tmp_dir.path().join("docs");
+        std::fs::create_dir(&docs).unwrap();
+        let workspace_folders = vec![WorkspaceFolder {
+            name: "docs".to_string(),
+            uri: Url::from_directory_path(&docs).unwrap(),
+        }];
+        assert!(!BaconLs::has_cargo_project(Some(&workspace_folders)));
+        // The tests run in the folder of the package.
+        assert!(BaconLs::has_cargo_project(None));
+        // Cargo projects are found in the parent folders too, like cargo does.
+        std::fs::write(tmp_dir.path().join("Cargo.toml"), "[workspace]").unwrap();
+        assert!(BaconLs::has_cargo_project(Some(&workspace_folders)));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_initialize_workspace_folders() {
+        let root = if cfg!(windows) {
+            "file:///C:/projects/bacon-ls/"
+        } else {
+            "file:///projects/bacon-ls/"
+        };
+        let folder = WorkspaceFolder {
+            uri: Url::parse(root).unwrap(),
+            name: "bacon-ls".to_string(),
+        };
+        let params = InitializeParams {
+            workspace_folders: Some(vec![folder.clone()]),
+            ..InitializeParams::default()
+        };
+        assert_eq!(
+            BaconLs::initialize_workspace_folders(&params),
+            vec![folder.clone()]
+        );
+
+        let params = InitializeParams {
+            workspace_folders: Some(vec![]),
+            root_uri: Some(folder.uri.clone()),
+            ..InitializeParams::default()
+        };
+        assert_eq!(
+            BaconLs::initialize_workspace_folders(&params),
+            vec![folder.clone()]
+        );
+
+        let params = InitializeParams {
+            root_path: Some(folder.uri.to_file_path().unwrap().display().to_string()),
+            ..InitializeParams::default()
+        };
+        assert_eq!(BaconLs::initialize_workspace_folders(&params), vec![folder]);
+
+        let current_dir = Url::from_directory_path(env::current_dir().unwrap()).unwrap();
+        let folders = BaconLs::initialize_workspace_folders(&InitializeParams::default());
+        assert_eq!(folders.len(), 1);
+        assert_eq!(folders[0].uri, current_dir);
     }
 
     #[test]
     fn test_can_configure_tracing() {
-        BaconLs::configure_tracing(Some("info".to_string()));
+        configure_tracing("info".to_string());
+        let settings = LogSettings::default();
+        assert!(log::set_log_level("debug", &settings).is_ok());
+        assert!(log::set_log_level("bacon_ls=[", &settings).is_err());
     }
 }
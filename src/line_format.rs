@@ -0,0 +1,203 @@
+/// Separator of the fields of the lines exported by bacon.
+const SEPARATOR: &str = "|:|";
+/// Format of the lines exported by bacon, as created in its preferences by bacon-ls.
+pub(crate) const LINE_FORMAT: &str = "{diagnostic.level}|:|{span.file_name}|:|{span.line_start}|:|{span.line_end}|:|{span.column_start}|:|{span.column_end}|:|{diagnostic.code}|:|{diagnostic.message}|:|{span.suggested_replacement}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Level,
+    FileName,
+    LineStart,
+    LineEnd,
+    ColumnStart,
+    ColumnEnd,
+    Message,
+    Replacement,
+    Code,
+    /// Field not used by bacon-ls, which is skipped.
+    Other,
+}
+
+impl Field {
+    fn parse(placeholder: &str) -> Self {
+        match placeholder.trim() {
+            "{diagnostic.level}" => Self::Level,
+            "{span.file_name}" => Self::FileName,
+            "{span.line_start}" => Self::LineStart,
+            "{span.line_end}" => Self::LineEnd,
+            "{span.column_start}" => Self::ColumnStart,
+            "{span.column_end}" => Self::ColumnEnd,
+            "{diagnostic.message}" => Self::Message,
+            "{span.suggested_replacement}" => Self::Replacement,
+            "{diagnostic.code}" => Self::Code,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Fields of a line exported by bacon.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Line<'l> {
+    pub(crate) level: &'l str,
+    pub(crate) file_name: &'l str,
+    /// Line start, line end, column start and column end, one based.
+    pub(crate) positions: [&'l str; 4],
+    pub(crate) message: &'l str,
+    pub(crate) replacement: Option<&'l str>,
+    pub(crate) code: Option<&'l str>,
+}
+
+/// Fields of the `line_format` of the bacon export, in their order. The last field takes the
+/// rest of the line, which can contain the separator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LineFormat {
+    format: String,
+    fields: Vec<Field>,
+}
+
+impl Default for LineFormat {
+    fn default() -> Self {
+        Self::parse(LINE_FORMAT).expect("the default line format is valid")
+    }
+}
+
+impl LineFormat {
+    /// Parse a `line_format`, which must have the level, the location and the message of the
+    /// diagnostics.
+    pub(crate) fn parse(format: &str) -> Option<Self> {
+        let fields: Vec<Field> = format.split(SEPARATOR).map(Field::parse).collect();
+        let required = [
+            Field::Level,
+            Field::FileName,
+            Field::LineStart,
+            Field::LineEnd,
+            Field::ColumnStart,
+            Field::ColumnEnd,
+            Field::Message,
+        ];
+        required
+            .iter()
+            .all(|field| fields.contains(field))
+            .then(|| Self {
+                format: format.to_string(),
+                fields,
+            })
+    }
+
+    /// Return true if the line starts a new diagnostic rather than continuing the message of
+    /// the previous one, from the level of the diagnostic.
+    pub(crate) fn starts_entry(&self, line: &str) -> bool {
+        let Some(index) = self.fields.iter().position(|field| *field == Field::Level) else {
+            return false;
+        };
+        line.splitn(index + 2, SEPARATOR)
+            .nth(index)
+            .is_some_and(|level| {
+                ["warning", "error", "info", "note", "failure-note", "help"]
+                    .iter()
+                    .any(|severity| level.starts_with(severity))
+            })
+    }
+
+    /// Split a line into its fields, or return None if it has too few of them. The lines
+    /// exported before the code was added to the format are still accepted.
+    pub(crate) fn split<'l>(&self, line: &'l str) -> Option<Line<'l>> {
+        let values: Vec<&str> = line.splitn(self.fields.len(), SEPARATOR).collect();
+        if values.len() != self.fields.len() {
+            if self.fields.contains(&Field::Code) {
+                let without_code = Self {
+                    format: self.format.clone(),
+                    fields: self
+                        .fields
+                        .iter()
+                        .copied()
+                        .filter(|field| *field != Field::Code)
+                        .collect(),
+                };
+                return without_code.split(line);
+            }
+            tracing::error!(
+                "malformed line: expected {} parts in the format of `{}` but found {}: {}",
+                self.fields.len(),
+                self.format,
+                values.len(),
+                line
+            );
+            return None;
+        }
+        let mut parsed = Line::default();
+        for (field, value) in self.fields.iter().zip(values) {
+            match field {
+                Field::Level => parsed.level = value,
+                Field::FileName => parsed.file_name = value,
+                Field::LineStart => parsed.positions[0] = value,
+                Field::LineEnd => parsed.positions[1] = value,
+                Field::ColumnStart => parsed.positions[2] = value,
+                Field::ColumnEnd => parsed.positions[3] = value,
+                Field::Message => parsed.message = value,
+                Field::Replacement => parsed.replacement = Some(value),
+                Field::Code => parsed.code = Some(value),
+                Field::Other => {}
+            }
+        }
+        Some(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_line_format() {
+        let format = LineFormat::default();
+        assert_eq!(
+            format
+                .split("warning|:|src/lib.rs|:|3|:|3|:|9|:|10|:|unused_variables|:|unused|:|none"),
+            Some(Line {
+                level: "warning",
+                file_name: "src/lib.rs",
+                positions: ["3", "3", "9", "10"],
+                message: "unused",
+                replacement: Some("none"),
+                code: Some("unused_variables"),
+            })
+        );
+        assert_eq!(
+            format.split("warning|:|src/lib.rs|:|3|:|3|:|9|:|10|:|unused|:|none"),
+            Some(Line {
+                level: "warning",
+                file_name: "src/lib.rs",
+                positions: ["3", "3", "9", "10"],
+                message: "unused",
+                replacement: Some("none"),
+                code: None,
+            })
+        );
+        assert_eq!(format.split("warning|:|src/lib.rs|:|3"), None);
+        assert!(format.starts_entry("warning|:|src/lib.rs|:|3"));
+        assert!(!format.starts_entry("  --> src/lib.rs:3:9"));
+
+        let format = LineFormat::parse("{diagnostic.code}|:|{diagnostic.level}|:|{span.label}|:|{span.file_name}|:|{span.line_start}|:|{span.line_end}|:|{span.column_start}|:|{span.column_end}|:|{diagnostic.message}").unwrap();
+        assert_eq!(
+            format.split(
+                "E0425|:|error|:|label|:|src/lib.rs|:|1|:|1|:|5|:|8|:|cannot find |:| value"
+            ),
+            Some(Line {
+                level: "error",
+                file_name: "src/lib.rs",
+                positions: ["1", "1", "5", "8"],
+                message: "cannot find |:| value",
+                replacement: None,
+                code: Some("E0425"),
+            })
+        );
+        assert!(format.starts_entry("E0425|:|error|:|label"));
+        assert!(!format.starts_entry("error: could not compile"));
+        assert_eq!(
+            LineFormat::parse("{diagnostic.level}|:|{span.file_name}|:|{diagnostic.message}"),
+            None
+        );
+    }
+}
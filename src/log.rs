@@ -0,0 +1,330 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use serde_json::Value;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{MessageType, SetTraceParams, TraceValue};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::writer::OptionalWriter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::error::{BaconLsError, Result};
+use crate::{BaconLs, PKG_NAME};
+
+pub(crate) const LOG_FILE_ENV: &str = "BACON_LS_LOG_FILE";
+pub(crate) const DEFAULT_LOG_MAX_SIZE_MB: u64 = 10;
+pub(crate) const DEFAULT_LOG_KEEP_FILES: usize = 3;
+pub(crate) const SET_LOG_LEVEL_COMMAND: &str = "bacon-ls.setLogLevel";
+pub(crate) const SET_TRACE_METHOD: &str = "$/setTrace";
+
+/// Log file of the process, which can be moved once the client settings are received.
+pub(crate) static LOG_FILE: OnceLock<LogFile> = OnceLock::new();
+/// Handle swapping the log filter at runtime.
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+/// Log level the process was started with, restored when the client turns the trace off.
+static STARTUP_LOG_LEVEL: OnceLock<String> = OnceLock::new();
+
+/// Log file set by `BACON_LS_LOG_FILE`, or in the state directory of the user.
+pub(crate) fn default_log_file() -> PathBuf {
+    let file_name = format!("{PKG_NAME}.log");
+    env::var_os(LOG_FILE_ENV)
+        .map(PathBuf::from)
+        .or_else(|| state_dir().map(|dir| dir.join(PKG_NAME).join(&file_name)))
+        .unwrap_or_else(|| PathBuf::from(file_name))
+}
+
+fn state_dir() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+    };
+    dir.filter(|dir| dir.is_absolute())
+}
+
+/// Log file and rotation chosen by the client settings.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LogSettings {
+    pub(crate) path: PathBuf,
+    pub(crate) max_size_mb: u64,
+    pub(crate) keep_files: usize,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            path: default_log_file(),
+            max_size_mb: DEFAULT_LOG_MAX_SIZE_MB,
+            keep_files: DEFAULT_LOG_KEEP_FILES,
+        }
+    }
+}
+
+/// Install the subscriber with a reloadable filter. The log file is opened only if the level is
+/// not `off`, or once the level is raised at runtime.
+pub(crate) fn configure_tracing(level: String) {
+    if level != "off" {
+        match LogFile::open(
+            default_log_file(),
+            DEFAULT_LOG_MAX_SIZE_MB,
+            DEFAULT_LOG_KEEP_FILES,
+        ) {
+            Ok(log_file) => {
+                LOG_FILE.get_or_init(|| log_file);
+            }
+            Err(e) => {
+                eprintln!("unable to open the {PKG_NAME} log file: {e}");
+                return;
+            }
+        }
+    }
+    let filter = EnvFilter::try_new(&level).unwrap_or_else(|e| {
+        eprintln!("invalid log level {level}: {e}");
+        EnvFilter::new("off")
+    });
+    let (filter, handle) = reload::Layer::new(filter);
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_writer(LogWriter)
+        .with_thread_names(true)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_line_number(true)
+        .with_target(false)
+        .compact();
+    if tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .try_init()
+        .is_ok()
+    {
+        LOG_FILTER.get_or_init(|| handle);
+        STARTUP_LOG_LEVEL.get_or_init(|| level);
+    }
+}
+
+/// Swap the log filter, opening the log file if logging was disabled until now.
+pub(crate) fn set_log_level(level: &str, settings: &LogSettings) -> Result<()> {
+    let handle = LOG_FILTER
+        .get()
+        .ok_or_else(|| BaconLsError::Config("logging is not configured".to_string()))?;
+    let filter = EnvFilter::try_new(level)
+        .map_err(|e| BaconLsError::Config(format!("invalid log level {level}: {e}")))?;
+    if level != "off" && LOG_FILE.get().is_none() {
+        let log_file = LogFile::open(
+            settings.path.clone(),
+            settings.max_size_mb,
+            settings.keep_files,
+        )
+        .map_err(|e| {
+            BaconLsError::io(
+                format!("unable to open log file {}", settings.path.display()),
+                e,
+            )
+        })?;
+        LOG_FILE.get_or_init(|| log_file);
+    }
+    handle
+        .reload(filter)
+        .map_err(|e| BaconLsError::Config(format!("unable to change the log level: {e}")))?;
+    tracing::info!("log level set to {level}");
+    Ok(())
+}
+
+/// Log level matching an LSP trace value.
+fn trace_log_level(value: TraceValue) -> String {
+    match value {
+        TraceValue::Off => STARTUP_LOG_LEVEL
+            .get()
+            .cloned()
+            .unwrap_or_else(|| "off".to_string()),
+        TraceValue::Messages => "info".to_string(),
+        TraceValue::Verbose => "debug".to_string(),
+    }
+}
+
+impl BaconLs {
+    /// Handle the `$/setTrace` notification.
+    pub(crate) async fn set_trace(&self, params: SetTraceParams) {
+        tracing::debug!("client sent $/setTrace notification: {params:?}");
+        let level = trace_log_level(params.value);
+        if let Err(e) = self.set_log_level(&level).await {
+            tracing::error!("{e}");
+        }
+    }
+
+    /// Handle the `bacon-ls.setLogLevel` command, taking the level as its only argument.
+    pub(crate) async fn set_log_level_command(&self, arguments: &[Value]) -> jsonrpc::Result<()> {
+        let Some(level) = arguments.first().and_then(Value::as_str) else {
+            return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams));
+        };
+        if let Err(e) = self.set_log_level(level).await {
+            tracing::error!("{e}");
+            if let Some(client) = self.client.as_ref() {
+                client.show_message(MessageType::ERROR, e.to_string()).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_log_level(&self, level: &str) -> Result<()> {
+        let settings = self.state.read().await.log_settings.clone();
+        set_log_level(level, &settings)
+    }
+}
+
+/// Path of a rotated log file, `bacon-ls.log.1` being the most recent.
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{index}"));
+    PathBuf::from(path)
+}
+
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, keep_files: usize) -> io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            keep_files,
+        })
+    }
+
+    /// Shift the rotated files, dropping the oldest one, and start a new log file.
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = rotated_path(&self.path, self.keep_files);
+            if oldest.exists() {
+                fs::remove_file(oldest)?;
+            }
+            for index in (1..self.keep_files).rev() {
+                let rotated = rotated_path(&self.path, index);
+                if rotated.exists() {
+                    fs::rename(&rotated, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        *self = Self::open(self.path.clone(), self.max_size, self.keep_files)?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Log file rotated when it grows over the maximum size, keeping the given number of rotated
+/// files.
+#[derive(Debug, Clone)]
+pub(crate) struct LogFile(Arc<Mutex<RotatingFile>>);
+
+impl LogFile {
+    pub(crate) fn open(path: PathBuf, max_size_mb: u64, keep_files: usize) -> io::Result<Self> {
+        let file = RotatingFile::open(path, max_size_mb * 1024 * 1024, keep_files)?;
+        Ok(Self(Arc::new(Mutex::new(file))))
+    }
+
+    /// Move the log to another file and change the rotation.
+    pub(crate) fn reconfigure(
+        &self,
+        path: PathBuf,
+        max_size_mb: u64,
+        keep_files: usize,
+    ) -> io::Result<()> {
+        let file = RotatingFile::open(path, max_size_mb * 1024 * 1024, keep_files)?;
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = file;
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).flush()
+    }
+}
+
+/// Writer of the log file, discarding the logs while it isn't open.
+struct LogWriter;
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = OptionalWriter<LogFile>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LOG_FILE.get().cloned().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_log_file_rotation() {
+        let tmp_dir = TempDir::new("bacon-ls").unwrap();
+        let path = tmp_dir.path().join("logs").join("bacon-ls.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+            "second\n"
+        );
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn test_trace_log_level() {
+        assert_eq!(trace_log_level(TraceValue::Messages), "info");
+        assert_eq!(trace_log_level(TraceValue::Verbose), "debug");
+    }
+}
@@ -1,20 +1,54 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashSet, path::PathBuf, sync::Arc, time::Duration};
 
+use serde_json::Value;
 use tower_lsp::{
     jsonrpc,
     lsp_types::{
         CodeAction, CodeActionKind, CodeActionOptions, CodeActionOrCommand, CodeActionParams,
-        CodeActionProviderCapability, CodeActionResponse, DeleteFilesParams,
-        DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-        DidSaveTextDocumentParams, InitializeParams, InitializeResult, InitializedParams,
-        MessageType, PositionEncodingKind, PublishDiagnosticsClientCapabilities, RenameFilesParams,
-        ServerCapabilities, ServerInfo, TextDocumentClientCapabilities, TextDocumentSyncCapability,
-        TextDocumentSyncKind, TextEdit, Url, WorkDoneProgressOptions, WorkspaceEdit,
+        CodeActionProviderCapability, CodeActionResponse, CodeLensOptions, DeleteFilesParams,
+        DidChangeTextDocumentParams, DidChangeWatchedFilesParams, DidCloseTextDocumentParams,
+        DidOpenTextDocumentParams, DidSaveTextDocumentParams, ExecuteCommandOptions,
+        ExecuteCommandParams, Hover, HoverParams, HoverProviderCapability, InitializeParams,
+        InitializeResult, InitializedParams, MessageType, PositionEncodingKind,
+        PublishDiagnosticsClientCapabilities, RenameFilesParams, ServerCapabilities, ServerInfo,
+        TextDocumentClientCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+        WorkDoneProgressOptions,
     },
     LanguageServer,
 };
 
-use crate::{bacon::Bacon, BaconLs, DiagnosticData, PKG_NAME, PKG_VERSION};
+#[cfg(feature = "runnables")]
+use tower_lsp::lsp_types::{CodeLens, CodeLensParams};
+
+use crate::audit::CargoAudit;
+#[cfg(feature = "bacon")]
+use crate::bacon::{Bacon, PreferencesTarget, RestartPolicy};
+use crate::code_action::{self, CodeActionSupport};
+use crate::custom::{CargoEvents, CustomCommand, CustomParser};
+use crate::debug_info::settings_digest;
+use crate::deny::CargoDeny;
+use crate::explain::EXPLAIN_COMMAND;
+use crate::fix_all::FIX_ALL_WORKSPACE_COMMAND;
+use crate::gc;
+#[cfg(feature = "bacon")]
+use crate::install_bacon::INSTALL_BACON_COMMAND;
+use crate::log::{LogSettings, LOG_FILE, SET_LOG_LEVEL_COMMAND};
+use crate::matrix::CommandMatrix;
+use crate::messages::Locale;
+use crate::miri::RUN_MIRI_COMMAND;
+use crate::path_mapping::PathMappings;
+use crate::pipeline::Pipeline;
+use crate::revision::CHECK_REVISION_COMMAND;
+use crate::run_id::RunId;
+#[cfg(feature = "runnables")]
+use crate::runnables::{Runnables, TestRunner, RUN_BENCH_COMMAND, RUN_TEST_COMMAND};
+use crate::rust_project::{RustProject, RUST_PROJECT_FILE};
+use crate::rustfmt::Rustfmt;
+#[cfg(feature = "bacon")]
+use crate::select_job::SELECT_JOB_COMMAND;
+use crate::typos::Typos;
+use crate::unused_deps::UnusedDependenciesChecker;
+use crate::{BaconLs, DiagnosticsFilter, PKG_NAME, PKG_VERSION};
 
 #[tower_lsp::async_trait]
 impl LanguageServer for BaconLs {
@@ -34,24 +68,101 @@ impl LanguageServer for BaconLs {
         }
 
         let mut diagnostics_data_supported = false;
+        let mut diagnostics_version_supported = false;
         if let Some(TextDocumentClientCapabilities {
             publish_diagnostics:
                 Some(PublishDiagnosticsClientCapabilities {
-                    data_support: Some(true),
+                    data_support,
+                    version_support,
                     ..
                 }),
             ..
         }) = params.capabilities.text_document
         {
+            diagnostics_data_supported = data_support == Some(true);
+            diagnostics_version_supported = version_support == Some(true);
+        }
+        if diagnostics_data_supported {
             tracing::info!("client supports diagnostics data");
-            diagnostics_data_supported = true;
         } else {
             tracing::warn!("client does not support diagnostics data");
         }
 
+        // Pick the first encoding supported by both ends, following the client preference order.
+        let position_encoding = params
+            .capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .and_then(|encodings| {
+                encodings.iter().find(|encoding| {
+                    [
+                        PositionEncodingKind::UTF8,
+                        PositionEncodingKind::UTF16,
+                        PositionEncodingKind::UTF32,
+                    ]
+                    .contains(encoding)
+                })
+            })
+            .cloned()
+            .unwrap_or(PositionEncodingKind::UTF16);
+        tracing::info!("negotiated position encoding {position_encoding:?}");
+
+        // Extension specific behaviors are only enabled for clients asking for them.
+        let server_status_notification = params
+            .capabilities
+            .experimental
+            .as_ref()
+            .and_then(|experimental| experimental.get("serverStatusNotification"))
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        if server_status_notification {
+            tracing::info!("client supports server status notifications");
+        }
+
+        let code_action_support = CodeActionSupport::new(
+            params
+                .capabilities
+                .text_document
+                .as_ref()
+                .and_then(|text_document| text_document.code_action.as_ref()),
+        );
+
+        let show_document = params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.show_document.as_ref())
+            .is_some_and(|show_document| show_document.support);
+
+        let watch_files = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|watched_files| watched_files.dynamic_registration)
+            .unwrap_or_default();
+
+        let mut provide_code_actions = true;
         let mut state = self.state.write().await;
-        state.workspace_folders = params.workspace_folders;
-        state.diagnostics_data_supported = diagnostics_data_supported;
+        let workspace_folders = BaconLs::initialize_workspace_folders(&params);
+        state
+            .diagnostics_store
+            .paths
+            .set_workspace_folders(&workspace_folders);
+        state.workspace_folders = Some(workspace_folders);
+        state.server_status_notification = server_status_notification;
+        state.show_document = show_document;
+        state.code_action_support = code_action_support;
+        state.locale = params
+            .locale
+            .as_deref()
+            .map(Locale::parse)
+            .unwrap_or_default();
+        state.watch_files = watch_files;
+        state.publisher.version_support = diagnostics_version_supported;
+        state.position_encoding = position_encoding.clone();
+        state.settings_digest = settings_digest(params.initialization_options.as_ref());
 
         if let Some(ops) = params.initialization_options {
             if let Some(values) = ops.as_object() {
@@ -61,6 +172,44 @@ impl LanguageServer for BaconLs {
                         .as_str()
                         .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
                         .to_string();
+                    state.locations_file_configured = true;
+                }
+                if let Some(value) = values.get("baconJobs") {
+                    let jobs = BaconLs::parse_string_list(value)?;
+                    if jobs.is_empty() {
+                        return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams));
+                    }
+                    state.bacon_jobs = jobs;
+                }
+                if let Some(value) = values.get("baconSocket") {
+                    state.bacon_socket = Some(
+                        value
+                            .as_str()
+                            .filter(|socket| !socket.is_empty())
+                            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                            .to_string(),
+                    );
+                }
+                if let Some(value) = values.get("diagnosticSource") {
+                    state.diagnostic_source = value
+                        .as_str()
+                        .filter(|source| !source.is_empty())
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                        .to_string();
+                }
+                if let Some(value) = values.get("provideCodeActions") {
+                    provide_code_actions = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("quickFixTitle") {
+                    state.publisher.quick_fix_title = Some(
+                        value
+                            .as_str()
+                            .filter(|template| !template.is_empty())
+                            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                            .to_string(),
+                    );
                 }
                 if let Some(value) = values.get("updateOnSave") {
                     state.update_on_save = value
@@ -79,6 +228,20 @@ impl LanguageServer for BaconLs {
                         .as_bool()
                         .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
                 }
+                if let Some(value) = values.get("updateOnChangeCooldownMillis") {
+                    state.update_on_change_cooldown_millis = Duration::from_millis(
+                        value
+                            .as_u64()
+                            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?,
+                    );
+                }
+                if let Some(value) = values.get("folderParallelism") {
+                    state.folder_parallelism = value
+                        .as_u64()
+                        .filter(|folder_parallelism| *folder_parallelism > 0)
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                        as usize;
+                }
                 if let Some(value) = values.get("validateBaconPreferences") {
                     state.validate_bacon_preferences = value
                         .as_bool()
@@ -95,11 +258,31 @@ impl LanguageServer for BaconLs {
                         .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
                         .to_string();
                 }
+                #[cfg(feature = "bacon")]
+                if let Some(value) = values.get("baconRestartPolicy") {
+                    state.bacon_restart_policy = value
+                        .as_str()
+                        .and_then(RestartPolicy::parse)
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("baconFallbackAfterSaves") {
+                    state.bacon_fallback_after_saves = value
+                        .as_u64()
+                        .and_then(|saves| u32::try_from(saves).ok())
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
                 if let Some(value) = values.get("createBaconPreferencesFile") {
                     state.create_bacon_preferences_file = value
                         .as_bool()
                         .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
                 }
+                #[cfg(feature = "bacon")]
+                if let Some(value) = values.get("preferencesCreationTarget") {
+                    state.preferences_creation_target = value
+                        .as_str()
+                        .and_then(PreferencesTarget::parse)
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
                 if let Some(value) = values.get("synchronizeAllOpenFilesWaitMillis") {
                     state.syncronize_all_open_files_wait_millis = Duration::from_millis(
                         value
@@ -107,27 +290,349 @@ impl LanguageServer for BaconLs {
                             .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?,
                     );
                 }
+                if ["logFile", "logMaxSizeMb", "logKeepFiles"]
+                    .iter()
+                    .any(|name| values.contains_key(*name))
+                {
+                    let log_settings = LogSettings::default();
+                    let path = values
+                        .get("logFile")
+                        .map(|value| {
+                            value
+                                .as_str()
+                                .map(PathBuf::from)
+                                .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))
+                        })
+                        .transpose()?
+                        .unwrap_or(log_settings.path);
+                    let max_size_mb = values
+                        .get("logMaxSizeMb")
+                        .map(|value| {
+                            value
+                                .as_u64()
+                                .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))
+                        })
+                        .transpose()?
+                        .unwrap_or(log_settings.max_size_mb);
+                    let keep_files = values
+                        .get("logKeepFiles")
+                        .map(|value| {
+                            value
+                                .as_u64()
+                                .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))
+                        })
+                        .transpose()?
+                        .map_or(log_settings.keep_files, |keep_files| keep_files as usize);
+                    // Logging is disabled if the log file was never opened, the settings are
+                    // used if the log level is raised later.
+                    if let Some(log_file) = LOG_FILE.get() {
+                        tracing::info!("moving the log to {}", path.display());
+                        if let Err(e) = log_file.reconfigure(path.clone(), max_size_mb, keep_files)
+                        {
+                            tracing::error!("unable to open log file {}: {e}", path.display());
+                        }
+                    }
+                    state.log_settings = LogSettings {
+                        path,
+                        max_size_mb,
+                        keep_files,
+                    };
+                }
+                if let Some(value) = values.get("cargoEnv") {
+                    state.cargo_env = BaconLs::parse_cargo_env(value)?;
+                }
+                if let Some(value) = values.get("cargoTargetDir") {
+                    state.cargo_target_dir = value
+                        .as_str()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                        .to_string();
+                }
+                if let Some(value) = values.get("baconCargoTargetDir") {
+                    state.bacon_cargo_target_dir = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("buildFolder") {
+                    state.build_folder = Some(
+                        value
+                            .as_str()
+                            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                            .to_string(),
+                    );
+                }
+                if let Some(value) = values.get("buildFolderMaxAgeDays") {
+                    state.build_folder_max_age_days = value
+                        .as_u64()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("publishAllFiles") {
+                    state.publish_all_files = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("publishAllFilesMax") {
+                    state.publish_all_files_max = value
+                        .as_u64()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                        as usize;
+                }
+                if let Some(value) = values.get("keepDiagnosticsOnClose") {
+                    state.keep_diagnostics_on_close = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("explainOnHover") {
+                    state.explain_on_hover = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("checkUnsavedFiles") {
+                    state.check_unsaved_files = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("notifyBuildStatus") {
+                    state.notify_build_status = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("miri") {
+                    state.miri = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("maxCommandOutputMb") {
+                    state.command_limits.set_max_output_mb(
+                        value
+                            .as_u64()
+                            .filter(|max_output_mb| *max_output_mb > 0)
+                            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?,
+                    );
+                }
+                if let Some(value) = values.get("skipWhenCargoLocked") {
+                    state.command_limits.skip_when_locked = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                #[cfg(feature = "runnables")]
+                if let Some(value) = values.get("testRunner") {
+                    state.test_runner = value
+                        .as_str()
+                        .and_then(TestRunner::parse)
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("publishTraceFile") {
+                    state.publish_trace_file =
+                        Some(PathBuf::from(value.as_str().ok_or(jsonrpc::Error::new(
+                            jsonrpc::ErrorCode::InvalidParams,
+                        ))?));
+                }
+                if let Some(value) = values.get("dormantWithoutCargoProject") {
+                    state.dormant_without_cargo_project = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("publishStatusDiagnostics") {
+                    state.publish_status_diagnostics = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("runCargoAudit") {
+                    if value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                    {
+                        state.providers.push(Arc::new(CargoAudit));
+                    }
+                }
+                if let Some(value) = values.get("runCargoDeny") {
+                    if value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                    {
+                        state.providers.push(Arc::new(CargoDeny));
+                    }
+                }
+                if let Some(value) = values.get("runTypos") {
+                    if value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                    {
+                        state.providers.push(Arc::new(Typos));
+                    }
+                }
+                if let Some(value) = values.get("runRustfmt") {
+                    if value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                    {
+                        state.providers.push(Arc::new(Rustfmt));
+                    }
+                }
+                if let Some(value) = values.get("unusedDependenciesChecker") {
+                    state.providers.push(Arc::new(
+                        value
+                            .as_str()
+                            .and_then(UnusedDependenciesChecker::parse)
+                            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?,
+                    ));
+                }
+                // Parsed before the commands forwarding them.
+                if let Some(value) = values.get("cargoEvents") {
+                    state.cargo_events = BaconLs::parse_string_list(value)?;
+                }
+                let cargo_events = CargoEvents::new(self.client.clone(), &state.cargo_events);
+                if let Some(value) = values.get("pipeline") {
+                    let pipeline =
+                        Pipeline::parse(value, self.client.clone(), cargo_events.clone()).map_err(
+                            |e| {
+                                tracing::error!("{e}");
+                                jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams)
+                            },
+                        )?;
+                    state.providers.push(Arc::new(pipeline));
+                }
+                if let Some(value) = values.get("customCommand") {
+                    let string_option = |name: &str| {
+                        values
+                            .get(name)
+                            .map(|value| {
+                                value
+                                    .as_str()
+                                    .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))
+                            })
+                            .transpose()
+                    };
+                    let parser = CustomParser::parse(
+                        string_option("customCommandParser")?.unwrap_or("cargo-json"),
+                        string_option("customCommandRegex")?,
+                    )
+                    .map_err(|e| {
+                        tracing::error!("{e}");
+                        jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams)
+                    })?;
+                    let command = CustomCommand {
+                        command: value
+                            .as_str()
+                            .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                            .to_string(),
+                        args: string_option("customCommandArgs")?
+                            .unwrap_or_default()
+                            .split_whitespace()
+                            .map(|arg| arg.to_string())
+                            .collect(),
+                        parser,
+                        events: cargo_events.clone(),
+                    };
+                    let strings_option = |name: &str| -> jsonrpc::Result<Vec<String>> {
+                        match values.get(name) {
+                            Some(value) => serde_json::from_value(value.clone()).map_err(|_| {
+                                jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams)
+                            }),
+                            None => Ok(Vec::new()),
+                        }
+                    };
+                    match CommandMatrix::new(
+                        command,
+                        &strings_option("featureSets")?,
+                        &strings_option("targets")?,
+                    ) {
+                        Ok(matrix) => state.providers.push(Arc::new(matrix)),
+                        Err(command) => state.providers.push(Arc::new(command)),
+                    }
+                }
+                if let Some(value) = values.get("rustProject") {
+                    let file = match value {
+                        serde_json::Value::Bool(enabled) => {
+                            enabled.then(|| RUST_PROJECT_FILE.to_string())
+                        }
+                        serde_json::Value::String(file) if !file.is_empty() => Some(file.clone()),
+                        _ => return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams)),
+                    };
+                    if let Some(file) = file {
+                        state.providers.push(Arc::new(RustProject {
+                            file,
+                            events: cargo_events.clone(),
+                        }));
+                    }
+                }
+                if let Some(value) = values.get("includeExternalDiagnostics") {
+                    state.diagnostics_store.include_external = value
+                        .as_bool()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                if let Some(value) = values.get("pathMappings") {
+                    state.diagnostics_store.paths.mappings = serde_json::from_value(value.clone())
+                        .map(PathMappings::new)
+                        .map_err(|_| jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?;
+                }
+                state.diagnostics_filter = DiagnosticsFilter::parse(values)?;
+                if let Some(value) = values.get("batchSeverities") {
+                    state.publisher.batch_severities = value
+                        .as_array()
+                        .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))?
+                        .iter()
+                        .map(|severity| {
+                            severity
+                                .as_str()
+                                .and_then(BaconLs::parse_severity_setting)
+                                .ok_or(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams))
+                        })
+                        .collect::<jsonrpc::Result<_>>()?;
+                }
             }
         }
+        // Quick fixes are stored in the diagnostics data, so they need the client to support it.
+        state.publisher.provide_code_actions = provide_code_actions && diagnostics_data_supported;
         tracing::debug!("loaded state from lsp settings: {state:#?}");
+        let miri = state.miri;
+        #[cfg(feature = "bacon")]
+        let run_bacon = state.run_bacon_in_background;
         drop(state);
 
+        let mut commands: Vec<String> = vec![
+            SET_LOG_LEVEL_COMMAND.to_string(),
+            CHECK_REVISION_COMMAND.to_string(),
+            EXPLAIN_COMMAND.to_string(),
+            FIX_ALL_WORKSPACE_COMMAND.to_string(),
+        ];
+        #[cfg(feature = "runnables")]
+        commands.extend([RUN_TEST_COMMAND.to_string(), RUN_BENCH_COMMAND.to_string()]);
+        if miri {
+            commands.push(RUN_MIRI_COMMAND.to_string());
+        }
+        #[cfg(feature = "bacon")]
+        commands.push(INSTALL_BACON_COMMAND.to_string());
+        #[cfg(feature = "bacon")]
+        if run_bacon {
+            commands.push(SELECT_JOB_COMMAND.to_string());
+        }
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                // Only support UTF-16 positions for now, which is the default when unspecified
-                position_encoding: Some(PositionEncodingKind::UTF16),
+                position_encoding: Some(position_encoding),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
-                code_action_provider: Some(CodeActionProviderCapability::Options(
-                    CodeActionOptions {
+                code_action_provider: provide_code_actions.then_some(
+                    CodeActionProviderCapability::Options(CodeActionOptions {
                         code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
                         work_done_progress_options: WorkDoneProgressOptions {
                             work_done_progress: Some(false),
                         },
-                        resolve_provider: None,
+                        resolve_provider: Some(code_action_support.resolve()),
+                    }),
+                ),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                code_lens_provider: cfg!(feature = "runnables").then_some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                execute_command_provider: (!commands.is_empty()).then_some(ExecuteCommandOptions {
+                    commands,
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: Some(false),
                     },
-                )),
+                }),
                 ..Default::default()
             },
             server_info: Some(ServerInfo {
@@ -138,13 +643,6 @@ impl LanguageServer for BaconLs {
     }
 
     async fn initialized(&self, _: InitializedParams) {
-        let state = self.state.read().await;
-        let run_bacon = state.run_bacon_in_background;
-        let bacon_command_args = state.run_bacon_in_background_command_args.clone();
-        let create_bacon_prefs = state.create_bacon_preferences_file;
-        let validate_prefs = state.validate_bacon_preferences;
-        drop(state);
-
         if let Some(client) = self.client.as_ref() {
             tracing::info!("{PKG_NAME} v{PKG_VERSION} lsp server initialized");
             client
@@ -153,40 +651,33 @@ impl LanguageServer for BaconLs {
                     format!("{PKG_NAME} v{PKG_VERSION} lsp server initialized"),
                 )
                 .await;
-            if validate_prefs {
-                if let Err(e) = Bacon::validate_preferences(create_bacon_prefs).await {
-                    tracing::error!("{e}");
-                    client.show_message(MessageType::ERROR, e).await;
-                }
-            } else {
-                tracing::warn!(
-                    "skipping validation of bacon preferences, validateBaconPreferences is false"
-                );
-            }
-
-            if run_bacon {
-                match Bacon::run_in_background("bacon", &bacon_command_args).await {
-                    Ok(command) => {
-                        tracing::info!(
-                            "bacon was started successfully and is running in the background"
-                        );
-                        let mut state = self.state.write().await;
-                        state.bacon_command_handle = Some(command);
-                        drop(state);
-                    }
-                    Err(e) => {
-                        tracing::error!("{e}");
-                        client.show_message(MessageType::ERROR, e).await;
-                    }
-                }
-            } else {
-                tracing::warn!("skipping background bacon startup, runBaconInBackground is false");
-            }
+            #[cfg(not(feature = "bacon"))]
+            tracing::warn!(
+                "{PKG_NAME} was built without the bacon feature: bacon preferences are not validated and bacon must be started manually"
+            );
         } else {
             tracing::error!(
                 "client doesn't seem to be connected, the LSP server will not function properly"
             );
         }
+        tokio::task::spawn(Self::log_debug_info(self.state.clone()));
+        gc::spawn_collect_build_folders(self.state.read().await.build_folder_max_age_days);
+        // Started first, as the bacon preferences tell which locations file to watch.
+        self.start().await;
+        let state = self.state.read().await;
+        // There is no locations file to watch when bacon streams its analysis.
+        let watch_files = state.watch_files && state.bacon_socket.is_none();
+        let locations_files: Vec<String> = state
+            .jobs_locations_files()
+            .into_iter()
+            .map(|(_, locations_file)| locations_file)
+            .collect();
+        let workspace_folders = state.workspace_folders.clone().unwrap_or_default();
+        drop(state);
+        if let (Some(client), true) = (self.client.as_ref(), watch_files) {
+            let watched = Self::watch_files(client, &locations_files, &workspace_folders).await;
+            self.state.write().await.locations_watched = watched && !locations_files.is_empty();
+        }
         let task_state = self.state.clone();
         let task_client = self.client.clone();
         tokio::task::spawn(Self::syncronize_diagnostics_for_all_open_files(
@@ -199,71 +690,170 @@ impl LanguageServer for BaconLs {
         tracing::debug!("client sent didOpen request");
         let mut state = self.state.write().await;
         state.open_files.insert(params.text_document.uri.clone());
-        let locations_file = state.locations_file.clone();
-        let workspace_folders = state.workspace_folders.clone();
+        state
+            .documents
+            .open(params.text_document.uri.clone(), params.text_document.text);
+        state
+            .publisher
+            .open(&params.text_document.uri, params.text_document.version);
+        let update_on_change = state.update_on_change;
         drop(state);
         let client = self.client.clone();
         Self::publish_diagnostics(
             client.as_ref(),
+            &self.state,
             &params.text_document.uri,
-            &locations_file,
-            workspace_folders.as_deref(),
+            false,
         )
         .await;
+        // A file never saved is checked with its content in the editor.
+        let never_saved = params
+            .text_document
+            .uri
+            .to_file_path()
+            .is_ok_and(|path| !path.exists());
+        if never_saved
+            && update_on_change
+            && Self::set_unsaved(&self.state, &params.text_document.uri, true).await
+        {
+            Self::schedule_change(client, self.state.clone(), params.text_document.uri).await;
+        }
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         tracing::debug!("client sent didClose request");
+        // The unsaved content of the file is dropped.
+        if Self::set_unsaved(&self.state, &params.text_document.uri, false).await {
+            tokio::task::spawn(Self::check_unsaved_files(
+                self.client.clone(),
+                self.state.clone(),
+                params.text_document.uri.clone(),
+            ));
+        }
         let mut state = self.state.write().await;
         state.open_files.remove(&params.text_document.uri);
-        let locations_file = state.locations_file.clone();
-        let workspace_folders = state.workspace_folders.clone();
+        state.documents.close(&params.text_document.uri);
+        state.publisher.close(&params.text_document.uri);
+        let keep_diagnostics_on_close = state.keep_diagnostics_on_close;
         drop(state);
         let client = self.client.clone();
-        Self::publish_diagnostics(
-            client.as_ref(),
-            &params.text_document.uri,
-            &locations_file,
-            workspace_folders.as_deref(),
-        )
-        .await;
-    }
-
-    async fn did_save(&self, params: DidSaveTextDocumentParams) {
-        let state = self.state.read().await;
-        let update_on_save = state.update_on_save;
-        let update_on_save_wait_millis = state.update_on_save_wait_millis;
-        let locations_file = state.locations_file.clone();
-        let workspace_folders = state.workspace_folders.clone();
-        drop(state);
-        tracing::debug!("client sent didSave request, updateOnSave is {update_on_save} after waiting bacon for {update_on_save_wait_millis:?}");
-        if update_on_save {
-            let client = self.client.clone();
-            tokio::time::sleep(update_on_save_wait_millis).await;
+        if keep_diagnostics_on_close {
+            // The diagnostics of the closed file are still published when they change, until
+            // the file is clean.
             Self::publish_diagnostics(
                 client.as_ref(),
+                &self.state,
                 &params.text_document.uri,
-                &locations_file,
-                workspace_folders.as_deref(),
+                false,
             )
             .await;
+        } else {
+            Self::clear_diagnostics(client.as_ref(), &self.state, &params.text_document.uri).await;
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let mut state = self.state.write().await;
+        let diagnostics_version = state.diagnostics_store.version(&params.text_document.uri);
+        state
+            .documents
+            .checkpoint(&params.text_document.uri, diagnostics_version, true);
+        drop(state);
+        if self.state.read().await.dormant {
+            tracing::debug!("client sent didSave request while dormant, ignoring it");
+            return;
+        }
+        tracing::debug!("client sent didSave request");
+        if Self::set_unsaved(&self.state, &params.text_document.uri, false).await {
+            tokio::task::spawn(Self::check_unsaved_files(
+                self.client.clone(),
+                self.state.clone(),
+                params.text_document.uri.clone(),
+            ));
+        }
+        Self::schedule_save(
+            self.client.clone(),
+            self.state.clone(),
+            params.text_document.uri,
+        )
+        .await;
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        if self.state.read().await.dormant {
+            tracing::debug!(
+                "client sent didChangeWatchedFiles notification while dormant, ignoring it"
+            );
+            return;
+        }
+        let jobs_locations_files = self.state.read().await.jobs_locations_files();
+        let mut locations_changed = false;
+        for change in params.changes {
+            if Self::is_build_file(&change.uri) {
+                tracing::debug!("build file {} changed", change.uri);
+                Self::schedule_save(self.client.clone(), self.state.clone(), change.uri).await;
+            } else if jobs_locations_files
+                .iter()
+                .any(|(_, locations_file)| Self::is_locations_file(&change.uri, locations_file))
+            {
+                locations_changed = true;
+            }
+        }
+        if locations_changed {
+            tracing::debug!("locations file changed, publishing the diagnostics");
+            // The client notifies a locations file once bacon wrote it, at the end of its run.
+            let completed_folders: HashSet<PathBuf> = self
+                .state
+                .read()
+                .await
+                .workspace_folders
+                .iter()
+                .flatten()
+                .map(|folder| PathBuf::from(folder.uri.path()))
+                .collect();
+            Self::publish_bacon_diagnostics(&self.state, self.client.as_ref(), &completed_folders)
+                .await;
         }
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let state = self.state.read().await;
-        let update_on_change = self.state.read().await.update_on_change;
-        let locations_file = state.locations_file.clone();
-        let workspace_folders = state.workspace_folders.clone();
+        let mut state = self.state.write().await;
+        let position_encoding = state.position_encoding.clone();
+        let changed = state
+            .documents
+            .change(
+                &params.text_document.uri,
+                params.content_changes,
+                &position_encoding,
+            )
+            .is_some();
+        drop(state);
+        if changed {
+            Self::set_unsaved(&self.state, &params.text_document.uri, true).await;
+        }
+        let mut state = self.state.write().await;
+        state
+            .publisher
+            .change(&params.text_document.uri, params.text_document.version);
+        let update_on_change = state.update_on_change;
+        let published = state.publisher.is_published(&params.text_document.uri);
         drop(state);
+        // The published diagnostics follow the edits until they are computed again.
+        if changed && published {
+            Self::publish_stored_diagnostics(
+                self.client.as_ref(),
+                &self.state,
+                &params.text_document.uri,
+                false,
+            )
+            .await;
+        }
         tracing::debug!("client sent didChange request, updateOnChange is {update_on_change}");
         if update_on_change {
-            let client = self.client.clone();
-            Self::publish_diagnostics(
-                client.as_ref(),
-                &params.text_document.uri,
-                &locations_file,
-                workspace_folders.as_deref(),
+            Self::schedule_change(
+                self.client.clone(),
+                self.state.clone(),
+                params.text_document.uri,
             )
             .await;
         }
@@ -275,7 +865,9 @@ impl LanguageServer for BaconLs {
             if let Ok(uri) = Url::parse(&file.uri) {
                 let mut state = self.state.write().await;
                 state.open_files.remove(&uri);
+                state.publisher.close(&uri);
                 drop(state);
+                Self::clear_diagnostics(self.client.as_ref(), &self.state, &uri).await;
             }
         }
     }
@@ -287,18 +879,13 @@ impl LanguageServer for BaconLs {
                 (Url::parse(&file.old_uri), Url::parse(&file.new_uri))
             {
                 let mut state = self.state.write().await;
-                let locations_file = state.locations_file.clone();
-                let workspace_folders = state.workspace_folders.clone();
                 state.open_files.remove(&old_uri);
+                state.publisher.close(&old_uri);
                 state.open_files.insert(new_uri.clone());
+                state.documents.rename(&old_uri, new_uri.clone());
                 drop(state);
-                Self::publish_diagnostics(
-                    self.client.as_ref(),
-                    &new_uri,
-                    &locations_file,
-                    workspace_folders.as_deref(),
-                )
-                .await;
+                Self::clear_diagnostics(self.client.as_ref(), &self.state, &old_uri).await;
+                Self::publish_diagnostics(self.client.as_ref(), &self.state, &new_uri, false).await;
             }
         }
     }
@@ -309,58 +896,22 @@ impl LanguageServer for BaconLs {
     ) -> jsonrpc::Result<Option<CodeActionResponse>> {
         tracing::debug!("code_action: {params:?}");
         let state = self.state.read().await;
-        let diagnostics_data_supported = state.diagnostics_data_supported;
-        drop(state);
-
-        if diagnostics_data_supported {
-            let actions = params
-                .context
-                .diagnostics
+        if state.publisher.provide_code_actions {
+            let uri = &params.text_document.uri;
+            let actions = state
+                .publisher
+                .code_actions(uri)
                 .iter()
-                .filter(|diag| diag.source == Some("bacon-ls".to_string()))
-                .flat_map(|diag| match &diag.data {
-                    Some(data) => {
-                        if let Ok(DiagnosticData { corrections }) =
-                            serde_json::from_value::<DiagnosticData>(data.clone())
-                        {
-                            corrections
-                                .iter()
-                                .map(|c| {
-                                    CodeActionOrCommand::CodeAction(CodeAction {
-                                        title: "Replace with clippy suggestion".to_string(),
-                                        kind: Some(CodeActionKind::QUICKFIX),
-                                        diagnostics: Some(vec![diag.clone()]),
-                                        edit: Some(WorkspaceEdit {
-                                            changes: Some(HashMap::from([(
-                                                params.text_document.uri.clone(),
-                                                vec![TextEdit {
-                                                    range: diag.range,
-                                                    new_text: c.to_string(),
-                                                }],
-                                            )])),
-                                            ..WorkspaceEdit::default()
-                                        }),
-                                        is_preferred: if corrections.len() == 1 {
-                                            Some(true)
-                                        } else {
-                                            None
-                                        },
-                                        ..CodeAction::default()
-                                    })
-                                })
-                                .collect()
-                        } else {
-                            tracing::error!(
-                                "deserialization failed: received {data:?} as diagnostic data",
-                            );
-                            vec![]
-                        }
-                    }
-                    None => {
-                        tracing::warn!("client doesn't support diagnostic data");
-                        vec![]
-                    }
+                .enumerate()
+                .filter(|(_, (diagnostic, _))| {
+                    params.context.diagnostics.iter().any(|diag| {
+                        diag.range == diagnostic.range && diag.message == diagnostic.message
+                    })
+                })
+                .filter_map(|(index, (_, action))| {
+                    state.code_action_support.present(uri, index, action)
                 })
+                .map(CodeActionOrCommand::CodeAction)
                 .collect::<Vec<_>>();
 
             Ok(Some(actions))
@@ -369,13 +920,128 @@ impl LanguageServer for BaconLs {
         }
     }
 
+    async fn code_action_resolve(&self, params: CodeAction) -> jsonrpc::Result<CodeAction> {
+        tracing::debug!("client sent codeAction/resolve request: {params:?}");
+        let state = self.state.read().await;
+        Ok(code_action::resolve(params, &state.publisher))
+    }
+
+    async fn hover(&self, params: HoverParams) -> jsonrpc::Result<Option<Hover>> {
+        tracing::debug!("client sent hover request: {params:?}");
+        Ok(self.hover_diagnostics(params).await)
+    }
+
+    #[cfg(feature = "runnables")]
+    async fn code_lens(&self, params: CodeLensParams) -> jsonrpc::Result<Option<Vec<CodeLens>>> {
+        tracing::debug!("client sent codeLens request");
+        let uri = params.text_document.uri;
+        let Ok(path) = uri.to_file_path() else {
+            return Ok(None);
+        };
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => Ok(Some(Runnables::code_lenses(&uri, &content))),
+            Err(e) => {
+                tracing::warn!("unable to read {} for code lenses: {e}", path.display());
+                Ok(None)
+            }
+        }
+    }
+
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> jsonrpc::Result<Option<Value>> {
+        tracing::debug!("client sent executeCommand request: {params:?}");
+        // The commands spawned to handle it follow the limits of the settings.
+        let command_limits = self.state.read().await.command_limits;
+        command_limits
+            .scope(async {
+                match params.command.as_str() {
+                    #[cfg(feature = "runnables")]
+                    RUN_TEST_COMMAND | RUN_BENCH_COMMAND => {
+                        let (Some(uri), Some(filter)) = (
+                            params
+                                .arguments
+                                .first()
+                                .and_then(Value::as_str)
+                                .and_then(|uri| Url::parse(uri).ok()),
+                            params.arguments.get(1).and_then(Value::as_str),
+                        ) else {
+                            return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams));
+                        };
+                        // The user waits for the outcome of the run, so every error is shown.
+                        if let Err(e) = self.run_runnable(&params.command, &uri, filter).await {
+                            tracing::error!("{e}");
+                            if let Some(client) = self.client.as_ref() {
+                                client.show_message(MessageType::ERROR, e.to_string()).await;
+                            }
+                        }
+                        Ok(None)
+                    }
+                    SET_LOG_LEVEL_COMMAND => {
+                        self.set_log_level_command(&params.arguments).await?;
+                        Ok(None)
+                    }
+                    CHECK_REVISION_COMMAND => {
+                        RunId::next()
+                            .scope(self.check_revision_command(&params.arguments))
+                            .await?;
+                        Ok(None)
+                    }
+                    EXPLAIN_COMMAND => Ok(Some(self.explain_command(&params.arguments).await?)),
+                    FIX_ALL_WORKSPACE_COMMAND => Ok(Some(self.fix_all_workspace_command().await?)),
+                    RUN_MIRI_COMMAND if self.state.read().await.miri => {
+                        RunId::next()
+                            .scope(self.run_miri_command(&params.arguments))
+                            .await?;
+                        Ok(None)
+                    }
+                    #[cfg(feature = "bacon")]
+                    SELECT_JOB_COMMAND if self.state.read().await.run_bacon_in_background => {
+                        self.select_job_command(&params.arguments).await?;
+                        Ok(None)
+                    }
+                    #[cfg(feature = "bacon")]
+                    INSTALL_BACON_COMMAND => {
+                        RunId::next().scope(self.install_bacon_command()).await?;
+                        Ok(None)
+                    }
+                    _ => Err(jsonrpc::Error::new(jsonrpc::ErrorCode::MethodNotFound)),
+                }
+            })
+            .await
+    }
+
     async fn shutdown(&self) -> jsonrpc::Result<()> {
         let state = self.state.read().await;
-        if let Some(handle) = state.bacon_command_handle.as_ref() {
-            tracing::info!("terminating bacon from running in background");
-            handle.abort();
+        for (folder_path, project) in state.projects.iter() {
+            if let Some(handle) = project.bacon_socket_handle.as_ref() {
+                handle.abort();
+            }
+            for handle in project.bacon_job_handles.iter() {
+                handle.abort();
+            }
+            if let Some(handle) = project.bacon_takeover_handle.as_ref() {
+                handle.abort();
+            }
+            if let Some(handle) = project.bacon_command_handle.as_ref() {
+                tracing::info!(
+                    "terminating bacon running in background in {}",
+                    folder_path.display()
+                );
+                handle.abort();
+            }
+            // Also released when the lock was taken to reuse a bacon already running.
+            #[cfg(feature = "bacon")]
+            Bacon::release_lock(folder_path).await;
         }
+        let temporary_build_folder = state.temporary_build_folder.clone();
         drop(state);
+        if let Some(build_folder) = temporary_build_folder {
+            if let Err(e) = gc::remove_build_folder(&build_folder).await {
+                tracing::warn!("{e}");
+            }
+        }
         if let Some(client) = self.client.as_ref() {
             tracing::info!("{PKG_NAME} v{PKG_VERSION} lsp server stopped");
             client
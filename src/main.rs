@@ -1,12 +1,61 @@
 //! Bacon Language Server
+use std::num::NonZeroUsize;
+
 use bacon_ls::BaconLs;
 
-#[tokio::main]
-async fn main() {
-    let args: bacon_ls::Args = argh::from_env();
+fn main() {
+    let args = bacon_ls::Args::from_env();
     if args.version {
         println!("{}", bacon_ls::PKG_VERSION);
-    } else {
-        BaconLs::serve().await;
+        return;
+    }
+    #[cfg(feature = "self-update")]
+    if let Some(bacon_ls::Command::SelfUpdate(self_update_args)) = args.command {
+        if let Err(e) = bacon_ls::self_update(self_update_args.force) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let worker_threads = args.worker_threads.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1)
+    });
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all().worker_threads(worker_threads.max(1));
+    if let Some(blocking_threads) = args.blocking_threads {
+        builder.max_blocking_threads(blocking_threads.max(1));
+    }
+    let runtime = builder.build().expect("failed to build the async runtime");
+    match args.command {
+        Some(bacon_ls::Command::Gc(gc)) => {
+            if let Err(e) = runtime.block_on(bacon_ls::gc(gc.max_age_days)) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        Some(bacon_ls::Command::Precommit(precommit)) => {
+            match runtime.block_on(bacon_ls::precommit(precommit.settings)) {
+                Ok(false) => {}
+                Ok(true) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("{e}");
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(bacon_ls::Command::Watch(watch)) => {
+            if let Err(e) = runtime.block_on(bacon_ls::watch(watch.settings, watch.status_file)) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            if let Err(e) = runtime.block_on(BaconLs::serve(args.pipe)) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
     }
 }
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use tower_lsp::lsp_types::{Diagnostic, Url};
+
+use crate::custom::{CustomCommand, CUSTOM_PROVIDER};
+use crate::error::Result;
+use crate::provider::DiagnosticsProvider;
+
+/// Build of the command matrix, with the arguments of a feature set and of a target.
+#[derive(Debug, Clone, PartialEq)]
+struct Variant {
+    args: Vec<String>,
+    /// Label appended to the messages of the diagnostics only reported by some builds.
+    label: String,
+}
+
+/// Custom command run once per feature set, like `--no-default-features` or
+/// `--features foo,bar`, and per target triple, like `wasm32-unknown-unknown`, for the crates
+/// whose errors only appear with some features or on some targets.
+#[derive(Debug)]
+pub(crate) struct CommandMatrix {
+    command: CustomCommand,
+    variants: Vec<Variant>,
+}
+
+impl CommandMatrix {
+    /// Matrix of the feature sets and of the targets, an empty list using the default features
+    /// or the host target. Returns the command alone if both are empty.
+    pub(crate) fn new(
+        command: CustomCommand,
+        feature_sets: &[String],
+        targets: &[String],
+    ) -> std::result::Result<Self, CustomCommand> {
+        if feature_sets.is_empty() && targets.is_empty() {
+            return Err(command);
+        }
+        let feature_sets: Vec<Option<&str>> = match feature_sets {
+            [] => vec![None],
+            feature_sets => feature_sets.iter().map(|set| Some(set.as_str())).collect(),
+        };
+        let targets: Vec<Option<&str>> = match targets {
+            [] => vec![None],
+            targets => targets.iter().map(|target| Some(target.as_str())).collect(),
+        };
+        let variants = feature_sets
+            .iter()
+            .flat_map(|feature_set| {
+                targets.iter().map(move |target| {
+                    let mut args: Vec<String> = feature_set
+                        .unwrap_or_default()
+                        .split_whitespace()
+                        .map(str::to_string)
+                        .collect();
+                    let mut labels = Vec::new();
+                    if let Some(feature_set) = feature_set {
+                        labels.push(match feature_set.trim() {
+                            "" => "default features",
+                            feature_set => feature_set,
+                        });
+                    }
+                    if let Some(target) = target {
+                        args.extend(["--target".to_string(), target.to_string()]);
+                        labels.push(target);
+                    }
+                    Variant {
+                        args,
+                        label: labels.join(" "),
+                    }
+                })
+            })
+            .collect();
+        Ok(Self { command, variants })
+    }
+
+    /// Command of a variant, whose arguments go before the `--` separating the arguments of the
+    /// tool run by cargo.
+    fn command(&self, variant: &Variant) -> CustomCommand {
+        let mut command = self.command.clone();
+        let end = command
+            .args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(command.args.len());
+        command.args.splice(end..end, variant.args.iter().cloned());
+        command
+    }
+}
+
+/// Merge the diagnostics of the variants, suffixing the messages of the ones which are only
+/// reported by some of them with their labels.
+fn merge(runs: Vec<(&str, HashMap<Url, Vec<Diagnostic>>)>) -> HashMap<Url, Vec<Diagnostic>> {
+    let runs_count = runs.len();
+    let mut merged: HashMap<Url, Vec<(Diagnostic, Vec<&str>)>> = HashMap::new();
+    for (label, diagnostics) in runs {
+        for (url, diagnostics) in diagnostics {
+            let file = merged.entry(url).or_default();
+            for diagnostic in diagnostics {
+                match file.iter_mut().find(|(merged, _)| *merged == diagnostic) {
+                    Some((_, labels)) if labels.contains(&label) => {}
+                    Some((_, labels)) => labels.push(label),
+                    None => file.push((diagnostic, vec![label])),
+                }
+            }
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(url, diagnostics)| {
+            let diagnostics = diagnostics
+                .into_iter()
+                .map(|(mut diagnostic, labels)| {
+                    if labels.len() < runs_count {
+                        diagnostic.message =
+                            format!("{} [{}]", diagnostic.message, labels.join(" | "));
+                    }
+                    diagnostic
+                })
+                .collect();
+            (url, diagnostics)
+        })
+        .collect()
+}
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for CommandMatrix {
+    fn name(&self) -> &'static str {
+        CUSTOM_PROVIDER
+    }
+
+    fn runs_on_save(&self, uri: &Url) -> bool {
+        self.command.runs_on_save(uri)
+    }
+
+    /// Run the command with each variant in turn, as they share the build directory, and merge
+    /// their diagnostics.
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let mut runs = Vec::new();
+        for variant in &self.variants {
+            let diagnostics = self.command(variant).run(folder_path, env).await?;
+            runs.push((variant.label.as_str(), diagnostics));
+        }
+        Ok(merge(runs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::CustomParser;
+    use pretty_assertions::assert_eq;
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|string| string.to_string()).collect()
+    }
+
+    #[test]
+    fn test_command() {
+        let command = CustomCommand {
+            command: "cargo".to_string(),
+            args: strings(&[
+                "clippy",
+                "--message-format",
+                "json",
+                "--",
+                "-W",
+                "clippy::pedantic",
+            ]),
+            parser: CustomParser::CargoJson,
+            events: None,
+        };
+        assert!(CommandMatrix::new(command.clone(), &[], &[]).is_err());
+
+        let matrix = CommandMatrix::new(
+            command,
+            &strings(&["", "--features foo,bar"]),
+            &strings(&["wasm32-unknown-unknown"]),
+        )
+        .unwrap();
+        let labels: Vec<&str> = matrix
+            .variants
+            .iter()
+            .map(|variant| variant.label.as_str())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                "default features wasm32-unknown-unknown",
+                "--features foo,bar wasm32-unknown-unknown"
+            ]
+        );
+        assert_eq!(
+            matrix.command(&matrix.variants[1]).args,
+            strings(&[
+                "clippy",
+                "--message-format",
+                "json",
+                "--features",
+                "foo,bar",
+                "--target",
+                "wasm32-unknown-unknown",
+                "--",
+                "-W",
+                "clippy::pedantic"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let url = Url::parse("file:///projects/bacon-ls/src/lib.rs").unwrap();
+        let diagnostic = |message: &str| Diagnostic {
+            message: message.to_string(),
+            ..Diagnostic::default()
+        };
+        let runs = vec![
+            (
+                "default features",
+                HashMap::from([(
+                    url.clone(),
+                    vec![diagnostic("unused import"), diagnostic("dead code")],
+                )]),
+            ),
+            (
+                "--no-default-features",
+                HashMap::from([(
+                    url.clone(),
+                    vec![diagnostic("unused import"), diagnostic("unresolved import")],
+                )]),
+            ),
+            (
+                "--features foo,bar",
+                HashMap::from([(
+                    url.clone(),
+                    vec![diagnostic("unused import"), diagnostic("unresolved import")],
+                )]),
+            ),
+        ];
+        let messages: Vec<String> = merge(runs)[&url]
+            .iter()
+            .map(|diagnostic| diagnostic.message.clone())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unused import",
+                "dead code [default features]",
+                "unresolved import [--no-default-features | --features foo,bar]"
+            ]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::PKG_NAME;
+
+/// Language of the messages shown to the user, picked from the locale of the client.
+///
+/// Translations are added with a variant, its language tag in [`Locale::parse`] and the
+/// matching arm in [`Message::text`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum Locale {
+    #[default]
+    English,
+}
+
+impl Locale {
+    /// Parse a locale like `en-US` by its language, falling back to English for the languages
+    /// without translation.
+    pub(crate) fn parse(locale: &str) -> Self {
+        let language = locale
+            .split(['-', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match language.as_str() {
+            "en" => Self::English,
+            _ => {
+                tracing::debug!("no translation for locale {locale}, using english");
+                Self::English
+            }
+        }
+    }
+}
+
+/// Message shown to the user with `window/showMessage` or as a status problem.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Message<'a> {
+    #[cfg(feature = "bacon")]
+    BaconRestarting {
+        status: &'a str,
+        delay_secs: u64,
+    },
+    #[cfg(feature = "bacon")]
+    BaconReused {
+        pid: u32,
+    },
+    #[cfg(feature = "bacon")]
+    BaconAdopted {
+        pid: u32,
+    },
+    #[cfg(feature = "bacon")]
+    BaconJobSelected {
+        job: &'a str,
+    },
+    #[cfg(feature = "bacon")]
+    BaconTooOld {
+        version: &'a str,
+        feature: &'a str,
+        required: &'a str,
+    },
+    #[cfg(feature = "bacon")]
+    BaconMissing {
+        command: &'a str,
+    },
+    #[cfg(feature = "bacon")]
+    BaconInstalled,
+    BaconNotUpdating {
+        locations_file: &'a str,
+        saves: u32,
+        command: &'a str,
+    },
+    BuildClean,
+    BuildFailing {
+        errors: usize,
+    },
+    Dormant,
+    FixesApplied {
+        fixes: usize,
+        files: usize,
+    },
+    FixesRejected {
+        reason: &'a str,
+    },
+    MiriRan {
+        diagnostics: usize,
+    },
+    NoFixes,
+    RevisionChecked {
+        rev: &'a str,
+        diagnostics: usize,
+    },
+    #[cfg(feature = "runnables")]
+    RunnableSucceeded {
+        filter: &'a str,
+    },
+    #[cfg(feature = "runnables")]
+    RunnableFailed {
+        filter: &'a str,
+    },
+}
+
+impl Message<'_> {
+    pub(crate) fn text(&self, locale: Locale) -> String {
+        match locale {
+            Locale::English => self.english(),
+        }
+    }
+
+    fn english(&self) -> String {
+        match *self {
+            #[cfg(feature = "bacon")]
+            Self::BaconRestarting { status, delay_secs } => {
+                format!("bacon exited with {status}, restarting it in {delay_secs} seconds")
+            }
+            #[cfg(feature = "bacon")]
+            Self::BaconReused { pid } => {
+                format!("bacon is already running in background for {PKG_NAME} instance {pid}, reusing it")
+            }
+            #[cfg(feature = "bacon")]
+            Self::BaconAdopted { pid } => {
+                format!("bacon {pid} is already running in the folder, reusing it")
+            }
+            #[cfg(feature = "bacon")]
+            Self::BaconJobSelected { job } => format!("bacon now runs the {job} job"),
+            #[cfg(feature = "bacon")]
+            Self::BaconTooOld {
+                version,
+                feature,
+                required,
+            } => format!(
+                "bacon {version} does not support {feature}, please upgrade it to {required} or newer"
+            ),
+            #[cfg(feature = "bacon")]
+            Self::BaconMissing { command } => format!(
+                "bacon is not installed, running `{command}` on save instead: run the bacon-ls.installBacon command to install it"
+            ),
+            #[cfg(feature = "bacon")]
+            Self::BaconInstalled => "bacon installed".to_string(),
+            Self::BaconNotUpdating {
+                locations_file,
+                saves,
+                command,
+            } => format!(
+                "bacon did not update {locations_file} after {saves} saves, running `{command}` instead"
+            ),
+            Self::BuildClean => "✅ the project builds cleanly".to_string(),
+            Self::BuildFailing { errors: 1 } => "❌ the project has 1 error".to_string(),
+            Self::BuildFailing { errors } => format!("❌ the project has {errors} errors"),
+            Self::Dormant => format!(
+                "no Cargo project found in the workspace folders, {PKG_NAME} is dormant until a Cargo.toml or a rust-project.json is created"
+            ),
+            Self::FixesApplied { fixes: 1, .. } => "applied 1 fix".to_string(),
+            Self::FixesApplied { fixes, files: 1 } => format!("applied {fixes} fixes in 1 file"),
+            Self::FixesApplied { fixes, files } => {
+                format!("applied {fixes} fixes in {files} files")
+            }
+            Self::FixesRejected { reason } => format!("unable to apply the fixes: {reason}"),
+            Self::NoFixes => "no fixes to apply in the workspace".to_string(),
+            Self::MiriRan { diagnostics } => {
+                format!("cargo miri test ran, its {diagnostics} diagnostics are published")
+            }
+            Self::RevisionChecked { rev, diagnostics } => {
+                format!("{rev} checked, its {diagnostics} diagnostics are published")
+            }
+            #[cfg(feature = "runnables")]
+            Self::RunnableSucceeded { filter } => format!("`{filter}` succeeded"),
+            #[cfg(feature = "runnables")]
+            Self::RunnableFailed { filter } => format!("`{filter}` failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(Locale::parse("en-US"), Locale::English);
+        assert_eq!(Locale::parse("it_IT"), Locale::English);
+        assert_eq!(
+            Message::BaconNotUpdating {
+                locations_file: ".bacon-locations",
+                saves: 3,
+                command: "cargo clippy",
+            }
+            .text(Locale::parse("en")),
+            "bacon did not update .bacon-locations after 3 saves, running `cargo clippy` instead"
+        );
+    }
+}
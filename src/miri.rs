@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticSeverity, MessageType, NumberOrString, Position, Range, Url,
+};
+
+use crate::custom::parse_cargo_json;
+use crate::error::{BaconLsError, Result};
+use crate::messages::{Locale, Message};
+use crate::progress::WorkProgress;
+use crate::{parsed_output_command, usage, BaconLs, DiagnosticData};
+
+pub(crate) const RUN_MIRI_COMMAND: &str = "bacon-ls.runMiri";
+pub(crate) const MIRI_PROVIDER: &str = "miri";
+const MIRI_SOURCE: &str = "miri";
+const UNDEFINED_BEHAVIOR_CODE: &str = "undefined-behavior";
+/// Header of the reports of Miri, printed after the name of the test it was running.
+const REPORT_HEADER: &str = "error: Undefined Behavior: ";
+
+/// Location of a `--> src/lib.rs:8:9` line of a report, one based.
+fn location(line: &str) -> Option<(&str, u32, u32)> {
+    let location = line.trim_start().strip_prefix("--> ")?;
+    let mut parts = location.trim_end().rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    Some((parts.next()?, line, column))
+}
+
+/// Convert the undefined behavior reports of Miri into diagnostics, on the first location of
+/// each report inside the workspace folder, as the offending line can be in the standard
+/// library with the code of the project calling it in the notes.
+fn parse_reports(stderr: &str, folder_path: &Path) -> Vec<(Url, Diagnostic)> {
+    let mut diagnostics = Vec::new();
+    let mut lines = stderr.lines();
+    while let Some(line) = lines.next() {
+        let Some(index) = line.find(REPORT_HEADER) else {
+            continue;
+        };
+        let message = line[index + REPORT_HEADER.len()..].trim().to_string();
+        let mut report = vec![&line[index..]];
+        report.extend(lines.by_ref().take_while(|line| !line.trim().is_empty()));
+        let Some((url, range)) = report.iter().enumerate().find_map(|(index, line)| {
+            let (file, line, column) = location(line)?;
+            let path = folder_path.join(file);
+            if !path.starts_with(folder_path) {
+                return None;
+            }
+            // The carets under the offending code give the end of the range.
+            let carets = report[index + 1..]
+                .iter()
+                .take(3)
+                .find_map(|line| {
+                    let (_, code) = line.split_once('|')?;
+                    let carets = code.trim().chars().take_while(|c| *c == '^').count();
+                    (carets > 0).then_some(carets as u32)
+                })
+                .unwrap_or(1);
+            let start = Position::new(line.saturating_sub(1), column.saturating_sub(1));
+            let end = Position::new(start.line, start.character + carets);
+            Some((Url::from_file_path(path).ok()?, Range::new(start, end)))
+        }) else {
+            tracing::warn!("no location in the workspace for the miri report: {message}");
+            continue;
+        };
+        let diagnostic = Diagnostic {
+            range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String(UNDEFINED_BEHAVIOR_CODE.to_string())),
+            source: Some(MIRI_SOURCE.to_string()),
+            message,
+            data: Some(serde_json::json!(DiagnosticData {
+                corrections: vec![],
+                edits: vec![],
+                title: None,
+                rendered: Some(report.join("\n").into()),
+            })),
+            ..Diagnostic::default()
+        };
+        diagnostics.push((url, diagnostic));
+    }
+    diagnostics
+}
+
+/// Run the tests of the folder with Miri and return the compiler errors and the undefined
+/// behavior reports.
+async fn run_miri(
+    folder_path: &Path,
+    filter: Option<&str>,
+    env: &HashMap<String, String>,
+) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+    let mut command = parsed_output_command("cargo");
+    command
+        .args(["miri", "test", "--message-format", "json"])
+        .args(filter)
+        .envs(env)
+        .current_dir(folder_path);
+    let output = usage::output(&mut command)
+        .await
+        .map_err(|e| BaconLsError::Spawn(format!("failed to run cargo miri test: {e}")))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let diagnostics: Vec<(Url, Diagnostic)> = parse_cargo_json(&stdout, folder_path, MIRI_SOURCE)
+        .into_iter()
+        .chain(parse_reports(&stderr, folder_path))
+        .collect();
+    // The tests hitting undefined behavior fail, any other failure is reported as is, like
+    // Miri not being installed.
+    if diagnostics.is_empty() && !output.status.success() {
+        return Err(BaconLsError::Spawn(format!(
+            "cargo miri test failed: {}",
+            stderr.lines().last().unwrap_or_default().trim()
+        )));
+    }
+    let mut by_file: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+    for (url, diagnostic) in diagnostics {
+        by_file.entry(url).or_default().push(diagnostic);
+    }
+    Ok(by_file)
+}
+
+impl BaconLs {
+    /// Handle the `bacon-ls.runMiri` command, taking an optional test filter. The diagnostics
+    /// of the run replace the ones of the previous run.
+    pub(crate) async fn run_miri_command(&self, arguments: &[Value]) -> jsonrpc::Result<()> {
+        let filter = match arguments {
+            [] => None,
+            [Value::String(filter)] => Some(filter.as_str()),
+            _ => return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams)),
+        };
+        let state = self.state.read().await;
+        let folder_path = state
+            .workspace_folders
+            .iter()
+            .flatten()
+            .map(|folder| PathBuf::from(folder.uri.path()))
+            .find(|folder_path| folder_path.join("Cargo.toml").is_file());
+        let locale = state.locale;
+        drop(state);
+        let Some(folder_path) = folder_path else {
+            tracing::warn!("no Cargo project found to run miri");
+            return Ok(());
+        };
+
+        let client = self.client.as_deref();
+        let progress = WorkProgress::begin(client, "running miri").await;
+        let cargo_env = BaconLs::folder_cargo_env(&self.state, &folder_path).await;
+        // Dropping the run kills cargo.
+        let result = tokio::select! {
+            result = run_miri(&folder_path, filter, &cargo_env) => result,
+            _ = progress.cancelled() => Err(BaconLsError::Cancelled),
+        };
+        let (message_type, message) = match result {
+            Ok(diagnostics) => {
+                progress.end(None).await;
+                let count = diagnostics.values().map(Vec::len).sum();
+                Self::set_provider_diagnostics(
+                    self.client.as_ref(),
+                    &self.state,
+                    MIRI_PROVIDER,
+                    diagnostics,
+                )
+                .await;
+                let message = Message::MiriRan { diagnostics: count };
+                tracing::info!("{}", message.text(Locale::English));
+                (MessageType::INFO, message.text(locale))
+            }
+            Err(BaconLsError::Cancelled) => {
+                progress
+                    .end(Some(BaconLsError::Cancelled.to_string()))
+                    .await;
+                tracing::info!("miri run cancelled");
+                return Ok(());
+            }
+            Err(e) => {
+                progress.end(Some(e.to_string())).await;
+                tracing::error!("{e}");
+                (MessageType::ERROR, e.to_string())
+            }
+        };
+        if let Some(client) = client {
+            client.show_message(message_type, message).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const STDERR: &str = "\
+running 2 tests
+test tests::dangling ... error: Undefined Behavior: memory access failed: alloc1234 has been freed, so this pointer is dangling
+   --> /rustc/library/core/src/ptr/mod.rs:1200:9
+    |
+1200|         read(src)
+    |         ^^^^^^^^^ memory access failed
+    |
+    = help: this indicates a bug in the program: it performed an invalid operation, and caused Undefined Behavior
+note: inside `tests::dangling`
+   --> src/lib.rs:14:18
+    |
+14  |         unsafe { std::ptr::read(ptr) };
+    |                  ^^^^^^^^^^^^^^^^^^^
+
+note: some details are omitted, run with `MIRIFLAGS=-Zmiri-backtrace=full` for a verbose backtrace
+
+error: aborting due to 1 previous error
+";
+
+    #[test]
+    fn test_parse_reports() {
+        let folder_path = Path::new("/projects/bacon-ls");
+        let diagnostics = parse_reports(STDERR, folder_path);
+        assert_eq!(diagnostics.len(), 1);
+        let (url, diagnostic) = &diagnostics[0];
+        assert_eq!(url.path(), "/projects/bacon-ls/src/lib.rs");
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(13, 17), Position::new(13, 36))
+        );
+        assert_eq!(
+            diagnostic.message,
+            "memory access failed: alloc1234 has been freed, so this pointer is dangling"
+        );
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        let rendered = DiagnosticData::rendered(diagnostic).unwrap();
+        assert!(rendered.starts_with("error: Undefined Behavior: memory access failed"));
+        assert!(rendered.ends_with("^^^^^^^^^^^^^^^^^^^"));
+
+        assert_eq!(
+            location("  --> src/lib.rs:14:18"),
+            Some(("src/lib.rs", 14, 18))
+        );
+        assert_eq!(location("14  |  code"), None);
+    }
+}
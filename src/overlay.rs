@@ -0,0 +1,426 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use tower_lsp::lsp_types::{Diagnostic, Url};
+use tower_lsp::Client;
+
+use crate::custom::{CustomCommand, CustomParser};
+use crate::error::BaconLsError;
+use crate::gc::prepare_build_folder;
+use crate::provider::DiagnosticsProvider;
+use crate::{BaconLs, State, FALLBACK_COMMAND_ARGS};
+
+/// Source of the diagnostics of the unsaved files, replacing the bacon ones of their folder.
+pub(crate) const UNSAVED_PROVIDER: &str = "unsaved";
+/// Kind of the build folders of the overlays, prefixing their names.
+pub(crate) const OVERLAY_BUILD_FOLDER: &str = "overlay";
+/// Files copied rather than linked in the overlay, as cargo may write them.
+const COPIED_FILES: [&str; 1] = ["Cargo.lock"];
+
+#[cfg(unix)]
+async fn link(original: &Path, link: &Path) -> io::Result<()> {
+    tokio::fs::symlink(original, link).await
+}
+
+#[cfg(not(unix))]
+async fn link(_original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "checking the unsaved files is not supported on this platform",
+    ))
+}
+
+/// Fill a directory of the overlay with links to the entries of the directory of the project
+/// it is missing, and drop the links to the entries removed since. The directory of a file never
+/// saved may be missing from the project.
+async fn populate(overlay_dir: &Path, dir: &Path) -> io::Result<()> {
+    tokio::fs::create_dir_all(overlay_dir).await?;
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = overlay_dir.join(entry.file_name());
+        if COPIED_FILES.iter().any(|file| entry.file_name() == *file) {
+            let content = tokio::fs::read(entry.path()).await?;
+            if tokio::fs::read(&path).await.ok().as_ref() != Some(&content) {
+                tokio::fs::write(&path, content).await?;
+            }
+        } else if tokio::fs::symlink_metadata(&path).await.is_err() {
+            link(&entry.path(), &path).await?;
+        }
+    }
+    let mut entries = tokio::fs::read_dir(overlay_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let dangling = entry.file_type().await?.is_symlink()
+            && tokio::fs::metadata(entry.path()).await.is_err();
+        if dangling {
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Turn the directories of a file of the overlay into directories of links, refreshing the
+/// ones which already are.
+async fn populate_parents(overlay_path: &Path, root: &Path, relative: &Path) -> io::Result<()> {
+    let mut overlay_dir = overlay_path.to_path_buf();
+    let mut dir = root.to_path_buf();
+    for component in relative.parent().into_iter().flat_map(Path::components) {
+        overlay_dir.push(component);
+        dir.push(component);
+        let is_dir = tokio::fs::symlink_metadata(&overlay_dir)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir());
+        if !is_dir {
+            let _ = tokio::fs::remove_file(&overlay_dir).await;
+        }
+        populate(&overlay_dir, &dir).await?;
+    }
+    Ok(())
+}
+
+/// Update the overlay of a Cargo workspace in place: links to the files of the workspace, but
+/// for the unsaved files, given relative to its root with their content. Only the directories
+/// of the unsaved files are created, the others are links too, so it is cheap even for a big
+/// project. The files written by the previous check and saved since are linked again, and the
+/// unchanged ones are kept, with their modification time, so cargo doesn't check them again.
+pub(crate) async fn materialize(
+    overlay_path: &Path,
+    root: &Path,
+    written: &BTreeSet<PathBuf>,
+    buffers: &BTreeMap<PathBuf, String>,
+) -> io::Result<()> {
+    populate(overlay_path, root).await?;
+    for relative in written.iter().filter(|path| !buffers.contains_key(*path)) {
+        populate_parents(overlay_path, root, relative).await?;
+        let path = overlay_path.join(relative);
+        let _ = tokio::fs::remove_file(&path).await;
+        let original = root.join(relative);
+        if tokio::fs::symlink_metadata(&original).await.is_ok() {
+            link(&original, &path).await?;
+        }
+    }
+    for (relative, content) in buffers {
+        populate_parents(overlay_path, root, relative).await?;
+        let path = overlay_path.join(relative);
+        let is_file = tokio::fs::symlink_metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_file());
+        if is_file && tokio::fs::read_to_string(&path).await.ok().as_ref() == Some(content) {
+            continue;
+        }
+        let _ = tokio::fs::remove_file(&path).await;
+        tokio::fs::write(&path, content).await?;
+    }
+    Ok(())
+}
+
+/// State of the overlay of a workspace folder, held while checking its unsaved files.
+#[derive(Debug, Default)]
+pub(crate) struct UnsavedCheck {
+    /// Root of the Cargo workspace of the folder, resolved on first use.
+    root: Option<PathBuf>,
+    /// Build folder of the overlay, created with the root.
+    overlay_path: Option<PathBuf>,
+    /// Hash of the unsaved files at the last check.
+    hash: Option<u64>,
+    /// Files written in the overlay, relative to the root.
+    written: BTreeSet<PathBuf>,
+}
+
+/// Uri of a file of the overlay in the Cargo workspace.
+fn folder_uri(uri: &Url, overlay_path: &Path, root: &Path) -> Option<Url> {
+    let path = uri.to_file_path().ok()?;
+    Url::from_file_path(root.join(path.strip_prefix(overlay_path).ok()?)).ok()
+}
+
+impl BaconLs {
+    /// Mark an open file as changed since it was saved, or not anymore once it is saved or
+    /// closed, returning true if the file was or is unsaved.
+    pub(crate) async fn set_unsaved(state: &Arc<RwLock<State>>, uri: &Url, unsaved: bool) -> bool {
+        let mut state = state.write().await;
+        if !state.check_unsaved_files {
+            return false;
+        }
+        match unsaved {
+            true => {
+                state.unsaved_files.insert(uri.clone());
+                true
+            }
+            false => state.unsaved_files.remove(uri),
+        }
+    }
+
+    /// Check the workspace folder of a file with its unsaved files written over it in an
+    /// overlay, publishing the diagnostics in place of the bacon ones. Once all the files of
+    /// the folder are saved, the bacon diagnostics are published again.
+    pub(crate) async fn check_unsaved_files(
+        client: Option<Arc<Client>>,
+        state: Arc<RwLock<State>>,
+        uri: Url,
+    ) {
+        let mut write_state = state.write().await;
+        let Some(folder_path) =
+            Self::workspace_folder_path(&uri, write_state.workspace_folders.as_deref())
+        else {
+            return;
+        };
+        let last_check = write_state.project(&folder_path).unsaved_check.clone();
+        drop(write_state);
+        // Checks of the same folder run one at a time, with the content at the time they start.
+        let mut last_check = last_check.lock().await;
+        let cargo_env = Self::folder_cargo_env(&state, &folder_path).await;
+        // The overlay is the one of the whole Cargo workspace, for the members of a workspace to
+        // be built in it.
+        let (root, overlay_path) =
+            match last_check.root.clone().zip(last_check.overlay_path.clone()) {
+                Some(paths) => paths,
+                None => {
+                    let root = Self::cargo_workspace_root(&folder_path, &cargo_env)
+                        .await
+                        .unwrap_or_else(|| folder_path.clone());
+                    let target_dir = root.join(
+                        cargo_env
+                            .get("CARGO_TARGET_DIR")
+                            .map_or("target", String::as_str),
+                    );
+                    let overlay_path =
+                        match prepare_build_folder(OVERLAY_BUILD_FOLDER, &folder_path, &target_dir)
+                            .await
+                        {
+                            Ok(overlay_path) => overlay_path,
+                            Err(e) => {
+                                tracing::warn!("unable to check the unsaved files: {e}");
+                                return;
+                            }
+                        };
+                    // The overlay left by a previous session is built again. Links are removed, not
+                    // followed.
+                    let _ = tokio::fs::remove_dir_all(&overlay_path).await;
+                    last_check.root = Some(root.clone());
+                    last_check.overlay_path = Some(overlay_path.clone());
+                    (root, overlay_path)
+                }
+            };
+        let mut write_state = state.write().await;
+        let mut buffers = BTreeMap::new();
+        // Content of the open files the diagnostics of the check are computed on, the saved one
+        // for the files which are not unsaved.
+        let mut checkpoints: Vec<(Url, Option<u64>)> = write_state
+            .open_files
+            .iter()
+            .filter(|uri| Path::new(uri.path()).starts_with(&folder_path))
+            .filter(|uri| !write_state.unsaved_files.contains(*uri))
+            .map(|uri| (uri.clone(), None))
+            .collect();
+        for uri in write_state.unsaved_files.clone() {
+            let Some(relative) = uri
+                .to_file_path()
+                .ok()
+                .filter(|path| path.starts_with(&folder_path))
+                .and_then(|path| Some(path.strip_prefix(&root).ok()?.to_path_buf()))
+            else {
+                continue;
+            };
+            let Some(content) = write_state.documents.get(&uri) else {
+                continue;
+            };
+            buffers.insert(relative, content.to_string());
+            let diagnostics_version = write_state.diagnostics_store.version(&uri);
+            let checkpoint = write_state
+                .documents
+                .checkpoint(&uri, diagnostics_version, false);
+            checkpoints.push((uri, checkpoint));
+        }
+        drop(write_state);
+        let mut hasher = DefaultHasher::new();
+        buffers.hash(&mut hasher);
+        let hash = hasher.finish();
+        if last_check.hash == Some(hash) {
+            // The stored diagnostics were computed on the same content.
+            let mut write_state = state.write().await;
+            for (uri, checkpoint) in checkpoints {
+                write_state.documents.checked(&uri, checkpoint);
+            }
+            return;
+        }
+
+        let diagnostics = if buffers.is_empty() {
+            state.write().await.unsaved_folders.remove(&folder_path);
+            HashMap::new()
+        } else {
+            let mut args: Vec<String> = FALLBACK_COMMAND_ARGS.map(|arg| arg.to_string()).to_vec();
+            // Only the package of a member of the workspace is checked, from the root, as the
+            // file names are relative to it.
+            let manifest_path = folder_path.join("Cargo.toml");
+            if folder_path != root && manifest_path.exists() {
+                let manifest_path =
+                    overlay_path.join(manifest_path.strip_prefix(&root).unwrap_or(&manifest_path));
+                args.extend([
+                    "--manifest-path".to_string(),
+                    manifest_path.display().to_string(),
+                ]);
+            }
+            let command = CustomCommand {
+                command: "cargo".to_string(),
+                args,
+                parser: CustomParser::CargoJson,
+                events: None,
+            };
+            let result =
+                match materialize(&overlay_path, &root, &last_check.written, &buffers).await {
+                    Ok(()) => {
+                        last_check.written = buffers.keys().cloned().collect();
+                        let command_limits = state.read().await.command_limits;
+                        command_limits
+                            .scope(command.run(&overlay_path, &cargo_env))
+                            .await
+                    }
+                    Err(e) => {
+                        // Built again from scratch by the next check. Links are removed, not
+                        // followed.
+                        last_check.written.clear();
+                        let _ = tokio::fs::remove_dir_all(&overlay_path).await;
+                        Err(BaconLsError::io(
+                            format!("unable to write the overlay {}", overlay_path.display()),
+                            e,
+                        ))
+                    }
+                };
+            match result {
+                Ok(diagnostics) => {
+                    state
+                        .write()
+                        .await
+                        .unsaved_folders
+                        .insert(folder_path.clone());
+                    let mut by_file: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+                    for (uri, diagnostics) in diagnostics {
+                        let uri = folder_uri(&uri, &overlay_path, &root)
+                            .filter(|uri| Path::new(uri.path()).starts_with(&folder_path));
+                        if let Some(uri) = uri {
+                            by_file.entry(uri).or_default().extend(diagnostics);
+                        }
+                    }
+                    by_file
+                }
+                Err(e) => {
+                    tracing::warn!("unable to check the unsaved files: {e}");
+                    return;
+                }
+            }
+        };
+        last_check.hash = Some(hash);
+        drop(last_check);
+        let mut write_state = state.write().await;
+        for (uri, checkpoint) in checkpoints {
+            write_state.documents.checked(&uri, checkpoint);
+        }
+        drop(write_state);
+        Self::set_folder_diagnostics(
+            client.as_ref(),
+            &state,
+            UNSAVED_PROVIDER,
+            &folder_path,
+            diagnostics,
+        )
+        .await;
+        // The bacon diagnostics of the other files of the folder are hidden or shown again.
+        let read_state = state.read().await;
+        let uris: Vec<Url> = read_state
+            .open_files
+            .iter()
+            .chain(read_state.diagnostics_store.uris())
+            .filter(|uri| Path::new(uri.path()).starts_with(&folder_path))
+            .cloned()
+            .collect();
+        drop(read_state);
+        for uri in uris.iter() {
+            Self::publish_diagnostics(client.as_ref(), &state, uri, false).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_materialize() {
+        let tmp_dir = TempDir::new("overlay").unwrap();
+        let folder_path = tmp_dir.path().join("project");
+        let root = folder_path.as_path();
+        std::fs::create_dir_all(folder_path.join("src/module")).unwrap();
+        std::fs::create_dir_all(folder_path.join("tests")).unwrap();
+        std::fs::write(folder_path.join("Cargo.toml"), "[package]").unwrap();
+        std::fs::write(folder_path.join("Cargo.lock"), "version = 4").unwrap();
+        std::fs::write(folder_path.join("src/lib.rs"), "mod module;").unwrap();
+        std::fs::write(folder_path.join("src/module/mod.rs"), "saved").unwrap();
+        std::fs::write(folder_path.join("tests/it.rs"), "").unwrap();
+        let overlay_path = tmp_dir.path().join("overlay");
+        let buffers = BTreeMap::from([
+            (PathBuf::from("src/module/mod.rs"), "unsaved".to_string()),
+            // Never saved, in a new directory.
+            (PathBuf::from("src/new/mod.rs"), "new".to_string()),
+        ]);
+
+        materialize(&overlay_path, root, &BTreeSet::new(), &buffers)
+            .await
+            .unwrap();
+        let is_link = |path: &str| {
+            std::fs::symlink_metadata(overlay_path.join(path))
+                .unwrap()
+                .is_symlink()
+        };
+        assert!(is_link("Cargo.toml"));
+        assert!(!is_link("Cargo.lock"));
+        assert!(!is_link("src"));
+        assert!(is_link("src/lib.rs"));
+        assert!(!is_link("src/module/mod.rs"));
+        assert!(is_link("tests"));
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&overlay_path.join("src/module/mod.rs")), "unsaved");
+        assert_eq!(read(&folder_path.join("src/module/mod.rs")), "saved");
+        assert_eq!(read(&overlay_path.join("src/new/mod.rs")), "new");
+        assert_eq!(read(&overlay_path.join("tests/it.rs")), "");
+
+        // Updated in place: the unchanged files are kept, the saved ones linked again, and the
+        // entries added to or removed from the project since are followed.
+        let modified = |path: &str| {
+            std::fs::metadata(overlay_path.join(path))
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+        let new_modified = modified("src/new/mod.rs");
+        std::fs::write(folder_path.join("src/module/added.rs"), "").unwrap();
+        std::fs::remove_file(folder_path.join("src/lib.rs")).unwrap();
+        std::fs::write(folder_path.join("Cargo.lock"), "version = 5").unwrap();
+        let written = buffers.keys().cloned().collect();
+        let buffers = BTreeMap::from([(PathBuf::from("src/new/mod.rs"), "new".to_string())]);
+        materialize(&overlay_path, root, &written, &buffers)
+            .await
+            .unwrap();
+        assert_eq!(modified("src/new/mod.rs"), new_modified);
+        assert!(is_link("src/module/mod.rs"));
+        assert_eq!(read(&overlay_path.join("src/module/mod.rs")), "saved");
+        assert!(is_link("src/module/added.rs"));
+        assert!(std::fs::symlink_metadata(overlay_path.join("src/lib.rs")).is_err());
+        assert_eq!(read(&overlay_path.join("Cargo.lock")), "version = 5");
+
+        let uri = Url::from_file_path(overlay_path.join("src/lib.rs")).unwrap();
+        assert_eq!(
+            folder_uri(&uri, &overlay_path, &folder_path),
+            Some(Url::from_file_path(folder_path.join("src/lib.rs")).unwrap())
+        );
+    }
+}
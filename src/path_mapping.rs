@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{Url, WorkspaceFolder};
+
+/// Prefix of the paths seen by cargo and bacon, like in a container, replaced by the prefix of
+/// the same files seen by the editor.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct PathMapping {
+    pub(crate) from: PathBuf,
+    pub(crate) to: PathBuf,
+}
+
+/// The `pathMappings` setting, the first matching prefix being applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct PathMappings(Vec<PathMapping>);
+
+impl PathMappings {
+    pub(crate) fn new(mappings: Vec<PathMapping>) -> Self {
+        Self(mappings)
+    }
+
+    /// Convert the URI of a file seen by the tools into the one seen by the editor, matching
+    /// whole path components, so it matches the URIs sent by the client.
+    pub(crate) fn to_client(&self, uri: &Url) -> Url {
+        if self.0.is_empty() || uri.scheme() != "file" {
+            return uri.clone();
+        }
+        let path = Path::new(uri.path());
+        self.0
+            .iter()
+            .find_map(|mapping| {
+                let relative = path.strip_prefix(&mapping.from).ok()?;
+                Url::from_file_path(mapping.to.join(relative)).ok()
+            })
+            .unwrap_or_else(|| uri.clone())
+    }
+}
+
+/// Resolution of the URIs of the diagnostics and of the client into the same URIs, the ones
+/// of the files under the workspace folders as seen by the editor. Tools like cargo report the
+/// canonical paths of the files, which differ from the editor ones in a symlinked checkout.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PathResolver {
+    pub(crate) mappings: PathMappings,
+    folder_paths: Vec<PathBuf>,
+    /// Canonical path of the workspace folders reached through a symlink, with their path.
+    folders: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PathResolver {
+    pub(crate) fn set_workspace_folders(&mut self, workspace_folders: &[WorkspaceFolder]) {
+        self.folder_paths = workspace_folders
+            .iter()
+            .map(|folder| PathBuf::from(folder.uri.path()))
+            .collect();
+        self.folders = self
+            .folder_paths
+            .iter()
+            .filter_map(|folder_path| {
+                let canonical = std::fs::canonicalize(folder_path).ok()?;
+                (canonical != *folder_path).then(|| (canonical, folder_path.clone()))
+            })
+            .collect();
+    }
+
+    /// Return true if a resolved URI is a file of the workspace folders, or if there are no
+    /// folders.
+    pub(crate) fn is_in_workspace(&self, uri: &Url) -> bool {
+        self.folder_paths.is_empty()
+            || (uri.scheme() == "file"
+                && self
+                    .folder_paths
+                    .iter()
+                    .any(|folder_path| Path::new(uri.path()).starts_with(folder_path)))
+    }
+
+    fn under_folder(&self, path: &Path) -> Option<PathBuf> {
+        self.folders.iter().find_map(|(canonical, folder_path)| {
+            Some(folder_path.join(path.strip_prefix(canonical).ok()?))
+        })
+    }
+
+    /// Resolve a URI, mapped with the `pathMappings`, into the URI of the file under the
+    /// workspace folder it belongs to, if it is reached through a symlink.
+    pub(crate) fn resolve(&self, uri: &Url) -> Url {
+        let uri = self.mappings.to_client(uri);
+        if self.folders.is_empty() || uri.scheme() != "file" || self.is_in_workspace(&uri) {
+            return uri;
+        }
+        let path = Path::new(uri.path());
+        // The files are only canonicalized when their path is not under a canonical folder,
+        // like when a directory of the folder is a symlink too.
+        self.under_folder(path)
+            .or_else(|| self.under_folder(&std::fs::canonicalize(path).ok()?))
+            .and_then(|path| Url::from_file_path(path).ok())
+            .unwrap_or(uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_path_mappings() {
+        let mappings = PathMappings::new(
+            serde_json::from_value(serde_json::json!([
+                {"from": "/workspace", "to": "/home/user/project"},
+                {"from": "/usr/local/cargo", "to": "/home/user/.cargo"},
+            ]))
+            .unwrap(),
+        );
+        let url = |path: &str| Url::from_file_path(path).unwrap();
+        assert_eq!(
+            mappings.to_client(&url("/workspace/src/lib.rs")),
+            url("/home/user/project/src/lib.rs")
+        );
+        assert_eq!(
+            mappings.to_client(&url("/usr/local/cargo/registry/src/serde/lib.rs")),
+            url("/home/user/.cargo/registry/src/serde/lib.rs")
+        );
+        // Only whole components match.
+        assert_eq!(
+            mappings.to_client(&url("/workspaces/src/lib.rs")),
+            url("/workspaces/src/lib.rs")
+        );
+        assert_eq!(
+            PathMappings::default().to_client(&url("/workspace/src/lib.rs")),
+            url("/workspace/src/lib.rs")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_resolver() {
+        let tmp_dir = tempdir::TempDir::new("paths").unwrap();
+        let checkout = tmp_dir.path().join("mnt/work");
+        std::fs::create_dir_all(checkout.join("src")).unwrap();
+        std::fs::write(checkout.join("src/lib.rs"), "").unwrap();
+        let checkout = std::fs::canonicalize(checkout).unwrap();
+        let folder_path = tmp_dir.path().join("work");
+        std::os::unix::fs::symlink(&checkout, &folder_path).unwrap();
+        let mut resolver = PathResolver::default();
+        resolver.set_workspace_folders(&[WorkspaceFolder {
+            uri: Url::from_file_path(&folder_path).unwrap(),
+            name: "work".to_string(),
+        }]);
+        let expected = Url::from_file_path(folder_path.join("src/lib.rs")).unwrap();
+        // The canonical path reported by cargo and the one of the editor are the same file.
+        assert_eq!(
+            resolver.resolve(&Url::from_file_path(checkout.join("src/lib.rs")).unwrap()),
+            expected
+        );
+        assert_eq!(resolver.resolve(&expected), expected);
+        let outside = Url::from_file_path(tmp_dir.path().join("other.rs")).unwrap();
+        assert_eq!(resolver.resolve(&outside), outside);
+        assert!(resolver.is_in_workspace(&expected));
+        assert!(!resolver.is_in_workspace(&outside));
+    }
+}
@@ -0,0 +1,86 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Error returned by Windows when all the instances of the named pipe are busy.
+#[cfg(windows)]
+const ERROR_PIPE_BUSY: i32 = 231;
+
+/// Split an argument like `--pipe=name`, which clients following the LSP specification pass,
+/// into the `--pipe name` form of the command line options.
+pub(crate) fn split_pipe_arg(arg: String) -> Vec<String> {
+    match arg.strip_prefix("--pipe=") {
+        Some(name) => vec!["--pipe".to_string(), name.to_string()],
+        None => vec![arg],
+    }
+}
+
+/// Connect to the pipe created by the client for the pipe transport of the LSP specification: a
+/// named pipe on Windows, or a socket file on the other platforms.
+pub(crate) async fn connect(
+    name: &str,
+) -> io::Result<(impl AsyncRead + Unpin, impl AsyncWrite + Unpin)> {
+    let stream = open(name).await?;
+    tracing::info!("connected to the pipe {name}");
+    Ok(tokio::io::split(stream))
+}
+
+#[cfg(windows)]
+async fn open(name: &str) -> io::Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+    loop {
+        match ClientOptions::new().open(name) {
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn open(name: &str) -> io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(name).await
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn open(_name: &str) -> io::Result<tokio::io::DuplexStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pipes are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_split_pipe_arg() {
+        assert_eq!(
+            split_pipe_arg("--pipe=/tmp/lsp.sock".to_string()),
+            vec!["--pipe", "/tmp/lsp.sock"]
+        );
+        assert_eq!(split_pipe_arg("--pipe".to_string()), vec!["--pipe"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_connect() {
+        let tmp_dir = TempDir::new("pipe").unwrap();
+        let path = tmp_dir.path().join("lsp.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let (mut read, mut write) = connect(path.to_str().unwrap()).await.unwrap();
+        let (mut client, _) = listener.accept().await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut received = [0; 4];
+        read.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"ping");
+        write.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"pong");
+    }
+}
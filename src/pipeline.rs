@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Url};
+use tower_lsp::Client;
+
+use crate::custom::{CargoEvents, CustomCommand, CustomParser};
+use crate::error::{BaconLsError, Result};
+use crate::progress::WorkProgress;
+use crate::provider::DiagnosticsProvider;
+
+pub(crate) const PIPELINE_PROVIDER: &str = "pipeline";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StageSettings {
+    name: String,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    parser: Option<String>,
+    regex: Option<String>,
+}
+
+/// Command of the pipeline, whose diagnostics are labeled with the stage name.
+#[derive(Debug)]
+struct PipelineStage {
+    name: String,
+    command: CustomCommand,
+}
+
+/// Commands run one after the other, e.g. clippy then the tests then the docs, stopping at the
+/// first one reporting errors.
+#[derive(Debug)]
+pub(crate) struct Pipeline {
+    stages: Vec<PipelineStage>,
+    client: Option<Arc<Client>>,
+}
+
+impl Pipeline {
+    pub(crate) fn parse(
+        value: &serde_json::Value,
+        client: Option<Arc<Client>>,
+        events: Option<CargoEvents>,
+    ) -> Result<Self> {
+        let settings: Vec<StageSettings> = serde_json::from_value(value.clone())
+            .map_err(|e| BaconLsError::Config(format!("invalid pipeline: {e}")))?;
+        let stages = settings
+            .into_iter()
+            .map(|stage| {
+                let parser = CustomParser::parse(
+                    stage.parser.as_deref().unwrap_or("cargo-json"),
+                    stage.regex.as_deref(),
+                )
+                .map_err(|e| {
+                    BaconLsError::Config(format!("invalid pipeline stage {}: {e}", stage.name))
+                })?;
+                Ok(PipelineStage {
+                    name: stage.name,
+                    command: CustomCommand {
+                        command: stage.command,
+                        args: stage.args,
+                        parser,
+                        events: events.clone(),
+                    },
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { stages, client })
+    }
+}
+
+fn has_errors(diagnostics: &HashMap<Url, Vec<Diagnostic>>) -> bool {
+    diagnostics
+        .values()
+        .flatten()
+        .any(|diagnostic| diagnostic.severity == Some(DiagnosticSeverity::ERROR))
+}
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for Pipeline {
+    fn name(&self) -> &'static str {
+        PIPELINE_PROVIDER
+    }
+
+    fn runs_on_save(&self, _uri: &Url) -> bool {
+        true
+    }
+
+    /// Run the stages in order, merging their diagnostics, until one reports errors.
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let progress = WorkProgress::begin(self.client.as_deref(), "pipeline").await;
+        let mut diagnostics: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+        for (index, stage) in self.stages.iter().enumerate() {
+            progress
+                .report(
+                    format!("{} ({}/{})", stage.name, index + 1, self.stages.len()),
+                    (index * 100 / self.stages.len()) as u32,
+                )
+                .await;
+            // Dropping the run of the stage kills its process.
+            let result = tokio::select! {
+                result = progress.show_messages(stage.command.run(folder_path, env)) => result,
+                _ = progress.cancelled() => {
+                    tracing::info!("pipeline cancelled during stage {}", stage.name);
+                    progress.end(Some("cancelled".to_string())).await;
+                    return Err(BaconLsError::Cancelled);
+                }
+            };
+            let stage_diagnostics = match result {
+                Ok(stage_diagnostics) => stage_diagnostics,
+                Err(e @ BaconLsError::Locked(_)) => {
+                    progress.end(Some("skipped".to_string())).await;
+                    return Err(e);
+                }
+                Err(e) => {
+                    progress.end(Some(format!("{} failed", stage.name))).await;
+                    return Err(BaconLsError::Spawn(format!(
+                        "pipeline stage {} failed: {e}",
+                        stage.name
+                    )));
+                }
+            };
+            let failed = has_errors(&stage_diagnostics);
+            for (url, stage_diagnostics) in stage_diagnostics {
+                diagnostics
+                    .entry(url)
+                    .or_default()
+                    .extend(stage_diagnostics.into_iter().map(|diagnostic| Diagnostic {
+                        source: Some(stage.name.clone()),
+                        ..diagnostic
+                    }));
+            }
+            if failed {
+                tracing::info!(
+                    "pipeline stage {} reported errors, skipping the next stages",
+                    stage.name
+                );
+                progress
+                    .end(Some(format!("{} reported errors", stage.name)))
+                    .await;
+                return Ok(diagnostics);
+            }
+        }
+        progress.end(None).await;
+        Ok(diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_pipeline() {
+        let pipeline = Pipeline::parse(
+            &serde_json::json!([
+                {"name": "clippy", "command": "cargo", "args": ["clippy", "--message-format", "json"]},
+                {"name": "doc", "command": "cargo", "args": ["doc"], "parser": "gcc-style"},
+            ]),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(pipeline.stages.len(), 2);
+        assert_eq!(pipeline.stages[0].name, "clippy");
+        assert!(matches!(
+            pipeline.stages[0].command.parser,
+            CustomParser::CargoJson
+        ));
+        assert!(matches!(
+            pipeline.stages[1].command.parser,
+            CustomParser::Regex(_)
+        ));
+
+        assert!(Pipeline::parse(&serde_json::json!([{"name": "test"}]), None, None).is_err());
+        assert!(Pipeline::parse(
+            &serde_json::json!([{"name": "test", "command": "cargo", "parser": "xml"}]),
+            None,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_has_errors() {
+        let url = Url::parse("file:///projects/bacon-ls/src/lib.rs").unwrap();
+        let warning = Diagnostic {
+            severity: Some(DiagnosticSeverity::WARNING),
+            ..Diagnostic::default()
+        };
+        let mut diagnostics = HashMap::from([(url.clone(), vec![warning])]);
+        assert!(!has_errors(&diagnostics));
+        diagnostics.get_mut(&url).unwrap().push(Diagnostic {
+            severity: Some(DiagnosticSeverity::ERROR),
+            ..Diagnostic::default()
+        });
+        assert!(has_errors(&diagnostics));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity};
+
+use crate::cli::{finding_line, CliSettings};
+use crate::custom::{CustomCommand, CustomParser};
+use crate::error::{BaconLsError, Result};
+use crate::provider::DiagnosticsProvider;
+use crate::usage;
+use crate::{git, parsed_output_command, FALLBACK_COMMAND_ARGS};
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    workspace_root: PathBuf,
+    packages: Vec<Package>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+    manifest_path: PathBuf,
+}
+
+/// Packages containing the files, picking the innermost package of each file.
+fn staged_packages(packages: &[Package], files: &[PathBuf]) -> BTreeSet<String> {
+    files
+        .iter()
+        .filter_map(|file| {
+            packages
+                .iter()
+                .filter_map(|package| Some((package, package.manifest_path.parent()?)))
+                .filter(|(_, package_path)| file.starts_with(package_path))
+                .max_by_key(|(_, package_path)| package_path.as_os_str().len())
+                .map(|(package, _)| package.name.clone())
+        })
+        .collect()
+}
+
+/// Findings to report by file: the errors of every file, as a staged file can break the build
+/// of another one, and the warnings of the staged files only.
+fn scoped_findings(
+    findings: impl IntoIterator<Item = (PathBuf, Vec<Diagnostic>)>,
+    files: &[PathBuf],
+) -> BTreeMap<PathBuf, Vec<Diagnostic>> {
+    findings
+        .into_iter()
+        .map(|(path, mut diagnostics)| {
+            if !files.contains(&path) {
+                diagnostics
+                    .retain(|diagnostic| diagnostic.severity == Some(DiagnosticSeverity::ERROR));
+            }
+            (path, diagnostics)
+        })
+        .filter(|(_, diagnostics)| !diagnostics.is_empty())
+        .collect()
+}
+
+/// Check the Rust files staged in git with `cargo clippy`, scoped to their packages, and print
+/// the findings kept by the diagnostics filter of the settings file. The files are checked as
+/// they are in the working tree.
+///
+/// Returns true if errors were found.
+pub async fn precommit(settings: Option<PathBuf>) -> Result<bool> {
+    let settings = CliSettings::load(settings.as_deref()).await?;
+    let current_dir = std::env::current_dir()
+        .map_err(|e| BaconLsError::io("unable to read the current directory", e))?;
+    let repo_root = PathBuf::from(git(&current_dir, &["rev-parse", "--show-toplevel"]).await?);
+    let staged = git(
+        &repo_root,
+        &["diff", "--cached", "--name-only", "--diff-filter=ACMR"],
+    )
+    .await?;
+    let files: Vec<PathBuf> = staged
+        .lines()
+        .map(|file| repo_root.join(file))
+        .filter(|file| file.extension().is_some_and(|extension| extension == "rs"))
+        .collect();
+    if files.is_empty() {
+        eprintln!("no Rust files staged, nothing to check");
+        return Ok(false);
+    }
+
+    let output = usage::output(
+        parsed_output_command("cargo")
+            .args(["metadata", "--format-version", "1", "--no-deps"])
+            .envs(&settings.cargo_env)
+            .current_dir(&repo_root),
+    )
+    .await
+    .map_err(|e| BaconLsError::Spawn(format!("failed to run cargo metadata: {e}")))?;
+    if !output.status.success() {
+        return Err(BaconLsError::Spawn(format!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let metadata: Metadata = serde_json::from_slice(&output.stdout)
+        .map_err(|e| BaconLsError::Parse(format!("invalid cargo metadata output: {e}")))?;
+    let packages = staged_packages(&metadata.packages, &files);
+    if packages.is_empty() {
+        eprintln!("the staged files don't belong to a Cargo package, nothing to check");
+        return Ok(false);
+    }
+
+    let mut args: Vec<String> = FALLBACK_COMMAND_ARGS.map(|arg| arg.to_string()).to_vec();
+    for package in packages.iter() {
+        args.extend(["--package".to_string(), package.clone()]);
+    }
+    let command = CustomCommand {
+        command: "cargo".to_string(),
+        args,
+        parser: CustomParser::CargoJson,
+        events: None,
+    };
+    let diagnostics = settings
+        .command_limits
+        .scope(command.run(&metadata.workspace_root, &settings.cargo_env))
+        .await?;
+    let findings = diagnostics
+        .into_iter()
+        .filter_map(|(uri, diagnostics)| Some((uri.to_file_path().ok()?, diagnostics)))
+        .map(|(path, diagnostics)| {
+            // The targets sharing the file, like the binary and its tests, report it once each.
+            let mut unique: Vec<Diagnostic> = Vec::new();
+            for diagnostic in settings.filter.apply(diagnostics) {
+                if !unique.contains(&diagnostic) {
+                    unique.push(diagnostic);
+                }
+            }
+            (path, unique)
+        });
+    let findings = scoped_findings(findings, &files);
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    for (path, diagnostics) in findings.iter() {
+        let path = path.strip_prefix(&current_dir).unwrap_or(path);
+        for diagnostic in diagnostics {
+            match diagnostic.severity {
+                Some(DiagnosticSeverity::ERROR) => errors += 1,
+                Some(DiagnosticSeverity::WARNING) => warnings += 1,
+                _ => {}
+            }
+            println!("{}", finding_line(path, diagnostic));
+        }
+    }
+    eprintln!(
+        "checked {} staged files: {errors} errors, {warnings} warnings",
+        files.len()
+    );
+    Ok(errors > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_scoped_findings() {
+        let diagnostic = |severity| Diagnostic {
+            severity: Some(severity),
+            ..Diagnostic::default()
+        };
+        let staged = PathBuf::from("/projects/app/src/lib.rs");
+        let other = PathBuf::from("/projects/app/src/main.rs");
+        let warnings = PathBuf::from("/projects/app/src/util.rs");
+        let findings = scoped_findings(
+            [
+                (
+                    staged.clone(),
+                    vec![
+                        diagnostic(DiagnosticSeverity::WARNING),
+                        diagnostic(DiagnosticSeverity::ERROR),
+                    ],
+                ),
+                (
+                    other.clone(),
+                    vec![
+                        diagnostic(DiagnosticSeverity::WARNING),
+                        diagnostic(DiagnosticSeverity::ERROR),
+                    ],
+                ),
+                (warnings, vec![diagnostic(DiagnosticSeverity::WARNING)]),
+            ],
+            std::slice::from_ref(&staged),
+        );
+        assert_eq!(
+            findings,
+            BTreeMap::from([
+                (
+                    staged,
+                    vec![
+                        diagnostic(DiagnosticSeverity::WARNING),
+                        diagnostic(DiagnosticSeverity::ERROR),
+                    ]
+                ),
+                (other, vec![diagnostic(DiagnosticSeverity::ERROR)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_staged_packages() {
+        let package = |name: &str, manifest_path: &str| Package {
+            name: name.to_string(),
+            manifest_path: PathBuf::from(manifest_path),
+        };
+        let packages = vec![
+            package("app", "/projects/app/Cargo.toml"),
+            package("core", "/projects/app/crates/core/Cargo.toml"),
+        ];
+        let files = vec![
+            PathBuf::from("/projects/app/crates/core/src/lib.rs"),
+            PathBuf::from("/projects/app/crates/core/src/parser.rs"),
+            PathBuf::from("/projects/other/src/main.rs"),
+        ];
+        assert_eq!(
+            staged_packages(&packages, &files),
+            BTreeSet::from(["core".to_string()])
+        );
+        assert_eq!(
+            staged_packages(&packages, &[PathBuf::from("/projects/app/build.rs")]),
+            BTreeSet::from(["app".to_string()])
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use tokio::sync::{mpsc, watch};
+use tower_lsp::{
+    lsp_types::{
+        notification::Progress, request::WorkDoneProgressCreate, NumberOrString, ProgressParams,
+        ProgressParamsValue, WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressCancelParams,
+        WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport,
+    },
+    Client,
+};
+
+use crate::run_id::RunId;
+use crate::{BaconLs, PKG_NAME};
+
+pub(crate) const WORK_DONE_PROGRESS_CANCEL_METHOD: &str = "window/workDoneProgress/cancel";
+
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(0);
+/// Senders cancelling the progress in flight, by token.
+static IN_FLIGHT: Mutex<Vec<(NumberOrString, watch::Sender<bool>)>> = Mutex::new(Vec::new());
+
+tokio::task_local! {
+    /// Messages of the commands run by the current task, shown by the progress it runs under.
+    static MESSAGES: mpsc::UnboundedSender<String>;
+}
+
+/// Show a message of a command, like cargo waiting for a lock, in the progress the current task
+/// runs under, if any.
+pub(crate) fn report_message(message: &str) {
+    let _ = MESSAGES.try_with(|messages| messages.send(message.to_string()));
+}
+
+/// Sender of the messages of the current task, to report the messages of the tasks it spawns
+/// with [`with_messages`].
+pub(crate) fn messages() -> Option<mpsc::UnboundedSender<String>> {
+    MESSAGES.try_with(Clone::clone).ok()
+}
+
+/// Run the future, reporting its messages to the given sender.
+pub(crate) async fn with_messages<F: Future>(
+    messages: Option<mpsc::UnboundedSender<String>>,
+    future: F,
+) -> F::Output {
+    match messages {
+        Some(messages) => MESSAGES.scope(messages, future).await,
+        None => future.await,
+    }
+}
+
+/// Work done progress shown by the client, which is a no-op if the client doesn't support it.
+/// The user can cancel it from the client while it is in flight.
+pub(crate) struct WorkProgress<'c> {
+    client: Option<&'c Client>,
+    token: NumberOrString,
+    cancelled: watch::Receiver<bool>,
+}
+
+/// Mark the progress with the given token as cancelled, returning false if it isn't in flight.
+fn cancel(token: &NumberOrString) -> bool {
+    let in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+    in_flight
+        .iter()
+        .find(|(in_flight, _)| in_flight == token)
+        .is_some_and(|(_, sender)| sender.send(true).is_ok())
+}
+
+impl<'c> WorkProgress<'c> {
+    /// Start the progress, whose title mentions the run it belongs to, if any.
+    pub(crate) async fn begin(client: Option<&'c Client>, title: &str) -> Self {
+        let token = NumberOrString::String(format!(
+            "{PKG_NAME}/{}",
+            NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+        ));
+        let client = match client {
+            Some(client) => client
+                .send_request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                    token: token.clone(),
+                })
+                .await
+                .inspect_err(|e| tracing::debug!("client doesn't support progress: {e}"))
+                .ok()
+                .map(|_| client),
+            None => None,
+        };
+        let (sender, cancelled) = watch::channel(false);
+        IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((token.clone(), sender));
+        let progress = Self {
+            client,
+            token,
+            cancelled,
+        };
+        progress
+            .notify(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: match RunId::current() {
+                    Some(run_id) => format!("{title} ({run_id})"),
+                    None => title.to_string(),
+                },
+                cancellable: Some(true),
+                ..WorkDoneProgressBegin::default()
+            }))
+            .await;
+        progress
+    }
+
+    /// Wait until the user cancels the progress.
+    pub(crate) async fn cancelled(&self) {
+        let mut cancelled = self.cancelled.clone();
+        if cancelled.wait_for(|cancelled| *cancelled).await.is_err() {
+            // The sender lives as long as the progress, so this can't be reached.
+            std::future::pending::<()>().await;
+        }
+    }
+
+    pub(crate) async fn report(&self, message: String, percentage: u32) {
+        self.notify(WorkDoneProgress::Report(WorkDoneProgressReport {
+            message: Some(message),
+            percentage: Some(percentage),
+            ..WorkDoneProgressReport::default()
+        }))
+        .await;
+    }
+
+    /// Run the future, showing the messages reported by its commands as the progress message.
+    pub(crate) async fn show_messages<F: Future>(&self, future: F) -> F::Output {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let future = MESSAGES.scope(sender, future);
+        tokio::pin!(future);
+        loop {
+            tokio::select! {
+                output = &mut future => return output,
+                Some(message) = receiver.recv() => {
+                    self.notify(WorkDoneProgress::Report(WorkDoneProgressReport {
+                        message: Some(message),
+                        ..WorkDoneProgressReport::default()
+                    }))
+                    .await;
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn end(self, message: Option<String>) {
+        self.notify(WorkDoneProgress::End(WorkDoneProgressEnd { message }))
+            .await;
+    }
+
+    async fn notify(&self, value: WorkDoneProgress) {
+        if let Some(client) = self.client {
+            client
+                .send_notification::<Progress>(ProgressParams {
+                    token: self.token.clone(),
+                    value: ProgressParamsValue::WorkDone(value),
+                })
+                .await;
+        }
+    }
+}
+
+impl Drop for WorkProgress<'_> {
+    fn drop(&mut self) {
+        IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|(token, _)| *token != self.token);
+    }
+}
+
+impl BaconLs {
+    /// Handle the `window/workDoneProgress/cancel` notification.
+    pub(crate) async fn work_done_progress_cancel(&self, params: WorkDoneProgressCancelParams) {
+        tracing::debug!("client sent workDoneProgress/cancel notification: {params:?}");
+        if !cancel(&params.token) {
+            tracing::debug!("progress {:?} is not in flight", params.token);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_progress() {
+        let progress = WorkProgress::begin(None, "test").await;
+        let token = progress.token.clone();
+        assert!(cancel(&token));
+        progress.cancelled().await;
+        drop(progress);
+        assert!(!cancel(&token));
+    }
+
+    #[tokio::test]
+    async fn test_messages() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        report_message("not reported");
+        with_messages(Some(sender), async {
+            // The spawned tasks report to the progress of the task spawning them.
+            tokio::spawn(with_messages(messages(), async {
+                report_message("Blocking waiting for file lock on build directory");
+            }))
+            .await
+            .unwrap()
+        })
+        .await;
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            "Blocking waiting for file lock on build directory"
+        );
+        assert!(receiver.try_recv().is_err());
+        assert!(messages().is_none());
+    }
+}
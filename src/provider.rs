@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::MutexGuard;
+
+use tower_lsp::lsp_types::{Diagnostic, Url};
+
+use crate::error::Result;
+
+/// Source of diagnostics running next to bacon, whose results are merged with the bacon ones
+/// when publishing.
+#[tower_lsp::async_trait]
+pub(crate) trait DiagnosticsProvider: Debug + Send + Sync {
+    /// Key of the provider diagnostics, which must be unique across the providers.
+    fn name(&self) -> &'static str;
+
+    /// Return true if saving the file can change the diagnostics of the provider.
+    fn runs_on_save(&self, uri: &Url) -> bool;
+
+    /// Run the provider in a workspace folder, with the configured cargo environment, and
+    /// return its diagnostics for each file, or [`BaconLsError::Cancelled`] if the user cancelled
+    /// it.
+    ///
+    /// [`BaconLsError::Cancelled`]: crate::BaconLsError::Cancelled
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>>;
+}
+
+/// Runs of the providers in a workspace folder, one at a time so the tools don't build the
+/// folder concurrently, with an older run finishing last overwriting the newer diagnostics.
+/// The providers queued while a run is in progress are merged into a single next run.
+#[derive(Debug, Default)]
+pub(crate) struct RunQueue {
+    pending: Mutex<Vec<Arc<dyn DiagnosticsProvider>>>,
+    running: tokio::sync::Mutex<()>,
+}
+
+impl RunQueue {
+    /// Queue providers and wait for the run in progress, returning the providers of the next
+    /// run with the guard to hold while it runs, or none if another queued run took them.
+    pub(crate) async fn next(
+        &self,
+        providers: Vec<Arc<dyn DiagnosticsProvider>>,
+    ) -> Option<(MutexGuard<'_, ()>, Vec<Arc<dyn DiagnosticsProvider>>)> {
+        {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            for provider in providers {
+                if !pending
+                    .iter()
+                    .any(|queued| queued.name() == provider.name())
+                {
+                    pending.push(provider);
+                }
+            }
+        }
+        let running = self.running.lock().await;
+        let providers =
+            std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        (!providers.is_empty()).then_some((running, providers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Debug)]
+    struct Provider(&'static str);
+
+    #[tower_lsp::async_trait]
+    impl DiagnosticsProvider for Provider {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn runs_on_save(&self, _uri: &Url) -> bool {
+            true
+        }
+
+        async fn run(
+            &self,
+            _folder_path: &Path,
+            _env: &HashMap<String, String>,
+        ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+            Ok(HashMap::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_queue() {
+        let queue = RunQueue::default();
+        let names = |providers: &[Arc<dyn DiagnosticsProvider>]| -> Vec<&'static str> {
+            providers.iter().map(|provider| provider.name()).collect()
+        };
+        let (running, providers) = queue.next(vec![Arc::new(Provider("audit"))]).await.unwrap();
+        assert_eq!(names(&providers), vec!["audit"]);
+        // The runs queued while the first one is in progress wait for it, and the first of them
+        // runs the providers of both.
+        let second = queue.next(vec![Arc::new(Provider("custom"))]);
+        let third = queue.next(vec![
+            Arc::new(Provider("custom")),
+            Arc::new(Provider("typos")),
+        ]);
+        tokio::pin!(second, third);
+        let wait = std::time::Duration::from_millis(50);
+        assert!(tokio::time::timeout(wait, &mut second).await.is_err());
+        assert!(tokio::time::timeout(wait, &mut third).await.is_err());
+        drop(running);
+        let (running, providers) = second.await.unwrap();
+        assert_eq!(names(&providers), vec!["custom", "typos"]);
+        drop(running);
+        assert!(third.await.is_none());
+    }
+}
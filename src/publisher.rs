@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::{
+    CodeAction, Diagnostic, DiagnosticSeverity, PublishDiagnosticsParams, Url,
+};
+
+use crate::BaconLs;
+
+/// Bookkeeping of what was sent to the client: the diagnostics last published for each file,
+/// the batched ones and the versions of the open documents, gated on the client capabilities.
+#[derive(Debug, Default)]
+pub(crate) struct Publisher {
+    /// Severities published only once the bacon run is completed.
+    pub(crate) batch_severities: Vec<DiagnosticSeverity>,
+    /// True if code actions are provided, which needs the client to keep the diagnostics data
+    /// carrying the quick fixes.
+    pub(crate) provide_code_actions: bool,
+    /// Template of the titles of the quick fixes, set by `quickFixTitle`.
+    pub(crate) quick_fix_title: Option<String>,
+    /// True if the client accepts the document version of the published diagnostics.
+    pub(crate) version_support: bool,
+    /// Version of each open document.
+    versions: HashMap<Url, i32>,
+    /// Batched diagnostics of each file, kept while a run is in progress.
+    batched: HashMap<Url, Vec<Diagnostic>>,
+    /// Last diagnostics published for each file.
+    published: HashMap<Url, Vec<Diagnostic>>,
+    /// Quick fixes of the published diagnostics of each file, prepared when publishing so
+    /// code action requests don't deserialize the diagnostics data.
+    code_actions: HashMap<Url, Vec<(Diagnostic, CodeAction)>>,
+}
+
+impl Publisher {
+    /// Track an opened document, whose diagnostics are always published again as the client
+    /// may have dropped them.
+    pub(crate) fn open(&mut self, uri: &Url, version: i32) {
+        self.versions.insert(uri.clone(), version);
+        self.published.remove(uri);
+    }
+
+    pub(crate) fn change(&mut self, uri: &Url, version: i32) {
+        self.versions.insert(uri.clone(), version);
+    }
+
+    pub(crate) fn close(&mut self, uri: &Url) {
+        self.versions.remove(uri);
+    }
+
+    /// Forget the diagnostics published for a file, returning true if the client has some to
+    /// clear.
+    pub(crate) fn clear(&mut self, uri: &Url) -> bool {
+        self.batched.remove(uri);
+        self.code_actions.remove(uri);
+        self.published
+            .remove(uri)
+            .is_some_and(|diagnostics| !diagnostics.is_empty())
+    }
+
+    pub(crate) fn is_published(&self, uri: &Url) -> bool {
+        self.published.contains_key(uri)
+    }
+
+    /// Files whose published diagnostics are not empty.
+    pub(crate) fn published_uris(&self) -> impl Iterator<Item = &Url> {
+        self.published
+            .iter()
+            .filter(|(_, diagnostics)| !diagnostics.is_empty())
+            .map(|(uri, _)| uri)
+    }
+
+    /// Diagnostics last published for the file.
+    pub(crate) fn published(&self, uri: &Url) -> &[Diagnostic] {
+        self.published
+            .get(uri)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn code_actions(&self, uri: &Url) -> &[(Diagnostic, CodeAction)] {
+        self.code_actions
+            .get(uri)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Hold back the batched severities of a file until the run is completed.
+    pub(crate) fn batch(
+        &mut self,
+        uri: &Url,
+        diagnostics: Vec<Diagnostic>,
+        run_completed: bool,
+    ) -> Vec<Diagnostic> {
+        if self.batch_severities.is_empty() {
+            return diagnostics;
+        }
+        let (diagnostics, batched) = merge_batched_diagnostics(
+            diagnostics,
+            &self.batch_severities,
+            self.batched.get(uri),
+            run_completed,
+        );
+        self.batched.insert(uri.clone(), batched);
+        diagnostics
+    }
+
+    /// Record the diagnostics about to be published for a file, returning the notification
+    /// to send, or `None` if the client already has them.
+    pub(crate) fn prepare(
+        &mut self,
+        uri: &Url,
+        diagnostics: Vec<Diagnostic>,
+    ) -> Option<PublishDiagnosticsParams> {
+        // Clients keep the last diagnostics published for a file, so unchanged ones are not
+        // sent again, as it happens most of the times the locations file is polled.
+        if self.published.get(uri) == Some(&diagnostics) {
+            tracing::debug!("diagnostics of {uri} are unchanged, skipping publish");
+            return None;
+        }
+        self.published.insert(uri.clone(), diagnostics.clone());
+        if self.provide_code_actions {
+            let code_actions = diagnostics
+                .iter()
+                .flat_map(|diagnostic| {
+                    BaconLs::code_actions(uri, diagnostic, self.quick_fix_title.as_deref())
+                        .into_iter()
+                        .map(|action| (diagnostic.clone(), action))
+                })
+                .collect();
+            self.code_actions.insert(uri.clone(), code_actions);
+        }
+        let version = self
+            .version_support
+            .then(|| self.versions.get(uri).copied())
+            .flatten();
+        Some(PublishDiagnosticsParams::new(
+            uri.clone(),
+            diagnostics,
+            version,
+        ))
+    }
+}
+
+/// Split freshly parsed diagnostics between the ones streamed immediately and the ones
+/// batched until the bacon run is completed. While the run is still in progress, the
+/// previously published batched diagnostics are kept in place of the fresh ones.
+///
+/// Returns the diagnostics to publish and the batched diagnostics to remember.
+fn merge_batched_diagnostics(
+    diagnostics: Vec<Diagnostic>,
+    batch_severities: &[DiagnosticSeverity],
+    previously_batched: Option<&Vec<Diagnostic>>,
+    run_completed: bool,
+) -> (Vec<Diagnostic>, Vec<Diagnostic>) {
+    let (batched, mut streamed): (Vec<Diagnostic>, Vec<Diagnostic>) =
+        diagnostics.into_iter().partition(|diagnostic| {
+            diagnostic
+                .severity
+                .is_some_and(|severity| batch_severities.contains(&severity))
+        });
+    let batched = match previously_batched {
+        Some(previous) if !run_completed => previous.clone(),
+        _ => batched,
+    };
+    streamed.extend(batched.iter().cloned());
+    (streamed, batched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_merge_batched_diagnostics() {
+        let diagnostic = |severity, message: &str| Diagnostic {
+            severity: Some(severity),
+            message: message.to_string(),
+            ..Diagnostic::default()
+        };
+        let batch_severities = [DiagnosticSeverity::WARNING, DiagnosticSeverity::HINT];
+        let fresh = vec![
+            diagnostic(DiagnosticSeverity::ERROR, "new error"),
+            diagnostic(DiagnosticSeverity::WARNING, "new warning"),
+        ];
+        let previous = vec![diagnostic(DiagnosticSeverity::HINT, "old hint")];
+
+        // Run in progress: errors are streamed, batched severities come from the previous run.
+        let (published, batched) =
+            merge_batched_diagnostics(fresh.clone(), &batch_severities, Some(&previous), false);
+        assert_eq!(
+            published
+                .iter()
+                .map(|d| d.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["new error", "old hint"]
+        );
+        assert_eq!(batched, previous);
+
+        // Run completed: everything fresh is published.
+        let (published, batched) =
+            merge_batched_diagnostics(fresh.clone(), &batch_severities, Some(&previous), true);
+        assert_eq!(
+            published
+                .iter()
+                .map(|d| d.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["new error", "new warning"]
+        );
+        assert_eq!(batched.len(), 1);
+
+        // Nothing published before for this file: fresh diagnostics are used.
+        let (published, _) = merge_batched_diagnostics(fresh, &batch_severities, None, false);
+        assert_eq!(published.len(), 2);
+    }
+
+    #[test]
+    fn test_publisher() {
+        let uri = Url::parse("file:///projects/bacon-ls/src/lib.rs").unwrap();
+        let diagnostics = vec![Diagnostic {
+            message: "unused".to_string(),
+            ..Diagnostic::default()
+        }];
+        let mut publisher = Publisher::default();
+        publisher.open(&uri, 3);
+
+        let params = publisher.prepare(&uri, diagnostics.clone()).unwrap();
+        assert_eq!(params.version, None);
+        assert!(publisher.is_published(&uri));
+        assert_eq!(publisher.published_uris().collect::<Vec<_>>(), vec![&uri]);
+        // Unchanged diagnostics are not sent again.
+        assert!(publisher.prepare(&uri, diagnostics.clone()).is_none());
+
+        // Reopened documents are always published, with their version if supported.
+        publisher.version_support = true;
+        publisher.open(&uri, 4);
+        let params = publisher.prepare(&uri, diagnostics.clone()).unwrap();
+        assert_eq!(params.version, Some(4));
+        publisher.close(&uri);
+        let params = publisher.prepare(&uri, vec![]).unwrap();
+        assert_eq!(params.version, None);
+        assert_eq!(publisher.published_uris().count(), 0);
+
+        // Only non empty diagnostics need to be cleared from the client.
+        assert!(!publisher.clear(&uri));
+        publisher.prepare(&uri, diagnostics.clone()).unwrap();
+        assert!(publisher.clear(&uri));
+        assert!(!publisher.is_published(&uri));
+    }
+}
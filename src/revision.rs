@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::{Diagnostic, MessageType, Url};
+
+use crate::custom::{CustomCommand, CustomParser};
+use crate::error::{BaconLsError, Result};
+use crate::gc::{cache_dir, default_build_folder, prepare_build_folder};
+use crate::messages::{Locale, Message};
+use crate::progress::WorkProgress;
+use crate::provider::DiagnosticsProvider;
+use crate::{git, BaconLs, FALLBACK_COMMAND_ARGS, PKG_NAME};
+
+pub(crate) const CHECK_REVISION_COMMAND: &str = "bacon-ls.checkRevision";
+pub(crate) const REVISION_PROVIDER: &str = "revision";
+/// Scheme of the read-only documents the diagnostics of a revision are published to.
+const REVISION_SCHEME: &str = "bacon-ls-revision";
+
+/// Uri of the file of the revision, at the path of the same file in the working tree.
+fn revision_uri(rev: &str, uri: &Url, scratch_path: &Path, repo_root: &Path) -> Option<Url> {
+    let path = uri.to_file_path().ok()?;
+    let path = repo_root.join(path.strip_prefix(scratch_path).ok()?);
+    let file_uri = Url::from_file_path(path).ok()?;
+    let mut uri = Url::parse(&format!("{REVISION_SCHEME}://{}", file_uri.path())).ok()?;
+    uri.query_pairs_mut().append_pair("rev", rev);
+    Some(uri)
+}
+
+/// Kind of the build folders of the checkouts, prefixing their names.
+pub(crate) const REVISION_BUILD_FOLDER: &str = "revision";
+
+/// Git checkout of a revision in a build folder, next to the working tree of the repository.
+/// The checkout is kept after the check, and switched to the next revision checked, so cargo
+/// only rebuilds the files which differ, even after a restart.
+struct Checkout {
+    repo_root: PathBuf,
+    scratch_path: PathBuf,
+    commit: String,
+}
+
+impl Checkout {
+    async fn add(
+        folder_path: &Path,
+        rev: &str,
+        build_folder: Option<&Path>,
+        target_dir: &Path,
+    ) -> Result<Self> {
+        if rev.starts_with('-') {
+            return Err(BaconLsError::Config(format!("invalid revision {rev}")));
+        }
+        let commit = git(
+            folder_path,
+            &["rev-parse", "--verify", &format!("{rev}^{{commit}}")],
+        )
+        .await?;
+        let repo_root = PathBuf::from(git(folder_path, &["rev-parse", "--show-toplevel"]).await?);
+        let scratch_path = match build_folder {
+            Some(build_folder) => build_folder.to_path_buf(),
+            None => prepare_build_folder(REVISION_BUILD_FOLDER, &repo_root, target_dir).await?,
+        };
+        let checkout = Self {
+            repo_root,
+            scratch_path,
+            commit,
+        };
+        if checkout.scratch_path.join(".git").exists() {
+            match checkout.switch().await {
+                Ok(()) => {
+                    checkout.touch();
+                    return Ok(checkout);
+                }
+                Err(e) => tracing::warn!("recreating the checkout of {rev}: {e}"),
+            }
+        }
+        // Left behind by a check which didn't complete, or not a checkout of the repository.
+        if checkout.scratch_path.exists() {
+            checkout.remove().await;
+        }
+        if let Some(parent) = checkout.scratch_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| BaconLsError::io(format!("error creating {}", parent.display()), e))?;
+        }
+        git(
+            &checkout.repo_root,
+            &[
+                "worktree",
+                "add",
+                "--detach",
+                &checkout.scratch_path.to_string_lossy(),
+                &checkout.commit,
+            ],
+        )
+        .await?;
+        Ok(checkout)
+    }
+
+    /// Mark the checkout as used, for the garbage collection of the stale build folders.
+    fn touch(&self) {
+        let git_file = self.scratch_path.join(".git");
+        if let Err(e) = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&git_file)
+            .and_then(|file| file.set_modified(std::time::SystemTime::now()))
+        {
+            tracing::debug!("unable to touch {}: {e}", git_file.display());
+        }
+    }
+
+    /// Switch the existing checkout to the commit, touching only the files which differ.
+    async fn switch(&self) -> Result<()> {
+        let toplevel = git(&self.scratch_path, &["rev-parse", "--show-toplevel"]).await?;
+        let common_dir = git(
+            &self.scratch_path,
+            &["rev-parse", "--path-format=absolute", "--git-common-dir"],
+        )
+        .await?;
+        let repo_common_dir = git(
+            &self.repo_root,
+            &["rev-parse", "--path-format=absolute", "--git-common-dir"],
+        )
+        .await?;
+        let scratch_path =
+            std::fs::canonicalize(&self.scratch_path).unwrap_or(self.scratch_path.clone());
+        if Path::new(&toplevel) != scratch_path || common_dir != repo_common_dir {
+            return Err(BaconLsError::Config(format!(
+                "{} is not a checkout of {}",
+                self.scratch_path.display(),
+                self.repo_root.display()
+            )));
+        }
+        git(
+            &self.scratch_path,
+            &["checkout", "--detach", "--force", &self.commit],
+        )
+        .await?;
+        git(&self.scratch_path, &["clean", "--force", "-d"]).await?;
+        Ok(())
+    }
+
+    async fn remove(&self) {
+        let scratch_path = self.scratch_path.to_string_lossy();
+        if let Err(e) = git(
+            &self.repo_root,
+            &["worktree", "remove", "--force", &scratch_path],
+        )
+        .await
+        {
+            tracing::warn!("{e}");
+            let _ = tokio::fs::remove_dir_all(&self.scratch_path).await;
+            let _ = git(&self.repo_root, &["worktree", "prune"]).await;
+        }
+    }
+}
+
+/// Run cargo on a checkout of the revision and return its diagnostics on the revision uris.
+/// The build directory is kept across checks, so checking again only builds what changed.
+async fn check_revision(
+    folder_path: &Path,
+    rev: &str,
+    build_folder: Option<&Path>,
+    env: &HashMap<String, String>,
+    progress: &WorkProgress<'_>,
+) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+    let target_dir = folder_path.join(env.get("CARGO_TARGET_DIR").map_or("target", String::as_str));
+    let checkout = Checkout::add(folder_path, rev, build_folder, &target_dir).await?;
+    let prefix = git(folder_path, &["rev-parse", "--show-prefix"]).await;
+    let mut env = env.clone();
+    env.entry("CARGO_TARGET_DIR".to_string())
+        .or_insert_with(|| {
+            env::temp_dir()
+                .join(format!("{PKG_NAME}-revision-target"))
+                .to_string_lossy()
+                .to_string()
+        });
+    let command = CustomCommand {
+        command: "cargo".to_string(),
+        args: FALLBACK_COMMAND_ARGS.map(|arg| arg.to_string()).to_vec(),
+        parser: CustomParser::CargoJson,
+        events: None,
+    };
+    let result = match prefix {
+        Ok(prefix) => {
+            let project_path = checkout.scratch_path.join(prefix);
+            tokio::select! {
+                result = progress.show_messages(command.run(&project_path, &env)) => result,
+                _ = progress.cancelled() => Err(BaconLsError::Cancelled),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    Ok(result?
+        .into_iter()
+        .filter_map(|(uri, diagnostics)| {
+            let uri = revision_uri(rev, &uri, &checkout.scratch_path, &checkout.repo_root)?;
+            Some((uri, diagnostics))
+        })
+        .collect())
+}
+
+impl BaconLs {
+    /// Handle the `bacon-ls.checkRevision` command, taking the revision as its only argument.
+    /// The diagnostics of the revision replace the ones of the previous check.
+    pub(crate) async fn check_revision_command(&self, arguments: &[Value]) -> jsonrpc::Result<()> {
+        let Some(rev) = arguments.first().and_then(Value::as_str) else {
+            return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams));
+        };
+        let state = self.state.read().await;
+        let folder_path = state
+            .workspace_folders
+            .iter()
+            .flatten()
+            .map(|folder| PathBuf::from(folder.uri.path()))
+            .find(|folder_path| folder_path.join("Cargo.toml").is_file());
+        let build_folder = state.build_folder.clone();
+        let locale = state.locale;
+        drop(state);
+        let Some(folder_path) = folder_path else {
+            tracing::warn!("no Cargo project found to check revision {rev}");
+            return Ok(());
+        };
+
+        let client = self.client.as_deref();
+        let progress = WorkProgress::begin(client, &format!("checking {rev}")).await;
+        // The checkout shares the target directory of the folder, to reuse its build cache.
+        let cargo_env = BaconLs::folder_cargo_env(&self.state, &folder_path).await;
+        let build_folder = build_folder.map(|build_folder| folder_path.join(build_folder));
+        // Without a cache directory, the checkout is made in the temporary directory and
+        // removed on shutdown.
+        if build_folder.is_none() && cache_dir().is_none() {
+            if let Ok(repo_root) = git(&folder_path, &["rev-parse", "--show-toplevel"]).await {
+                self.state.write().await.temporary_build_folder = Some(default_build_folder(
+                    REVISION_BUILD_FOLDER,
+                    Path::new(&repo_root),
+                ));
+            }
+        }
+        let result = check_revision(
+            &folder_path,
+            rev,
+            build_folder.as_deref(),
+            &cargo_env,
+            &progress,
+        )
+        .await;
+        let (message_type, message) = match result {
+            Ok(diagnostics) => {
+                progress.end(None).await;
+                let count = diagnostics.values().map(Vec::len).sum();
+                Self::set_provider_diagnostics(
+                    self.client.as_ref(),
+                    &self.state,
+                    REVISION_PROVIDER,
+                    diagnostics,
+                )
+                .await;
+                let message = Message::RevisionChecked {
+                    rev,
+                    diagnostics: count,
+                };
+                tracing::info!("{}", message.text(Locale::English));
+                (MessageType::INFO, message.text(locale))
+            }
+            Err(BaconLsError::Cancelled) => {
+                progress
+                    .end(Some(BaconLsError::Cancelled.to_string()))
+                    .await;
+                tracing::info!("check of revision {rev} cancelled");
+                return Ok(());
+            }
+            // The user waits for the outcome of the check, so every error is shown.
+            Err(e) => {
+                progress.end(Some(e.to_string())).await;
+                tracing::error!("{e}");
+                (MessageType::ERROR, e.to_string())
+            }
+        };
+        if let Some(client) = client {
+            client.show_message(message_type, message).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_revision_uri() {
+        let uri = Url::parse("file:///tmp/bacon-ls-revision-1/crates/app/src/lib.rs").unwrap();
+        let checked = revision_uri(
+            "origin/main",
+            &uri,
+            Path::new("/tmp/bacon-ls-revision-1"),
+            Path::new("/projects/bacon-ls"),
+        )
+        .unwrap();
+        assert_eq!(
+            checked.as_str(),
+            "bacon-ls-revision:///projects/bacon-ls/crates/app/src/lib.rs?rev=origin%2Fmain"
+        );
+        // Files outside of the checkout are dropped.
+        let uri = Url::parse("file:///home/user/.cargo/registry/src/lib.rs").unwrap();
+        assert_eq!(
+            revision_uri(
+                "main",
+                &uri,
+                Path::new("/tmp/bacon-ls-revision-1"),
+                Path::new("/projects/bacon-ls")
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::Instrument;
+
+static NEXT_RUN_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    static CURRENT_RUN_ID: RunId;
+}
+
+/// Identifier of a diagnostics run, like the update after a save, recorded in the spans of its
+/// logs and in its progress messages, so the logs of overlapping runs can be told apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RunId(u64);
+
+impl RunId {
+    pub(crate) fn next() -> Self {
+        Self(NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Run of the current task, if it belongs to one.
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT_RUN_ID.try_with(|run_id| *run_id).ok()
+    }
+
+    /// Run the future as part of the run, in a span carrying its id. Tasks spawned by the future
+    /// must be scoped again, as they don't inherit it.
+    pub(crate) fn scope<F: Future>(self, future: F) -> impl Future<Output = F::Output> {
+        CURRENT_RUN_ID.scope(
+            self,
+            future.instrument(tracing::info_span!("run", id = self.0)),
+        )
+    }
+}
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "run {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_run_id() {
+        assert_eq!(RunId::current(), None);
+        let run_id = RunId::next();
+        assert_ne!(RunId::next(), run_id);
+        let current = run_id.scope(async { RunId::current() }).await;
+        assert_eq!(current, Some(run_id));
+        assert_eq!(RunId(42).to_string(), "run 42");
+    }
+}
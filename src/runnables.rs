@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+use tower_lsp::lsp_types::{
+    CodeLens, Command as LspCommand, Diagnostic, DiagnosticSeverity, Position, Range, Url,
+};
+
+use crate::error::{BaconLsError, Result};
+use crate::usage;
+use crate::{parsed_output_command, PKG_NAME};
+
+pub(crate) const RUN_TEST_COMMAND: &str = "bacon-ls.runTest";
+pub(crate) const RUN_BENCH_COMMAND: &str = "bacon-ls.runBench";
+pub(crate) const TEST_PROVIDER: &str = "test";
+
+/// Tool used to run tests from code lenses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TestRunner {
+    #[default]
+    Cargo,
+    Nextest,
+}
+
+impl TestRunner {
+    pub(crate) fn parse(runner: &str) -> Option<Self> {
+        match runner {
+            "cargo" => Some(Self::Cargo),
+            "nextest" => Some(Self::Nextest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunnableKind {
+    Test,
+    Bench,
+}
+
+#[derive(Debug)]
+struct Module {
+    path: String,
+    line: u32,
+    depth: i64,
+    tests: usize,
+}
+
+/// Outcome of a test or bench run.
+#[derive(Debug, Default)]
+pub(crate) struct RunOutcome {
+    pub(crate) success: bool,
+    pub(crate) diagnostics: HashMap<Url, Vec<Diagnostic>>,
+}
+
+pub(crate) struct Runnables;
+
+impl Runnables {
+    /// Module path of a source file relative to its crate `src` folder, e.g. `src/foo/bar.rs`
+    /// becomes `foo::bar`, while crate roots and `mod.rs` files map to their parent module. The
+    /// binaries of `src/bin` are crates of their own.
+    fn file_module_path(file_path: &Path) -> Vec<String> {
+        let components: Vec<String> = file_path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+        let Some(src_index) = components.iter().rposition(|c| c == "src") else {
+            return vec![];
+        };
+        let mut module_path: Vec<String> = components[src_index + 1..].to_vec();
+        if module_path.len() > 1 && module_path[0] == "bin" {
+            module_path.drain(..2);
+            if module_path.is_empty() {
+                return module_path;
+            }
+        }
+        if let Some(file_name) = module_path.pop() {
+            let stem = file_name.trim_end_matches(".rs");
+            if !["lib", "main", "mod"].contains(&stem) {
+                module_path.push(stem.to_string());
+            }
+        }
+        module_path
+    }
+
+    fn runnable_kind(attribute: &str) -> Option<RunnableKind> {
+        let attribute = attribute.strip_prefix("#[")?;
+        let name = attribute.split([']', '(']).next().unwrap_or_default();
+        if name == "test" || name.ends_with("::test") {
+            Some(RunnableKind::Test)
+        } else if name == "bench" {
+            Some(RunnableKind::Bench)
+        } else {
+            None
+        }
+    }
+
+    fn item_name<'a>(line: &'a str, keyword: &str) -> Option<&'a str> {
+        let position = line.find(keyword)?;
+        if position > 0 && !line[..position].ends_with(' ') && !line[..position].ends_with(')') {
+            return None;
+        }
+        let rest = &line[position + keyword.len()..];
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        (end > 0).then(|| &rest[..end])
+    }
+
+    fn lens(line: u32, title: &str, command: &str, uri: &Url, filter: &str) -> CodeLens {
+        CodeLens {
+            range: Range::new(Position::new(line, 0), Position::new(line, 0)),
+            command: Some(LspCommand {
+                title: title.to_string(),
+                command: command.to_string(),
+                arguments: Some(vec![
+                    serde_json::json!(uri.to_string()),
+                    serde_json::json!(filter),
+                ]),
+            }),
+            data: None,
+        }
+    }
+
+    /// Find tests and benches in a source file using a lightweight line based scan and return
+    /// the code lenses to run them.
+    pub(crate) fn code_lenses(uri: &Url, content: &str) -> Vec<CodeLens> {
+        let file_module_path = uri
+            .to_file_path()
+            .map(|path| Self::file_module_path(&path))
+            .unwrap_or_default();
+        let mut lenses = vec![];
+        let mut modules: Vec<Module> = vec![];
+        let mut closed_modules: Vec<Module> = vec![];
+        let mut pending: Option<RunnableKind> = None;
+        let mut depth: i64 = 0;
+
+        for (index, line) in content.lines().enumerate() {
+            let index = index as u32;
+            let trimmed = line.trim();
+            if trimmed.starts_with("//") {
+                continue;
+            }
+            if let Some(kind) = Self::runnable_kind(trimmed) {
+                pending = Some(kind);
+            } else if let Some(kind) = pending {
+                if let Some(name) = Self::item_name(trimmed, "fn ") {
+                    let filter = file_module_path
+                        .iter()
+                        .map(String::as_str)
+                        .chain(modules.last().map(|m| m.path.as_str()))
+                        .chain([name])
+                        .collect::<Vec<_>>()
+                        .join("::");
+                    let (title, command) = match kind {
+                        RunnableKind::Test => ("▶ Run test", RUN_TEST_COMMAND),
+                        RunnableKind::Bench => ("▶ Run bench", RUN_BENCH_COMMAND),
+                    };
+                    lenses.push(Self::lens(index, title, command, uri, &filter));
+                    if kind == RunnableKind::Test {
+                        for module in modules.iter_mut() {
+                            module.tests += 1;
+                        }
+                    }
+                    pending = None;
+                } else if !trimmed.starts_with("#[") {
+                    pending = None;
+                }
+            }
+            if trimmed.ends_with('{') {
+                if let Some(name) = Self::item_name(trimmed, "mod ") {
+                    let path = match modules.last() {
+                        Some(parent) => format!("{}::{name}", parent.path),
+                        None => name.to_string(),
+                    };
+                    modules.push(Module {
+                        path,
+                        line: index,
+                        depth,
+                        tests: 0,
+                    });
+                }
+            }
+            depth += trimmed.matches('{').count() as i64;
+            depth -= trimmed.matches('}').count() as i64;
+            while modules.last().is_some_and(|m| depth <= m.depth) {
+                closed_modules.extend(modules.pop());
+            }
+        }
+        closed_modules.append(&mut modules);
+
+        for module in closed_modules.iter().filter(|m| m.tests > 0) {
+            // The trailing separator matches the tests of the module, not the ones of the
+            // modules sharing its prefix.
+            let filter = file_module_path
+                .iter()
+                .map(String::as_str)
+                .chain([module.path.as_str(), ""])
+                .collect::<Vec<_>>()
+                .join("::");
+            lenses.push(Self::lens(
+                module.line,
+                "▶ Run all tests in module",
+                RUN_TEST_COMMAND,
+                uri,
+                &filter,
+            ));
+        }
+        lenses.sort_by_key(|lens| lens.range.start.line);
+        lenses
+    }
+
+    /// Parse the panics reported by the test harness into diagnostics.
+    fn parse_test_output(output: &str, folder_path: &Path) -> HashMap<Url, Vec<Diagnostic>> {
+        let mut diagnostics: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+        let mut lines = output.lines().peekable();
+        while let Some(line) = lines.next() {
+            let Some(rest) = line.trim().strip_prefix("thread '") else {
+                continue;
+            };
+            let Some((test_name, rest)) = rest.split_once("' panicked at ") else {
+                continue;
+            };
+            let (location, mut message) = if let Some(rest) = rest.strip_prefix('\'') {
+                // Old panic format: thread 'name' panicked at 'message', src/lib.rs:1:1
+                match rest.rsplit_once("', ") {
+                    Some((message, location)) => (location.to_string(), message.to_string()),
+                    None => continue,
+                }
+            } else {
+                // New panic format: thread 'name' panicked at src/lib.rs:1:1:\nmessage
+                (rest.trim_end_matches(':').to_string(), String::new())
+            };
+            while let Some(next) = lines.peek() {
+                if next.trim().is_empty() || next.starts_with("note:") || next.starts_with("----") {
+                    break;
+                }
+                if !message.is_empty() {
+                    message.push('\n');
+                }
+                message.push_str(lines.next().unwrap_or_default());
+            }
+            let mut location_split = location.rsplitn(3, ':');
+            let (Some(column), Some(line), Some(file)) = (
+                location_split.next().and_then(|c| c.parse::<u32>().ok()),
+                location_split.next().and_then(|l| l.parse::<u32>().ok()),
+                location_split.next(),
+            ) else {
+                tracing::warn!("unable to parse test panic location {location}");
+                continue;
+            };
+            let Ok(url) = Url::from_file_path(folder_path.join(file)) else {
+                continue;
+            };
+            let position = Position::new(line.saturating_sub(1), column.saturating_sub(1));
+            diagnostics.entry(url).or_default().push(Diagnostic {
+                range: Range::new(position, position),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some(PKG_NAME.to_string()),
+                message: format!("test `{test_name}` failed: {message}"),
+                ..Diagnostic::default()
+            });
+        }
+        diagnostics
+    }
+
+    /// Arguments of cargo running the tests or benches matching the filter: the ones of a
+    /// module, given with a trailing `::`, or else the one with this fully qualified path only.
+    fn args(command: &str, filter: &str, test_runner: TestRunner) -> Vec<String> {
+        let args: &[&str] = match (command, test_runner) {
+            (RUN_BENCH_COMMAND, _) => &["bench"],
+            (_, TestRunner::Cargo) => &["test"],
+            (_, TestRunner::Nextest) => &["nextest", "run"],
+        };
+        let exact: &[&str] = match filter.ends_with("::") {
+            true => &[],
+            false => &["--", "--exact"],
+        };
+        args.iter()
+            .chain([&filter])
+            .chain(exact)
+            .map(|arg| arg.to_string())
+            .collect()
+    }
+
+    /// Cargo command running the tests or benches matching the filter in the given folder,
+    /// with the cargo environment of the folder.
+    fn command(
+        args: &[String],
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> tokio::process::Command {
+        let mut command = parsed_output_command("cargo");
+        command.args(args).envs(env).current_dir(folder_path);
+        command
+    }
+
+    /// Run the tests or benches matching the filter in the given folder, with the cargo
+    /// environment of the folder.
+    pub(crate) async fn run(
+        command: &str,
+        filter: &str,
+        folder_path: &Path,
+        test_runner: TestRunner,
+        env: &HashMap<String, String>,
+    ) -> Result<RunOutcome> {
+        let args = Self::args(command, filter, test_runner);
+        tracing::info!(
+            "running `cargo {}` in {}",
+            args.join(" "),
+            folder_path.display()
+        );
+        let output = usage::output(&mut Self::command(&args, folder_path, env))
+            .await
+            .map_err(|e| {
+                BaconLsError::Spawn(format!("failed to run cargo {}: {e}", args.join(" ")))
+            })?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut diagnostics = Self::parse_test_output(&stdout, folder_path);
+        for (url, mut file_diagnostics) in Self::parse_test_output(&stderr, folder_path) {
+            diagnostics
+                .entry(url)
+                .or_default()
+                .append(&mut file_diagnostics);
+        }
+        Ok(RunOutcome {
+            success: output.status.success(),
+            diagnostics,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SOURCE: &str = r#"
+fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        assert_eq!(add(1, 2), 3);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_add_async() {
+        assert_eq!(add(1, 2), 3);
+    }
+
+    #[bench]
+    fn bench_add(b: &mut Bencher) {
+        b.iter(|| add(1, 2));
+    }
+}
+"#;
+
+    fn lens_summary(lenses: &[CodeLens]) -> Vec<(u32, String, String)> {
+        lenses
+            .iter()
+            .map(|lens| {
+                let command = lens.command.as_ref().unwrap();
+                (
+                    lens.range.start.line,
+                    command.command.clone(),
+                    command.arguments.as_ref().unwrap()[1]
+                        .as_str()
+                        .unwrap()
+                        .to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_code_lenses() {
+        let uri = Url::parse("file:///app/bacon-ls/src/bacon.rs").unwrap();
+        let lenses = Runnables::code_lenses(&uri, SOURCE);
+        assert_eq!(
+            lens_summary(&lenses),
+            vec![
+                (
+                    6,
+                    RUN_TEST_COMMAND.to_string(),
+                    "bacon::tests::".to_string()
+                ),
+                (
+                    10,
+                    RUN_TEST_COMMAND.to_string(),
+                    "bacon::tests::test_add".to_string()
+                ),
+                (
+                    16,
+                    RUN_TEST_COMMAND.to_string(),
+                    "bacon::tests::test_add_async".to_string()
+                ),
+                (
+                    21,
+                    RUN_BENCH_COMMAND.to_string(),
+                    "bacon::tests::bench_add".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_module_path() {
+        assert_eq!(
+            Runnables::file_module_path(Path::new("/app/src/lib.rs")),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            Runnables::file_module_path(Path::new("/app/src/foo/mod.rs")),
+            vec!["foo".to_string()]
+        );
+        assert_eq!(
+            Runnables::file_module_path(Path::new("/app/src/foo/bar.rs")),
+            vec!["foo".to_string(), "bar".to_string()]
+        );
+        assert_eq!(
+            Runnables::file_module_path(Path::new("/app/src/bin/tool.rs")),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            Runnables::file_module_path(Path::new("/app/src/bin/tool/cli.rs")),
+            vec!["cli".to_string()]
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_parse_test_output() {
+        let output = r#"
+running 2 tests
+test tests::test_ok ... ok
+test tests::test_ko ... FAILED
+
+failures:
+
+---- tests::test_ko stdout ----
+
+thread 'tests::test_ko' panicked at src/lib.rs:12:9:
+assertion `left == right` failed
+  left: 1
+ right: 2
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+thread 'tests::test_old' panicked at 'explicit panic', src/bacon.rs:3:5
+"#;
+        let diagnostics = Runnables::parse_test_output(output, Path::new("/app"));
+        let lib = &diagnostics[&Url::parse("file:///app/src/lib.rs").unwrap()];
+        assert_eq!(lib.len(), 1);
+        assert_eq!(lib[0].range.start, Position::new(11, 8));
+        assert_eq!(
+            lib[0].message,
+            "test `tests::test_ko` failed: assertion `left == right` failed\n  left: 1\n right: 2"
+        );
+        let bacon = &diagnostics[&Url::parse("file:///app/src/bacon.rs").unwrap()];
+        assert_eq!(bacon[0].range.start, Position::new(2, 4));
+        assert_eq!(
+            bacon[0].message,
+            "test `tests::test_old` failed: explicit panic"
+        );
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_command() {
+        let folder_path = Path::new("/project");
+        let env = HashMap::from([
+            ("RUSTFLAGS".to_string(), "-Dwarnings".to_string()),
+            (
+                "CARGO_TARGET_DIR".to_string(),
+                "/project/target/bacon-ls".to_string(),
+            ),
+        ]);
+        let args = Runnables::args(RUN_TEST_COMMAND, "tests::test_add", TestRunner::Nextest);
+        assert_eq!(
+            args,
+            vec!["nextest", "run", "tests::test_add", "--", "--exact"]
+        );
+        assert_eq!(
+            Runnables::args(RUN_TEST_COMMAND, "tests::", TestRunner::Cargo),
+            vec!["test", "tests::"]
+        );
+        assert_eq!(
+            Runnables::args(RUN_BENCH_COMMAND, "bench_add", TestRunner::Nextest),
+            vec!["bench", "bench_add", "--", "--exact"]
+        );
+        let command = Runnables::command(&args, folder_path, &env);
+        let command = command.as_std();
+        let envs: HashMap<_, _> = command
+            .get_envs()
+            .filter_map(|(key, value)| Some((key.to_str()?, value?.to_str()?)))
+            .collect();
+        assert_eq!(
+            envs.get("CARGO_TARGET_DIR"),
+            Some(&"/project/target/bacon-ls")
+        );
+        assert_eq!(envs.get("RUSTFLAGS"), Some(&"-Dwarnings"));
+        assert_eq!(command.get_current_dir(), Some(folder_path));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{Diagnostic, Url};
+
+use crate::custom::{CargoEvents, CustomCommand, CustomParser};
+use crate::error::{BaconLsError, Result};
+use crate::provider::DiagnosticsProvider;
+
+pub(crate) const RUST_PROJECT_PROVIDER: &str = "rust-project";
+/// Project file of rust-analyzer describing the crates built without Cargo, e.g. by Bazel or
+/// Buck.
+pub(crate) const RUST_PROJECT_FILE: &str = "rust-project.json";
+/// Placeholder of the check runnable replaced by the labels of the crates.
+const LABEL_PLACEHOLDER: &str = "{label}";
+
+/// Subset of a `rust-project.json` needed to check its crates.
+#[derive(Debug, Deserialize)]
+struct ProjectJson {
+    #[serde(default)]
+    crates: Vec<Crate>,
+    #[serde(default)]
+    runnables: Vec<Runnable>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Crate {
+    build: Option<Build>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Build {
+    label: String,
+}
+
+/// Command of the build system, like the check run by rust-analyzer on save.
+#[derive(Debug, Deserialize)]
+struct Runnable {
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    cwd: Option<PathBuf>,
+    kind: String,
+}
+
+impl ProjectJson {
+    /// Check command of the project, printing cargo JSON messages, with the `{label}` argument
+    /// replaced by the labels of all the crates, and its working directory.
+    fn check_command(
+        &self,
+        folder_path: &Path,
+        events: Option<CargoEvents>,
+    ) -> Option<(CustomCommand, PathBuf)> {
+        let runnable = ["flycheck", "check"].iter().find_map(|kind| {
+            self.runnables
+                .iter()
+                .find(|runnable| runnable.kind == *kind)
+        })?;
+        let labels: Vec<&str> = self
+            .crates
+            .iter()
+            .filter_map(|krate| Some(krate.build.as_ref()?.label.as_str()))
+            .collect();
+        let args = runnable
+            .args
+            .iter()
+            .flat_map(|arg| match arg.as_str() {
+                LABEL_PLACEHOLDER => labels.iter().map(|label| label.to_string()).collect(),
+                _ => vec![arg.clone()],
+            })
+            .collect();
+        let cwd = match runnable.cwd.as_deref() {
+            Some(cwd) => folder_path.join(cwd),
+            None => folder_path.to_path_buf(),
+        };
+        let command = CustomCommand {
+            command: runnable.program.clone(),
+            args,
+            parser: CustomParser::CargoJson,
+            events,
+        };
+        Some((command, cwd))
+    }
+}
+
+/// Check of the crates of a `rust-project.json`, for the projects built without Cargo. The
+/// check runnable of the project must print cargo JSON messages.
+#[derive(Debug, Clone)]
+pub(crate) struct RustProject {
+    /// Path of the project file, relative to the workspace folder.
+    pub(crate) file: String,
+    pub(crate) events: Option<CargoEvents>,
+}
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for RustProject {
+    fn name(&self) -> &'static str {
+        RUST_PROJECT_PROVIDER
+    }
+
+    fn runs_on_save(&self, uri: &Url) -> bool {
+        uri.path().ends_with(".rs") || uri.path().ends_with(&self.file)
+    }
+
+    /// Run the check runnable of the project file of the workspace folder, if any.
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let path = folder_path.join(&self.file);
+        let content = match tokio::fs::read(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => {
+                return Err(BaconLsError::io(
+                    format!("unable to read {}", path.display()),
+                    e,
+                ))
+            }
+        };
+        let project: ProjectJson = serde_json::from_slice(&content)
+            .map_err(|e| BaconLsError::Config(format!("invalid {}: {e}", path.display())))?;
+        let Some((command, cwd)) = project.check_command(folder_path, self.events.clone()) else {
+            return Err(BaconLsError::Config(format!(
+                "{} has no check runnable",
+                path.display()
+            )));
+        };
+        command.run(&cwd, env).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_check_command() {
+        let project: ProjectJson = serde_json::from_str(
+            r#"{
+                "sysroot_src": "/rust/library",
+                "crates": [
+                    {"root_module": "app/main.rs", "edition": "2021", "deps": [], "build": {"label": "//app:main", "build_file": "app/BUILD", "target_kind": "bin"}},
+                    {"root_module": "core/lib.rs", "edition": "2021", "deps": [], "build": {"label": "//core:lib", "build_file": "core/BUILD", "target_kind": "lib"}},
+                    {"root_module": "gen/lib.rs", "edition": "2021", "deps": []}
+                ],
+                "runnables": [
+                    {"program": "bazel", "args": ["test", "{label}"], "cwd": "/projects/mono", "kind": "testOne"},
+                    {"program": "bazel", "args": ["build", "--config=clippy-json", "{label}"], "cwd": "tools", "kind": "flycheck"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let folder_path = Path::new("/projects/mono");
+        let (command, cwd) = project.check_command(folder_path, None).unwrap();
+        assert_eq!(command.command, "bazel");
+        assert_eq!(
+            command.args,
+            vec!["build", "--config=clippy-json", "//app:main", "//core:lib"]
+        );
+        assert_eq!(cwd, PathBuf::from("/projects/mono/tools"));
+
+        let project: ProjectJson = serde_json::from_str(r#"{"crates": []}"#).unwrap();
+        assert!(project.check_command(folder_path, None).is_none());
+    }
+}
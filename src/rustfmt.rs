@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, Url};
+
+use crate::error::{BaconLsError, Result};
+use crate::parsed_output_command;
+use crate::provider::DiagnosticsProvider;
+use crate::usage;
+use crate::{DiagnosticData, DiagnosticEdit};
+
+pub(crate) const RUSTFMT_PROVIDER: &str = "rustfmt";
+
+/// Region of a file differing from the `rustfmt` output.
+#[derive(Debug, Default, PartialEq)]
+struct Mismatch {
+    /// First line of the original region, one based.
+    original_begin_line: u32,
+    /// Last line of the original region, inclusive, before the first one for an insertion.
+    original_end_line: u32,
+    /// Formatted lines replacing the original region.
+    expected: Vec<String>,
+}
+
+fn mismatch_diagnostic(mismatch: &Mismatch) -> Diagnostic {
+    // Lines are one based and inclusive, an empty original region is an insertion.
+    let start = mismatch.original_begin_line.saturating_sub(1);
+    let end = mismatch.original_end_line.max(start);
+    let new_text: String = mismatch
+        .expected
+        .iter()
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let range = Range::new(Position::new(start, 0), Position::new(end, 0));
+    let data = serde_json::json!(DiagnosticData {
+        corrections: vec![],
+        edits: vec![DiagnosticEdit {
+            range,
+            new_text: new_text.into(),
+        }],
+        title: Some("Format with rustfmt".into()),
+        rendered: None,
+    });
+    Diagnostic {
+        range,
+        severity: Some(DiagnosticSeverity::HINT),
+        source: Some(RUSTFMT_PROVIDER.to_string()),
+        message: "code is not formatted".to_string(),
+        data: Some(data),
+        ..Diagnostic::default()
+    }
+}
+
+/// File and first line of the chunk of a `Diff in src/lib.rs:12:` header, or of the
+/// `Diff in src/lib.rs at line 12:` one of the older versions.
+fn diff_header(line: &str) -> Option<(&str, u32)> {
+    let header = line.strip_prefix("Diff in ")?.strip_suffix(':')?;
+    let (file, line) = header
+        .rsplit_once(" at line ")
+        .or_else(|| header.rsplit_once(':'))?;
+    Some((file, line.parse().ok()?))
+}
+
+/// Mismatches of the chunks of a file, whose lines are prefixed by ` ` for the unchanged ones,
+/// `-` for the original ones and `+` for the formatted ones.
+fn chunk_mismatches(lines: &[&str], first_line: u32) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    let mut current: Option<Mismatch> = None;
+    let mut original_line = first_line;
+    for line in lines {
+        let (marker, content) = line.split_at(line.len().min(1));
+        match marker {
+            "-" | "+" => {
+                let mismatch = current.get_or_insert_with(|| Mismatch {
+                    original_begin_line: original_line,
+                    original_end_line: original_line.saturating_sub(1),
+                    ..Mismatch::default()
+                });
+                if marker == "-" {
+                    mismatch.original_end_line = original_line;
+                    original_line += 1;
+                } else {
+                    mismatch.expected.push(content.to_string());
+                }
+            }
+            _ => {
+                mismatches.extend(current.take());
+                original_line += 1;
+            }
+        }
+    }
+    mismatches.extend(current);
+    mismatches
+}
+
+/// Parse the diff printed by `cargo fmt --check`, made of chunks each starting with a header.
+#[tracing::instrument(level = "debug", name = "parse", skip_all)]
+fn parse_rustfmt_output(output: &str, folder_path: &Path) -> HashMap<Url, Vec<Diagnostic>> {
+    let mut diagnostics: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+    let lines: Vec<&str> = output.lines().collect();
+    let mut index = 0;
+    while index < lines.len() {
+        let Some((file, first_line)) = diff_header(lines[index]) else {
+            index += 1;
+            continue;
+        };
+        let end = lines[index + 1..]
+            .iter()
+            .position(|line| diff_header(line).is_some())
+            .map_or(lines.len(), |position| index + 1 + position);
+        if let Ok(url) = Url::from_file_path(folder_path.join(file)) {
+            diagnostics.entry(url).or_default().extend(
+                chunk_mismatches(&lines[index + 1..end], first_line)
+                    .iter()
+                    .map(mismatch_diagnostic),
+            );
+        }
+        index = end;
+    }
+    diagnostics
+}
+
+fn command(folder_path: &Path, env: &HashMap<String, String>) -> tokio::process::Command {
+    let mut command = parsed_output_command("cargo");
+    command
+        .envs(env)
+        .args(["fmt", "--check", "--", "--color", "never"])
+        .current_dir(folder_path);
+    command
+}
+
+/// Report the regions of the Rust files differing from the `rustfmt` output.
+#[derive(Debug)]
+pub(crate) struct Rustfmt;
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for Rustfmt {
+    fn name(&self) -> &'static str {
+        RUSTFMT_PROVIDER
+    }
+
+    fn runs_on_save(&self, uri: &Url) -> bool {
+        uri.path().ends_with(".rs")
+    }
+
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let output = usage::output(&mut command(folder_path, env))
+            .await
+            .map_err(|e| BaconLsError::Spawn(format!("failed to run cargo fmt: {e}")))?;
+        // cargo fmt exits with an error when files are not formatted, so only a failure without
+        // output is considered an error.
+        if !output.status.success() && output.stdout.is_empty() {
+            return Err(BaconLsError::Spawn(format!(
+                "cargo fmt failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(parse_rustfmt_output(
+            &String::from_utf8_lossy(&output.stdout),
+            folder_path,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_rustfmt_output() {
+        let folder_path = std::env::current_dir().unwrap();
+        let output = format!(
+            "\
+Diff in {}:1:
+ use std::io;
+ fn main() {{
+-    let x   = 1;
++    let x = 1;
+ 
+-
+-
+-    println!(\"{{}}\",x);
++    println!(\"{{}}\", x);
+ }}
+-fn a(){{}}
++fn a() {{}}
+ 
+Diff in macros/src/lib.rs at line 8:
+ }}
++
+ fn b() {{}}
+",
+            folder_path.join("src/lib.rs").display()
+        );
+        let diagnostics = parse_rustfmt_output(&output, &folder_path);
+        assert_eq!(diagnostics.len(), 2);
+
+        let url = Url::from_file_path(folder_path.join("src/lib.rs")).unwrap();
+        let edits: Vec<(Range, String)> = diagnostics[&url]
+            .iter()
+            .map(|diagnostic| {
+                assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::HINT));
+                let data: DiagnosticData =
+                    serde_json::from_value(diagnostic.data.clone().unwrap()).unwrap();
+                (data.edits[0].range, data.edits[0].new_text.to_string())
+            })
+            .collect();
+        let range = |start, end| Range::new(Position::new(start, 0), Position::new(end, 0));
+        assert_eq!(
+            edits,
+            vec![
+                (range(2, 3), "    let x = 1;\n".to_string()),
+                (range(4, 7), "    println!(\"{}\", x);\n".to_string()),
+                (range(8, 9), "fn a() {}\n".to_string()),
+            ]
+        );
+
+        // An inserted blank line.
+        let url = Url::from_file_path(folder_path.join("macros/src/lib.rs")).unwrap();
+        let data: DiagnosticData =
+            serde_json::from_value(diagnostics[&url][0].data.clone().unwrap()).unwrap();
+        assert_eq!(data.edits[0].range, range(8, 8));
+        assert_eq!(data.edits[0].new_text, "\n");
+    }
+
+    #[test]
+    fn test_command() {
+        let folder_path = Path::new("/project");
+        let env = HashMap::from([("CARGO_TARGET_DIR".to_string(), "target/ls".to_string())]);
+        let command = command(folder_path, &env);
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "cargo");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["fmt", "--check", "--", "--color", "never"]
+        );
+        assert_eq!(command.get_current_dir(), Some(folder_path));
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+
+use serde_json::Value;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::MessageType;
+
+use crate::bacon::Bacon;
+use crate::messages::{Locale, Message};
+use crate::BaconLs;
+
+pub(crate) const SELECT_JOB_COMMAND: &str = "bacon-ls.selectJob";
+
+/// Replace the job of the bacon command line arguments, or add it if missing.
+fn with_job(command_args: &str, job: &str) -> String {
+    let mut args: Vec<&str> = command_args.split_whitespace().collect();
+    match args.iter().position(|arg| *arg == "-j" || *arg == "--job") {
+        Some(index) if index + 1 < args.len() => args[index + 1] = job,
+        Some(_) => args.push(job),
+        None => args.extend(["-j", job]),
+    }
+    args.join(" ")
+}
+
+impl BaconLs {
+    /// Handle the `bacon-ls.selectJob` command, taking the name of a job of the bacon
+    /// preferences: the bacon running in background is restarted with that job, which then
+    /// exports the locations file.
+    pub(crate) async fn select_job_command(&self, arguments: &[Value]) -> jsonrpc::Result<()> {
+        let [Value::String(job)] = arguments else {
+            return Err(jsonrpc::Error::new(jsonrpc::ErrorCode::InvalidParams));
+        };
+        let state = self.state.read().await;
+        let validate_prefs = state.validate_bacon_preferences;
+        let command_args = with_job(&state.run_bacon_in_background_command_args, job);
+        let restart_policy = state.bacon_restart_policy;
+        let locale = state.locale;
+        let folder_path = state
+            .workspace_folders
+            .iter()
+            .flatten()
+            .next()
+            .map(|folder| PathBuf::from(folder.uri.path()));
+        drop(state);
+
+        let result = match validate_prefs {
+            true => Bacon::validate_preferences(folder_path.as_deref(), None, None, &[job])
+                .await
+                .map(|_| ()),
+            false => Ok(()),
+        };
+        let (message_type, message) = match result {
+            Ok(()) => {
+                let mut state = self.state.write().await;
+                state.run_bacon_in_background_command_args = command_args.clone();
+                state.bacon_jobs[0] = job.clone();
+                // Only the bacon started by this instance can be restarted.
+                let folder_paths: Vec<_> = state
+                    .projects
+                    .iter()
+                    .filter(|(_, project)| project.bacon_command_handle.is_some())
+                    .map(|(folder_path, _)| folder_path.clone())
+                    .collect();
+                drop(state);
+                let mut errors = Vec::new();
+                for folder_path in folder_paths {
+                    let cargo_env = Self::bacon_cargo_env(&self.state, &folder_path).await;
+                    match Bacon::run_in_background(
+                        "bacon",
+                        &command_args,
+                        &folder_path,
+                        cargo_env,
+                        restart_policy,
+                        self.client.clone(),
+                        locale,
+                        true,
+                    )
+                    .await
+                    {
+                        Ok(command) => {
+                            // Dropping the previous bacon kills it.
+                            let mut state = self.state.write().await;
+                            let project = state.project(&folder_path);
+                            if let Some(previous) = project.bacon_command_handle.replace(command) {
+                                previous.abort();
+                            }
+                        }
+                        Err(e) => errors.push(e),
+                    }
+                }
+                match errors.first() {
+                    None => {
+                        let message = Message::BaconJobSelected { job };
+                        tracing::info!("{}", message.text(Locale::English));
+                        (MessageType::INFO, message.text(locale))
+                    }
+                    Some(e) => {
+                        tracing::error!("{e}");
+                        (MessageType::ERROR, e.to_string())
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::error!("{e}");
+                (MessageType::ERROR, e.to_string())
+            }
+        };
+        if let Some(client) = self.client.as_ref() {
+            client.show_message(message_type, message).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_with_job() {
+        assert_eq!(
+            with_job("--headless -j bacon-ls", "bacon-ls-test"),
+            "--headless -j bacon-ls-test"
+        );
+        assert_eq!(
+            with_job("--job bacon-ls --headless", "check"),
+            "--job check --headless"
+        );
+        assert_eq!(with_job("--headless", "check"), "--headless -j check");
+        assert_eq!(with_job("--headless -j", "check"), "--headless -j check");
+    }
+}
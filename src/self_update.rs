@@ -0,0 +1,300 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::{BaconLsError, Result};
+use crate::PKG_VERSION;
+
+const RELEASES_API_URL: &str = "https://api.github.com/repos/crisidev/bacon-ls/releases/latest";
+const RELEASES_DOWNLOAD_URL: &str = "https://github.com/crisidev/bacon-ls/releases/download";
+
+/// Identifier of a pre-release, the numeric ones coming before the others.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum PreRelease {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+/// Semantic version of a release, ordered by its numbers, then by its pre-release identifiers,
+/// a release coming after its pre-releases.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Version {
+    numbers: [u64; 3],
+    release: bool,
+    pre_release: Vec<PreRelease>,
+}
+
+impl Version {
+    /// Parse a version like `0.13.0` or `v0.14.0-beta.2+build`, ignoring the build metadata.
+    fn parse(version: &str) -> Option<Self> {
+        let version = version.trim_start_matches('v');
+        let version = version
+            .split_once('+')
+            .map_or(version, |(version, _)| version);
+        let (numbers, pre_release) = match version.split_once('-') {
+            Some((numbers, pre_release)) => (numbers, Some(pre_release)),
+            None => (version, None),
+        };
+        let numbers: Vec<u64> = numbers
+            .split('.')
+            .map(|number| number.parse().ok())
+            .collect::<Option<_>>()?;
+        Some(Self {
+            numbers: numbers.try_into().ok()?,
+            release: pre_release.is_none(),
+            pre_release: pre_release
+                .into_iter()
+                .flat_map(|pre_release| pre_release.split('.'))
+                .map(|identifier| match identifier.parse() {
+                    Ok(number) => PreRelease::Numeric(number),
+                    Err(_) => PreRelease::Alphanumeric(identifier.to_string()),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Target triple of the release artifact matching the running binary.
+fn release_target() -> Option<&'static str> {
+    if cfg!(all(
+        target_os = "linux",
+        target_arch = "x86_64",
+        target_env = "musl"
+    )) {
+        Some("x86_64-unknown-linux-musl")
+    } else if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        Some("x86_64-unknown-linux-gnu")
+    } else if cfg!(all(
+        target_os = "linux",
+        target_arch = "x86",
+        target_env = "musl"
+    )) {
+        Some("i686-unknown-linux-musl")
+    } else if cfg!(all(
+        target_os = "linux",
+        target_arch = "aarch64",
+        target_env = "musl"
+    )) {
+        Some("aarch64-unknown-linux-musl")
+    } else if cfg!(all(target_os = "linux", target_arch = "aarch64")) {
+        Some("aarch64-unknown-linux-gnu")
+    } else if cfg!(all(target_os = "linux", target_arch = "arm")) {
+        Some("arm-unknown-linux-gnueabihf")
+    } else if cfg!(all(target_os = "macos", target_arch = "x86_64")) {
+        Some("x86_64-apple-darwin")
+    } else if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        Some("aarch64-apple-darwin")
+    } else if cfg!(all(target_os = "windows", target_arch = "x86_64")) {
+        Some("x86_64-pc-windows-msvc")
+    } else if cfg!(all(target_os = "windows", target_arch = "x86")) {
+        Some("i686-pc-windows-msvc")
+    } else if cfg!(all(target_os = "windows", target_arch = "aarch64")) {
+        Some("aarch64-pc-windows-msvc")
+    } else {
+        None
+    }
+}
+
+fn archive_name(tag: &str, target: &str) -> String {
+    if target.contains("windows") {
+        format!("bacon-ls-{tag}-{target}.zip")
+    } else {
+        format!("bacon-ls-{tag}-{target}.tar.gz")
+    }
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", url])
+        .output()
+        .map_err(|e| BaconLsError::Spawn(format!("failed to run curl: {e}")))?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(BaconLsError::Spawn(format!(
+            "failed to download {url}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+fn latest_tag() -> Result<String> {
+    let body = download(RELEASES_API_URL)?;
+    let release: serde_json::Value = serde_json::from_slice(&body).map_err(|e| {
+        BaconLsError::Parse(format!("error parsing latest release information: {e}"))
+    })?;
+    release["tag_name"]
+        .as_str()
+        .map(|tag| tag.to_string())
+        .ok_or_else(|| BaconLsError::Parse("latest release information has no tag".to_string()))
+}
+
+/// Verify the archive against the content of a `sha256sum` style checksum file. Downloaded from
+/// the same release as the archive, it only detects a corrupted or truncated download: it
+/// doesn't authenticate the archive, which is as trusted as the GitHub release it comes from.
+fn verify_checksum(archive: &[u8], checksum_file: &str) -> Result<()> {
+    let expected = checksum_file
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| BaconLsError::Parse("empty checksum file".to_string()))?;
+    let actual = Sha256::digest(archive)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    if expected.eq_ignore_ascii_case(&actual) {
+        Ok(())
+    } else {
+        Err(BaconLsError::Parse(format!(
+            "checksum mismatch: expected {expected}, got {actual}"
+        )))
+    }
+}
+
+fn extract(archive_path: &Path, destination: &Path) -> Result<PathBuf> {
+    // Both GNU tar and the bsdtar shipped with Windows and macOS can extract the archive.
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(archive_path)
+        .arg("-C")
+        .arg(destination)
+        .status()
+        .map_err(|e| BaconLsError::Spawn(format!("failed to run tar: {e}")))?;
+    if !status.success() {
+        return Err(BaconLsError::Spawn(format!(
+            "failed to extract {}",
+            archive_path.display()
+        )));
+    }
+    let binary = destination.join(format!("bacon-ls{}", std::env::consts::EXE_SUFFIX));
+    if binary.exists() {
+        Ok(binary)
+    } else {
+        Err(BaconLsError::Parse(format!(
+            "{} not found in the release archive",
+            binary.display()
+        )))
+    }
+}
+
+fn replace_executable(new_binary: &Path) -> Result<()> {
+    let current = std::env::current_exe()
+        .map_err(|e| BaconLsError::io("unable to locate the current executable", e))?;
+    let staged = current.with_extension("new");
+    fs::copy(new_binary, &staged).map_err(|e| {
+        BaconLsError::io(
+            format!("error staging new executable {}", staged.display()),
+            e,
+        )
+    })?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755)).map_err(|e| {
+            BaconLsError::io(
+                format!("error setting permissions on {}", staged.display()),
+                e,
+            )
+        })?;
+    }
+    // A running executable can be renamed but not overwritten on Windows.
+    let backup = current.with_extension("old");
+    fs::rename(&current, &backup)
+        .map_err(|e| BaconLsError::io(format!("error moving {} away", current.display()), e))?;
+    if let Err(e) = fs::rename(&staged, &current) {
+        let _ = fs::rename(&backup, &current);
+        return Err(BaconLsError::io(
+            format!("error installing {}", current.display()),
+            e,
+        ));
+    }
+    let _ = fs::remove_file(&backup);
+    Ok(())
+}
+
+/// Replace the running executable with the latest release published on GitHub, unless it is
+/// older than the running version and the downgrade is not forced.
+pub fn self_update(force: bool) -> Result<()> {
+    let target = release_target().ok_or_else(|| {
+        BaconLsError::Config("no release binary is published for this platform".to_string())
+    })?;
+    let tag = latest_tag()?;
+    let latest = Version::parse(&tag)
+        .ok_or_else(|| BaconLsError::Parse(format!("invalid latest release version {tag}")))?;
+    match Version::parse(PKG_VERSION).map(|current| latest.cmp(&current)) {
+        Some(Ordering::Equal) => {
+            println!("bacon-ls {PKG_VERSION} is already the latest version");
+            return Ok(());
+        }
+        Some(Ordering::Less) if !force => {
+            return Err(BaconLsError::Config(format!(
+                "the latest release {tag} is older than bacon-ls {PKG_VERSION}, \
+                 use --force to downgrade"
+            )));
+        }
+        _ => {}
+    }
+
+    let archive_name = archive_name(&tag, target);
+    println!("downloading {archive_name}");
+    let archive = download(&format!("{RELEASES_DOWNLOAD_URL}/{tag}/{archive_name}"))?;
+    let checksum = download(&format!(
+        "{RELEASES_DOWNLOAD_URL}/{tag}/{archive_name}.sha256"
+    ))?;
+    verify_checksum(&archive, &String::from_utf8_lossy(&checksum))?;
+
+    let work_dir =
+        std::env::temp_dir().join(format!("bacon-ls-self-update-{}", std::process::id()));
+    fs::create_dir_all(&work_dir)
+        .map_err(|e| BaconLsError::io(format!("error creating {}", work_dir.display()), e))?;
+    let archive_path = work_dir.join(&archive_name);
+    let result = fs::write(&archive_path, &archive)
+        .map_err(|e| BaconLsError::io(format!("error writing {}", archive_path.display()), e))
+        .and_then(|_| extract(&archive_path, &work_dir))
+        .and_then(|binary| replace_executable(&binary));
+    let _ = fs::remove_dir_all(&work_dir);
+    result?;
+    println!("bacon-ls updated from {PKG_VERSION} to {tag}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_name() {
+        assert_eq!(
+            archive_name("0.13.0", "x86_64-unknown-linux-musl"),
+            "bacon-ls-0.13.0-x86_64-unknown-linux-musl.tar.gz"
+        );
+        assert_eq!(
+            archive_name("0.13.0", "x86_64-pc-windows-msvc"),
+            "bacon-ls-0.13.0-x86_64-pc-windows-msvc.zip"
+        );
+    }
+
+    #[test]
+    fn test_version() {
+        let version = |version| Version::parse(version).unwrap();
+        assert!(version("v0.14.0") > version("0.13.2"));
+        assert!(version("0.13.10") > version("0.13.9"));
+        assert!(version("1.0.0") > version("1.0.0-rc.1"));
+        assert!(version("1.0.0-beta.11") > version("1.0.0-beta.2"));
+        assert!(version("1.0.0-beta") > version("1.0.0-alpha.1"));
+        assert_eq!(version("1.0.0+build"), version("1.0.0"));
+        assert_eq!(Version::parse("1.0"), None);
+        assert_eq!(Version::parse("latest"), None);
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let checksum =
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  hello.tar.gz\n";
+        assert!(verify_checksum(b"hello", checksum).is_ok());
+        assert!(verify_checksum(b"bacon", checksum).is_err());
+        assert!(verify_checksum(b"hello", "").is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::sync::RwLock;
+use tower_lsp::Client;
+
+use crate::analysis::parse_analysis;
+use crate::store::BACON_SOURCE;
+use crate::{BaconLs, State};
+
+const TCP_SCHEME: &str = "tcp://";
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Address of the socket where bacon streams its analysis, from the `baconSocket` setting.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SocketAddress {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unix(path) => write!(f, "{}", path.display()),
+            Self::Tcp(address) => write!(f, "{TCP_SCHEME}{address}"),
+        }
+    }
+}
+
+impl SocketAddress {
+    /// Parse `tcp://host:port` or the path of a unix socket, relative to the workspace folder or
+    /// absolute.
+    pub(crate) fn parse(value: &str, folder_path: &Path) -> Self {
+        match value.strip_prefix(TCP_SCHEME) {
+            Some(address) => Self::Tcp(address.to_string()),
+            None => Self::Unix(folder_path.join(value)),
+        }
+    }
+
+    async fn connect(&self) -> std::io::Result<Box<dyn AsyncBufRead + Send + Unpin>> {
+        match self {
+            Self::Tcp(address) => Ok(Box::new(BufReader::new(
+                tokio::net::TcpStream::connect(address).await?,
+            ))),
+            #[cfg(unix)]
+            Self::Unix(path) => Ok(Box::new(BufReader::new(
+                tokio::net::UnixStream::connect(path).await?,
+            ))),
+            #[cfg(not(unix))]
+            Self::Unix(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "unix sockets are not supported on this platform",
+            )),
+        }
+    }
+}
+
+/// Addresses of the sockets of the workspace folders with the folder they report on. An
+/// address shared by several folders, like a TCP one, is followed once, for the first folder.
+pub(crate) fn socket_addresses(
+    bacon_socket: &str,
+    folder_paths: &[PathBuf],
+) -> BTreeMap<SocketAddress, PathBuf> {
+    let mut addresses = BTreeMap::new();
+    for folder_path in folder_paths {
+        addresses
+            .entry(SocketAddress::parse(bacon_socket, folder_path))
+            .or_insert_with(|| folder_path.clone());
+    }
+    addresses
+}
+
+impl BaconLs {
+    /// Follow the analysis streamed by bacon on a socket, reconnecting when bacon is not
+    /// listening yet or exits. Each line is a JSON analysis replacing the diagnostics of the
+    /// folder, like a rewrite of the locations file.
+    pub(crate) async fn follow_bacon_socket(
+        client: Option<Arc<Client>>,
+        state: Arc<RwLock<State>>,
+        address: SocketAddress,
+        folder_path: PathBuf,
+    ) {
+        let mut delay = MIN_RECONNECT_DELAY;
+        loop {
+            match address.connect().await {
+                Ok(mut reader) => {
+                    tracing::info!("following the analysis of bacon on {address}");
+                    delay = MIN_RECONNECT_DELAY;
+                    let mut line = String::new();
+                    loop {
+                        line.clear();
+                        match reader.read_line(&mut line).await {
+                            Ok(0) => break,
+                            Ok(_) if line.trim().is_empty() => {}
+                            Ok(_) => {
+                                Self::receive_analysis(client.as_ref(), &state, &folder_path, &line)
+                                    .await
+                            }
+                            Err(e) => {
+                                tracing::warn!("error reading the bacon socket {address}: {e}");
+                                break;
+                            }
+                        }
+                    }
+                    tracing::info!("bacon closed the socket {address}");
+                }
+                Err(e) => tracing::debug!("unable to connect to the bacon socket {address}: {e}"),
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    /// Replace the diagnostics of the folder with the ones of an analysis and publish them.
+    async fn receive_analysis(
+        client: Option<&Arc<Client>>,
+        state: &Arc<RwLock<State>>,
+        folder_path: &Path,
+        analysis: &str,
+    ) {
+        let (entries, complete) = parse_analysis(analysis.as_bytes(), folder_path);
+        if !complete {
+            tracing::warn!("received an incomplete analysis from bacon");
+        }
+        tracing::debug!("received {} diagnostics from bacon", entries.len());
+        let read_state = state.read().await;
+        let locations_file = read_state.locations_file.clone();
+        let workspace_folders = read_state.workspace_folders.clone();
+        let locations_indexes = read_state.locations_indexes.clone();
+        let open_files: Vec<_> = read_state.open_files.iter().cloned().collect();
+        drop(read_state);
+        // The index of the locations file of the folder keeps the streamed diagnostics, merged
+        // with the ones of the other folders.
+        let mut indexes = locations_indexes.lock().await;
+        indexes
+            .entry(folder_path.join(&locations_file))
+            .or_default()
+            .set_entries(entries);
+        let diagnostics =
+            Self::diagnostics(&indexes, &locations_file, workspace_folders.as_deref());
+        drop(indexes);
+        state
+            .write()
+            .await
+            .diagnostics_store
+            .replace(BACON_SOURCE, diagnostics);
+        for uri in open_files.iter() {
+            Self::publish_diagnostics(client, state, uri, true).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_socket_addresses() {
+        let folder_paths = [PathBuf::from("/projects/a"), PathBuf::from("/projects/b")];
+        assert_eq!(
+            socket_addresses(".bacon.socket", &folder_paths),
+            BTreeMap::from([
+                (
+                    SocketAddress::Unix(PathBuf::from("/projects/a/.bacon.socket")),
+                    PathBuf::from("/projects/a")
+                ),
+                (
+                    SocketAddress::Unix(PathBuf::from("/projects/b/.bacon.socket")),
+                    PathBuf::from("/projects/b")
+                ),
+            ])
+        );
+        assert_eq!(
+            socket_addresses("tcp://127.0.0.1:7878", &folder_paths),
+            BTreeMap::from([(
+                SocketAddress::Tcp("127.0.0.1:7878".to_string()),
+                PathBuf::from("/projects/a")
+            )])
+        );
+        assert_eq!(
+            SocketAddress::parse("/tmp/bacon.socket", Path::new("/projects/a")).to_string(),
+            "/tmp/bacon.socket"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::SystemTime;
+
+use tower_lsp::lsp_types::{Diagnostic, Url};
+
+use crate::path_mapping::PathResolver;
+
+/// Source of the diagnostics read from the bacon locations files.
+pub(crate) const BACON_SOURCE: &str = "bacon";
+
+/// Diagnostics of a file, by source.
+#[derive(Debug, Default)]
+pub(crate) struct FileDiagnostics {
+    /// Incremented each time the diagnostics of the file change.
+    pub(crate) version: u64,
+    /// Last time the diagnostics of a source were set, changed or not.
+    pub(crate) checked: Option<SystemTime>,
+    sources: BTreeMap<&'static str, Vec<Diagnostic>>,
+}
+
+impl FileDiagnostics {
+    pub(crate) fn source(&self, source: &str) -> &[Diagnostic] {
+        self.sources
+            .get(source)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Diagnostics of all the sources but one with their source, in the sources order.
+    pub(crate) fn other_sources<'a>(
+        &'a self,
+        source: &'a str,
+    ) -> impl Iterator<Item = (&'static str, &'a Diagnostic)> + 'a {
+        self.sources
+            .iter()
+            .filter(move |(name, _)| **name != source)
+            .flat_map(|(name, diagnostics)| {
+                diagnostics.iter().map(|diagnostic| (*name, diagnostic))
+            })
+    }
+}
+
+/// Latest diagnostics of every file, written by bacon and the providers and read when
+/// publishing.
+#[derive(Debug, Default)]
+pub(crate) struct DiagnosticsStore {
+    files: HashMap<Url, FileDiagnostics>,
+    /// Resolution of the paths of the diagnostics and of the client to the ones seen by the
+    /// editor, so both match.
+    pub(crate) paths: PathResolver,
+    /// Whether the diagnostics of the files outside the workspace folders, like the ones of the
+    /// registry or of the sysroot, are kept.
+    pub(crate) include_external: bool,
+}
+
+impl DiagnosticsStore {
+    pub(crate) fn get(&self, uri: &Url) -> Option<&FileDiagnostics> {
+        self.files
+            .get(uri)
+            .or_else(|| self.files.get(&self.paths.resolve(uri)))
+    }
+
+    /// Version of the diagnostics of a file, 0 if it never had any.
+    pub(crate) fn version(&self, uri: &Url) -> u64 {
+        self.get(uri).map_or(0, |file| file.version)
+    }
+
+    /// Files with diagnostics of any source.
+    pub(crate) fn uris(&self) -> impl Iterator<Item = &Url> {
+        self.files
+            .iter()
+            .filter(|(_, file)| !file.sources.is_empty())
+            .map(|(uri, _)| uri)
+    }
+
+    /// Set the diagnostics of a source for a file, returning true if they changed.
+    pub(crate) fn set(
+        &mut self,
+        source: &'static str,
+        uri: &Url,
+        diagnostics: Vec<Diagnostic>,
+    ) -> bool {
+        let file = self.files.entry(uri.clone()).or_default();
+        file.checked = Some(SystemTime::now());
+        let changed = if diagnostics.is_empty() {
+            file.sources.remove(source).is_some()
+        } else if file.sources.get(source) != Some(&diagnostics) {
+            file.sources.insert(source, diagnostics);
+            true
+        } else {
+            false
+        };
+        if changed {
+            file.version += 1;
+        }
+        changed
+    }
+
+    /// Replace the diagnostics of a source for all the files, returning the files whose
+    /// diagnostics changed.
+    pub(crate) fn replace(
+        &mut self,
+        source: &'static str,
+        diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    ) -> Vec<Url> {
+        self.replace_matching(source, diagnostics, |_| true)
+    }
+
+    /// Replace the diagnostics of a source for the files of a workspace folder, keeping the
+    /// ones of the other folders, returning the files whose diagnostics changed.
+    pub(crate) fn replace_in_folder(
+        &mut self,
+        source: &'static str,
+        folder_path: &Path,
+        diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    ) -> Vec<Url> {
+        self.replace_matching(source, diagnostics, |uri| {
+            Path::new(uri.path()).starts_with(folder_path)
+        })
+    }
+
+    fn replace_matching(
+        &mut self,
+        source: &'static str,
+        diagnostics: HashMap<Url, Vec<Diagnostic>>,
+        replaced: impl Fn(&Url) -> bool,
+    ) -> Vec<Url> {
+        let mut diagnostics: HashMap<Url, Vec<Diagnostic>> =
+            diagnostics
+                .into_iter()
+                .fold(HashMap::new(), |mut mapped, (uri, file_diagnostics)| {
+                    let uri = self.paths.resolve(&uri);
+                    if self.include_external || self.paths.is_in_workspace(&uri) {
+                        mapped.entry(uri).or_default().extend(file_diagnostics);
+                    } else {
+                        tracing::debug!("dropping the diagnostics of the external file {uri}");
+                    }
+                    mapped
+                });
+        let uris: Vec<Url> = self
+            .files
+            .iter()
+            .filter(|(uri, file)| {
+                file.sources.contains_key(source)
+                    && !diagnostics.contains_key(*uri)
+                    && replaced(uri)
+            })
+            .map(|(uri, _)| uri.clone())
+            .chain(diagnostics.keys().cloned())
+            .collect();
+        uris.into_iter()
+            .filter(|uri| {
+                let file_diagnostics = diagnostics.remove(uri).unwrap_or_default();
+                self.set(source, uri, file_diagnostics)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path_mapping::PathMappings;
+    use pretty_assertions::assert_eq;
+
+    fn diagnostic(message: &str) -> Diagnostic {
+        Diagnostic {
+            message: message.to_string(),
+            ..Diagnostic::default()
+        }
+    }
+
+    #[test]
+    fn test_diagnostics_store() {
+        let lib = Url::parse("file:///projects/bacon-ls/src/lib.rs").unwrap();
+        let main = Url::parse("file:///projects/bacon-ls/src/main.rs").unwrap();
+        let mut store = DiagnosticsStore::default();
+
+        assert!(store.set(BACON_SOURCE, &lib, vec![diagnostic("unused")]));
+        assert!(!store.set(BACON_SOURCE, &lib, vec![diagnostic("unused")]));
+        let changed = store.replace(
+            "rustfmt",
+            HashMap::from([
+                (lib.clone(), vec![diagnostic("unformatted")]),
+                (main.clone(), vec![diagnostic("unformatted")]),
+            ]),
+        );
+        assert_eq!(changed.len(), 2);
+        let file = store.get(&lib).unwrap();
+        assert_eq!(file.version, 2);
+        assert_eq!(file.source(BACON_SOURCE), &[diagnostic("unused")]);
+        assert_eq!(
+            file.other_sources(BACON_SOURCE).collect::<Vec<_>>(),
+            vec![("rustfmt", &diagnostic("unformatted"))]
+        );
+
+        // Files missing from the replacement are cleared.
+        let changed = store.replace(
+            "rustfmt",
+            HashMap::from([(lib.clone(), vec![diagnostic("unformatted")])]),
+        );
+        assert_eq!(changed, vec![main.clone()]);
+        assert!(store.get(&main).unwrap().source("rustfmt").is_empty());
+        assert_eq!(store.get(&main).unwrap().version, 2);
+
+        // Only the files of the folder are replaced.
+        let other = Url::parse("file:///projects/other/src/lib.rs").unwrap();
+        store.set("rustfmt", &other, vec![diagnostic("unformatted")]);
+        let changed =
+            store.replace_in_folder("rustfmt", Path::new("/projects/bacon-ls"), HashMap::new());
+        assert_eq!(changed, vec![lib.clone()]);
+        assert_eq!(
+            store.get(&other).unwrap().source("rustfmt"),
+            &[diagnostic("unformatted")]
+        );
+
+        // The diagnostics of the tools are stored on the paths seen by the editor.
+        store.paths.mappings = serde_json::from_value(serde_json::json!([
+            {"from": "/workspace", "to": "/projects/bacon-ls"}
+        ]))
+        .map(PathMappings::new)
+        .unwrap();
+        let changed = store.replace(
+            "rustfmt",
+            HashMap::from([(
+                Url::parse("file:///workspace/src/main.rs").unwrap(),
+                vec![diagnostic("unformatted")],
+            )]),
+        );
+        assert_eq!(changed, vec![other, main.clone()]);
+        assert_eq!(
+            store.get(&main).unwrap().source("rustfmt"),
+            &[diagnostic("unformatted")]
+        );
+
+        // The diagnostics outside the workspace folders are dropped unless asked to keep them.
+        store
+            .paths
+            .set_workspace_folders(&[tower_lsp::lsp_types::WorkspaceFolder {
+                uri: Url::parse("file:///projects/bacon-ls").unwrap(),
+                name: "bacon-ls".to_string(),
+            }]);
+        let registry =
+            Url::parse("file:///home/user/.cargo/registry/src/serde/src/lib.rs").unwrap();
+        let external = || HashMap::from([(registry.clone(), vec![diagnostic("deprecated")])]);
+        assert!(store.replace("custom", external()).is_empty());
+        store.include_external = true;
+        assert_eq!(store.replace("custom", external()), vec![registry]);
+    }
+}
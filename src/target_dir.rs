@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use tokio::sync::RwLock;
+
+use crate::usage;
+use crate::{parsed_output_command, BaconLs, State};
+
+/// Environment variables setting the target directory of cargo.
+const TARGET_DIR_VARS: [&str; 2] = ["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"];
+
+/// Return true if the `build.target-dir` key is set in the content of a cargo configuration
+/// file, either in the `[build]` table or as a dotted key.
+fn config_sets_target_dir(config: &str) -> bool {
+    let mut table = "";
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            table = line;
+            continue;
+        }
+        let Some((key, _)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().trim_matches('"');
+        if (table == "[build]" && key == "target-dir") || key == "build.target-dir" {
+            return true;
+        }
+    }
+    false
+}
+
+/// Configuration files read by cargo in a folder: the ones of the folder and its parents, and
+/// the one of the cargo home.
+fn cargo_config_files(folder_path: &Path) -> Vec<PathBuf> {
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+    folder_path
+        .ancestors()
+        .map(|folder| folder.join(".cargo"))
+        .chain(cargo_home)
+        .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+        .collect()
+}
+
+impl BaconLs {
+    /// Root of the Cargo workspace of a folder, from `cargo metadata`.
+    pub(crate) async fn cargo_workspace_root(
+        folder_path: &Path,
+        cargo_env: &HashMap<String, String>,
+    ) -> Option<PathBuf> {
+        let output = usage::output(
+            parsed_output_command("cargo")
+                .args(["metadata", "--format-version", "1", "--no-deps"])
+                .envs(cargo_env)
+                .current_dir(folder_path),
+        )
+        .await
+        .ok()
+        .filter(|output| output.status.success())?;
+        let metadata: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+        metadata["workspace_root"].as_str().map(PathBuf::from)
+    }
+
+    /// Target directory set by bacon-ls for the cargo commands run in a folder: `cargoTargetDir`,
+    /// relative to the root of the Cargo workspace of the folder, unless cargo gets one from
+    /// `cargoEnv`, the environment or its configuration files.
+    async fn resolve_cargo_target_dir(
+        cargo_env: &HashMap<String, String>,
+        cargo_target_dir: &str,
+        folder_path: &Path,
+    ) -> Option<PathBuf> {
+        if cargo_target_dir.is_empty()
+            || TARGET_DIR_VARS
+                .iter()
+                .any(|var| cargo_env.contains_key(*var) || env::var_os(var).is_some())
+        {
+            return None;
+        }
+        for config_file in cargo_config_files(folder_path) {
+            if let Ok(config) = tokio::fs::read_to_string(&config_file).await {
+                if config_sets_target_dir(&config) {
+                    tracing::debug!(
+                        "{} sets the target directory, not setting CARGO_TARGET_DIR",
+                        config_file.display()
+                    );
+                    return None;
+                }
+            }
+        }
+        let cargo_target_dir = Path::new(cargo_target_dir);
+        if cargo_target_dir.is_absolute() {
+            return Some(cargo_target_dir.to_path_buf());
+        }
+        let workspace_root = Self::cargo_workspace_root(folder_path, cargo_env)
+            .await
+            .unwrap_or_else(|| folder_path.to_path_buf());
+        Some(workspace_root.join(cargo_target_dir))
+    }
+
+    /// Environment of the cargo commands run in a workspace folder, with `CARGO_TARGET_DIR` set
+    /// to its target directory, resolved on first use.
+    pub(crate) async fn folder_cargo_env(
+        state: &RwLock<State>,
+        folder_path: &Path,
+    ) -> HashMap<String, String> {
+        let read_state = state.read().await;
+        let mut env = read_state.cargo_env.clone();
+        let resolved = read_state
+            .projects
+            .get(folder_path)
+            .and_then(|project| project.cargo_target_dir.clone());
+        let cargo_target_dir = read_state.cargo_target_dir.clone();
+        drop(read_state);
+        let target_dir = match resolved {
+            Some(target_dir) => target_dir,
+            None => {
+                let target_dir =
+                    Self::resolve_cargo_target_dir(&env, &cargo_target_dir, folder_path).await;
+                state.write().await.project(folder_path).cargo_target_dir =
+                    Some(target_dir.clone());
+                target_dir
+            }
+        };
+        if let Some(target_dir) = target_dir {
+            env.insert(
+                "CARGO_TARGET_DIR".to_string(),
+                target_dir.display().to_string(),
+            );
+        }
+        env
+    }
+
+    /// Environment of bacon in a workspace folder, which only uses the target directory of
+    /// bacon-ls if `baconCargoTargetDir` is set.
+    #[cfg(feature = "bacon")]
+    pub(crate) async fn bacon_cargo_env(
+        state: &RwLock<State>,
+        folder_path: &Path,
+    ) -> HashMap<String, String> {
+        let read_state = state.read().await;
+        if !read_state.bacon_cargo_target_dir {
+            return read_state.cargo_env.clone();
+        }
+        drop(read_state);
+        Self::folder_cargo_env(state, folder_path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_config_sets_target_dir() {
+        assert!(config_sets_target_dir(
+            "[build]\njobs = 4\ntarget-dir = \"/tmp/target\"\n"
+        ));
+        assert!(config_sets_target_dir("build.target-dir = \"target/ls\"\n"));
+        assert!(!config_sets_target_dir(
+            "[build]\njobs = 4\n\n[alias]\ntarget-dir = \"build\"\n"
+        ));
+        assert!(!config_sets_target_dir(""));
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_resolve_cargo_target_dir() {
+        let tmp_dir = TempDir::new("bacon-ls").unwrap();
+        let folder_path = tmp_dir.path().join("project");
+        std::fs::create_dir_all(folder_path.join(".cargo")).unwrap();
+        let resolve = |cargo_env: HashMap<String, String>, cargo_target_dir: &'static str| {
+            let folder_path = folder_path.clone();
+            async move {
+                BaconLs::resolve_cargo_target_dir(&cargo_env, cargo_target_dir, &folder_path).await
+            }
+        };
+        // Without a Cargo workspace, the target directory is relative to the folder.
+        assert_eq!(
+            resolve(HashMap::new(), "target/bacon-ls").await,
+            Some(folder_path.join("target/bacon-ls"))
+        );
+        assert_eq!(
+            resolve(HashMap::new(), "/tmp/target").await,
+            Some(PathBuf::from("/tmp/target"))
+        );
+        assert_eq!(resolve(HashMap::new(), "").await, None);
+        let cargo_env = HashMap::from([("CARGO_TARGET_DIR".to_string(), "target/ls".to_string())]);
+        assert_eq!(resolve(cargo_env, "target/bacon-ls").await, None);
+
+        // A member of a workspace shares the target directory of the workspace.
+        std::fs::write(
+            tmp_dir.path().join("project/Cargo.toml"),
+            "[workspace]\nmembers = [\"member\"]\nresolver = \"2\"\n",
+        )
+        .unwrap();
+        let member_path = folder_path.join("member");
+        std::fs::create_dir_all(member_path.join("src")).unwrap();
+        std::fs::write(
+            member_path.join("Cargo.toml"),
+            "[package]\nname = \"member\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        std::fs::write(member_path.join("src/lib.rs"), "").unwrap();
+        assert_eq!(
+            BaconLs::resolve_cargo_target_dir(&HashMap::new(), "target/bacon-ls", &member_path)
+                .await,
+            Some(folder_path.join("target/bacon-ls"))
+        );
+
+        // The cargo configuration of the project sets its own.
+        std::fs::write(
+            folder_path.join(".cargo/config.toml"),
+            "[build]\ntarget-dir = \"build\"\n",
+        )
+        .unwrap();
+        assert_eq!(resolve(HashMap::new(), "target/bacon-ls").await, None);
+    }
+}
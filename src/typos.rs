@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, Url};
+
+use crate::error::{BaconLsError, Result};
+use crate::parsed_output_command;
+use crate::provider::DiagnosticsProvider;
+use crate::usage::{self, record_json_bytes};
+use crate::DiagnosticData;
+
+pub(crate) const TYPOS_PROVIDER: &str = "typos";
+
+/// Line of the `typos --format json` output.
+#[derive(Debug, Deserialize)]
+struct Typo {
+    #[serde(rename = "type")]
+    kind: String,
+    path: String,
+    /// One based.
+    line_num: u32,
+    /// Offset of the typo in its line, in bytes.
+    byte_offset: usize,
+    typo: String,
+    #[serde(default)]
+    corrections: Vec<String>,
+}
+
+/// Diagnostic of a typo, whose corrections are offered as quick fixes. The line of the typo
+/// converts its byte offset into a column, which is kept if the line is missing.
+fn typo_diagnostic(typo: &Typo, line: Option<&str>) -> Diagnostic {
+    let column = line
+        .and_then(|line| line.get(..typo.byte_offset))
+        .map_or(typo.byte_offset, |before| before.chars().count()) as u32;
+    let start = Position::new(typo.line_num.saturating_sub(1), column);
+    let end = Position::new(start.line, column + typo.typo.chars().count() as u32);
+    let corrections: Vec<String> = typo
+        .corrections
+        .iter()
+        .map(|correction| format!("`{correction}`"))
+        .collect();
+    let message = match corrections.as_slice() {
+        [] => format!("`{}` is misspelled", typo.typo),
+        corrections => format!("`{}` should be {}", typo.typo, corrections.join(" or ")),
+    };
+    Diagnostic {
+        range: Range::new(start, end),
+        severity: Some(DiagnosticSeverity::HINT),
+        source: Some(TYPOS_PROVIDER.to_string()),
+        message,
+        data: (!typo.corrections.is_empty()).then(|| {
+            serde_json::json!(DiagnosticData {
+                corrections: typo
+                    .corrections
+                    .iter()
+                    .map(|correction| correction.as_str().into())
+                    .collect(),
+                edits: vec![],
+                title: None,
+                rendered: None,
+            })
+        }),
+        ..Diagnostic::default()
+    }
+}
+
+/// Report the misspelled words found by `typos` in the workspace folder.
+#[derive(Debug)]
+pub(crate) struct Typos;
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for Typos {
+    fn name(&self) -> &'static str {
+        TYPOS_PROVIDER
+    }
+
+    /// Any file can have typos, and saving `typos.toml` changes them.
+    fn runs_on_save(&self, _uri: &Url) -> bool {
+        true
+    }
+
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let output = usage::output(
+            parsed_output_command("typos")
+                .envs(env)
+                .args(["--format", "json"])
+                .current_dir(folder_path),
+        )
+        .await
+        .map_err(|e| {
+            BaconLsError::Spawn(format!("failed to run typos, is typos-cli installed? {e}"))
+        })?;
+        record_json_bytes(output.stdout.len());
+        // typos exits with 2 when it finds typos.
+        if !output.status.success() && output.stdout.is_empty() {
+            return Err(BaconLsError::Spawn(format!(
+                "typos failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let mut typos: HashMap<String, Vec<Typo>> = HashMap::new();
+        for typo in String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Typo>(line).ok())
+            .filter(|typo| typo.kind == "typo")
+        {
+            typos.entry(typo.path.clone()).or_default().push(typo);
+        }
+        let mut diagnostics = HashMap::new();
+        for (path, typos) in typos {
+            let path = folder_path.join(path);
+            let Ok(url) = Url::from_file_path(&path) else {
+                continue;
+            };
+            let content = tokio::fs::read_to_string(&path)
+                .await
+                .inspect_err(|e| tracing::debug!("unable to read {}: {e}", path.display()))
+                .unwrap_or_default();
+            let lines: Vec<&str> = content.lines().collect();
+            let file_diagnostics = typos
+                .iter()
+                .map(|typo| {
+                    let line = lines.get(typo.line_num.saturating_sub(1) as usize).copied();
+                    typo_diagnostic(typo, line)
+                })
+                .collect();
+            diagnostics.insert(url, file_diagnostics);
+        }
+        Ok(diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_typo_diagnostic() {
+        let typo: Typo = serde_json::from_str(
+            r#"{"type":"typo","path":"./src/lib.rs","line_num":3,"byte_offset":10,"typo":"teh","corrections":["the"]}"#,
+        )
+        .unwrap();
+        let diagnostic = typo_diagnostic(&typo, Some("// café, teh end"));
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(2, 9), Position::new(2, 12))
+        );
+        assert_eq!(diagnostic.message, "`teh` should be `the`");
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::HINT));
+        assert_eq!(
+            diagnostic.data,
+            Some(serde_json::json!({"corrections": ["the"]}))
+        );
+
+        let typo = Typo {
+            corrections: vec![],
+            ..typo
+        };
+        let diagnostic = typo_diagnostic(&typo, None);
+        assert_eq!(diagnostic.range.start, Position::new(2, 10));
+        assert_eq!(diagnostic.message, "`teh` is misspelled");
+        assert_eq!(diagnostic.data, None);
+    }
+}
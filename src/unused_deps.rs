@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Url};
+
+use crate::audit::{dependency_declarations, find_declaration, is_cargo_manifest};
+use crate::error::{BaconLsError, Result};
+use crate::parsed_output_command;
+use crate::provider::DiagnosticsProvider;
+use crate::usage::{self, record_json_bytes};
+use crate::{DiagnosticData, DiagnosticEdit};
+
+pub(crate) const UNUSED_DEPENDENCIES_PROVIDER: &str = "unused-dependencies";
+
+/// Tool used to find the unused dependencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnusedDependenciesChecker {
+    Machete,
+    Udeps,
+}
+
+impl UnusedDependenciesChecker {
+    pub(crate) fn parse(checker: &str) -> Option<Self> {
+        match checker {
+            "cargo-machete" => Some(Self::Machete),
+            "cargo-udeps" => Some(Self::Udeps),
+            _ => None,
+        }
+    }
+
+    fn tool(&self) -> &'static str {
+        match self {
+            Self::Machete => "cargo-machete",
+            Self::Udeps => "cargo-udeps",
+        }
+    }
+}
+
+/// Dependency reported as unused, with the kind of its table if the tool tells it.
+#[derive(Debug, PartialEq)]
+struct UnusedDependency {
+    kind: Option<&'static str>,
+    name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct UdepsReport {
+    #[serde(default)]
+    unused_deps: HashMap<String, UdepsPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UdepsPackage {
+    manifest_path: PathBuf,
+    #[serde(default)]
+    normal: Vec<String>,
+    #[serde(default)]
+    development: Vec<String>,
+    #[serde(default)]
+    build: Vec<String>,
+}
+
+/// Parse the `cargo machete` output, listing the unused dependencies under a
+/// `<package> -- <manifest path>:` line for each package.
+fn parse_machete_output(
+    output: &str,
+    folder_path: &Path,
+) -> HashMap<PathBuf, Vec<UnusedDependency>> {
+    let mut unused: HashMap<PathBuf, Vec<UnusedDependency>> = HashMap::new();
+    let mut manifest_path: Option<PathBuf> = None;
+    for line in output.lines() {
+        if line.starts_with(char::is_whitespace) {
+            if let (Some(manifest_path), Some(name)) =
+                (&manifest_path, line.split_whitespace().next())
+            {
+                unused
+                    .entry(manifest_path.clone())
+                    .or_default()
+                    .push(UnusedDependency {
+                        kind: None,
+                        name: name.to_string(),
+                    });
+            }
+        } else {
+            manifest_path = line
+                .strip_suffix(':')
+                .and_then(|line| line.split_once(" -- "))
+                .map(|(_, path)| folder_path.join(path));
+        }
+    }
+    unused
+}
+
+fn parse_udeps_output(output: &[u8]) -> Result<HashMap<PathBuf, Vec<UnusedDependency>>> {
+    record_json_bytes(output.len());
+    let report: UdepsReport = serde_json::from_slice(output)
+        .map_err(|e| BaconLsError::Parse(format!("failed to parse cargo udeps output: {e}")))?;
+    Ok(report
+        .unused_deps
+        .into_values()
+        .map(|package| {
+            let unused = [
+                ("dependencies", package.normal),
+                ("dev-dependencies", package.development),
+                ("build-dependencies", package.build),
+            ]
+            .into_iter()
+            .flat_map(|(kind, names)| {
+                names.into_iter().map(move |name| UnusedDependency {
+                    kind: Some(kind),
+                    name,
+                })
+            })
+            .collect();
+            (package.manifest_path, unused)
+        })
+        .collect())
+}
+
+/// Diagnostics of the unused dependencies declared in a manifest, each with a quick fix removing
+/// the declaration.
+fn unused_dependencies_diagnostics(
+    manifest: &str,
+    unused: &[UnusedDependency],
+    checker: UnusedDependenciesChecker,
+) -> Vec<Diagnostic> {
+    let declarations = dependency_declarations(manifest);
+    unused
+        .iter()
+        .map(|UnusedDependency { kind, name }| {
+            // Tools report either the package name or the key of renamed dependencies.
+            let declaration = find_declaration(&declarations, *kind, name);
+            let data = declaration.map(|declaration| {
+                serde_json::json!(DiagnosticData {
+                    corrections: vec![],
+                    edits: vec![DiagnosticEdit {
+                        range: declaration.entry,
+                        new_text: "".into(),
+                    }],
+                    title: Some(format!("Remove unused dependency `{name}`").into()),
+                    rendered: None,
+                })
+            });
+            Diagnostic {
+                range: declaration
+                    .map(|declaration| declaration.range)
+                    .unwrap_or_default(),
+                severity: Some(DiagnosticSeverity::WARNING),
+                source: Some(checker.tool().to_string()),
+                message: match kind {
+                    Some(kind) if *kind != "dependencies" => {
+                        format!("unused dependency `{name}` in `[{kind}]`")
+                    }
+                    _ => format!("unused dependency `{name}`"),
+                },
+                data,
+                ..Diagnostic::default()
+            }
+        })
+        .collect()
+}
+
+#[tower_lsp::async_trait]
+impl DiagnosticsProvider for UnusedDependenciesChecker {
+    fn name(&self) -> &'static str {
+        UNUSED_DEPENDENCIES_PROVIDER
+    }
+
+    /// Dependencies become unused by editing the sources, so any save can change the findings,
+    /// but cargo-udeps builds the whole workspace, so it only runs again when a manifest is
+    /// saved.
+    fn runs_on_save(&self, uri: &Url) -> bool {
+        match self {
+            Self::Machete => true,
+            Self::Udeps => is_cargo_manifest(uri),
+        }
+    }
+
+    /// Run the checker in the workspace folder and return the unused dependencies as diagnostics
+    /// on the Cargo manifests declaring them.
+    async fn run(
+        &self,
+        folder_path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+        let checker = *self;
+        let mut command = parsed_output_command("cargo");
+        command.envs(env);
+        match checker {
+            UnusedDependenciesChecker::Machete => command.arg("machete"),
+            // cargo-udeps only works with a nightly toolchain.
+            UnusedDependenciesChecker::Udeps => {
+                command.args(["+nightly", "udeps", "--all-targets", "--output", "json"])
+            }
+        };
+        let output = usage::output(command.current_dir(folder_path))
+            .await
+            .map_err(|e| BaconLsError::Spawn(format!("failed to run {}: {e}", checker.tool())))?;
+        // Both tools exit with an error when unused dependencies are found.
+        let unused = match checker {
+            UnusedDependenciesChecker::Machete => {
+                if output.status.code() == Some(2) {
+                    return Err(BaconLsError::Spawn(format!(
+                        "cargo machete failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )));
+                }
+                parse_machete_output(&String::from_utf8_lossy(&output.stdout), folder_path)
+            }
+            UnusedDependenciesChecker::Udeps => {
+                if output.stdout.is_empty() {
+                    return Err(BaconLsError::Spawn(format!(
+                        "cargo udeps failed, is cargo-udeps installed? {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )));
+                }
+                parse_udeps_output(&output.stdout)?
+            }
+        };
+
+        let mut diagnostics = HashMap::new();
+        for (manifest_path, names) in unused {
+            let manifest = tokio::fs::read_to_string(&manifest_path)
+                .await
+                .map_err(|e| {
+                    BaconLsError::io(format!("unable to read {}", manifest_path.display()), e)
+                })?;
+            let url = Url::from_file_path(&manifest_path).map_err(|_| {
+                BaconLsError::Parse(format!("invalid manifest path {}", manifest_path.display()))
+            })?;
+            diagnostics.insert(
+                url,
+                unused_dependencies_diagnostics(&manifest, &names, checker),
+            );
+        }
+        Ok(diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tower_lsp::lsp_types::{Position, Range};
+
+    #[test]
+    fn test_parse_machete_output() {
+        let output = r#"Analyzing dependencies of crates in this directory...
+cargo-machete found the following unused dependencies in /projects/bacon-ls:
+bacon-ls -- ./Cargo.toml:
+	tokio
+	serde_json
+bacon-ls-macros -- ./macros/Cargo.toml:
+	syn
+
+If you believe cargo-machete has detected an unused dependency incorrectly,
+you can add the dependency to the list of dependencies to ignore in the
+`[package.metadata.cargo-machete]` section of the appropriate Cargo.toml.
+Done!
+"#;
+        let folder_path = Path::new("/projects/bacon-ls");
+        let unused = parse_machete_output(output, folder_path);
+        assert_eq!(unused.len(), 2);
+        let names = |manifest_path: &str| {
+            unused[&folder_path.join(manifest_path)]
+                .iter()
+                .map(|dependency| (dependency.kind, dependency.name.as_str()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("./Cargo.toml"),
+            vec![(None, "tokio"), (None, "serde_json")]
+        );
+        assert_eq!(names("./macros/Cargo.toml"), vec![(None, "syn")]);
+    }
+
+    #[test]
+    fn test_parse_udeps_output() {
+        let output = br#"{
+            "success": false,
+            "unused_deps": {
+                "bacon-ls 0.12.0 (path+file:///projects/bacon-ls)": {
+                    "manifest_path": "/projects/bacon-ls/Cargo.toml",
+                    "normal": ["tokio"],
+                    "development": ["tempdir"],
+                    "build": []
+                }
+            },
+            "note": "Note: They might be false-positive."
+        }"#;
+        let unused = parse_udeps_output(output).unwrap();
+        assert_eq!(
+            unused[Path::new("/projects/bacon-ls/Cargo.toml")],
+            vec![
+                UnusedDependency {
+                    kind: Some("dependencies"),
+                    name: "tokio".to_string()
+                },
+                UnusedDependency {
+                    kind: Some("dev-dependencies"),
+                    name: "tempdir".to_string()
+                },
+            ]
+        );
+        assert!(parse_udeps_output(b"error: no such command: `udeps`").is_err());
+    }
+
+    #[test]
+    fn test_unused_dependencies_diagnostics() {
+        let manifest = r#"[package]
+name = "bacon-ls"
+
+[dependencies]
+argh = "0.1.13"
+json = { version = "1.0", package = "serde_json" }
+
+[dev-dependencies]
+argh = "0.1.13"
+"#;
+        let unused = [
+            UnusedDependency {
+                kind: None,
+                name: "json".to_string(),
+            },
+            UnusedDependency {
+                kind: None,
+                name: "missing".to_string(),
+            },
+            UnusedDependency {
+                kind: Some("dev-dependencies"),
+                name: "argh".to_string(),
+            },
+        ];
+        let diagnostics =
+            unused_dependencies_diagnostics(manifest, &unused, UnusedDependenciesChecker::Machete);
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[0].source.as_deref(), Some("cargo-machete"));
+        assert_eq!(
+            diagnostics[0].range,
+            Range::new(Position::new(5, 0), Position::new(5, 4))
+        );
+        assert_eq!(diagnostics[0].message, "unused dependency `json`");
+        let data: DiagnosticData =
+            serde_json::from_value(diagnostics[0].data.clone().unwrap()).unwrap();
+        assert_eq!(
+            data.edits[0].range,
+            Range::new(Position::new(5, 0), Position::new(6, 0))
+        );
+        assert_eq!(data.edits[0].new_text, "");
+        assert_eq!(
+            data.title.as_deref(),
+            Some("Remove unused dependency `json`")
+        );
+        assert_eq!(diagnostics[1].range, Range::default());
+        assert!(diagnostics[1].data.is_none());
+        // Only the dev-dependency is removed, not the normal dependency of the same package.
+        assert_eq!(diagnostics[2].range.start, Position::new(8, 0));
+        assert_eq!(
+            diagnostics[2].message,
+            "unused dependency `argh` in `[dev-dependencies]`"
+        );
+    }
+}
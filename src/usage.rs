@@ -0,0 +1,338 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{ChildStderr, ChildStdout, Command};
+use tower_lsp::jsonrpc;
+
+use crate::BaconLs;
+
+pub(crate) const TIMINGS_METHOD: &str = "bacon-ls/timings";
+pub(crate) const DEFAULT_MAX_OUTPUT_MB: u64 = 64;
+
+tokio::task_local! {
+    static RUN_USAGE: RefCell<RunUsage>;
+    static COMMAND_LIMITS: CommandLimits;
+}
+
+/// Limits of the commands run by bacon-ls, from the settings of the server, or of the command
+/// run from the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CommandLimits {
+    /// Bytes captured from each output stream of a command, set by `maxCommandOutputMb`.
+    pub(crate) max_output_bytes: u64,
+    /// Skip the runs waiting for the cargo lock, set by `skipWhenCargoLocked`.
+    pub(crate) skip_when_locked: bool,
+}
+
+impl Default for CommandLimits {
+    fn default() -> Self {
+        Self {
+            max_output_bytes: DEFAULT_MAX_OUTPUT_MB * 1024 * 1024,
+            skip_when_locked: false,
+        }
+    }
+}
+
+impl CommandLimits {
+    pub(crate) fn set_max_output_mb(&mut self, max_output_mb: u64) {
+        self.max_output_bytes = max_output_mb.saturating_mul(1024 * 1024);
+    }
+
+    /// Run a future, the commands it spawns following these limits.
+    pub(crate) async fn scope<T>(self, future: impl Future<Output = T>) -> T {
+        COMMAND_LIMITS.scope(self, future).await
+    }
+
+    /// Limits of the commands of the current task, the default ones outside of a scope.
+    pub(crate) fn current() -> Self {
+        COMMAND_LIMITS
+            .try_with(|limits| *limits)
+            .unwrap_or_default()
+    }
+}
+
+/// Resources used by a run of a provider, summed over the processes it spawned.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RunUsage {
+    pub(crate) duration_ms: u64,
+    pub(crate) processes: u32,
+    /// User and system CPU time of the processes.
+    pub(crate) cpu_time_ms: u64,
+    /// Highest resident set size of the processes.
+    pub(crate) peak_rss_kb: u64,
+    /// Size of the JSON output parsed into diagnostics.
+    pub(crate) json_bytes: u64,
+}
+
+impl RunUsage {
+    /// Run a future, recording the usage of the processes it spawns and the JSON it parses.
+    pub(crate) async fn measure<T>(future: impl Future<Output = T>) -> (T, Self) {
+        let start = Instant::now();
+        RUN_USAGE
+            .scope(RefCell::new(Self::default()), async {
+                let output = future.await;
+                let mut usage = RUN_USAGE.with(|usage| usage.borrow().clone());
+                usage.duration_ms = start.elapsed().as_millis() as u64;
+                (output, usage)
+            })
+            .await
+    }
+
+    fn add_process(&mut self, cpu_time: Duration, peak_rss_kb: u64) {
+        self.processes += 1;
+        self.cpu_time_ms += cpu_time.as_millis() as u64;
+        self.peak_rss_kb = self.peak_rss_kb.max(peak_rss_kb);
+    }
+}
+
+/// Count bytes of JSON parsed by the current run, if it is measured.
+pub(crate) fn record_json_bytes(bytes: usize) {
+    let _ = RUN_USAGE.try_with(|usage| usage.borrow_mut().json_bytes += bytes as u64);
+}
+
+fn record_process(cpu_time: Duration, peak_rss_kb: u64) {
+    let _ = RUN_USAGE.try_with(|usage| usage.borrow_mut().add_process(cpu_time, peak_rss_kb));
+}
+
+/// Child process whose resource usage is recorded when it exits, killed if dropped before.
+pub(crate) struct MeasuredChild {
+    child: Option<std::process::Child>,
+    /// True once a blocking task is waiting for the exit, and will reap the process.
+    waiting: bool,
+    exited: bool,
+}
+
+impl MeasuredChild {
+    /// Spawn the command, which is not tied to the tokio reaper so the exit can be waited with
+    /// the resource usage of the process.
+    pub(crate) fn spawn(command: &mut Command) -> io::Result<Self> {
+        let child = command.as_std_mut().spawn()?;
+        Ok(Self {
+            child: Some(child),
+            waiting: false,
+            exited: false,
+        })
+    }
+
+    fn child(&mut self) -> &mut std::process::Child {
+        self.child
+            .as_mut()
+            .expect("the child is only taken on drop")
+    }
+
+    pub(crate) fn take_stdout(&mut self) -> io::Result<Option<ChildStdout>> {
+        self.child()
+            .stdout
+            .take()
+            .map(ChildStdout::from_std)
+            .transpose()
+    }
+
+    pub(crate) fn take_stderr(&mut self) -> io::Result<Option<ChildStderr>> {
+        self.child()
+            .stderr
+            .take()
+            .map(ChildStderr::from_std)
+            .transpose()
+    }
+
+    #[cfg(unix)]
+    pub(crate) async fn wait(mut self) -> io::Result<ExitStatus> {
+        let pid = self.child().id() as libc::pid_t;
+        self.waiting = true;
+        let (status, cpu_time, peak_rss_kb) = tokio::task::spawn_blocking(move || wait4(pid))
+            .await
+            .map_err(io::Error::other)??;
+        self.exited = true;
+        record_process(cpu_time, peak_rss_kb);
+        Ok(status)
+    }
+
+    /// The resource usage is not available, only the number of processes is recorded.
+    #[cfg(not(unix))]
+    pub(crate) async fn wait(mut self) -> io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.child().try_wait()? {
+                self.exited = true;
+                record_process(Duration::ZERO, 0);
+                return Ok(status);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+impl Drop for MeasuredChild {
+    fn drop(&mut self) {
+        if self.exited {
+            return;
+        }
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+        tracing::debug!("killing process {} abandoned before exiting", child.id());
+        let _ = child.kill();
+        if !self.waiting {
+            std::thread::spawn(move || child.wait());
+        }
+    }
+}
+
+/// Wait for a process to exit, returning its status, CPU time and peak RSS in KiB.
+#[cfg(unix)]
+fn wait4(pid: libc::pid_t) -> io::Result<(ExitStatus, Duration, u64)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: rusage is plain data, zeroed is a valid value.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: the pointers are valid for the duration of the call.
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } != -1 {
+            break;
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+    let time = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    let cpu_time = time(rusage.ru_utime) + time(rusage.ru_stime);
+    // macOS reports the peak RSS in bytes, other systems in KiB.
+    let peak_rss_kb = if cfg!(target_os = "macos") {
+        rusage.ru_maxrss as u64 / 1024
+    } else {
+        rusage.ru_maxrss as u64
+    };
+    Ok((ExitStatus::from_raw(status), cpu_time, peak_rss_kb))
+}
+
+/// Read and drop the rest of an output stream whose captured part reached the limit, so the
+/// process doesn't block on a full pipe, warning about the truncation.
+pub(crate) async fn discard_rest(
+    reader: &mut (impl AsyncRead + Unpin),
+    program: &str,
+    stream: &str,
+) -> io::Result<()> {
+    let dropped = tokio::io::copy(reader, &mut tokio::io::sink()).await?;
+    if dropped > 0 {
+        tracing::warn!(
+            "{stream} of {program} truncated to {} bytes, dropping {dropped} bytes, raise maxCommandOutputMb to keep it",
+            CommandLimits::current().max_output_bytes
+        );
+    }
+    Ok(())
+}
+
+async fn read_limited(
+    reader: Option<impl AsyncRead + Unpin>,
+    limit: u64,
+    program: &str,
+    stream: &str,
+) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    if let Some(mut reader) = reader {
+        (&mut reader).take(limit).read_to_end(&mut buffer).await?;
+        discard_rest(&mut reader, program, stream).await?;
+    }
+    Ok(buffer)
+}
+
+/// Run the command to completion collecting its output, like `Command::output`, recording its
+/// resource usage. Each stream is truncated to `maxCommandOutputMb`.
+#[tracing::instrument(level = "debug", name = "spawn", skip_all, fields(program = ?command.as_std().get_program()))]
+pub(crate) async fn output(command: &mut Command) -> io::Result<Output> {
+    let program = command.as_std().get_program().to_string_lossy().to_string();
+    let mut child = MeasuredChild::spawn(
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+    let limit = CommandLimits::current().max_output_bytes;
+    let (stdout, stderr) = tokio::try_join!(
+        read_limited(child.take_stdout()?, limit, &program, "stdout"),
+        read_limited(child.take_stderr()?, limit, &program, "stderr"),
+    )?;
+    let status = child.wait().await?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+impl BaconLs {
+    /// Handle the `bacon-ls/timings` request, returning the usage of the last run of each
+    /// provider.
+    pub(crate) async fn timings(&self) -> jsonrpc::Result<BTreeMap<&'static str, RunUsage>> {
+        Ok(self.state.read().await.run_usages.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_run_usage() {
+        let (output, usage) = RunUsage::measure(async {
+            record_json_bytes(42);
+            output(Command::new("cargo").arg("--version")).await
+        })
+        .await;
+        assert!(output.unwrap().status.success());
+        assert_eq!(usage.processes, 1);
+        assert_eq!(usage.json_bytes, 42);
+        #[cfg(target_os = "linux")]
+        assert!(usage.peak_rss_kb > 0);
+
+        // Nothing is recorded outside of a measured run.
+        record_json_bytes(42);
+    }
+
+    #[tokio::test]
+    async fn test_read_limited() {
+        let output = b"error: boom\nwarning: meh\n";
+        assert_eq!(
+            read_limited(Some(&output[..]), 11, "cargo", "stdout")
+                .await
+                .unwrap(),
+            b"error: boom"
+        );
+        assert_eq!(
+            read_limited(Some(&output[..]), 1024, "cargo", "stdout")
+                .await
+                .unwrap(),
+            output
+        );
+        assert!(read_limited(None::<&[u8]>, 1024, "cargo", "stderr")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_command_limits() {
+        let mut limits = CommandLimits::default();
+        limits.set_max_output_mb(1);
+        let output = limits
+            .scope(async {
+                assert_eq!(CommandLimits::current().max_output_bytes, 1024 * 1024);
+                output(Command::new("cargo").arg("--version")).await
+            })
+            .await;
+        assert!(output.unwrap().status.success());
+        assert_eq!(CommandLimits::current(), CommandLimits::default());
+    }
+}
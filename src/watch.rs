@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Url};
+
+#[cfg(feature = "bacon")]
+use crate::bacon::{Bacon, BaconLockStatus, Export, RestartPolicy};
+use crate::cli::{finding_line, CliSettings};
+use crate::error::{BaconLsError, Result};
+use crate::line_format::LineFormat;
+#[cfg(feature = "bacon")]
+use crate::messages::{Locale, Message};
+use crate::{DiagnosticsFilter, LocationsIndex, LOCATIONS_FILE};
+#[cfg(feature = "bacon")]
+use crate::{BACON_BACKGROUND_COMMAND_ARGS, BACON_JOB};
+
+/// Interval between the checks of the locations file.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Outcome of the last bacon run, written to the status file.
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WatchStatus {
+    errors: usize,
+    warnings: usize,
+    /// Files with errors or warnings.
+    files: usize,
+    /// Seconds since the Unix epoch of the update.
+    updated_at: u64,
+}
+
+impl WatchStatus {
+    fn new(findings: &BTreeMap<PathBuf, Vec<Diagnostic>>, updated_at: SystemTime) -> Self {
+        let count = |diagnostics: &[Diagnostic], severity| {
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.severity == Some(severity))
+                .count()
+        };
+        let mut status = Self {
+            updated_at: updated_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            ..Self::default()
+        };
+        for diagnostics in findings.values() {
+            let errors = count(diagnostics, DiagnosticSeverity::ERROR);
+            let warnings = count(diagnostics, DiagnosticSeverity::WARNING);
+            status.errors += errors;
+            status.warnings += warnings;
+            status.files += usize::from(errors + warnings > 0);
+        }
+        status
+    }
+
+    fn summary(&self) -> String {
+        let plural = |count: usize, word: &str| match count {
+            1 => format!("1 {word}"),
+            _ => format!("{count} {word}s"),
+        };
+        format!(
+            "{}, {} in {}",
+            plural(self.errors, "error"),
+            plural(self.warnings, "warning"),
+            plural(self.files, "file")
+        )
+    }
+}
+
+/// Diagnostics of the locations file kept by the filter, by file.
+fn findings(
+    by_file: HashMap<Url, Vec<(Url, Diagnostic)>>,
+    filter: &DiagnosticsFilter,
+) -> BTreeMap<PathBuf, Vec<Diagnostic>> {
+    by_file
+        .into_iter()
+        .filter_map(|(uri, diagnostics)| {
+            let diagnostics = diagnostics.into_iter().map(|(_, diagnostic)| diagnostic);
+            Some((
+                uri.to_file_path().ok()?,
+                filter.apply(diagnostics.collect()),
+            ))
+        })
+        .collect()
+}
+
+/// Print the errors and warnings followed by the summary, replacing the previous ones on a
+/// terminal.
+fn print(folder_path: &Path, findings: &BTreeMap<PathBuf, Vec<Diagnostic>>, status: &WatchStatus) {
+    if std::io::stdout().is_terminal() {
+        print!("\x1b[2J\x1b[H");
+    }
+    for (path, diagnostics) in findings.iter() {
+        let path = path.strip_prefix(folder_path).unwrap_or(path);
+        for diagnostic in diagnostics.iter().filter(|diagnostic| {
+            matches!(
+                diagnostic.severity,
+                Some(DiagnosticSeverity::ERROR | DiagnosticSeverity::WARNING)
+            )
+        }) {
+            println!("{}", finding_line(path, diagnostic));
+        }
+    }
+    println!("{}", status.summary());
+}
+
+/// Validate the bacon preferences and start bacon in the folder, unless an instance of
+/// bacon-ls already runs it. Returns the locations file exported by the preferences.
+#[cfg(feature = "bacon")]
+async fn start_bacon(
+    folder_path: &Path,
+    settings: &CliSettings,
+) -> Result<(Option<Export>, Option<tokio::task::JoinHandle<()>>)> {
+    let locations_file =
+        Bacon::validate_preferences(Some(folder_path), None, None, &[BACON_JOB]).await?;
+    match Bacon::acquire_lock(folder_path).await? {
+        BaconLockStatus::Acquired => {}
+        BaconLockStatus::Adopted { bacon } => {
+            eprintln!(
+                "{}",
+                Message::BaconAdopted { pid: bacon }.text(Locale::default())
+            );
+            return Ok((locations_file, None));
+        }
+        BaconLockStatus::Held { owner } => {
+            eprintln!(
+                "{}",
+                Message::BaconReused { pid: owner }.text(Locale::default())
+            );
+            return Ok((locations_file, None));
+        }
+    }
+    let bacon = Bacon::run_in_background(
+        "bacon",
+        BACON_BACKGROUND_COMMAND_ARGS,
+        folder_path,
+        settings.cargo_env.clone(),
+        RestartPolicy::default(),
+        None,
+        Locale::default(),
+        true,
+    )
+    .await;
+    if bacon.is_err() {
+        Bacon::release_lock(folder_path).await;
+    }
+    Ok((locations_file, Some(bacon?)))
+}
+
+/// Run bacon in the current directory and print the diagnostics of the locations file each
+/// time it changes, filtered like in the editor, writing the status file if given.
+pub async fn watch(settings: Option<PathBuf>, status_file: Option<PathBuf>) -> Result<()> {
+    let settings = CliSettings::load(settings.as_deref()).await?;
+    let folder_path = std::env::current_dir()
+        .map_err(|e| BaconLsError::io("unable to read the current directory", e))?;
+    #[cfg(feature = "bacon")]
+    let (exported, _bacon) = start_bacon(&folder_path, &settings).await?;
+    #[cfg(feature = "bacon")]
+    let (exported, line_format) = match exported {
+        Some(export) => (Some(export.path), export.line_format),
+        None => (None, LineFormat::default()),
+    };
+    #[cfg(not(feature = "bacon"))]
+    let (exported, line_format) = (None, LineFormat::default());
+    let locations_file = settings
+        .locations_file
+        .clone()
+        .or(exported)
+        .unwrap_or_else(|| LOCATIONS_FILE.to_string());
+    let bacon_locations = folder_path.join(locations_file);
+    eprintln!("watching {}", bacon_locations.display());
+    let mut index = LocationsIndex::new(line_format);
+    loop {
+        if index.refresh(&bacon_locations, &folder_path).await {
+            let mut by_file = HashMap::new();
+            index.diagnostics(&folder_path, &mut by_file);
+            let findings = findings(by_file, &settings.filter);
+            let status = WatchStatus::new(&findings, SystemTime::now());
+            print(&folder_path, &findings, &status);
+            if let Some(status_file) = status_file.as_ref() {
+                let status = serde_json::to_string(&status).unwrap_or_default();
+                if let Err(e) = tokio::fs::write(status_file, status).await {
+                    eprintln!("unable to write status file {}: {e}", status_file.display());
+                }
+            }
+        }
+        tokio::time::sleep(WATCH_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_watch_status() {
+        let diagnostic = |severity| Diagnostic {
+            severity: Some(severity),
+            ..Diagnostic::default()
+        };
+        let findings = BTreeMap::from([
+            (
+                PathBuf::from("/projects/app/src/lib.rs"),
+                vec![
+                    diagnostic(DiagnosticSeverity::ERROR),
+                    diagnostic(DiagnosticSeverity::HINT),
+                    diagnostic(DiagnosticSeverity::WARNING),
+                ],
+            ),
+            (
+                PathBuf::from("/projects/app/src/main.rs"),
+                vec![diagnostic(DiagnosticSeverity::INFORMATION)],
+            ),
+        ]);
+        let status = WatchStatus::new(&findings, SystemTime::UNIX_EPOCH + Duration::from_secs(42));
+        assert_eq!(
+            status,
+            WatchStatus {
+                errors: 1,
+                warnings: 1,
+                files: 1,
+                updated_at: 42,
+            }
+        );
+        assert_eq!(status.summary(), "1 error, 1 warning in 1 file");
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            r#"{"errors":1,"warnings":1,"files":1,"updatedAt":42}"#
+        );
+        assert_eq!(
+            WatchStatus::default().summary(),
+            "0 errors, 0 warnings in 0 files"
+        );
+    }
+}
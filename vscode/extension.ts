@@ -2,14 +2,42 @@ import * as vscode from "vscode";
 import * as os from "os";
 
 import {
+  ClientCapabilities,
+  FeatureState,
   LanguageClient,
   LanguageClientOptions,
+  NotificationType,
   ServerOptions,
+  StaticFeature,
   Executable,
 } from "vscode-languageclient/node";
 
 let client: LanguageClient | undefined;
 
+interface ServerStatusParams {
+  health: "ok" | "warning" | "error";
+  message?: string;
+}
+
+const serverStatus = new NotificationType<ServerStatusParams>(
+  "bacon-ls/serverStatus",
+);
+
+// Tell the server this client handles the bacon-ls specific notifications.
+class ServerStatusFeature implements StaticFeature {
+  fillClientCapabilities(capabilities: ClientCapabilities): void {
+    capabilities.experimental = {
+      ...capabilities.experimental,
+      serverStatusNotification: true,
+    };
+  }
+  getState(): FeatureState {
+    return { kind: "static" };
+  }
+  initialize(): void {}
+  dispose(): void {}
+}
+
 export async function activate(
   context: vscode.ExtensionContext,
 ): Promise<void> {
@@ -21,6 +49,13 @@ export async function activate(
   // when the extension is deactivated
   context.subscriptions.push(outputChannel);
 
+  const statusBarItem = vscode.window.createStatusBarItem(
+    vscode.StatusBarAlignment.Left,
+  );
+  statusBarItem.text = "$(check) bacon-ls";
+  statusBarItem.show();
+  context.subscriptions.push(statusBarItem);
+
   context.subscriptions.push(
     vscode.workspace.onDidChangeConfiguration(
       async (e: vscode.ConfigurationChangeEvent) => {
@@ -49,6 +84,7 @@ export async function activate(
 
       try {
         client = await createClient(context, name, outputChannel);
+        client.registerFeature(new ServerStatusFeature());
       } catch (err) {
         vscode.window.showErrorMessage(
           `${err instanceof Error ? err.message : err}`,
@@ -58,6 +94,11 @@ export async function activate(
 
       // Start the client. This will also launch the server
       await client.start();
+      client.onNotification(serverStatus, (params) => {
+        statusBarItem.text =
+          params.health === "ok" ? "$(check) bacon-ls" : "$(warning) bacon-ls";
+        statusBarItem.tooltip = params.message;
+      });
     }),
   );
 